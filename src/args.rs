@@ -0,0 +1,184 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    number::Number,
+    string::LuaString,
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+/// The arguments of a call to a native function, numbered from 1 as in Lua.
+///
+/// The `check_*` methods raise the same "bad argument" errors as the
+/// reference `luaL_check*` functions.
+#[derive(Debug)]
+pub struct Args {
+    name: &'static str,
+    values: Vec<Value>,
+}
+
+impl Args {
+    pub fn new(name: &'static str, values: Vec<Value>) -> Self {
+        Self { name, values }
+    }
+
+    /// The name of the called function.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Whether argument `n` was passed at all, even as `nil`.
+    pub fn is_given(&self, n: usize) -> bool {
+        n <= self.values.len()
+    }
+
+    /// Argument `n`, or `nil` if it was not passed.
+    pub fn get(&self, n: usize) -> &Value {
+        const NIL: &Value = &Value::Nil;
+        self.values.get(n - 1).unwrap_or(NIL)
+    }
+
+    /// The arguments from `n` on.
+    pub fn rest(&self, n: usize) -> &[Value] {
+        self.values.get(n - 1..).unwrap_or_default()
+    }
+
+    pub fn into_vec(self) -> Vec<Value> {
+        self.values
+    }
+
+    pub fn error(&self, n: usize, msg: impl std::fmt::Display) -> Error {
+        Error::new(format!("bad argument #{} to '{}' ({})", n, self.name, msg))
+    }
+
+    pub fn type_error(&self, n: usize, expected: &str) -> Error {
+        let actual = match self.values.get(n - 1) {
+            None => "no value".to_string(),
+            Some(v) => type_name_of(v),
+        };
+        self.error(n, format!("{} expected, got {}", expected, actual))
+    }
+
+    pub fn check_any(&self, n: usize) -> Result<&Value, Error> {
+        match self.values.get(n - 1) {
+            Some(v) => Ok(v),
+            None => Err(self.error(n, "value expected")),
+        }
+    }
+
+    pub fn check_table(&self, n: usize) -> Result<Table, Error> {
+        match self.get(n) {
+            Value::Table(t) => Ok(t.clone()),
+            _ => Err(self.type_error(n, "table")),
+        }
+    }
+
+    pub fn check_function(&self, n: usize) -> Result<Function, Error> {
+        match self.get(n) {
+            Value::Function(f) => Ok(f.clone()),
+            _ => Err(self.type_error(n, "function")),
+        }
+    }
+
+    pub fn check_number(&self, n: usize) -> Result<Number, Error> {
+        self.get(n)
+            .to_number()
+            .ok_or_else(|| self.type_error(n, "number"))
+    }
+
+    pub fn check_float(&self, n: usize) -> Result<f64, Error> {
+        self.get(n)
+            .to_float()
+            .ok_or_else(|| self.type_error(n, "number"))
+    }
+
+    pub fn opt_float(&self, n: usize, default: f64) -> Result<f64, Error> {
+        match self.get(n) {
+            Value::Nil => Ok(default),
+            _ => self.check_float(n),
+        }
+    }
+
+    pub fn check_integer(&self, n: usize) -> Result<i64, Error> {
+        let v = self.get(n);
+        match v.to_integer() {
+            Some(i) => Ok(i),
+            None if v.to_number().is_some() => {
+                Err(self.error(n, "number has no integer representation"))
+            }
+            None => Err(self.type_error(n, "number")),
+        }
+    }
+
+    pub fn opt_integer(&self, n: usize, default: i64) -> Result<i64, Error> {
+        match self.get(n) {
+            Value::Nil => Ok(default),
+            _ => self.check_integer(n),
+        }
+    }
+
+    /// A string argument; numbers are converted.
+    pub fn check_string(&self, n: usize) -> Result<LuaString, Error> {
+        self.get(n)
+            .to_lua_string()
+            .ok_or_else(|| self.type_error(n, "string"))
+    }
+
+    pub fn opt_string(&self, n: usize) -> Result<Option<LuaString>, Error> {
+        match self.get(n) {
+            Value::Nil => Ok(None),
+            _ => self.check_string(n).map(Some),
+        }
+    }
+
+    /// An argument that must be one of `options`, returning its position.
+    pub fn check_option(
+        &self,
+        n: usize,
+        default: Option<&str>,
+        options: &[&str],
+    ) -> Result<usize, Error> {
+        let name = match (self.get(n), default) {
+            (Value::Nil, Some(default)) => LuaString::from(default),
+            _ => self.check_string(n)?,
+        };
+        options
+            .iter()
+            .position(|o| o.as_bytes() == name.as_bytes())
+            .ok_or_else(|| self.error(n, format!("invalid option '{}'", name)))
+    }
+}
+
+/// The type name used in errors: a string `__name` in the metatable takes
+/// precedence, as in `luaL_typeerror`.
+pub(crate) fn type_name_of(v: &Value) -> String {
+    let metatable = match v {
+        Value::Table(t) => t.metatable(),
+        Value::Userdata(u) => u.metatable(),
+        _ => None,
+    };
+    if let Some(Value::String(name)) = metatable.map(|mt| mt.get_str("__name")) {
+        return name.to_string();
+    }
+    v.type_name().to_string()
+}
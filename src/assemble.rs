@@ -0,0 +1,675 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A textual assembler for Lua 5.4 bytecode.
+//!
+//! The text is read line by line and `;` starts a comment. Lines outside any
+//! `.function` describe the main function; `.function [name]` opens a nested
+//! function of the enclosing one, closed by `.end`. Within a function:
+//!
+//! ```text
+//! .source "@hello.lua"       ; defaults to the enclosing function's
+//! .linedefined 1
+//! .lastlinedefined 3
+//! .params 2
+//! .vararg
+//! .maxstack 4                ; defaults to one past the highest A register
+//! .upvalue _ENV 1 0          ; name instack idx [kind]
+//! .const "print"             ; nil, true, false, integers, floats, strings
+//! .local x start end         ; pcs or labels
+//! .line 2                    ; line of the following instructions
+//! loop:
+//!     GETTABUP 2 0 0
+//!     JMP loop
+//! ```
+//!
+//! Instructions take their operands in the order `luac -l` lists them. A `k`
+//! suffix on C sets the k bit where `luac -l` prints one. Jumps of `JMP`,
+//! `FORPREP`, `FORLOOP`, `TFORPREP` and `TFORLOOP` may name a label instead
+//! of a raw offset: `FORPREP` targets the loop exit, the loops their body and
+//! `TFORPREP` its `TFORCALL`. `CLOSURE` may name a nested function.
+
+use std::{collections::HashMap, io};
+
+use crate::{
+    constants::ABSLINEINFO,
+    instruction::{Instruction, MAXARG_C, OFFSET_SC},
+    opcode::*,
+    proto::{AbsLineInfo, Constant, LazyProto, LocVar, Proto, Upvalue},
+    string::LuaString,
+};
+
+/// Assembles `text` into the main function it describes.
+pub fn assemble(text: &str) -> io::Result<Proto> {
+    let mut stack = vec![Function::new(None, None)];
+    for (n, source) in text.lines().enumerate() {
+        let line = n + 1;
+        let mut tokens = tokenize(source).map_err(|msg| error(line, msg))?;
+        if let Some(Token::Word(w)) = tokens.first() {
+            if let Some(label) = w.strip_suffix(':') {
+                let f = stack.last_mut().unwrap();
+                if f.labels.insert(label.to_string(), f.code.len()).is_some() {
+                    return Err(error(line, format!("duplicate label '{}'", label)));
+                }
+                tokens.remove(0);
+            }
+        }
+        let mut tokens = tokens.into_iter();
+        let directive = match tokens.next() {
+            None => continue,
+            Some(Token::Word(w)) => w,
+            Some(Token::Str(_)) => return Err(error(line, "unexpected string")),
+        };
+        let mut operands = Operands {
+            line,
+            tokens: tokens.collect(),
+            next: 0,
+        };
+        match directive.as_str() {
+            ".function" => {
+                let name = operands.optional_word()?;
+                let source = stack.last().unwrap().source.clone();
+                stack.push(Function::new(name, source));
+            }
+            ".end" => {
+                if stack.len() == 1 {
+                    return Err(error(line, "'.end' outside of a function"));
+                }
+                let f = stack.pop().unwrap();
+                let parent = stack.last_mut().unwrap();
+                parent.names.push(f.name.clone());
+                parent.protos.push(f.finish()?);
+            }
+            ".source" => stack.last_mut().unwrap().source = Some(operands.string()?.into()),
+            ".linedefined" => stack.last_mut().unwrap().linedefined = operands.int(0, i32::MAX)?,
+            ".lastlinedefined" => {
+                stack.last_mut().unwrap().lastlinedefined = operands.int(0, i32::MAX)?
+            }
+            ".params" => stack.last_mut().unwrap().numparams = operands.int(0, 255)? as u8,
+            ".vararg" => stack.last_mut().unwrap().is_vararg = 1,
+            ".maxstack" => stack.last_mut().unwrap().maxstacksize = Some(operands.int(0, 255)?),
+            ".upvalue" => {
+                let name = operands.name()?;
+                let instack = operands.int(0, 1)? as u8;
+                let idx = operands.int(0, 255)? as u8;
+                let kind = operands.optional_int(0, 255)?.unwrap_or(0) as u8;
+                stack.last_mut().unwrap().upvalues.push(Upvalue {
+                    name,
+                    instack,
+                    idx,
+                    kind,
+                });
+            }
+            ".const" => {
+                let constant = operands.constant()?;
+                stack.last_mut().unwrap().constants.push(constant);
+            }
+            ".local" => {
+                let name = operands.name()?;
+                let start = operands.target()?;
+                let end = operands.target()?;
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .locals
+                    .push((name, start, end, line));
+            }
+            ".line" => stack.last_mut().unwrap().line = Some(operands.int(0, i32::MAX)?),
+            d if d.starts_with('.') => {
+                return Err(error(line, format!("unknown directive '{}'", d)))
+            }
+            name => stack.last_mut().unwrap().instruction(name, &mut operands)?,
+        }
+        operands.finish()?;
+    }
+    if stack.len() > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing '.end' at end of input",
+        ));
+    }
+    stack.pop().unwrap().finish()
+}
+
+fn error(line: usize, msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, msg),
+    )
+}
+
+/// A jump or `CLOSURE` operand still to be resolved against the labels or
+/// nested functions of its function.
+struct Fixup {
+    pc: usize,
+    line: usize,
+    label: String,
+}
+
+/// A function being assembled.
+struct Function {
+    name: Option<String>,
+    source: Option<LuaString>,
+    linedefined: i32,
+    lastlinedefined: i32,
+    numparams: u8,
+    is_vararg: u8,
+    maxstacksize: Option<i32>,
+    code: Vec<Instruction>,
+    constants: Vec<Constant>,
+    upvalues: Vec<Upvalue>,
+    protos: Vec<Proto>,
+    names: Vec<Option<String>>,
+    labels: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+    locals: Vec<(Option<LuaString>, Target, Target, usize)>,
+    /// The line of the next instruction, once any `.line` is given.
+    line: Option<i32>,
+    lines: Vec<Option<i32>>,
+}
+
+impl Function {
+    fn new(name: Option<String>, source: Option<LuaString>) -> Self {
+        Self {
+            name,
+            source,
+            linedefined: 0,
+            lastlinedefined: 0,
+            numparams: 0,
+            is_vararg: 0,
+            maxstacksize: None,
+            code: vec![],
+            constants: vec![],
+            upvalues: vec![],
+            protos: vec![],
+            names: vec![],
+            labels: HashMap::new(),
+            fixups: vec![],
+            locals: vec![],
+            line: None,
+            lines: vec![],
+        }
+    }
+
+    fn instruction(&mut self, name: &str, operands: &mut Operands) -> io::Result<()> {
+        let line = operands.line;
+        let op = find(name).ok_or_else(|| error(line, format!("unknown opcode '{}'", name)))?;
+        let pc = self.code.len();
+        let arg = |o: &mut Operands| o.int(i32::MIN, i32::MAX).map(|v| v as isize);
+        let signed = |o: &mut Operands| {
+            o.int(-OFFSET_SC as i32, (MAXARG_C - OFFSET_SC) as i32)
+                .map(|v| v as isize + OFFSET_SC)
+        };
+        let flag = |o: &mut Operands| o.int(0, 1).map(|v| v != 0);
+        let i = match op {
+            OP_MOVE | OP_UNM | OP_BNOT | OP_NOT | OP_LEN | OP_CONCAT | OP_LOADNIL | OP_GETUPVAL
+            | OP_SETUPVAL => Instruction::encode_abc(op, arg(operands)?, arg(operands)?, 0, false),
+            OP_LOADI | OP_LOADF => Instruction::encode_asbx(op, arg(operands)?, arg(operands)?),
+            OP_LOADK | OP_CLOSURE => {
+                let a = arg(operands)?;
+                let bx = match operands.target()? {
+                    Target::Pc(bx) => bx as isize,
+                    Target::Label(label) if op == OP_CLOSURE => {
+                        self.fixups.push(Fixup { pc, line, label });
+                        0
+                    }
+                    Target::Label(name) => {
+                        return Err(error(line, format!("unexpected label '{}'", name)))
+                    }
+                };
+                Instruction::encode_abx(op, a, bx)
+            }
+            OP_LOADKX => Instruction::encode_abx(op, arg(operands)?, 0),
+            OP_LOADFALSE | OP_LFALSESKIP | OP_LOADTRUE | OP_CLOSE | OP_TBC | OP_RETURN1
+            | OP_VARARGPREP => Instruction::encode_abc(op, arg(operands)?, 0, 0, false),
+            OP_GETTABUP | OP_GETTABLE | OP_GETI | OP_GETFIELD | OP_NEWTABLE | OP_SETTABUP
+            | OP_SETTABLE | OP_SETI | OP_SETFIELD | OP_SELF | OP_TAILCALL | OP_RETURN
+            | OP_SETLIST | OP_ADDK | OP_SUBK | OP_MULK | OP_MODK | OP_POWK | OP_DIVK | OP_IDIVK
+            | OP_BANDK | OP_BORK | OP_BXORK | OP_ADD | OP_SUB | OP_MUL | OP_MOD | OP_POW
+            | OP_DIV | OP_IDIV | OP_BAND | OP_BOR | OP_BXOR | OP_SHL | OP_SHR | OP_MMBIN
+            | OP_CALL => {
+                let a = arg(operands)?;
+                let b = arg(operands)?;
+                let (c, k) = operands.c_with_k()?;
+                Instruction::encode_abc(op, a, b, c, k)
+            }
+            OP_ADDI | OP_SHRI | OP_SHLI => {
+                let a = arg(operands)?;
+                let b = arg(operands)?;
+                Instruction::encode_abc(op, a, b, signed(operands)?, false)
+            }
+            OP_MMBINI => {
+                let a = arg(operands)?;
+                let sb = signed(operands)?;
+                let c = arg(operands)?;
+                Instruction::encode_abc(op, a, sb, c, flag(operands)?)
+            }
+            OP_MMBINK => {
+                let a = arg(operands)?;
+                let b = arg(operands)?;
+                let c = arg(operands)?;
+                Instruction::encode_abc(op, a, b, c, flag(operands)?)
+            }
+            OP_EQ | OP_LT | OP_LE | OP_TESTSET | OP_EQK => {
+                let a = arg(operands)?;
+                let b = arg(operands)?;
+                Instruction::encode_abc(op, a, b, 0, flag(operands)?)
+            }
+            OP_EQI | OP_LTI | OP_LEI | OP_GTI | OP_GEI => {
+                let a = arg(operands)?;
+                let sb = signed(operands)?;
+                let k = flag(operands)?;
+                // C tells whether the immediate was written as a float.
+                let c = operands.optional_int(0, 1)?.unwrap_or(0) as isize;
+                Instruction::encode_abc(op, a, sb, c, k)
+            }
+            OP_TEST => {
+                let a = arg(operands)?;
+                Instruction::encode_abc(op, a, 0, 0, flag(operands)?)
+            }
+            OP_TFORCALL | OP_VARARG => {
+                let a = arg(operands)?;
+                Instruction::encode_abc(op, a, 0, arg(operands)?, false)
+            }
+            OP_RETURN0 => Instruction::encode_abc(op, 0, 0, 0, false),
+            OP_JMP if operands.peek_int() => Instruction::encode_sj(op, arg(operands)?),
+            OP_JMP => {
+                let label = operands.word()?;
+                self.fixups.push(Fixup { pc, line, label });
+                Instruction::encode_sj(op, 0)
+            }
+            OP_FORPREP | OP_FORLOOP | OP_TFORPREP | OP_TFORLOOP => {
+                let a = arg(operands)?;
+                match operands.target()? {
+                    Target::Pc(bx) => Instruction::encode_abx(op, a, bx as isize),
+                    Target::Label(label) => {
+                        self.fixups.push(Fixup { pc, line, label });
+                        Instruction::encode_abx(op, a, 0)
+                    }
+                }
+            }
+            OP_EXTRAARG => Instruction::encode_ax(op, arg(operands)?),
+            _ => return Err(error(line, format!("unknown opcode '{}'", name))),
+        };
+        self.code.push(i.map_err(|e| error(line, e))?);
+        self.lines.push(self.line);
+        Ok(())
+    }
+
+    fn label(&self, line: usize, label: &str) -> io::Result<usize> {
+        self.labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| error(line, format!("undefined label '{}'", label)))
+    }
+
+    fn finish(mut self) -> io::Result<Proto> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let pc = fixup.pc as isize;
+            let i = self.code[fixup.pc];
+            let (a, _) = i.a_bx();
+            let target = || self.label(fixup.line, &fixup.label).map(|pc| pc as isize);
+            let i = match i.opcode() {
+                OP_CLOSURE => {
+                    let index = self
+                        .names
+                        .iter()
+                        .position(|n| n.as_ref() == Some(&fixup.label));
+                    let bx = index.ok_or_else(|| {
+                        error(fixup.line, format!("no function '{}'", fixup.label))
+                    })?;
+                    Instruction::encode_abx(OP_CLOSURE, a, bx as isize)
+                }
+                OP_JMP => Instruction::encode_sj(OP_JMP, target()? - pc - 1),
+                OP_FORPREP => Instruction::encode_abx(OP_FORPREP, a, target()? - pc - 2),
+                OP_TFORPREP => Instruction::encode_abx(OP_TFORPREP, a, target()? - pc - 1),
+                op => Instruction::encode_abx(op, a, pc + 1 - target()?),
+            };
+            self.code[fixup.pc] = i.map_err(|e| error(fixup.line, e))?;
+        }
+
+        let mut locvars = vec![];
+        for (varname, start, end, line) in std::mem::take(&mut self.locals) {
+            let pc = |t: Target| match t {
+                Target::Pc(pc) => Ok(pc as i32),
+                Target::Label(label) => self.label(line, &label).map(|pc| pc as i32),
+            };
+            locvars.push(LocVar {
+                varname,
+                startpc: pc(start)?,
+                endpc: pc(end)?,
+            });
+        }
+
+        let (lineinfo, abslineinfo) = self.lineinfo();
+        let maxstacksize = match self.maxstacksize {
+            Some(n) => n as u8,
+            None => self
+                .code
+                .iter()
+                .filter(|i| {
+                    !matches!(i.opmode(), OP_MODE_AX | OP_MODE_SJ)
+                        && !matches!(i.opcode(), OP_SETTABUP | OP_RETURN0)
+                })
+                .map(|i| i.abc().0 + 1)
+                .max()
+                .unwrap_or(0)
+                .max(2) as u8,
+        };
+        Ok(Proto {
+            linedefined: self.linedefined,
+            lastlinedefined: self.lastlinedefined,
+            numparams: self.numparams,
+            is_vararg: self.is_vararg,
+            maxstacksize,
+            source: self.source,
+            code: self.code,
+            constants: self.constants,
+            upvalues: self.upvalues,
+            protos: self.protos.into_iter().map(LazyProto::loaded).collect(),
+            lineinfo,
+            abslineinfo,
+            locvars,
+        })
+    }
+
+    /// Encodes the lines of the instructions as `lcode.c` does, or not at all
+    /// if no `.line` was given.
+    fn lineinfo(&self) -> (Vec<i8>, Vec<AbsLineInfo>) {
+        const LIMLINEDIFF: i32 = 0x80;
+        const MAXIWTHABS: i32 = 128;
+
+        if self.lines.iter().all(Option::is_none) {
+            return (vec![], vec![]);
+        }
+        let mut lineinfo = vec![];
+        let mut abslineinfo = vec![];
+        let mut previous = self.linedefined;
+        let mut iwthabs = 0;
+        for (pc, line) in self.lines.iter().enumerate() {
+            let line = line.unwrap_or(self.linedefined);
+            let mut delta = line - previous;
+            if delta.abs() >= LIMLINEDIFF || iwthabs >= MAXIWTHABS {
+                abslineinfo.push(AbsLineInfo {
+                    pc: pc as i32,
+                    line,
+                });
+                delta = ABSLINEINFO as i32;
+                iwthabs = 1;
+            } else {
+                iwthabs += 1;
+            }
+            lineinfo.push(delta as i8);
+            previous = line;
+        }
+        (lineinfo, abslineinfo)
+    }
+}
+
+fn check(line: usize, v: i64, min: i64, max: i64) -> io::Result<()> {
+    if (min..=max).contains(&v) {
+        Ok(())
+    } else {
+        Err(error(
+            line,
+            format!("operand {} out of range [{}, {}]", v, min, max),
+        ))
+    }
+}
+
+enum Token {
+    Word(String),
+    Str(Vec<u8>),
+}
+
+/// A pc or a label naming one.
+enum Target {
+    Pc(usize),
+    Label(String),
+}
+
+struct Operands {
+    line: usize,
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Operands {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.next);
+        if token.is_some() {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn peek_int(&self) -> bool {
+        matches!(self.tokens.get(self.next), Some(Token::Word(w)) if w.parse::<i64>().is_ok())
+    }
+
+    fn word(&mut self) -> io::Result<String> {
+        let line = self.line;
+        match self.next() {
+            Some(Token::Word(w)) => Ok(w.clone()),
+            Some(Token::Str(_)) => Err(error(line, "unexpected string")),
+            None => Err(error(line, "missing operand")),
+        }
+    }
+
+    fn optional_word(&mut self) -> io::Result<Option<String>> {
+        match self.tokens.get(self.next) {
+            None => Ok(None),
+            Some(_) => self.word().map(Some),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        let line = self.line;
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s.clone()),
+            Some(Token::Word(w)) => Err(error(line, format!("expected a string, got '{}'", w))),
+            None => Err(error(line, "missing operand")),
+        }
+    }
+
+    /// A name given as an identifier or a string, or `-` for none.
+    fn name(&mut self) -> io::Result<Option<LuaString>> {
+        let line = self.line;
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Some(s.clone().into())),
+            Some(Token::Word(w)) if w == "-" => Ok(None),
+            Some(Token::Word(w)) => Ok(Some(w.as_str().into())),
+            None => Err(error(line, "missing operand")),
+        }
+    }
+
+    fn int(&mut self, min: i32, max: i32) -> io::Result<i32> {
+        let line = self.line;
+        let w = self.word()?;
+        let v: i64 = w
+            .parse()
+            .map_err(|_| error(line, format!("expected an integer, got '{}'", w)))?;
+        check(line, v, min as i64, max as i64)?;
+        Ok(v as i32)
+    }
+
+    fn optional_int(&mut self, min: i32, max: i32) -> io::Result<Option<i32>> {
+        match self.tokens.get(self.next) {
+            None => Ok(None),
+            Some(_) => self.int(min, max).map(Some),
+        }
+    }
+
+    /// An iABC C operand, with a `k` suffix setting the k bit.
+    fn c_with_k(&mut self) -> io::Result<(isize, bool)> {
+        let line = self.line;
+        let w = self.word()?;
+        let (digits, k) = match w.strip_suffix('k') {
+            Some(digits) => (digits, true),
+            None => (w.as_str(), false),
+        };
+        let c = digits
+            .parse()
+            .map_err(|_| error(line, format!("expected an integer, got '{}'", w)))?;
+        Ok((c, k))
+    }
+
+    fn target(&mut self) -> io::Result<Target> {
+        let w = self.word()?;
+        Ok(match w.parse() {
+            Ok(pc) => Target::Pc(pc),
+            Err(_) if w.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                return Err(error(self.line, format!("bad operand '{}'", w)))
+            }
+            Err(_) => Target::Label(w),
+        })
+    }
+
+    fn constant(&mut self) -> io::Result<Constant> {
+        let line = self.line;
+        if let Some(Token::Str(_)) = self.tokens.get(self.next) {
+            return Ok(Constant::String(self.string()?.into()));
+        }
+        let w = self.word()?;
+        Ok(match w.as_str() {
+            "nil" => Constant::Nil,
+            "true" => Constant::Boolean(true),
+            "false" => Constant::Boolean(false),
+            "inf" => Constant::Number(f64::INFINITY),
+            "-inf" => Constant::Number(f64::NEG_INFINITY),
+            "nan" => Constant::Number(f64::NAN),
+            w => match w.parse() {
+                Ok(i) => Constant::Integer(i),
+                Err(_) => Constant::Number(
+                    w.parse()
+                        .map_err(|_| error(line, format!("bad constant '{}'", w)))?,
+                ),
+            },
+        })
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        if self.next < self.tokens.len() {
+            Err(error(self.line, "too many operands"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Splits a line into words and quoted strings, dropping any comment.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            tokens.push(Token::Str(unquote(c, &mut chars)?));
+        } else {
+            let mut end = line.len();
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    end = i;
+                    break;
+                }
+                chars.next();
+            }
+            tokens.push(Token::Word(line[start..end].to_string()));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads the rest of a string literal with Lua's escapes.
+fn unquote(
+    quote: char,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Result<Vec<u8>, String> {
+    let mut s = vec![];
+    let mut buf = [0; 4];
+    loop {
+        let c = match chars.next() {
+            None => return Err("unfinished string".to_string()),
+            Some((_, c)) if c == quote => return Ok(s),
+            Some((_, '\\')) => match chars.next() {
+                None => return Err("unfinished string".to_string()),
+                Some((_, c)) => c,
+            },
+            Some((_, c)) => {
+                s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+        };
+        match c {
+            'a' => s.push(0x07),
+            'b' => s.push(0x08),
+            'f' => s.push(0x0C),
+            'n' => s.push(b'\n'),
+            'r' => s.push(b'\r'),
+            't' => s.push(b'\t'),
+            'v' => s.push(0x0B),
+            '\\' | '"' | '\'' => s.push(c as u8),
+            'x' => {
+                let mut v = 0;
+                for _ in 0..2 {
+                    let d = chars.next().and_then(|(_, c)| c.to_digit(16));
+                    v = v * 16 + d.ok_or("hexadecimal digit expected")?;
+                }
+                s.push(v as u8);
+            }
+            'u' => {
+                if chars.next().map(|(_, c)| c) != Some('{') {
+                    return Err("missing '{' in \\u{xxxx}".to_string());
+                }
+                let mut v = 0_u32;
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => {
+                            let d = c.to_digit(16).ok_or("hexadecimal digit expected")?;
+                            v = v
+                                .checked_mul(16)
+                                .map(|v| v + d)
+                                .ok_or("UTF-8 value too large")?;
+                        }
+                        None => return Err("missing '}' in \\u{xxxx}".to_string()),
+                    }
+                }
+                let c = char::from_u32(v).ok_or("invalid UTF-8 value")?;
+                s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            c if c.is_ascii_digit() => {
+                let mut v = c.to_digit(10).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|&(_, c)| c.to_digit(10)) {
+                        Some(d) => {
+                            v = v * 10 + d;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if v > 0xFF {
+                    return Err("decimal escape too large".to_string());
+                }
+                s.push(v as u8);
+            }
+            c => return Err(format!("invalid escape sequence '\\{}'", c)),
+        }
+    }
+}
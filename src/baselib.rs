@@ -0,0 +1,469 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The basic library, as `lbaselib.c`.
+
+use std::{
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
+use crate::{
+    args::Args,
+    closure::Closure,
+    constants::ESC_LUA,
+    number,
+    state::State,
+    string::LuaString,
+    value::{strerror, Error, Function, Value},
+};
+
+impl State {
+    /// Opens the basic library into the globals.
+    pub fn open_base(&self) {
+        let g = self.globals();
+        self.register(
+            &g,
+            &[
+                ("assert", assert),
+                ("collectgarbage", collectgarbage),
+                ("dofile", dofile),
+                ("error", error),
+                ("getmetatable", getmetatable),
+                ("ipairs", ipairs),
+                ("load", load),
+                ("loadfile", loadfile),
+                ("next", next),
+                ("pairs", pairs),
+                ("pcall", pcall),
+                ("print", print),
+                ("rawequal", rawequal),
+                ("rawget", rawget),
+                ("rawlen", rawlen),
+                ("rawset", rawset),
+                ("select", select),
+                ("setmetatable", setmetatable),
+                ("tonumber", tonumber),
+                ("tostring", tostring),
+                ("type", type_),
+                ("warn", warn),
+                ("xpcall", xpcall),
+            ],
+        );
+        g.set_str("_G", g.clone());
+        g.set_str("_VERSION", "Lua 5.4");
+        self.loaded().set_str("_G", g);
+    }
+
+    /// Loads a chunk as `load` does. Only binary chunks can be loaded: there
+    /// is no compiler for source text.
+    pub(crate) fn load_chunk(
+        &self,
+        chunk: Vec<u8>,
+        chunkname: &[u8],
+        mode: &[u8],
+    ) -> Result<Closure, String> {
+        let name = match chunkname.first() {
+            Some(b'@' | b'=') => String::from_utf8_lossy(&chunkname[1..]).into_owned(),
+            Some(&b) if b == ESC_LUA[0] => "binary string".to_string(),
+            _ => String::from_utf8_lossy(chunkname).into_owned(),
+        };
+        let mode = String::from_utf8_lossy(mode);
+        if chunk.first() == Some(&ESC_LUA[0]) {
+            if !mode.contains('b') {
+                return Err(format!(
+                    "attempt to load a binary chunk (mode is '{}')",
+                    mode
+                ));
+            }
+            self.load_binary(chunk).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    format!("{}: bad binary format (truncated chunk)", name)
+                }
+                _ => format!("{}: {}", name, e),
+            })
+        } else if !mode.contains('t') {
+            Err(format!("attempt to load a text chunk (mode is '{}')", mode))
+        } else {
+            Err(format!(
+                "{}: cannot load text chunks, only precompiled ones",
+                name
+            ))
+        }
+    }
+}
+
+/// A Lua closure as a value, with its environment replaced by `env` if any.
+pub(crate) fn closure_value(mut closure: Closure, env: Option<Value>) -> Value {
+    if let (Some(env), Some(first)) = (env, closure.upvalues.first_mut()) {
+        *first = env;
+    }
+    Value::Function(Function::Lua(Rc::new(closure)))
+}
+
+fn fail(msg: impl Into<LuaString>) -> Vec<Value> {
+    vec![Value::Nil, Value::String(msg.into())]
+}
+
+fn assert(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if args.get(1).is_truthy() {
+        return Ok(args.into_vec());
+    }
+    args.check_any(1)?;
+    if args.is_given(2) {
+        Err(Error::from_value(args.get(2).clone()))
+    } else {
+        Err(Error::new("assertion failed!"))
+    }
+}
+
+fn collectgarbage(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    // Memory is reference counted, so there is no collector to control.
+    const OPTIONS: &[&str] = &[
+        "stop",
+        "restart",
+        "collect",
+        "count",
+        "step",
+        "setpause",
+        "setstepmul",
+        "isrunning",
+        "generational",
+        "incremental",
+    ];
+    Ok(vec![
+        match OPTIONS[args.check_option(1, Some("collect"), OPTIONS)?] {
+            "count" => Value::Number(0.0),
+            "step" | "isrunning" => Value::Boolean(true),
+            "setpause" => Value::Integer(200),
+            "setstepmul" => Value::Integer(100),
+            "generational" | "incremental" => Value::from("incremental"),
+            _ => Value::Integer(0),
+        },
+    ])
+}
+
+fn dofile(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let filename = args.opt_string(1)?;
+    let closure = load_file(state, filename.as_ref(), b"bt").map_err(Error::new)?;
+    state.call(&closure_value(closure, None), vec![])
+}
+
+/// Raises `msg` as an error. Without call information there is no position
+/// to prepend for `level`.
+fn error(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    Err(Error::from_value(args.get(1).clone()))
+}
+
+fn getmetatable(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let v = args.check_any(1)?;
+    Ok(vec![match state.metatable(v) {
+        None => Value::Nil,
+        Some(mt) => match mt.get_str("__metatable") {
+            Value::Nil => Value::Table(mt),
+            protected => protected,
+        },
+    }])
+}
+
+fn ipairs(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_any(1)?.clone();
+    let iter = Function::native("ipairs_aux", |state, args| {
+        let i = args.check_integer(2)?.wrapping_add(1);
+        match state.index(args.get(1), &Value::Integer(i))? {
+            Value::Nil => Ok(vec![Value::Nil]),
+            v => Ok(vec![Value::Integer(i), v]),
+        }
+    });
+    Ok(vec![iter.into(), t, Value::Integer(0)])
+}
+
+fn load(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mode = args.opt_string(3)?.unwrap_or_else(|| "bt".into());
+    let (chunk, default_name) = match args.get(1) {
+        Value::Function(_) => {
+            let reader = args.get(1).clone();
+            let mut chunk = vec![];
+            loop {
+                match state.call1(&reader, vec![])? {
+                    Value::Nil => break,
+                    Value::String(s) if s.is_empty() => break,
+                    Value::String(s) => chunk.extend_from_slice(&s),
+                    _ => return Ok(fail("reader function must return a string")),
+                }
+            }
+            (chunk, LuaString::from("=(load)"))
+        }
+        v => match v.to_lua_string() {
+            Some(s) => (s.to_vec(), s),
+            None => return Err(args.type_error(1, "function")),
+        },
+    };
+    let chunkname = args.opt_string(2)?.unwrap_or(default_name);
+    match state.load_chunk(chunk, &chunkname, &mode) {
+        Ok(closure) => {
+            let env = args.is_given(4).then(|| args.get(4).clone());
+            Ok(vec![closure_value(closure, env)])
+        }
+        Err(msg) => Ok(fail(msg)),
+    }
+}
+
+/// Reads and loads a file, or the standard input if `filename` is `None`.
+pub(crate) fn load_file(
+    state: &State,
+    filename: Option<&LuaString>,
+    mode: &[u8],
+) -> Result<Closure, String> {
+    let (chunk, chunkname) = match filename {
+        Some(filename) => {
+            let path = filename.to_string();
+            let chunk = std::fs::read(&path)
+                .map_err(|e| format!("cannot open {}: {}", path, strerror(&e)))?;
+            (chunk, format!("@{}", path))
+        }
+        None => {
+            let mut chunk = vec![];
+            io::stdin()
+                .read_to_end(&mut chunk)
+                .map_err(|e| format!("cannot read stdin: {}", strerror(&e)))?;
+            (chunk, "=stdin".to_string())
+        }
+    };
+    // Skip a first line starting with '#', keeping the newline so that line
+    // numbers stay right.
+    let chunk = match chunk.first() {
+        Some(b'#') => match chunk.iter().position(|&b| b == b'\n') {
+            Some(i) => chunk[i..].to_vec(),
+            None => vec![],
+        },
+        _ => chunk,
+    };
+    // A binary chunk after the comment line starts with its signature.
+    let chunk = match chunk.iter().position(|&b| b == ESC_LUA[0]) {
+        Some(i) if chunk[..i].iter().all(|&b| b == b'\n') => chunk[i..].to_vec(),
+        _ => chunk,
+    };
+    state.load_chunk(chunk, chunkname.as_bytes(), mode)
+}
+
+fn loadfile(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let filename = args.opt_string(1)?;
+    let mode = args.opt_string(2)?.unwrap_or_else(|| "bt".into());
+    match load_file(state, filename.as_ref(), &mode) {
+        Ok(closure) => {
+            let env = args.is_given(3).then(|| args.get(3).clone());
+            Ok(vec![closure_value(closure, env)])
+        }
+        Err(msg) => Ok(fail(msg)),
+    }
+}
+
+fn next(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_table(1)?;
+    Ok(match t.next(args.get(2))? {
+        Some((k, v)) => vec![k, v],
+        None => vec![Value::Nil],
+    })
+}
+
+fn pairs(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_any(1)?;
+    match state.metafield(t, "__pairs") {
+        Value::Nil => Ok(vec![
+            Function::native("next", next).into(),
+            t.clone(),
+            Value::Nil,
+        ]),
+        handler => {
+            let mut results = state.call(&handler, vec![t.clone()])?;
+            results.resize(3, Value::Nil);
+            Ok(results)
+        }
+    }
+}
+
+fn pcall(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let f = args.check_any(1)?.clone();
+    match state.call(&f, args.rest(2).to_vec()) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(e) => Ok(vec![Value::Boolean(false), e.into_value()]),
+    }
+}
+
+fn print(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut line = vec![];
+    for (i, v) in args.into_vec().iter().enumerate() {
+        if i > 0 {
+            line.push(b'\t');
+        }
+        line.extend_from_slice(&state.tostring(v)?);
+    }
+    line.push(b'\n');
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&line)?;
+    stdout.flush()?;
+    Ok(vec![])
+}
+
+fn rawequal(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let a = args.check_any(1)?;
+    let b = args.check_any(2)?;
+    Ok(vec![Value::Boolean(a.raw_equal(b))])
+}
+
+fn rawget(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_table(1)?;
+    Ok(vec![t.get(args.check_any(2)?)])
+}
+
+fn rawlen(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    match args.get(1) {
+        Value::Table(t) => Ok(vec![Value::Integer(t.len())]),
+        Value::String(s) => Ok(vec![Value::Integer(s.len() as i64)]),
+        _ => Err(args.type_error(1, "table or string")),
+    }
+}
+
+fn rawset(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_table(1)?;
+    let k = args.check_any(2)?.clone();
+    let v = args.check_any(3)?.clone();
+    t.set(k, v)?;
+    Ok(vec![Value::Table(t)])
+}
+
+fn select(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let n = args.len() as i64;
+    if let Value::String(s) = args.get(1) {
+        if s.as_bytes() == b"#" {
+            return Ok(vec![Value::Integer(n - 1)]);
+        }
+    }
+    let i = args.check_integer(1)?;
+    let i = if i < 0 { n + i } else { i.min(n) };
+    if i < 1 {
+        return Err(args.error(1, "index out of range"));
+    }
+    Ok(args.rest(i as usize + 1).to_vec())
+}
+
+fn setmetatable(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.check_table(1)?;
+    let mt = match args.get(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt.clone()),
+        _ => return Err(args.type_error(2, "nil or table")),
+    };
+    if let Some(old) = t.metatable() {
+        if !old.get_str("__metatable").is_nil() {
+            return Err(Error::new("cannot change a protected metatable"));
+        }
+    }
+    t.set_metatable(mt);
+    Ok(vec![Value::Table(t)])
+}
+
+fn tonumber(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if args.get(2).is_nil() {
+        let v = args.check_any(1)?;
+        return Ok(vec![match v {
+            Value::Integer(_) | Value::Number(_) => v.clone(),
+            Value::String(s) => number::str2number(s).map_or(Value::Nil, Value::from),
+            _ => Value::Nil,
+        }]);
+    }
+    let base = args.check_integer(2)?;
+    let s = match args.get(1) {
+        Value::String(s) => s.clone(),
+        _ => return Err(args.type_error(1, "string")),
+    };
+    if !(2..=36).contains(&base) {
+        return Err(args.error(2, "base out of range"));
+    }
+    Ok(vec![
+        str2int(&s, base as u32).map_or(Value::Nil, Value::Integer)
+    ])
+}
+
+/// Converts a numeral in `base`, wrapping around on overflow.
+fn str2int(s: &[u8], base: u32) -> Option<i64> {
+    let s = std::str::from_utf8(s).ok()?;
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\x0B');
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut n = 0_i64;
+    for c in digits.chars() {
+        let d = c.to_digit(36).filter(|d| *d < base)?;
+        n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+    }
+    Some(if negative { n.wrapping_neg() } else { n })
+}
+
+fn tostring(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let v = args.check_any(1)?;
+    Ok(vec![Value::String(state.tostring(v)?)])
+}
+
+fn type_(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let v = args.check_any(1)?;
+    Ok(vec![Value::from(v.type_name())])
+}
+
+fn warn(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    args.check_string(1)?;
+    let mut msg = vec![];
+    for n in 1..=args.len() {
+        msg.extend_from_slice(&args.check_string(n)?);
+    }
+    if args.len() == 1 && msg.first() == Some(&b'@') {
+        match &msg[..] {
+            b"@on" => state.warnings.set(true),
+            b"@off" => state.warnings.set(false),
+            _ => {}
+        }
+    } else if state.warnings.get() {
+        let mut stderr = io::stderr().lock();
+        stderr.write_all(b"Lua warning: ")?;
+        stderr.write_all(&msg)?;
+        stderr.write_all(b"\n")?;
+    }
+    Ok(vec![])
+}
+
+fn xpcall(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let f = args.check_any(1)?.clone();
+    let handler = Value::Function(args.check_function(2)?);
+    match state.call(&f, args.rest(3).to_vec()) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(e) => {
+            let v = match state.call1(&handler, vec![e.into_value()]) {
+                Ok(v) => v,
+                Err(e) => e.into_value(),
+            };
+            Ok(vec![Value::Boolean(false), v])
+        }
+    }
+}
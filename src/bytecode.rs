@@ -12,78 +12,196 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{io, ops::Not};
+use std::{
+    future::Future,
+    io::{self, Cursor},
+    ops::Not,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
 
+use bytes::Bytes;
 use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 
 use crate::{
     closure::Closure,
     constants::*,
     instruction::Instruction,
-    proto::{AbsLineInfo, Constant, LocVar, Proto, Upvalue},
+    proto::{AbsLineInfo, Constant, LazyProto, LocVar, Proto, Upvalue},
+    string::{Interner, LuaString},
+    value::Value,
+    verify::{self, Enclosing},
 };
 
 pub async fn undump<R: AsyncRead + Send + Unpin>(reader: R) -> io::Result<Closure> {
-    let mut r = Reader::new(reader);
+    undump_inner(Reader::new(reader)).await
+}
+
+/// Like [`undump`], but nested protos are only located in the chunk and left
+/// undecoded until [`LazyProto::load`] is first called on them.
+pub async fn undump_lazy<R: AsyncRead + Send + Unpin>(mut reader: R) -> io::Result<Closure> {
+    let mut chunk = vec![];
+    reader.read_to_end(&mut chunk).await?;
+    undump_inner(Reader::lazy(Bytes::from(chunk))).await
+}
+
+pub(crate) async fn undump_inner<R: AsyncRead + Send + Unpin>(
+    mut r: Reader<R>,
+) -> io::Result<Closure> {
     r.check_header().await?;
     let sizeupvalues = r.read_byte().await?;
     let proto = r.read_proto().await?;
 
-    assert_eq!(proto.upvalues.len(), sizeupvalues as usize);
+    if proto.upvalues.len() != sizeupvalues as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad number of upvalues in main function",
+        ));
+    }
 
-    let upvalues = vec![None; proto.upvalues.len()];
+    let upvalues = vec![Value::Nil; proto.upvalues.len()];
     Ok(Closure { proto, upvalues })
 }
 
+/// Loads a chunk held in memory without an async runtime: reading from memory
+/// never has to wait, so the loader completes on its first poll.
+pub(crate) fn undump_in_memory(chunk: Bytes, strings: Arc<Interner>) -> io::Result<Closure> {
+    let future = undump_inner(Reader::new(Cursor::new(chunk)).interning(strings));
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut cx) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "in-memory chunk is not ready",
+        )),
+    }
+}
+
+/// Decodes a nested proto located by a lazy [`Reader`]. Its own nested protos
+/// are left undecoded in turn.
+pub(crate) async fn undump_proto(
+    chunk: Bytes,
+    parent_source: Option<LuaString>,
+    enclosing: Enclosing,
+    strings: Arc<Interner>,
+) -> io::Result<Proto> {
+    Reader::lazy(chunk)
+        .interning(strings)
+        .read_proto_inner(parent_source, Some(enclosing))
+        .await
+}
+
 pub struct Reader<R: AsyncRead + Send + Unpin> {
     buf: BufReader<R>,
+    /// Number of bytes consumed so far.
+    offset: usize,
+    /// The whole chunk being read, present only when nested protos are loaded
+    /// lazily.
+    chunk: Option<Bytes>,
+    strings: Arc<Interner>,
 }
 
 impl<R: AsyncRead + Send + Unpin> Reader<R> {
     pub fn new(reader: R) -> Self {
         Self {
             buf: BufReader::new(reader),
+            offset: 0,
+            chunk: None,
+            strings: Arc::new(Interner::new()),
         }
     }
 
+    /// Interns short strings read from the chunk into `strings`.
+    pub fn interning(mut self, strings: Arc<Interner>) -> Self {
+        self.strings = strings;
+        self
+    }
+
     pub async fn read_bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
         let mut v = vec![];
         for _ in 0..n {
             v.push(self.buf.read_u8().await?);
         }
+        self.offset += n;
         Ok(v)
     }
 
     pub async fn read_byte(&mut self) -> io::Result<u8> {
-        self.buf.read_u8().await
+        let b = self.buf.read_u8().await?;
+        self.offset += 1;
+        Ok(b)
     }
 
     pub async fn read_i8(&mut self) -> io::Result<i8> {
-        self.buf.read_i8().await
+        let i = self.buf.read_i8().await?;
+        self.offset += 1;
+        Ok(i)
     }
 
     pub async fn read_u32(&mut self) -> io::Result<u32> {
-        self.buf.read_u32_le().await
+        let u = self.buf.read_u32_le().await?;
+        self.offset += 4;
+        Ok(u)
     }
 
     pub async fn read_lua_integer(&mut self) -> io::Result<i64> {
-        self.buf.read_i64_le().await
+        let i = self.buf.read_i64_le().await?;
+        self.offset += 8;
+        Ok(i)
     }
 
     pub async fn read_lua_number(&mut self) -> io::Result<f64> {
-        self.buf.read_f64_le().await
+        let n = self.buf.read_f64_le().await?;
+        self.offset += 8;
+        Ok(n)
+    }
+
+    async fn skip_bytes(&mut self, n: usize) -> io::Result<()> {
+        let mut skipped = (&mut self.buf).take(n as u64);
+        let m = tokio::io::copy(&mut skipped, &mut tokio::io::sink()).await? as usize;
+        if m < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.offset += n;
+        Ok(())
     }
 
     pub async fn check_header(&mut self) -> io::Result<()> {
-        assert_eq!(self.read_bytes(4).await?, ESC_LUA);
-        assert_eq!(self.read_byte().await?, LUAC_VERSION);
-        assert_eq!(self.read_byte().await?, LUAC_FORMAT);
-        assert_eq!(self.read_bytes(6).await?, LUAC_DATA);
-        assert_eq!(self.read_byte().await?, INSTRUCTION_SIZE);
-        assert_eq!(self.read_byte().await?, LUA_INTEGER_SIZE);
-        assert_eq!(self.read_byte().await?, LUA_NUMBER_SIZE);
-        assert_eq!(self.read_lua_integer().await?, LUAC_INT);
-        assert_eq!(self.read_lua_number().await?, LUAC_NUM);
+        let check = |ok: bool, what: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad binary format ({})", what),
+                ))
+            }
+        };
+        check(self.read_bytes(4).await? == ESC_LUA, "not a binary chunk")?;
+        check(self.read_byte().await? == LUAC_VERSION, "version mismatch")?;
+        check(self.read_byte().await? == LUAC_FORMAT, "format mismatch")?;
+        check(self.read_bytes(6).await? == LUAC_DATA, "corrupted chunk")?;
+        check(
+            self.read_byte().await? == INSTRUCTION_SIZE,
+            "Instruction size mismatch",
+        )?;
+        check(
+            self.read_byte().await? == LUA_INTEGER_SIZE,
+            "lua_Integer size mismatch",
+        )?;
+        check(
+            self.read_byte().await? == LUA_NUMBER_SIZE,
+            "lua_Number size mismatch",
+        )?;
+        check(
+            self.read_lua_integer().await? == LUAC_INT,
+            "integer format mismatch",
+        )?;
+        check(
+            self.read_lua_number().await? == LUAC_NUM,
+            "float format mismatch",
+        )?;
         Ok(())
     }
 
@@ -113,13 +231,13 @@ impl<R: AsyncRead + Send + Unpin> Reader<R> {
         Ok(i as i32)
     }
 
-    async fn read_string(&mut self) -> io::Result<Option<String>> {
+    async fn read_string(&mut self) -> io::Result<Option<LuaString>> {
         let size = self.read_usize_varint().await?;
         Ok(if size == 0 {
             None
         } else {
             let bytes = self.read_bytes(size - 1).await?;
-            String::from_utf8(bytes).ok()
+            Some(self.strings.intern(bytes))
         })
     }
 
@@ -209,21 +327,45 @@ impl<R: AsyncRead + Send + Unpin> Reader<R> {
     }
 
     #[async_recursion::async_recursion]
-    async fn read_protos(&mut self, parent_source: Option<String>) -> io::Result<Vec<Proto>> {
+    async fn read_protos(
+        &mut self,
+        parent_source: Option<LuaString>,
+        enclosing: Enclosing,
+    ) -> io::Result<Vec<LazyProto>> {
         let n = self.read_i32_varint().await?;
         let mut v = vec![];
         for _ in 0..n {
-            v.push(self.read_proto_inner(parent_source.clone()).await?)
+            let proto = match self.chunk.clone() {
+                None => LazyProto::loaded(
+                    self.read_proto_inner(parent_source.clone(), Some(enclosing))
+                        .await?,
+                ),
+                Some(chunk) => {
+                    let start = self.offset;
+                    self.skip_proto().await?;
+                    LazyProto::deferred(
+                        chunk.slice(start..self.offset),
+                        parent_source.clone(),
+                        enclosing,
+                        self.strings.clone(),
+                    )
+                }
+            };
+            v.push(proto)
         }
         Ok(v)
     }
 
     pub async fn read_proto(&mut self) -> io::Result<Proto> {
-        self.read_proto_inner(None).await
+        self.read_proto_inner(None, None).await
     }
 
     #[async_recursion::async_recursion]
-    async fn read_proto_inner(&mut self, parent_source: Option<String>) -> io::Result<Proto> {
+    async fn read_proto_inner(
+        &mut self,
+        parent_source: Option<LuaString>,
+        enclosing: Option<Enclosing>,
+    ) -> io::Result<Proto> {
         let source = self.read_string().await?.or(parent_source);
         let linedefined = self.read_i32_varint().await?;
         let lastlinedefined = self.read_i32_varint().await?;
@@ -233,7 +375,11 @@ impl<R: AsyncRead + Send + Unpin> Reader<R> {
         let code = self.read_code().await?;
         let constants = self.read_constants().await?;
         let mut upvalues = self.read_upvalues().await?;
-        let protos = self.read_protos(source.clone()).await?;
+        let enclosing_protos = Enclosing {
+            maxstacksize,
+            upvalues: upvalues.len(),
+        };
+        let protos = self.read_protos(source.clone(), enclosing_protos).await?;
         let lineinfo = self.read_lineinfo().await?;
         let abslineinfo = self.read_abslineinfo().await?;
         let locvars = self.read_locvars().await?;
@@ -254,7 +400,7 @@ impl<R: AsyncRead + Send + Unpin> Reader<R> {
             }
         }
 
-        Ok(Proto {
+        let proto = Proto {
             linedefined,
             lastlinedefined,
             numparams,
@@ -268,6 +414,86 @@ impl<R: AsyncRead + Send + Unpin> Reader<R> {
             lineinfo,
             abslineinfo,
             locvars,
-        })
+        };
+        verify::verify(&proto, enclosing)?;
+        Ok(proto)
+    }
+
+    async fn skip_string(&mut self) -> io::Result<()> {
+        let size = self.read_usize_varint().await?;
+        if size > 0 {
+            self.skip_bytes(size - 1).await?;
+        }
+        Ok(())
+    }
+
+    /// Skips over a proto without decoding it; mirrors the layout consumed by
+    /// [`Reader::read_proto_inner`].
+    #[async_recursion::async_recursion]
+    async fn skip_proto(&mut self) -> io::Result<()> {
+        self.skip_string().await?; // source
+        self.read_i32_varint().await?; // linedefined
+        self.read_i32_varint().await?; // lastlinedefined
+        self.skip_bytes(3).await?; // numparams, is_vararg, maxstacksize
+
+        let n = self.read_i32_varint().await? as usize;
+        self.skip_bytes(n * INSTRUCTION_SIZE as usize).await?;
+
+        let n = self.read_i32_varint().await?;
+        for _ in 0..n {
+            match self.read_byte().await? {
+                LUA_V_NIL | LUA_V_FALSE | LUA_V_TRUE => {}
+                LUA_V_NUM_FLT => self.skip_bytes(LUA_NUMBER_SIZE as usize).await?,
+                LUA_V_NUM_INT => self.skip_bytes(LUA_INTEGER_SIZE as usize).await?,
+                LUA_V_SHR_STR | LUA_V_LNG_STR => self.skip_string().await?,
+                tag => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed tag: {}", tag),
+                    ))
+                }
+            }
+        }
+
+        let n = self.read_i32_varint().await? as usize;
+        self.skip_bytes(n * 3).await?; // upvalues
+
+        let n = self.read_i32_varint().await?;
+        for _ in 0..n {
+            self.skip_proto().await?;
+        }
+
+        let n = self.read_i32_varint().await? as usize;
+        self.skip_bytes(n).await?; // lineinfo
+
+        let n = self.read_i32_varint().await?;
+        for _ in 0..n {
+            self.read_i32_varint().await?; // pc
+            self.read_i32_varint().await?; // line
+        }
+
+        let n = self.read_i32_varint().await?;
+        for _ in 0..n {
+            self.skip_string().await?; // varname
+            self.read_i32_varint().await?; // startpc
+            self.read_i32_varint().await?; // endpc
+        }
+
+        let n = self.read_i32_varint().await?;
+        for _ in 0..n {
+            self.skip_string().await?; // upvalue name
+        }
+
+        Ok(())
+    }
+}
+
+impl Reader<Cursor<Bytes>> {
+    /// Creates a reader over an in-memory chunk whose nested protos are not
+    /// decoded until they are first loaded.
+    pub fn lazy(chunk: Bytes) -> Self {
+        let mut r = Self::new(Cursor::new(chunk.clone()));
+        r.chunk = Some(chunk);
+        r
     }
 }
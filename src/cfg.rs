@@ -0,0 +1,314 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Range;
+
+use crate::{instruction::Instruction, opcode::*, proto::Proto};
+
+/// A maximal run of instructions entered only at its first instruction and
+/// left only after its last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The pcs of the instructions in the block.
+    pub pcs: Range<usize>,
+    pub succs: Vec<usize>,
+    pub preds: Vec<usize>,
+}
+
+/// A natural loop: the blocks which can reach a back edge into `header`
+/// without passing through `header`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    pub header: usize,
+    /// Sources of the back edges into `header`.
+    pub latches: Vec<usize>,
+    /// All blocks of the loop, `header` included, in ascending order.
+    pub body: Vec<usize>,
+}
+
+/// The control-flow graph of a proto's code. Block 0 is the entry.
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    /// Maps each pc to the block containing it.
+    block_of: Vec<usize>,
+    /// Immediate dominators; the entry and unreachable blocks have none.
+    idoms: Vec<Option<usize>>,
+    /// Reachable blocks in reverse postorder.
+    rpo: Vec<usize>,
+    loops: Vec<Loop>,
+}
+
+/// Returns the pcs control may continue to after the instruction at `pc`.
+pub fn successors(code: &[Instruction], pc: usize) -> Vec<usize> {
+    let i = code[pc];
+    let (_, bx) = i.a_bx();
+    let next = pc as isize + 1;
+    let targets = match i.opcode() {
+        OP_RETURN | OP_RETURN0 | OP_RETURN1 => vec![],
+        OP_JMP => vec![next + i.sj()],
+        OP_LFALSESKIP => vec![next + 1],
+        OP_FORLOOP | OP_TFORLOOP => vec![next, next - bx],
+        OP_FORPREP => vec![next, next + bx + 1],
+        OP_TFORPREP => vec![next + bx],
+        _ if i.t_mode() => vec![next, next + 1],
+        _ => vec![next],
+    };
+    targets
+        .into_iter()
+        .filter(|pc| (0..code.len() as isize).contains(pc))
+        .map(|pc| pc as usize)
+        .collect()
+}
+
+/// Whether the instruction at `pc` transfers control anywhere but the next
+/// instruction.
+fn ends_block(code: &[Instruction], pc: usize) -> bool {
+    successors(code, pc) != [pc + 1]
+}
+
+impl Cfg {
+    pub fn new(proto: &Proto) -> Self {
+        let code = &proto.code;
+        let n = code.len();
+
+        let mut leader = vec![false; n + 1];
+        leader[0] = true;
+        leader[n] = true;
+        for pc in 0..n {
+            if ends_block(code, pc) {
+                leader[pc + 1] = true;
+                for s in successors(code, pc) {
+                    leader[s] = true;
+                }
+            }
+        }
+
+        let mut blocks = vec![];
+        let mut block_of = vec![0; n];
+        let mut start = 0;
+        for pc in 1..=n {
+            if leader[pc] {
+                block_of[start..pc].fill(blocks.len());
+                blocks.push(BasicBlock {
+                    pcs: start..pc,
+                    succs: vec![],
+                    preds: vec![],
+                });
+                start = pc;
+            }
+        }
+
+        for b in 0..blocks.len() {
+            let last = blocks[b].pcs.end - 1;
+            let mut succs: Vec<usize> = successors(code, last)
+                .into_iter()
+                .map(|pc| block_of[pc])
+                .collect();
+            succs.dedup();
+            for &s in &succs {
+                blocks[s].preds.push(b);
+            }
+            blocks[b].succs = succs;
+        }
+
+        let mut cfg = Self {
+            blocks,
+            block_of,
+            idoms: vec![],
+            rpo: vec![],
+            loops: vec![],
+        };
+        cfg.compute_rpo();
+        cfg.compute_dominators();
+        cfg.compute_loops();
+        cfg
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Returns the block containing the instruction at `pc`.
+    pub fn block_of(&self, pc: usize) -> usize {
+        self.block_of[pc]
+    }
+
+    /// Reachable blocks in reverse postorder.
+    pub fn reverse_postorder(&self) -> &[usize] {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, b: usize) -> bool {
+        b == 0 || self.idoms[b].is_some()
+    }
+
+    /// Returns the immediate dominator of `b`, which is `None` for the entry
+    /// and for unreachable blocks.
+    pub fn idom(&self, b: usize) -> Option<usize> {
+        self.idoms[b]
+    }
+
+    /// Returns the blocks immediately dominated by `b`, i.e. its children in
+    /// the dominator tree.
+    pub fn dominated_by(&self, b: usize) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|&c| self.idoms[c] == Some(b))
+            .collect()
+    }
+
+    /// Whether every path from the entry to `b` passes through `a`.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut b = b;
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idoms[b] {
+                Some(d) => b = d,
+                None => return false,
+            }
+        }
+    }
+
+    /// Natural loops, ordered by header.
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    fn compute_rpo(&mut self) {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = vec![];
+        // iterative DFS keeping the index of the next successor to visit
+        let mut stack = vec![];
+        if !self.blocks.is_empty() {
+            visited[0] = true;
+            stack.push((0, 0));
+        }
+        while let Some((b, i)) = stack.pop() {
+            match self.blocks[b].succs.get(i) {
+                Some(&s) => {
+                    stack.push((b, i + 1));
+                    if !visited[s] {
+                        visited[s] = true;
+                        stack.push((s, 0));
+                    }
+                }
+                None => postorder.push(b),
+            }
+        }
+        postorder.reverse();
+        self.rpo = postorder;
+    }
+
+    /// Computes immediate dominators following Cooper, Harvey and Kennedy's
+    /// "A Simple, Fast Dominance Algorithm".
+    fn compute_dominators(&mut self) {
+        let n = self.blocks.len();
+        let mut order = vec![usize::MAX; n];
+        for (i, &b) in self.rpo.iter().enumerate() {
+            order[b] = i;
+        }
+        let mut idoms: Vec<Option<usize>> = vec![None; n];
+        if n == 0 {
+            self.idoms = idoms;
+            return;
+        }
+        idoms[0] = Some(0);
+        let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while order[a] > order[b] {
+                    a = idoms[a].unwrap();
+                }
+                while order[b] > order[a] {
+                    b = idoms[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in self.rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &p in &self.blocks[b].preds {
+                    if idoms[p].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(d) => intersect(&idoms, p, d),
+                    });
+                }
+                if new_idom.is_some() && idoms[b] != new_idom {
+                    idoms[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idoms[0] = None;
+        self.idoms = idoms;
+    }
+
+    fn compute_loops(&mut self) {
+        let mut loops: Vec<Loop> = vec![];
+        for b in 0..self.blocks.len() {
+            for &h in &self.blocks[b].succs {
+                if !self.dominates(h, b) {
+                    continue;
+                }
+                let body = self.natural_loop(h, b);
+                match loops.iter_mut().find(|l| l.header == h) {
+                    Some(l) => {
+                        l.latches.push(b);
+                        l.body.extend(body);
+                        l.body.sort_unstable();
+                        l.body.dedup();
+                    }
+                    None => loops.push(Loop {
+                        header: h,
+                        latches: vec![b],
+                        body,
+                    }),
+                }
+            }
+        }
+        loops.sort_by_key(|l| l.header);
+        self.loops = loops;
+    }
+
+    /// Collects the blocks of the loop formed by the back edge `latch` ->
+    /// `header`.
+    fn natural_loop(&self, header: usize, latch: usize) -> Vec<usize> {
+        let mut in_loop = vec![false; self.blocks.len()];
+        in_loop[header] = true;
+        let mut stack = vec![];
+        if !in_loop[latch] {
+            in_loop[latch] = true;
+            stack.push(latch);
+        }
+        while let Some(b) = stack.pop() {
+            for &p in &self.blocks[b].preds {
+                if !in_loop[p] && self.is_reachable(p) {
+                    in_loop[p] = true;
+                    stack.push(p);
+                }
+            }
+        }
+        (0..self.blocks.len()).filter(|&b| in_loop[b]).collect()
+    }
+}
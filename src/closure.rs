@@ -12,10 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::proto::{Proto, Upvalue};
+use crate::{proto::Proto, value::Value};
 
 #[derive(Debug)]
 pub struct Closure {
     pub(crate) proto: Proto,
-    pub(crate) upvalues: Vec<Option<Upvalue>>,
+    pub(crate) upvalues: Vec<Value>,
+}
+
+impl Closure {
+    pub fn proto(&self) -> &Proto {
+        &self.proto
+    }
 }
@@ -23,6 +23,12 @@ pub const LUA_NUMBER_SIZE: u8 = 8;
 pub const LUAC_INT: i64 = 0x5678;
 pub const LUAC_NUM: f64 = 370.5;
 
+/// Marks an entry of `lineinfo` whose line is given by `abslineinfo` instead.
+pub const ABSLINEINFO: i8 = -0x80;
+
+/// Maximum length of short strings, which are interned.
+pub const LUAI_MAXSHORTLEN: usize = 40;
+
 pub const fn make_varint(t: u8, v: u8) -> u8 {
     t | (v << 4)
 }
@@ -41,3 +47,47 @@ pub const LUA_V_NUM_INT: u8 = make_varint(LUA_T_NUMBER, 0);
 pub const LUA_V_NUM_FLT: u8 = make_varint(LUA_T_NUMBER, 1);
 pub const LUA_V_SHR_STR: u8 = make_varint(LUA_T_STRING, 0);
 pub const LUA_V_LNG_STR: u8 = make_varint(LUA_T_STRING, 1);
+
+/// Metamethod events
+pub const TM_INDEX: usize = 0;
+pub const TM_NEWINDEX: usize = 1;
+pub const TM_LEN: usize = 4;
+pub const TM_EQ: usize = 5;
+pub const TM_ADD: usize = 6;
+pub const TM_SUB: usize = 7;
+pub const TM_SHL: usize = 16;
+pub const TM_SHR: usize = 17;
+pub const TM_UNM: usize = 18;
+pub const TM_BNOT: usize = 19;
+pub const TM_LT: usize = 20;
+pub const TM_LE: usize = 21;
+pub const TM_CONCAT: usize = 22;
+pub const TM_CLOSE: usize = 24;
+
+pub const TM_NAMES: &[&str] = &[
+    "__index",
+    "__newindex",
+    "__gc",
+    "__mode",
+    "__len",
+    "__eq",
+    "__add",
+    "__sub",
+    "__mul",
+    "__mod",
+    "__pow",
+    "__div",
+    "__idiv",
+    "__band",
+    "__bor",
+    "__bxor",
+    "__shl",
+    "__shr",
+    "__unm",
+    "__bnot",
+    "__lt",
+    "__le",
+    "__concat",
+    "__call",
+    "__close",
+];
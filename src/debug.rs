@@ -0,0 +1,240 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Infers what a register holds by walking back over the code that set it,
+//! so runtime errors can say "attempt to call a nil value (global 'foo')" as
+//! reference Lua does.
+
+use std::fmt::{Display, Formatter};
+
+use crate::{
+    constants::*,
+    opcode::*,
+    proto::{Constant, Proto},
+    string::LuaString,
+};
+
+const LUA_ENV: &[u8] = b"_ENV";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameKind {
+    Global,
+    Local,
+    Field,
+    Method,
+    Upvalue,
+    Constant,
+    ForIterator,
+    Metamethod,
+}
+
+impl NameKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NameKind::Global => "global",
+            NameKind::Local => "local",
+            NameKind::Field => "field",
+            NameKind::Method => "method",
+            NameKind::Upvalue => "upvalue",
+            NameKind::Constant => "constant",
+            NameKind::ForIterator => "for iterator",
+            NameKind::Metamethod => "metamethod",
+        }
+    }
+}
+
+/// What a value is known as in the source, e.g. `global 'print'`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectName {
+    pub kind: NameKind,
+    pub name: LuaString,
+}
+
+impl ObjectName {
+    fn new(kind: NameKind, name: impl Into<LuaString>) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+        }
+    }
+}
+
+impl Display for ObjectName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} '{}'", self.kind.as_str(), self.name)
+    }
+}
+
+impl Proto {
+    /// Names the value in register `reg` just before the instruction at
+    /// `lastpc` executes, if it can be told from the code.
+    pub fn object_name(&self, lastpc: usize, reg: usize) -> Option<ObjectName> {
+        if let Some(name) = self.local_name(reg, lastpc) {
+            return Some(ObjectName::new(NameKind::Local, name.clone()));
+        }
+        let pc = self.find_set_reg(lastpc, reg)?;
+        let i = self.code[pc];
+        let (a, _, b, c) = i.abc();
+        match i.opcode() {
+            OP_MOVE if b < a => self.object_name(pc, b as usize),
+            OP_GETTABUP => {
+                let name = self.constant_name(c);
+                Some(ObjectName::new(self.indexed_kind(pc, b, true), name))
+            }
+            OP_GETTABLE => {
+                let name = self.register_name(pc, c);
+                Some(ObjectName::new(self.indexed_kind(pc, b, false), name))
+            }
+            OP_GETI => Some(ObjectName::new(NameKind::Field, "integer index")),
+            OP_GETFIELD => {
+                let name = self.constant_name(c);
+                Some(ObjectName::new(self.indexed_kind(pc, b, false), name))
+            }
+            OP_GETUPVAL => Some(ObjectName::new(NameKind::Upvalue, self.upvalue_name(b))),
+            op @ (OP_LOADK | OP_LOADKX) => {
+                let k = match op {
+                    OP_LOADK => i.a_bx().1,
+                    _ => self.code.get(pc + 1)?.ax(),
+                };
+                match self.constants.get(k as usize) {
+                    Some(Constant::String(s)) => {
+                        Some(ObjectName::new(NameKind::Constant, s.clone()))
+                    }
+                    _ => None,
+                }
+            }
+            OP_SELF => {
+                let name = match i.abc().1 {
+                    0 => self.register_name(pc, c),
+                    _ => self.constant_name(c),
+                };
+                Some(ObjectName::new(NameKind::Method, name))
+            }
+            _ => None,
+        }
+    }
+
+    /// Names the function called by the instruction at `pc`, which may be a
+    /// metamethod invoked implicitly.
+    pub fn call_name(&self, pc: usize) -> Option<ObjectName> {
+        let i = *self.code.get(pc)?;
+        let tm = match i.opcode() {
+            OP_CALL | OP_TAILCALL => return self.object_name(pc, i.abc().0 as usize),
+            OP_TFORCALL => return Some(ObjectName::new(NameKind::ForIterator, "for iterator")),
+            OP_SELF | OP_GETTABUP | OP_GETTABLE | OP_GETI | OP_GETFIELD => TM_INDEX,
+            OP_SETTABUP | OP_SETTABLE | OP_SETI | OP_SETFIELD => TM_NEWINDEX,
+            OP_MMBIN | OP_MMBINI | OP_MMBINK => i.abc().3 as usize,
+            OP_UNM => TM_UNM,
+            OP_BNOT => TM_BNOT,
+            OP_LEN => TM_LEN,
+            OP_CONCAT => TM_CONCAT,
+            OP_EQ => TM_EQ,
+            OP_LT | OP_LTI | OP_GTI => TM_LT,
+            OP_LE | OP_LEI | OP_GEI => TM_LE,
+            OP_CLOSE | OP_RETURN => TM_CLOSE,
+            _ => return None,
+        };
+        let name = TM_NAMES.get(tm)?;
+        Some(ObjectName::new(NameKind::Metamethod, &name[2..]))
+    }
+
+    /// Returns the suffix reference Lua appends to type errors about the value
+    /// in register `reg` at `pc`, e.g. ` (global 'foo')`, or an empty string.
+    pub fn varinfo(&self, pc: usize, reg: usize) -> String {
+        match self.object_name(pc, reg) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        }
+    }
+
+    /// Finds the last instruction before `lastpc` that modified `reg`, unless
+    /// it was executed only conditionally.
+    fn find_set_reg(&self, lastpc: usize, reg: usize) -> Option<usize> {
+        let mut lastpc = lastpc;
+        if self.code.get(lastpc)?.mm_mode() {
+            // the metamethod call follows an instruction which did not
+            // actually complete
+            lastpc = lastpc.checked_sub(1)?;
+        }
+        let reg = reg as isize;
+        let mut setreg = None;
+        // any code before this address is conditional
+        let mut jmptarget = 0;
+        for (pc, i) in self.code[..lastpc].iter().enumerate() {
+            let (a, _, b, _) = i.abc();
+            let change = match i.opcode() {
+                OP_LOADNIL => a <= reg && reg <= a + b,
+                OP_TFORCALL => reg >= a + 2,
+                OP_CALL | OP_TAILCALL => reg >= a,
+                OP_JMP => {
+                    let dest = pc as isize + 1 + i.sj();
+                    if dest <= lastpc as isize && dest > jmptarget {
+                        jmptarget = dest;
+                    }
+                    false
+                }
+                _ => i.a_mode() && reg == a,
+            };
+            if change {
+                setreg = if (pc as isize) < jmptarget {
+                    None
+                } else {
+                    Some(pc)
+                };
+            }
+        }
+        setreg
+    }
+
+    /// Tells whether indexing the table in `t` reads a global or a field.
+    fn indexed_kind(&self, pc: usize, t: isize, is_upvalue: bool) -> NameKind {
+        let is_env = if is_upvalue {
+            self.upvalue_name(t).as_bytes() == LUA_ENV
+        } else {
+            self.object_name(pc, t as usize)
+                .is_some_and(|n| n.name.as_bytes() == LUA_ENV)
+        };
+        if is_env {
+            NameKind::Global
+        } else {
+            NameKind::Field
+        }
+    }
+
+    fn upvalue_name(&self, idx: isize) -> LuaString {
+        self.upvalues
+            .get(idx as usize)
+            .and_then(|u| u.name.clone())
+            .unwrap_or_else(|| "?".into())
+    }
+
+    fn constant_name(&self, idx: isize) -> LuaString {
+        match self.constants.get(idx as usize) {
+            Some(Constant::String(s)) => s.clone(),
+            _ => "?".into(),
+        }
+    }
+
+    /// Names a key held in a register, which is only known if it was loaded
+    /// from a constant.
+    fn register_name(&self, pc: usize, reg: isize) -> LuaString {
+        match self.object_name(pc, reg as usize) {
+            Some(ObjectName {
+                kind: NameKind::Constant,
+                name,
+            }) => name,
+            _ => "?".into(),
+        }
+    }
+}
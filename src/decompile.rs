@@ -0,0 +1,1712 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reconstructs Lua source from a proto.
+//!
+//! Values computed into temporary registers are kept as pending expressions
+//! until an instruction consumes them, so that they fold back into the
+//! expressions they came from. Locals are named and scoped after `locvars`,
+//! loops are found from the back edges of the control-flow graph, and
+//! conditionals from the test-and-jump pairs the Lua compiler emits. Jumps
+//! which fit none of these shapes become `goto`s.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter, Result},
+};
+
+use crate::{
+    cfg::Cfg,
+    constants::{TM_SHL, TM_SUB},
+    instruction::{Instruction, MAXARG_C, OFFSET_SC},
+    listing::{write_quoted, InstructionDisplay},
+    opcode::*,
+    proto::{Constant, Proto},
+    string::LuaString,
+};
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Lua source reconstructed from a proto and the nested protos which have
+/// been loaded.
+pub struct Decompiled {
+    body: Vec<Stmt>,
+}
+
+impl Decompiled {
+    pub fn new(proto: &Proto) -> Self {
+        Self {
+            body: decompile(proto).body.unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for Decompiled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_block(f, &self.body, 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(LuaString),
+    Vararg,
+    /// A named local and its register.
+    Local(usize, LuaString),
+    /// A register without a local name.
+    Temp(usize),
+    Upvalue(LuaString),
+    Global(LuaString),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    MethodCall(Box<Expr>, Box<Expr>, Vec<Expr>),
+    /// The method `OP_SELF` looked up, waiting for its call.
+    SelfMethod(Box<Expr>, Box<Expr>),
+    /// The receiver `OP_SELF` passes as the first argument.
+    SelfArg,
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    /// Truncates a call or vararg to one value.
+    Paren(Box<Expr>),
+    Function(Box<Function>),
+    Table(Vec<Field>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Function {
+    params: Vec<Expr>,
+    is_vararg: bool,
+    /// `None` if the proto has not been loaded.
+    body: Option<Vec<Stmt>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stmt {
+    Local {
+        names: Vec<Expr>,
+        values: Vec<Expr>,
+        close: bool,
+    },
+    LocalFunction(Expr, Box<Function>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Return(Vec<Expr>),
+    Break,
+    Goto(usize),
+    Label(usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Repeat(Vec<Stmt>, Expr),
+    NumericFor(Expr, Expr, Expr, Option<Expr>, Vec<Stmt>),
+    GenericFor(Vec<Expr>, Vec<Expr>, Vec<Stmt>),
+    /// An instruction which could not be decompiled, as listed.
+    Unknown(String),
+}
+
+/// What a register holds while the statement using it is being rebuilt.
+#[derive(Clone, Debug, PartialEq)]
+enum Slot {
+    Empty,
+    Value(Expr),
+    /// The first of the results of a call or vararg, all of them if `None`.
+    Results(Expr, Option<usize>),
+    /// One of the registers a preceding `Results` spans.
+    Covered,
+}
+
+impl Expr {
+    fn not(self) -> Expr {
+        match self {
+            Expr::Unary("not", e) => *e,
+            Expr::Binary("==", l, r) => Expr::Binary("~=", l, r),
+            Expr::Binary("~=", l, r) => Expr::Binary("==", l, r),
+            e => Expr::Unary("not", Box::new(e)),
+        }
+    }
+
+    fn binary(op: &'static str, l: Expr, r: Expr) -> Expr {
+        Expr::Binary(op, Box::new(l), Box::new(r))
+    }
+
+    /// Whether `pred` holds for this expression or any subexpression, not
+    /// looking into nested functions.
+    fn any(&self, pred: &impl Fn(&Expr) -> bool) -> bool {
+        if pred(self) {
+            return true;
+        }
+        match self {
+            Expr::Index(a, b) | Expr::SelfMethod(a, b) | Expr::Binary(_, a, b) => {
+                a.any(pred) || b.any(pred)
+            }
+            Expr::Call(f, args) => f.any(pred) || args.iter().any(|e| e.any(pred)),
+            Expr::MethodCall(o, k, args) => {
+                o.any(pred) || k.any(pred) || args.iter().any(|e| e.any(pred))
+            }
+            Expr::Unary(_, e) | Expr::Paren(e) => e.any(pred),
+            Expr::Table(fields) => fields.iter().any(|field| match field {
+                Field::Positional(v) => v.any(pred),
+                Field::Keyed(k, v) => k.any(pred) || v.any(pred),
+            }),
+            _ => false,
+        }
+    }
+
+    /// Whether evaluating the expression may observe or cause side effects,
+    /// so that it cannot be moved past a statement.
+    fn is_impure(&self) -> bool {
+        self.any(&|e| {
+            matches!(
+                e,
+                Expr::Upvalue(_)
+                    | Expr::Global(_)
+                    | Expr::Index(..)
+                    | Expr::Call(..)
+                    | Expr::MethodCall(..)
+                    | Expr::SelfMethod(..)
+            )
+        })
+    }
+
+    fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::MethodCall(..) | Expr::Vararg)
+    }
+}
+
+/// Decompiles `proto`, retrying while it finds jump targets which need labels
+/// before the code it has already gone past.
+fn decompile(proto: &Proto) -> Function {
+    let mut labels = BTreeSet::new();
+    loop {
+        let mut d = Decompiler::new(proto, labels.clone());
+        let body = d.function_body();
+        if d.labels == labels {
+            return Function {
+                params: (0..proto.numparams as usize)
+                    .map(|r| d.name(r, 0))
+                    .collect(),
+                is_vararg: proto.is_vararg != 0,
+                body: Some(body),
+            };
+        }
+        labels = d.labels;
+    }
+}
+
+struct Decompiler<'a> {
+    proto: &'a Proto,
+    code: &'a [Instruction],
+    /// Back edges of `while` and `repeat` loops as (header, latch) pcs.
+    back_edges: Vec<(usize, usize)>,
+    /// The register of each local in `locvars`.
+    registers: Vec<usize>,
+    declared: Vec<bool>,
+    slots: Vec<Slot>,
+    /// Registers referred to by their `rN` names, declared up front.
+    temps: BTreeSet<usize>,
+    /// Pcs which `goto`s jump to.
+    labels: BTreeSet<usize>,
+    placed: BTreeSet<usize>,
+    /// Exits of the enclosing loops, innermost last.
+    exits: Vec<usize>,
+}
+
+/// The state to roll back to when a tentative reading of some code fails.
+struct Snapshot {
+    slots: Vec<Slot>,
+    temps: BTreeSet<usize>,
+    declared: Vec<bool>,
+    labels: BTreeSet<usize>,
+    placed: BTreeSet<usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(proto: &'a Proto, labels: BTreeSet<usize>) -> Self {
+        let code = &proto.code[..];
+        let cfg = Cfg::new(proto);
+        let mut back_edges = vec![];
+        for l in cfg.loops() {
+            let header = cfg.blocks()[l.header].pcs.start;
+            for &latch in &l.latches {
+                let pc = cfg.blocks()[latch].pcs.end - 1;
+                let i = code[pc];
+                if i.opcode() == OP_JMP && pc as isize + 1 + i.sj() == header as isize {
+                    back_edges.push((header, pc));
+                }
+            }
+        }
+        let locvars = &proto.locvars;
+        let registers = (0..locvars.len())
+            .map(|i| {
+                let start = locvars[i].startpc.max(0) as usize;
+                locvars[..i].iter().filter(|v| v.is_active(start)).count()
+            })
+            .collect();
+        let declared = locvars
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v.startpc == 0 && i < proto.numparams as usize)
+            .collect();
+        Self {
+            proto,
+            code,
+            back_edges,
+            registers,
+            declared,
+            slots: vec![Slot::Empty; proto.maxstacksize as usize + 1],
+            temps: BTreeSet::new(),
+            labels,
+            placed: BTreeSet::new(),
+            exits: vec![],
+        }
+    }
+
+    fn function_body(&mut self) -> Vec<Stmt> {
+        let mut body = self.block(0, self.code.len());
+        self.flush(&mut body);
+        let numparams = self.proto.numparams as usize;
+        let temps: Vec<Expr> = self
+            .temps
+            .iter()
+            .filter(|&&r| r >= numparams)
+            .map(|&r| Expr::Temp(r))
+            .collect();
+        if !temps.is_empty() {
+            body.insert(
+                0,
+                Stmt::Local {
+                    names: temps,
+                    values: vec![],
+                    close: false,
+                },
+            );
+        }
+        body
+    }
+
+    /// Decompiles the instructions in `start..end`, leaving values which no
+    /// instruction consumed pending.
+    fn block(&mut self, start: usize, end: usize) -> Vec<Stmt> {
+        let mut out = vec![];
+        let mut pc = start;
+        while pc < end {
+            if self.labels.contains(&pc) && self.placed.insert(pc) {
+                self.flush(&mut out);
+                out.push(Stmt::Label(pc));
+            }
+            self.declare_locals(pc, &mut out);
+            pc = self.step(pc, end, &mut out);
+        }
+        out
+    }
+
+    /// Decompiles the statement starting at `pc`, returning the pc after it.
+    fn step(&mut self, pc: usize, end: usize, out: &mut Vec<Stmt>) -> usize {
+        if let Some(latch) = self
+            .back_edges
+            .iter()
+            .filter(|&&(h, l)| h == pc && l < end)
+            .map(|&(_, l)| l)
+            .max()
+        {
+            return self.loop_statement(pc, latch, out);
+        }
+
+        let i = self.code[pc];
+        let (a, k, b, c) = i.abc();
+        let (a, k, b, c) = (a as usize, k != 0, b as usize, c as usize);
+        let (_, bx) = i.a_bx();
+        let (_, sbx) = i.a_sbx();
+        match i.opcode() {
+            OP_MOVE => {
+                let v = self.reg(b, pc, out);
+                self.write(a, v, pc, out);
+            }
+            OP_LOADI => self.write(a, Expr::Integer(sbx as i64), pc, out),
+            OP_LOADF => self.write(a, Expr::Float(sbx as f64), pc, out),
+            OP_LOADK => {
+                let v = self.constant(bx as usize);
+                self.write(a, v, pc, out);
+            }
+            OP_LOADKX => {
+                let v = self.constant(self.extraarg(pc));
+                self.write(a, v, pc, out);
+                return pc + 2;
+            }
+            OP_LOADFALSE => self.write(a, Expr::Boolean(false), pc, out),
+            OP_LOADTRUE => self.write(a, Expr::Boolean(true), pc, out),
+            OP_LFALSESKIP => {
+                self.write(a, Expr::Boolean(false), pc, out);
+                self.goto(pc + 2, out);
+            }
+            OP_LOADNIL => {
+                for r in a..=a + b {
+                    self.write(r, Expr::Nil, pc, out);
+                }
+            }
+            OP_GETUPVAL => {
+                let v = self.upvalue(b);
+                self.write(a, v, pc, out);
+            }
+            OP_SETUPVAL => {
+                let v = self.reg(a, pc, out);
+                self.settle(out);
+                out.push(Stmt::Assign(vec![self.upvalue(b)], vec![v]));
+            }
+            OP_GETTABUP => {
+                let v = self.tabup(b, c);
+                self.write(a, v, pc, out);
+            }
+            OP_GETTABLE => {
+                let t = self.reg(b, pc, out);
+                let key = self.reg(c, pc, out);
+                self.write(a, Expr::Index(Box::new(t), Box::new(key)), pc, out);
+            }
+            OP_GETI => {
+                let t = self.reg(b, pc, out);
+                let key = Expr::Integer(c as i64);
+                self.write(a, Expr::Index(Box::new(t), Box::new(key)), pc, out);
+            }
+            OP_GETFIELD => {
+                let t = self.reg(b, pc, out);
+                let key = self.constant(c);
+                self.write(a, Expr::Index(Box::new(t), Box::new(key)), pc, out);
+            }
+            OP_SETTABUP => {
+                let v = self.rk(c, k, pc, out);
+                let target = self.tabup(a, b);
+                self.settle(out);
+                out.push(Stmt::Assign(vec![target], vec![v]));
+            }
+            OP_SETTABLE => {
+                let key = self.reg(b, pc, out);
+                self.store(a, key, c, k, pc, out);
+            }
+            OP_SETI => self.store(a, Expr::Integer(b as i64), c, k, pc, out),
+            OP_SETFIELD => {
+                let key = self.constant(b);
+                self.store(a, key, c, k, pc, out);
+            }
+            OP_NEWTABLE => {
+                self.write(a, Expr::Table(vec![]), pc, out);
+                return pc + 2;
+            }
+            OP_SELF => {
+                let obj = self.reg(b, pc, out);
+                let key = self.rk(c, k, pc, out);
+                self.slots[a + 1] = Slot::Value(Expr::SelfArg);
+                self.slots[a] = Slot::Value(Expr::SelfMethod(Box::new(obj), Box::new(key)));
+            }
+            OP_ADDI | OP_SHRI => {
+                let l = self.reg(b, pc, out);
+                let mut op = binary_op(i.opcode());
+                let mut imm = c as i64 - OFFSET_SC as i64;
+                let mut flip = false;
+                if let Some(mm) = self.code.get(pc + 1).filter(|i| i.opcode() == OP_MMBINI) {
+                    let (_, mk, _, event) = mm.abc();
+                    flip = mk != 0;
+                    // `x - 1` and `x << 1` are coded with the immediate negated
+                    match event as usize {
+                        TM_SUB => (op, imm) = ("-", -imm),
+                        TM_SHL => (op, imm) = ("<<", -imm),
+                        _ => {}
+                    }
+                }
+                let v = if flip {
+                    Expr::binary(op, Expr::Integer(imm), l)
+                } else {
+                    Expr::binary(op, l, Expr::Integer(imm))
+                };
+                self.write(a, v, pc, out);
+            }
+            OP_SHLI => {
+                let r = self.reg(b, pc, out);
+                let imm = Expr::Integer(c as i64 - OFFSET_SC as i64);
+                self.write(a, Expr::binary("<<", imm, r), pc, out);
+            }
+            op @ OP_ADDK..=OP_BXORK => {
+                let l = self.reg(b, pc, out);
+                let r = self.constant(c);
+                let flip = self
+                    .code
+                    .get(pc + 1)
+                    .is_some_and(|i| i.opcode() == OP_MMBINK && i.abc().1 != 0);
+                let v = if flip {
+                    Expr::binary(binary_op(op), r, l)
+                } else {
+                    Expr::binary(binary_op(op), l, r)
+                };
+                self.write(a, v, pc, out);
+            }
+            op @ OP_ADD..=OP_SHR => {
+                let l = self.reg(b, pc, out);
+                let r = self.reg(c, pc, out);
+                self.write(a, Expr::binary(binary_op(op), l, r), pc, out);
+            }
+            OP_MMBIN | OP_MMBINI | OP_MMBINK => {}
+            op @ (OP_UNM | OP_BNOT | OP_NOT | OP_LEN) => {
+                let v = self.reg(b, pc, out);
+                let op = match op {
+                    OP_UNM => "-",
+                    OP_BNOT => "~",
+                    OP_NOT => "not",
+                    _ => "#",
+                };
+                self.write(a, Expr::Unary(op, Box::new(v)), pc, out);
+            }
+            OP_CONCAT => {
+                let values: Vec<Expr> = (a..a + b).map(|r| self.reg(r, pc, out)).collect();
+                let v = values
+                    .into_iter()
+                    .rev()
+                    .reduce(|r, l| Expr::binary("..", l, r));
+                if let Some(v) = v {
+                    self.write(a, v, pc, out);
+                }
+            }
+            OP_CLOSE => {}
+            OP_TBC => {
+                if let Some(Stmt::Local { close, .. }) = out.last_mut() {
+                    *close = true;
+                }
+            }
+            OP_JMP => {
+                let target = (pc as isize + 1 + i.sj()) as usize;
+                if target != pc + 1 {
+                    self.flush(out);
+                    if self.exits.last() == Some(&target) {
+                        out.push(Stmt::Break);
+                    } else {
+                        self.goto(target, out);
+                    }
+                }
+            }
+            OP_EQ | OP_LT | OP_LE | OP_EQK | OP_EQI | OP_LTI | OP_LEI | OP_GTI | OP_GEI
+            | OP_TEST | OP_TESTSET
+                if self.code.get(pc + 1).map(|i| i.opcode()) == Some(OP_JMP) =>
+            {
+                return self.conditional(pc, end, out);
+            }
+            OP_CALL => {
+                let call = self.call(a, b, pc, out);
+                match c {
+                    0 => self.slots[a] = Slot::Results(call, None),
+                    1 => {
+                        self.settle(out);
+                        out.push(Stmt::Call(call));
+                    }
+                    2 => self.write(a, call, pc, out),
+                    c => self.set_results(a, call, c - 1),
+                }
+            }
+            OP_TAILCALL => {
+                let call = self.call(a, b, pc, out);
+                self.flush(out);
+                out.push(Stmt::Return(vec![call]));
+                if self.code.get(pc + 1).map(|i| i.opcode()) == Some(OP_RETURN) {
+                    return pc + 2;
+                }
+            }
+            OP_RETURN => {
+                let count = if b == 0 { None } else { Some(b - 1) };
+                let values = self.list(a, count, true, pc, out);
+                self.ret(values, pc, out);
+            }
+            OP_RETURN0 => self.ret(vec![], pc, out),
+            OP_RETURN1 => {
+                let values = self.list(a, Some(1), true, pc, out);
+                self.ret(values, pc, out);
+            }
+            OP_FORPREP if pc + bx as usize + 1 < end => {
+                return self.numeric_for(pc, a, pc + bx as usize + 1, out);
+            }
+            OP_TFORPREP if pc + bx as usize + 2 < end => {
+                return self.generic_for(pc, a, pc + bx as usize + 1, out);
+            }
+            OP_SETLIST => {
+                let count = if b == 0 { None } else { Some(b) };
+                let items = self.list(a + 1, count, true, pc, out);
+                let mut base = c;
+                if k {
+                    base += self.extraarg(pc) * (MAXARG_C as usize + 1);
+                }
+                match &mut self.slots[a] {
+                    Slot::Value(Expr::Table(fields)) => {
+                        fields.extend(items.into_iter().map(Field::Positional));
+                    }
+                    _ => {
+                        let t = self.reg(a, pc, out);
+                        self.settle(out);
+                        for (n, v) in items.into_iter().enumerate() {
+                            let key = Expr::Integer((base + n + 1) as i64);
+                            let target = Expr::Index(Box::new(t.clone()), Box::new(key));
+                            out.push(Stmt::Assign(vec![target], vec![v]));
+                        }
+                    }
+                }
+                if k {
+                    return pc + 2;
+                }
+            }
+            OP_CLOSURE => {
+                let f = match self.proto.protos.get(bx as usize).and_then(|p| p.get()) {
+                    Some(nested) => decompile(nested),
+                    None => Function {
+                        params: vec![],
+                        is_vararg: true,
+                        body: None,
+                    },
+                };
+                match self.claim_local(a, pc) {
+                    Some(name) => {
+                        self.flush(out);
+                        out.push(Stmt::LocalFunction(name, Box::new(f)));
+                    }
+                    None => self.write(a, Expr::Function(Box::new(f)), pc, out),
+                }
+            }
+            OP_VARARG => match c {
+                0 => self.slots[a] = Slot::Results(Expr::Vararg, None),
+                2 => self.write(a, Expr::Vararg, pc, out),
+                c => self.set_results(a, Expr::Vararg, c - 1),
+            },
+            OP_VARARGPREP | OP_EXTRAARG => {}
+            _ => {
+                self.flush(out);
+                out.push(Stmt::Unknown(
+                    InstructionDisplay::new(self.proto, pc).to_string(),
+                ));
+            }
+        }
+        pc + 1
+    }
+
+    /// Rebuilds a `while` or `repeat` loop whose back edge jumps from `latch`
+    /// to `header`.
+    fn loop_statement(&mut self, header: usize, latch: usize, out: &mut Vec<Stmt>) -> usize {
+        self.flush(out);
+        let exit = latch + 1;
+        self.exits.push(exit);
+        let until = latch
+            .checked_sub(1)
+            .filter(|&pc| pc >= header && is_test(self.code[pc].opcode()))
+            .filter(|&pc| self.code[pc].opcode() != OP_TESTSET);
+        let stmt = match until {
+            Some(test) => {
+                let mut body = self.block(header, test);
+                let (cond, k) = self.condition(test, &mut body);
+                self.flush(&mut body);
+                // the loop is left when the jump back is not taken
+                Stmt::Repeat(body, if k { cond.not() } else { cond })
+            }
+            None => {
+                let mut body = self.block(header, latch);
+                self.flush(&mut body);
+                let mut cond: Option<Expr> = None;
+                while let Some(Stmt::If(c, then, els)) = body.first() {
+                    if then != &[Stmt::Break] || !els.is_empty() {
+                        break;
+                    }
+                    let c = c.clone().not();
+                    cond = Some(match cond {
+                        Some(prev) => Expr::binary("and", prev, c),
+                        None => c,
+                    });
+                    body.remove(0);
+                }
+                Stmt::While(cond.unwrap_or(Expr::Boolean(true)), body)
+            }
+        };
+        self.exits.pop();
+        out.push(stmt);
+        exit
+    }
+
+    fn numeric_for(&mut self, pc: usize, a: usize, forloop: usize, out: &mut Vec<Stmt>) -> usize {
+        let init = self.reg(a, pc, out);
+        let limit = self.reg(a + 1, pc, out);
+        let step = self.reg(a + 2, pc, out);
+        self.flush(out);
+        let var = self.claim_local(a + 3, pc + 1).unwrap_or(Expr::Temp(a + 3));
+        self.exits.push(forloop + 1);
+        let mut body = self.block(pc + 1, forloop);
+        self.flush(&mut body);
+        self.exits.pop();
+        let step = Some(step).filter(|s| s != &Expr::Integer(1));
+        out.push(Stmt::NumericFor(var, init, limit, step, body));
+        forloop + 1
+    }
+
+    fn generic_for(&mut self, pc: usize, a: usize, call: usize, out: &mut Vec<Stmt>) -> usize {
+        let mut values = self.list(a, Some(4), false, pc, out);
+        while values.len() > 1 && values.last() == Some(&Expr::Nil) {
+            values.pop();
+        }
+        self.flush(out);
+        let count = self.code[call].abc().3 as usize;
+        let vars = (a + 4..a + 4 + count)
+            .map(|r| self.claim_local(r, pc + 1).unwrap_or(Expr::Temp(r)))
+            .collect();
+        self.exits.push(call + 2);
+        let mut body = self.block(pc + 1, call);
+        self.flush(&mut body);
+        self.exits.pop();
+        out.push(Stmt::GenericFor(vars, values, body));
+        call + 2
+    }
+
+    /// Rebuilds the statement or value starting with the test at `pc`, which
+    /// is followed by a jump.
+    fn conditional(&mut self, pc: usize, end: usize, out: &mut Vec<Stmt>) -> usize {
+        let op = self.code[pc].opcode();
+        let target = self.jump_target(pc);
+
+        // a comparison as a value, e.g. `local b = x < y`
+        if op != OP_TEST && op != OP_TESTSET && target == pc + 3 {
+            let a = self.code[pc + 2].abc().0;
+            let is_boolean = self.code[pc + 2].opcode() == OP_LFALSESKIP
+                && self.code.get(pc + 3).map(|i| (i.opcode(), i.abc().0)) == Some((OP_LOADTRUE, a))
+                && !self.labels.contains(&(pc + 2))
+                && !self.labels.contains(&(pc + 3));
+            if is_boolean {
+                let (cond, k) = self.condition(pc, out);
+                let v = if k { cond } else { cond.not() };
+                self.write(a as usize, v, pc + 3, out);
+                return pc + 4;
+            }
+        }
+
+        if op == OP_TEST || op == OP_TESTSET {
+            if let Some(join) = self.and_or(pc, end, out) {
+                return join;
+            }
+        }
+        if op == OP_TESTSET {
+            return self.testset(pc, end, out);
+        }
+
+        let tests = self.test_chain(pc, end);
+        let mut conds = vec![];
+        for (n, &test) in tests.iter().enumerate() {
+            if n > 0 {
+                let mut prelude = tests[n - 1] + 2;
+                while prelude < test {
+                    prelude = self.step(prelude, test, out);
+                }
+            }
+            let (cond, k) = self.condition(test, out);
+            conds.push((cond, k, self.jump_target(test)));
+        }
+        let last = *tests.last().unwrap();
+        let start = last + 2;
+        let (_, _, target) = conds[conds.len() - 1];
+        // the condition under which control falls through to `start`
+        let mut cond = None;
+        for (c, k, t) in conds.into_iter().rev() {
+            cond = Some(match cond {
+                None if k => c.not(),
+                None => c,
+                Some(rest) if t == target => Expr::binary("and", if k { c.not() } else { c }, rest),
+                Some(rest) => Expr::binary("or", if k { c } else { c.not() }, rest),
+            });
+        }
+        let cond = cond.unwrap();
+        self.flush(out);
+
+        if self.exits.last() == Some(&target) {
+            out.push(Stmt::If(cond.not(), vec![Stmt::Break], vec![]));
+            return start;
+        }
+        if target < start || target > end {
+            let mut then = vec![];
+            self.goto(target, &mut then);
+            out.push(Stmt::If(cond.not(), then, vec![]));
+            return start;
+        }
+
+        // a jump over the else part ends the then part
+        let skip = target
+            .checked_sub(1)
+            .filter(|&pc| pc > start && self.code[pc].opcode() == OP_JMP)
+            .map(|pc| (pc, self.jump_target(pc)))
+            .filter(|&(_, t)| t > target && t <= end);
+        let (then_end, next) = match skip {
+            Some((jmp, exit)) => (jmp, exit),
+            None => (target, target),
+        };
+        let mut then = self.block(start, then_end);
+        self.flush(&mut then);
+        let mut els = self.block(target, next);
+        self.flush(&mut els);
+        out.push(Stmt::If(cond, then, els));
+        next
+    }
+
+    /// Collects the tests, starting with the one at `pc`, which are joined by
+    /// `and`/`or` into one condition: all but the last jump either into the
+    /// then part or to where the last jumps.
+    fn test_chain(&self, pc: usize, end: usize) -> Vec<usize> {
+        let mut tests = vec![pc];
+        let mut q = pc + 2;
+        loop {
+            let mut r = q;
+            while r < end && self.is_pure(r, end) {
+                r += 1;
+            }
+            let is_test = r + 1 < end
+                && is_test(self.code[r].opcode())
+                && self.code[r].opcode() != OP_TESTSET
+                && self.code[r + 1].opcode() == OP_JMP;
+            let is_straight = (q..r + 2).all(|pc| {
+                !self.labels.contains(&pc) && !self.back_edges.iter().any(|&(h, _)| h == pc)
+            });
+            if !is_test || !is_straight {
+                break;
+            }
+            tests.push(r);
+            q = r + 2;
+        }
+        // keep the longest prefix forming a single condition
+        while tests.len() > 1 {
+            let last = *tests.last().unwrap();
+            let start = last + 2;
+            let target = self.jump_target(last);
+            let valid = (target >= start && target <= end || self.exits.last() == Some(&target))
+                && tests
+                    .iter()
+                    .all(|&t| [start, target].contains(&self.jump_target(t)));
+            if valid {
+                break;
+            }
+            tests.pop();
+        }
+        tests
+    }
+
+    /// Tries to read the code from the test at `pc` as an `and`/`or`
+    /// expression computed into one register, returning the pc where its
+    /// paths join.
+    fn and_or(&mut self, pc: usize, end: usize, out: &mut Vec<Stmt>) -> Option<usize> {
+        let target = self.jump_target(pc);
+        let mut joins = vec![target];
+        // in `x and y or z`, `x` jumps to `z` and `y` past it
+        if let Some(inner) = target
+            .checked_sub(2)
+            .filter(|&t| t > pc && self.is_test_pair(t))
+        {
+            joins.push(self.jump_target(inner));
+        }
+        for join in joins {
+            if join <= pc + 2 || join > end {
+                continue;
+            }
+            let last = self.code[join - 1];
+            if !last.a_mode() || is_test(last.opcode()) {
+                continue;
+            }
+            let reg = last.abc().0 as usize;
+            let saved = self.save();
+            let before = self.slots.clone();
+            let mut scratch = vec![];
+            let v = self.value(pc, join, reg, &mut scratch);
+            let settled = (0..self.slots.len())
+                .all(|r| r == reg || self.slots[r] == before[r] || self.slots[r] == Slot::Empty);
+            if let Some(v) = v.filter(|_| scratch.is_empty() && settled) {
+                self.write(reg, v, join - 1, out);
+                return Some(join);
+            }
+            self.restore(saved);
+        }
+        None
+    }
+
+    /// Reads `start..join` as an expression left in `reg` at `join`, made of
+    /// tests which jump to `join` with their operand as the value.
+    fn value(
+        &mut self,
+        start: usize,
+        join: usize,
+        reg: usize,
+        out: &mut Vec<Stmt>,
+    ) -> Option<Expr> {
+        let mut pc = start;
+        while pc < join && !self.is_test_pair(pc) {
+            if !self.is_pure(pc, join) {
+                return None;
+            }
+            pc = self.step(pc, join, out);
+        }
+        if pc >= join {
+            return match std::mem::replace(&mut self.slots[reg], Slot::Empty) {
+                Slot::Value(v) => Some(v),
+                _ => None,
+            };
+        }
+        let (a, k, _, _) = self.code[pc].abc();
+        let (a, k) = (a as usize, k != 0);
+        let op = self.code[pc].opcode();
+        let target = self.jump_target(pc);
+        if target == join && (op == OP_TEST || op == OP_TESTSET) && a == reg {
+            let (lhs, _) = self.condition(pc, out);
+            let rhs = self.value(pc + 2, join, reg, out)?;
+            return Some(Expr::binary(if k { "or" } else { "and" }, lhs, rhs));
+        }
+        let inner = target.wrapping_sub(2);
+        let is_ternary = op == OP_TEST
+            && !k
+            && target >= pc + 4
+            && target < join
+            && self.is_test_pair(inner)
+            && self.jump_target(inner) == join
+            && self.code[inner].abc().0 as usize == reg
+            && self.code[inner].abc().1 != 0;
+        if is_ternary {
+            let (x, _) = self.condition(pc, out);
+            let mut q = pc + 2;
+            while q < inner {
+                if !self.is_pure(q, inner) {
+                    return None;
+                }
+                q = self.step(q, inner, out);
+            }
+            let (y, _) = self.condition(inner, out);
+            let z = self.value(target, join, reg, out)?;
+            let x_and_y = Expr::binary("and", x, y);
+            return Some(Expr::binary("or", x_and_y, z));
+        }
+        None
+    }
+
+    /// Spells out a `TESTSET` which is not part of an `and`/`or` value: it
+    /// copies its operand when it jumps.
+    fn testset(&mut self, pc: usize, end: usize, out: &mut Vec<Stmt>) -> usize {
+        let (a, k, b, _) = self.code[pc].abc();
+        let (a, k, b) = (a as usize, k != 0, b as usize);
+        let target = self.jump_target(pc);
+        let mut lhs = self.reg(b, pc, out);
+        if !matches!(lhs, Expr::Local(..) | Expr::Temp(_)) {
+            out.push(Stmt::Assign(vec![Expr::Temp(b)], vec![lhs]));
+            self.temps.insert(b);
+            lhs = Expr::Temp(b);
+        }
+        self.flush(out);
+        let cond = if k { lhs.clone() } else { lhs.clone().not() };
+        let mut jumped = vec![];
+        self.write(a, lhs, pc, &mut jumped);
+        self.flush(&mut jumped);
+        if self.exits.last() == Some(&target) {
+            jumped.push(Stmt::Break);
+            out.push(Stmt::If(cond, jumped, vec![]));
+            return pc + 2;
+        }
+        if target < pc + 2 || target > end {
+            self.goto(target, &mut jumped);
+            out.push(Stmt::If(cond, jumped, vec![]));
+            return pc + 2;
+        }
+        let mut then = self.block(pc + 2, target);
+        self.flush(&mut then);
+        out.push(Stmt::If(cond.not(), then, jumped));
+        target
+    }
+
+    fn save(&self) -> Snapshot {
+        Snapshot {
+            slots: self.slots.clone(),
+            temps: self.temps.clone(),
+            declared: self.declared.clone(),
+            labels: self.labels.clone(),
+            placed: self.placed.clone(),
+        }
+    }
+
+    fn restore(&mut self, saved: Snapshot) {
+        self.slots = saved.slots;
+        self.temps = saved.temps;
+        self.declared = saved.declared;
+        self.labels = saved.labels;
+        self.placed = saved.placed;
+    }
+
+    /// Returns the comparison the test at `pc` makes and its `k` flag; the
+    /// following jump is skipped when the two differ.
+    fn condition(&mut self, pc: usize, out: &mut Vec<Stmt>) -> (Expr, bool) {
+        let i = self.code[pc];
+        let (a, k, b, c) = i.abc();
+        let (a, k, b) = (a as usize, k != 0, b as usize);
+        let imm = || {
+            let sb = b as i64 - OFFSET_SC as i64;
+            if c != 0 {
+                Expr::Float(sb as f64)
+            } else {
+                Expr::Integer(sb)
+            }
+        };
+        let cond = match i.opcode() {
+            op @ (OP_EQ | OP_LT | OP_LE) => {
+                let l = self.reg(a, pc, out);
+                let r = self.reg(b, pc, out);
+                let op = match op {
+                    OP_EQ => "==",
+                    OP_LT => "<",
+                    _ => "<=",
+                };
+                Expr::binary(op, l, r)
+            }
+            OP_EQK => {
+                let l = self.reg(a, pc, out);
+                Expr::binary("==", l, self.constant(b))
+            }
+            op @ (OP_EQI | OP_LTI | OP_LEI | OP_GTI | OP_GEI) => {
+                let l = self.reg(a, pc, out);
+                let op = match op {
+                    OP_EQI => "==",
+                    OP_LTI => "<",
+                    OP_LEI => "<=",
+                    OP_GTI => ">",
+                    _ => ">=",
+                };
+                Expr::binary(op, l, imm())
+            }
+            OP_TESTSET => self.reg(b, pc, out),
+            _ => self.reg(a, pc, out),
+        };
+        (cond, k)
+    }
+
+    /// Returns where the jump at `pc`, or following the test at `pc`, goes.
+    fn jump_target(&self, pc: usize) -> usize {
+        let jmp = match self.code[pc].opcode() {
+            OP_JMP => pc,
+            _ => pc + 1,
+        };
+        (jmp as isize + 1 + self.code[jmp].sj()) as usize
+    }
+
+    fn is_test_pair(&self, pc: usize) -> bool {
+        is_test(self.code[pc].opcode()) && self.code.get(pc + 1).map(|i| i.opcode()) == Some(OP_JMP)
+    }
+
+    /// Whether the instruction at `pc` only computes a temporary, so that it
+    /// may sit between the tests of one condition.
+    fn is_pure(&self, pc: usize, end: usize) -> bool {
+        let i = self.code[pc];
+        let a = i.abc().0 as usize;
+        let op = i.opcode();
+        let computes = matches!(
+            op,
+            OP_MOVE..=OP_LOADTRUE
+                | OP_LOADNIL..=OP_GETUPVAL
+                | OP_GETTABUP..=OP_GETFIELD
+                | OP_SELF..=OP_LEN
+                | OP_CONCAT
+                | OP_EXTRAARG
+        ) && op != OP_LFALSESKIP
+            || op == OP_CALL && i.abc().3 == 2;
+        computes
+            && self.local_at(a, pc).is_none()
+            && (pc + 1 == end
+                || !self
+                    .proto
+                    .locvars
+                    .iter()
+                    .any(|v| v.startpc as usize == pc + 1))
+    }
+
+    /// Declares the locals which come into scope at `pc`, initialized with
+    /// the values pending in their registers.
+    fn declare_locals(&mut self, pc: usize, out: &mut Vec<Stmt>) {
+        let closure = Some(self.code[pc])
+            .filter(|i| i.opcode() == OP_CLOSURE)
+            .map(|i| i.abc().0 as usize);
+        let mut registers = vec![];
+        for (n, v) in self.proto.locvars.iter().enumerate() {
+            let internal = v.varname().is_none_or(|s| s.starts_with(b"("));
+            if v.startpc as usize != pc || self.declared[n] || internal {
+                continue;
+            }
+            if closure == Some(self.registers[n]) {
+                // a `local function`, declared by the closure itself
+                continue;
+            }
+            self.declared[n] = true;
+            registers.push(self.registers[n]);
+        }
+        let (first, count) = match (registers.first(), registers.last()) {
+            (Some(&first), Some(&last)) => (first, last + 1 - first),
+            _ => return,
+        };
+        let names = registers.iter().map(|&r| self.name(r, pc)).collect();
+        let mut values = self.list(first, Some(count), false, pc, out);
+        if values.iter().all(|v| v == &Expr::Nil) {
+            values.clear();
+        }
+        out.push(Stmt::Local {
+            names,
+            values,
+            close: false,
+        });
+    }
+
+    /// Marks the local which comes into scope in `reg` at `pc` as declared,
+    /// returning its name.
+    fn claim_local(&mut self, reg: usize, pc: usize) -> Option<Expr> {
+        let n = (0..self.proto.locvars.len()).find(|&n| {
+            self.proto.locvars[n].startpc as usize == pc
+                && self.registers[n] == reg
+                && !self.declared[n]
+        })?;
+        self.declared[n] = true;
+        let name = self.proto.locvars[n].varname()?.clone();
+        Some(Expr::Local(reg, name))
+    }
+
+    fn local_at(&self, reg: usize, pc: usize) -> Option<&LuaString> {
+        self.proto.local_name(reg, pc)
+    }
+
+    /// Names register `reg` at `pc`.
+    fn name(&mut self, reg: usize, pc: usize) -> Expr {
+        match self.local_at(reg, pc) {
+            Some(name) => Expr::Local(reg, name.clone()),
+            None => {
+                self.temps.insert(reg);
+                Expr::Temp(reg)
+            }
+        }
+    }
+
+    /// Reads register `reg` as an operand of the instruction at `pc`.
+    fn reg(&mut self, reg: usize, pc: usize, out: &mut Vec<Stmt>) -> Expr {
+        match self.slots.get(reg) {
+            Some(Slot::Value(_)) => match std::mem::replace(&mut self.slots[reg], Slot::Empty) {
+                Slot::Value(e) => e,
+                _ => unreachable!(),
+            },
+            Some(Slot::Results(..) | Slot::Covered) => {
+                self.materialize(reg, out);
+                self.name(reg, pc)
+            }
+            _ => self.name(reg, pc),
+        }
+    }
+
+    fn rk(&mut self, c: usize, k: bool, pc: usize, out: &mut Vec<Stmt>) -> Expr {
+        if k {
+            self.constant(c)
+        } else {
+            self.reg(c, pc, out)
+        }
+    }
+
+    /// Reads `count` values starting at register `from`, or all of them up to
+    /// pending open results if `None`. With `truncate`, a trailing call known
+    /// to give one value is parenthesized so it stays at one.
+    fn list(
+        &mut self,
+        from: usize,
+        count: Option<usize>,
+        truncate: bool,
+        pc: usize,
+        out: &mut Vec<Stmt>,
+    ) -> Vec<Expr> {
+        let end = match count {
+            Some(n) => from + n,
+            None => (from..self.slots.len())
+                .find(|&r| matches!(self.slots[r], Slot::Results(_, None)))
+                .map_or(from, |r| r + 1),
+        };
+        let mut values = vec![];
+        let mut r = from;
+        while r < end {
+            let spans_rest = match &self.slots[r] {
+                Slot::Results(_, None) => true,
+                Slot::Results(_, Some(n)) => r + n == end,
+                _ => false,
+            };
+            if spans_rest {
+                if let Slot::Results(e, _) = std::mem::replace(&mut self.slots[r], Slot::Empty) {
+                    self.slots[r + 1..end].fill(Slot::Empty);
+                    values.push(e);
+                }
+                return values;
+            }
+            values.push(self.reg(r, pc, out));
+            r += 1;
+        }
+        if truncate {
+            if let Some(last) = values.pop() {
+                values.push(match last {
+                    e if e.is_multi() => Expr::Paren(Box::new(e)),
+                    e => e,
+                });
+            }
+        }
+        values
+    }
+
+    /// Builds the call of the function in `a` with `b - 1` arguments, or all
+    /// up to the top if `b` is 0.
+    fn call(&mut self, a: usize, b: usize, pc: usize, out: &mut Vec<Stmt>) -> Expr {
+        let f = self.reg(a, pc, out);
+        let count = if b == 0 { None } else { Some(b - 1) };
+        let mut args = self.list(a + 1, count, true, pc, out);
+        match f {
+            Expr::SelfMethod(obj, key) if args.first() == Some(&Expr::SelfArg) => {
+                args.remove(0);
+                Expr::MethodCall(obj, key, args)
+            }
+            f => Expr::Call(Box::new(f), args),
+        }
+    }
+
+    fn set_results(&mut self, a: usize, e: Expr, count: usize) {
+        self.slots[a] = Slot::Results(e, Some(count));
+        self.slots[a + 1..a + count].fill(Slot::Covered);
+    }
+
+    /// Stores `v` into register `reg` as the instruction at `pc` does.
+    fn write(&mut self, reg: usize, v: Expr, pc: usize, out: &mut Vec<Stmt>) {
+        if let Some(name) = self.local_at(reg, pc).cloned() {
+            // values read from the local before this assignment keep it
+            let reads = |e: &Expr| matches!(e, Expr::Local(r, _) if *r == reg);
+            for r in 0..self.slots.len() {
+                let conflicts = match &self.slots[r] {
+                    Slot::Value(e) | Slot::Results(e, _) => e.any(&reads),
+                    _ => false,
+                };
+                if conflicts {
+                    self.materialize(r, out);
+                }
+            }
+            out.push(Stmt::Assign(vec![Expr::Local(reg, name)], vec![v]));
+            return;
+        }
+        match &self.slots[reg] {
+            Slot::Value(e) if e.any(&|e| matches!(e, Expr::Call(..) | Expr::MethodCall(..))) => {
+                self.materialize(reg, out);
+            }
+            Slot::Results(..) | Slot::Covered => self.materialize(reg, out),
+            _ => {}
+        }
+        self.slots[reg] = Slot::Value(v);
+    }
+
+    /// Stores `RK(c)` into `key` of the table in register `a`, adding it to
+    /// the constructor if the table is still being built.
+    fn store(&mut self, a: usize, key: Expr, c: usize, k: bool, pc: usize, out: &mut Vec<Stmt>) {
+        let v = self.rk(c, k, pc, out);
+        if let Slot::Value(Expr::Table(fields)) = &mut self.slots[a] {
+            fields.push(Field::Keyed(key, v));
+            return;
+        }
+        let t = self.reg(a, pc, out);
+        self.settle(out);
+        out.push(Stmt::Assign(
+            vec![Expr::Index(Box::new(t), Box::new(key))],
+            vec![v],
+        ));
+    }
+
+    fn ret(&mut self, values: Vec<Expr>, pc: usize, out: &mut Vec<Stmt>) {
+        self.flush(out);
+        if !values.is_empty() || pc + 1 != self.code.len() {
+            out.push(Stmt::Return(values));
+        }
+    }
+
+    fn goto(&mut self, target: usize, out: &mut Vec<Stmt>) {
+        self.flush(out);
+        self.labels.insert(target);
+        out.push(Stmt::Goto(target));
+    }
+
+    /// Assigns the value pending in `reg`, and the registers sharing it, to
+    /// their temporary names.
+    fn materialize(&mut self, reg: usize, out: &mut Vec<Stmt>) {
+        let mut head = reg;
+        while head > 0 && self.slots[head] == Slot::Covered {
+            head -= 1;
+        }
+        match std::mem::replace(&mut self.slots[head], Slot::Empty) {
+            Slot::Value(e) => {
+                self.temps.insert(head);
+                out.push(Stmt::Assign(vec![Expr::Temp(head)], vec![e]));
+            }
+            Slot::Results(e, count) => {
+                let count = count.unwrap_or(1);
+                self.slots[head + 1..head + count].fill(Slot::Empty);
+                let targets = (head..head + count)
+                    .map(|r| {
+                        self.temps.insert(r);
+                        Expr::Temp(r)
+                    })
+                    .collect();
+                out.push(Stmt::Assign(targets, vec![e]));
+            }
+            _ => {}
+        }
+    }
+
+    /// Materializes all pending values, before control flow joins or splits.
+    fn flush(&mut self, out: &mut Vec<Stmt>) {
+        for r in 0..self.slots.len() {
+            self.materialize(r, out);
+        }
+    }
+
+    /// Materializes the pending values which a statement could change.
+    fn settle(&mut self, out: &mut Vec<Stmt>) {
+        for r in 0..self.slots.len() {
+            if let Slot::Value(e) | Slot::Results(e, _) = &self.slots[r] {
+                if e.is_impure() {
+                    self.materialize(r, out);
+                }
+            }
+        }
+    }
+
+    fn extraarg(&self, pc: usize) -> usize {
+        self.code.get(pc + 1).map_or(0, |i| i.ax() as usize)
+    }
+
+    fn constant(&self, idx: usize) -> Expr {
+        match self.proto.constants.get(idx) {
+            Some(Constant::Nil) | None => Expr::Nil,
+            Some(Constant::Boolean(b)) => Expr::Boolean(*b),
+            Some(Constant::Number(n)) => Expr::Float(*n),
+            Some(Constant::Integer(i)) => Expr::Integer(*i),
+            Some(Constant::String(s)) => Expr::String(s.clone()),
+        }
+    }
+
+    fn upvalue(&self, idx: usize) -> Expr {
+        let name = self.proto.upvalues.get(idx).and_then(|u| u.name.clone());
+        Expr::Upvalue(name.unwrap_or_else(|| format!("u{}", idx).into()))
+    }
+
+    /// Indexes upvalue `up` with constant `c`, which reads a global if the
+    /// upvalue is `_ENV`.
+    fn tabup(&self, up: usize, c: usize) -> Expr {
+        match (self.upvalue(up), self.constant(c)) {
+            (Expr::Upvalue(env), Expr::String(name)) if env.as_bytes() == b"_ENV" => {
+                Expr::Global(name)
+            }
+            (t, key) => Expr::Index(Box::new(t), Box::new(key)),
+        }
+    }
+}
+
+fn is_test(op: u8) -> bool {
+    matches!(op, OP_EQ..=OP_TESTSET)
+}
+
+fn binary_op(op: u8) -> &'static str {
+    match op {
+        OP_ADD | OP_ADDK | OP_ADDI => "+",
+        OP_SUB | OP_SUBK => "-",
+        OP_MUL | OP_MULK => "*",
+        OP_MOD | OP_MODK => "%",
+        OP_POW | OP_POWK => "^",
+        OP_DIV | OP_DIVK => "/",
+        OP_IDIV | OP_IDIVK => "//",
+        OP_BAND | OP_BANDK => "&",
+        OP_BOR | OP_BORK => "|",
+        OP_BXOR | OP_BXORK => "~",
+        OP_SHL => "<<",
+        _ => ">>",
+    }
+}
+
+/// Left and right priorities of a binary operator, as in `lparser.c`.
+fn binary_priority(op: &str) -> (u8, u8) {
+    match op {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "==" => (3, 3),
+        "|" => (4, 4),
+        "~" => (5, 5),
+        "&" => (6, 6),
+        "<<" | ">>" => (7, 7),
+        ".." => (9, 8),
+        "+" | "-" => (10, 10),
+        "*" | "/" | "//" | "%" => (11, 11),
+        _ => (14, 13),
+    }
+}
+
+const UNARY_PRIORITY: u8 = 12;
+
+fn priority(e: &Expr) -> u8 {
+    match e {
+        Expr::Binary(op, ..) => binary_priority(op).0,
+        Expr::Unary(..) => UNARY_PRIORITY,
+        Expr::Integer(i) if *i < 0 => UNARY_PRIORITY,
+        Expr::Float(n) if n.is_sign_negative() => UNARY_PRIORITY,
+        _ => u8::MAX,
+    }
+}
+
+fn is_name(s: &[u8]) -> bool {
+    match s.split_first() {
+        Some((c, rest)) => {
+            (c.is_ascii_alphabetic() || *c == b'_')
+                && rest.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+                && !KEYWORDS.iter().any(|k| k.as_bytes() == s)
+        }
+        None => false,
+    }
+}
+
+fn write_indent(f: &mut Formatter<'_>, indent: usize) -> Result {
+    write!(f, "{:1$}", "", indent * 2)
+}
+
+fn write_block(f: &mut Formatter<'_>, stmts: &[Stmt], indent: usize) -> Result {
+    for (n, stmt) in stmts.iter().enumerate() {
+        write_indent(f, indent)?;
+        match stmt {
+            // `return` must end a block
+            Stmt::Return(_) if n + 1 < stmts.len() => {
+                write!(f, "do ")?;
+                write_stmt(f, stmt, indent)?;
+                write!(f, " end")?;
+            }
+            _ => write_stmt(f, stmt, indent)?,
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn write_list(f: &mut Formatter<'_>, exprs: &[Expr], indent: usize) -> Result {
+    for (n, e) in exprs.iter().enumerate() {
+        if n > 0 {
+            write!(f, ", ")?;
+        }
+        write_expr(f, e, indent)?;
+    }
+    Ok(())
+}
+
+fn write_stmt(f: &mut Formatter<'_>, stmt: &Stmt, indent: usize) -> Result {
+    match stmt {
+        Stmt::Local {
+            names,
+            values,
+            close,
+        } => {
+            write!(f, "local ")?;
+            write_list(f, names, indent)?;
+            if *close {
+                write!(f, " <close>")?;
+            }
+            if !values.is_empty() {
+                write!(f, " = ")?;
+                write_list(f, values, indent)?;
+            }
+            Ok(())
+        }
+        Stmt::LocalFunction(name, func) => {
+            write!(f, "local function ")?;
+            write_expr(f, name, indent)?;
+            write_function(f, func, indent)
+        }
+        Stmt::Assign(targets, values) => {
+            if let ([target], [Expr::Function(func)]) = (&targets[..], &values[..]) {
+                if let Some(name) = function_name(target) {
+                    write!(f, "function {}", name)?;
+                    return write_function(f, func, indent);
+                }
+            }
+            write_list(f, targets, indent)?;
+            write!(f, " = ")?;
+            write_list(f, values, indent)
+        }
+        Stmt::Call(call) => write_expr(f, call, indent),
+        Stmt::Return(values) => {
+            write!(f, "return")?;
+            if !values.is_empty() {
+                write!(f, " ")?;
+                write_list(f, values, indent)?;
+            }
+            Ok(())
+        }
+        Stmt::Break => write!(f, "break"),
+        Stmt::Goto(pc) => write!(f, "goto label_{}", pc + 1),
+        Stmt::Label(pc) => write!(f, "::label_{}::", pc + 1),
+        Stmt::If(cond, then, els) => {
+            write!(f, "if ")?;
+            write_expr(f, cond, indent)?;
+            writeln!(f, " then")?;
+            write_block(f, then, indent + 1)?;
+            let mut els = els;
+            while let [Stmt::If(cond, then, rest)] = &els[..] {
+                write_indent(f, indent)?;
+                write!(f, "elseif ")?;
+                write_expr(f, cond, indent)?;
+                writeln!(f, " then")?;
+                write_block(f, then, indent + 1)?;
+                els = rest;
+            }
+            if !els.is_empty() {
+                write_indent(f, indent)?;
+                writeln!(f, "else")?;
+                write_block(f, els, indent + 1)?;
+            }
+            write_indent(f, indent)?;
+            write!(f, "end")
+        }
+        Stmt::While(cond, body) => {
+            write!(f, "while ")?;
+            write_expr(f, cond, indent)?;
+            writeln!(f, " do")?;
+            write_block(f, body, indent + 1)?;
+            write_indent(f, indent)?;
+            write!(f, "end")
+        }
+        Stmt::Repeat(body, cond) => {
+            writeln!(f, "repeat")?;
+            write_block(f, body, indent + 1)?;
+            write_indent(f, indent)?;
+            write!(f, "until ")?;
+            write_expr(f, cond, indent)
+        }
+        Stmt::NumericFor(var, init, limit, step, body) => {
+            write!(f, "for ")?;
+            write_expr(f, var, indent)?;
+            write!(f, " = ")?;
+            write_expr(f, init, indent)?;
+            write!(f, ", ")?;
+            write_expr(f, limit, indent)?;
+            if let Some(step) = step {
+                write!(f, ", ")?;
+                write_expr(f, step, indent)?;
+            }
+            writeln!(f, " do")?;
+            write_block(f, body, indent + 1)?;
+            write_indent(f, indent)?;
+            write!(f, "end")
+        }
+        Stmt::GenericFor(vars, values, body) => {
+            write!(f, "for ")?;
+            write_list(f, vars, indent)?;
+            write!(f, " in ")?;
+            write_list(f, values, indent)?;
+            writeln!(f, " do")?;
+            write_block(f, body, indent + 1)?;
+            write_indent(f, indent)?;
+            write!(f, "end")
+        }
+        Stmt::Unknown(listing) => write!(f, "-- {}", listing.replace('\t', " ")),
+    }
+}
+
+/// Spells `target` as the name in `function a.b.c() end`, if it is one.
+fn function_name(target: &Expr) -> Option<String> {
+    match target {
+        Expr::Global(name) if is_name(name) => Some(name.to_string()),
+        Expr::Index(t, key) => match (&**t, &**key) {
+            (Expr::Local(_, base) | Expr::Upvalue(base), Expr::String(key))
+                if is_name(base) && is_name(key) =>
+            {
+                Some(format!("{}.{}", base, key))
+            }
+            (t, Expr::String(key)) if is_name(key) => {
+                Some(format!("{}.{}", function_name(t)?, key))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn write_function(f: &mut Formatter<'_>, func: &Function, indent: usize) -> Result {
+    write!(f, "(")?;
+    write_list(f, &func.params, indent)?;
+    if func.is_vararg {
+        if !func.params.is_empty() {
+            write!(f, ", ")?;
+        }
+        write!(f, "...")?;
+    }
+    write!(f, ")")?;
+    match &func.body {
+        Some(body) if body.is_empty() => write!(f, " end"),
+        Some(body) => {
+            writeln!(f)?;
+            write_block(f, body, indent + 1)?;
+            write_indent(f, indent)?;
+            write!(f, "end")
+        }
+        None => write!(f, " --[[ not loaded ]] end"),
+    }
+}
+
+/// Writes `e` as an operand, in parentheses unless its priority is at least
+/// `min`.
+fn write_operand(f: &mut Formatter<'_>, e: &Expr, min: u8, indent: usize) -> Result {
+    if priority(e) < min {
+        write!(f, "(")?;
+        write_expr(f, e, indent)?;
+        write!(f, ")")
+    } else {
+        write_expr(f, e, indent)
+    }
+}
+
+/// Writes `e` where a prefix expression is expected, e.g. before a call.
+fn write_prefix(f: &mut Formatter<'_>, e: &Expr, indent: usize) -> Result {
+    match e {
+        Expr::Local(..)
+        | Expr::Temp(_)
+        | Expr::Upvalue(_)
+        | Expr::Global(_)
+        | Expr::Index(..)
+        | Expr::Call(..)
+        | Expr::MethodCall(..)
+        | Expr::Paren(_) => write_expr(f, e, indent),
+        e => {
+            write!(f, "(")?;
+            write_expr(f, e, indent)?;
+            write!(f, ")")
+        }
+    }
+}
+
+fn write_expr(f: &mut Formatter<'_>, e: &Expr, indent: usize) -> Result {
+    match e {
+        Expr::Nil => write!(f, "nil"),
+        Expr::Boolean(b) => write!(f, "{}", b),
+        Expr::Integer(i64::MIN) => write!(f, "0x8000000000000000"),
+        Expr::Integer(i) => write!(f, "{}", i),
+        Expr::Float(n) if n.is_nan() => write!(f, "(0/0)"),
+        Expr::Float(n) if n.is_infinite() => {
+            write!(f, "{}1e9999", if *n < 0.0 { "-" } else { "" })
+        }
+        Expr::Float(n) => write!(f, "{:?}", n),
+        Expr::String(s) => write_quoted(f, s),
+        Expr::Vararg => write!(f, "..."),
+        Expr::Local(_, name) | Expr::Upvalue(name) if is_name(name) => write!(f, "{}", name),
+        // a name which cannot be spelled falls back to that of its register
+        Expr::Local(r, _) | Expr::Temp(r) => write!(f, "r{}", r),
+        Expr::Upvalue(_) => write!(f, "_"),
+        Expr::Global(name) if is_name(name) => write!(f, "{}", name),
+        Expr::Global(name) => {
+            write!(f, "_ENV[")?;
+            write_quoted(f, name)?;
+            write!(f, "]")
+        }
+        Expr::Index(t, key) => {
+            write_prefix(f, t, indent)?;
+            match &**key {
+                Expr::String(s) if is_name(s) => write!(f, ".{}", s),
+                key => {
+                    write!(f, "[")?;
+                    write_expr(f, key, indent)?;
+                    write!(f, "]")
+                }
+            }
+        }
+        Expr::Call(func, args) => {
+            write_prefix(f, func, indent)?;
+            write!(f, "(")?;
+            write_list(f, args, indent)?;
+            write!(f, ")")
+        }
+        Expr::MethodCall(obj, key, args) => match &**key {
+            Expr::String(s) if is_name(s) => {
+                write_prefix(f, obj, indent)?;
+                write!(f, ":{}(", s)?;
+                write_list(f, args, indent)?;
+                write!(f, ")")
+            }
+            _ => {
+                write_prefix(f, &Expr::Index(obj.clone(), key.clone()), indent)?;
+                write!(f, "(")?;
+                write_list(f, &[&[(**obj).clone()], &args[..]].concat(), indent)?;
+                write!(f, ")")
+            }
+        },
+        Expr::SelfMethod(obj, key) => write_expr(f, &Expr::Index(obj.clone(), key.clone()), indent),
+        Expr::SelfArg => write!(f, "self"),
+        Expr::Binary(op, l, r) => {
+            let (lp, rp) = binary_priority(op);
+            // an operand of equal priority binds on the associative side only
+            write_operand(f, l, if lp > rp { lp + 1 } else { lp }, indent)?;
+            write!(f, " {} ", op)?;
+            write_operand(f, r, if lp > rp { lp } else { lp + 1 }, indent)
+        }
+        Expr::Unary(op, operand) => {
+            write!(f, "{}", op)?;
+            if *op == "not" {
+                write!(f, " ")?;
+            }
+            if *op == "-" && priority(operand) == UNARY_PRIORITY {
+                // keep `- -x` from reading as a comment
+                write!(f, "(")?;
+                write_expr(f, operand, indent)?;
+                write!(f, ")")
+            } else {
+                write_operand(f, operand, UNARY_PRIORITY, indent)
+            }
+        }
+        Expr::Paren(e) => {
+            write!(f, "(")?;
+            write_expr(f, e, indent)?;
+            write!(f, ")")
+        }
+        Expr::Function(func) => {
+            write!(f, "function")?;
+            write_function(f, func, indent)
+        }
+        Expr::Table(fields) => {
+            if fields.is_empty() {
+                return write!(f, "{{}}");
+            }
+            write!(f, "{{ ")?;
+            for (n, field) in fields.iter().enumerate() {
+                if n > 0 {
+                    write!(f, ", ")?;
+                }
+                match field {
+                    Field::Positional(v) => write_expr(f, v, indent)?,
+                    Field::Keyed(Expr::String(s), v) if is_name(s) => {
+                        write!(f, "{} = ", s)?;
+                        write_expr(f, v, indent)?;
+                    }
+                    Field::Keyed(k, v) => {
+                        write!(f, "[")?;
+                        write_expr(f, k, indent)?;
+                        write!(f, "] = ")?;
+                        write_expr(f, v, indent)?;
+                    }
+                }
+            }
+            write!(f, " }}")
+        }
+    }
+}
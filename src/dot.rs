@@ -0,0 +1,131 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graphviz exports of a function's control-flow graph and of the tree of
+//! nested protos.
+
+use std::fmt::{Display, Formatter, Result};
+
+use crate::{
+    cfg::Cfg,
+    listing::{describe, InstructionDisplay},
+    proto::Proto,
+};
+
+/// The control-flow graph of one function in DOT, with each block labeled by
+/// its disassembled instructions.
+pub struct CfgDot<'a> {
+    proto: &'a Proto,
+    cfg: Cfg,
+}
+
+impl<'a> CfgDot<'a> {
+    pub fn new(proto: &'a Proto) -> Self {
+        Self {
+            proto,
+            cfg: Cfg::new(proto),
+        }
+    }
+}
+
+impl Display for CfgDot<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(f, "digraph cfg {{")?;
+        writeln!(f, "  label=\"{}\";", escape(&describe(self.proto)))?;
+        writeln!(f, "  node [shape=box, fontname=monospace];")?;
+        for (b, block) in self.cfg.blocks().iter().enumerate() {
+            let mut label = String::new();
+            for pc in block.pcs.clone() {
+                let line = match self.proto.line_for_pc(pc) {
+                    Some(line) if line > 0 => line.to_string(),
+                    _ => "-".to_string(),
+                };
+                let text = format!(
+                    "{}\t[{}]\t{}",
+                    pc + 1,
+                    line,
+                    InstructionDisplay::new(self.proto, pc)
+                );
+                label.push_str(&escape(&text.replace('\t', " ")));
+                label.push_str("\\l");
+            }
+            let style = if self.cfg.is_reachable(b) {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            writeln!(f, "  b{} [label=\"{}\"{}];", b, label, style)?;
+        }
+        for (b, block) in self.cfg.blocks().iter().enumerate() {
+            for &s in &block.succs {
+                let back = self.cfg.dominates(s, b);
+                writeln!(
+                    f,
+                    "  b{} -> b{}{};",
+                    b,
+                    s,
+                    if back { " [style=bold]" } else { "" }
+                )?;
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// The hierarchy of nested protos in DOT, each labeled with its source and
+/// line range. Protos which have not been loaded are drawn dashed.
+pub struct ProtoTreeDot<'a> {
+    proto: &'a Proto,
+}
+
+impl<'a> ProtoTreeDot<'a> {
+    pub fn new(proto: &'a Proto) -> Self {
+        Self { proto }
+    }
+
+    /// Writes the subtree rooted at `p` whose node id is `id`, returning the
+    /// next free id.
+    fn write_tree(
+        f: &mut Formatter<'_>,
+        p: &Proto,
+        id: usize,
+    ) -> std::result::Result<usize, std::fmt::Error> {
+        writeln!(f, "  f{} [label=\"{}\"];", id, escape(&describe(p)))?;
+        let mut next = id + 1;
+        for (i, nested) in p.protos.iter().enumerate() {
+            writeln!(f, "  f{} -> f{} [label=\"{}\"];", id, next, i)?;
+            match nested.get() {
+                Some(nested) => next = Self::write_tree(f, nested, next)?,
+                None => {
+                    writeln!(f, "  f{} [label=\"(not loaded)\", style=dashed];", next)?;
+                    next += 1;
+                }
+            }
+        }
+        Ok(next)
+    }
+}
+
+impl Display for ProtoTreeDot<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(f, "digraph protos {{")?;
+        writeln!(f, "  node [shape=box];")?;
+        Self::write_tree(f, self.proto, 0)?;
+        writeln!(f, "}}")
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
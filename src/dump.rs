@@ -0,0 +1,161 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    constants::*,
+    proto::{Constant, Proto},
+    string::LuaString,
+};
+
+/// Encodes `proto` as a Lua 5.4 binary chunk, the inverse of
+/// [`undump`](crate::undump).
+///
+/// Nested protos that were never decoded are copied from the original chunk
+/// verbatim.
+pub fn dump(proto: &Proto) -> Vec<u8> {
+    let mut w = Writer { buf: vec![] };
+    w.write_header();
+    w.write_byte(proto.upvalues.len() as u8);
+    w.write_proto(proto, None);
+    w.buf
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn write_byte(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn write_header(&mut self) {
+        self.buf.extend_from_slice(ESC_LUA);
+        self.write_byte(LUAC_VERSION);
+        self.write_byte(LUAC_FORMAT);
+        self.buf.extend_from_slice(LUAC_DATA);
+        self.write_byte(INSTRUCTION_SIZE);
+        self.write_byte(LUA_INTEGER_SIZE);
+        self.write_byte(LUA_NUMBER_SIZE);
+        self.buf.extend_from_slice(&LUAC_INT.to_le_bytes());
+        self.buf.extend_from_slice(&LUAC_NUM.to_le_bytes());
+    }
+
+    /// Writes `x` in groups of 7 bits, most significant first, with the high
+    /// bit set on the last byte.
+    fn write_varint(&mut self, mut x: usize) {
+        let mut groups = vec![(x & 0x7F) as u8 | 0x80];
+        x >>= 7;
+        while x != 0 {
+            groups.push((x & 0x7F) as u8);
+            x >>= 7;
+        }
+        self.buf.extend(groups.iter().rev());
+    }
+
+    fn write_string(&mut self, s: Option<&LuaString>) {
+        match s {
+            None => self.write_varint(0),
+            Some(s) => {
+                self.write_varint(s.len() + 1);
+                self.buf.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    fn write_proto(&mut self, proto: &Proto, parent_source: Option<&LuaString>) {
+        // luac omits the source of nested functions sharing their parent's.
+        match (&proto.source, parent_source) {
+            (Some(source), Some(parent)) if source == parent => self.write_string(None),
+            (source, _) => self.write_string(source.as_ref()),
+        }
+        self.write_varint(proto.linedefined as usize);
+        self.write_varint(proto.lastlinedefined as usize);
+        self.write_byte(proto.numparams);
+        self.write_byte(proto.is_vararg);
+        self.write_byte(proto.maxstacksize);
+
+        self.write_varint(proto.code.len());
+        for i in &proto.code {
+            self.buf.extend_from_slice(&u32::from(*i).to_le_bytes());
+        }
+
+        self.write_varint(proto.constants.len());
+        for k in &proto.constants {
+            match k {
+                Constant::Nil => self.write_byte(LUA_V_NIL),
+                Constant::Boolean(false) => self.write_byte(LUA_V_FALSE),
+                Constant::Boolean(true) => self.write_byte(LUA_V_TRUE),
+                Constant::Number(n) => {
+                    self.write_byte(LUA_V_NUM_FLT);
+                    self.buf.extend_from_slice(&n.to_le_bytes());
+                }
+                Constant::Integer(i) => {
+                    self.write_byte(LUA_V_NUM_INT);
+                    self.buf.extend_from_slice(&i.to_le_bytes());
+                }
+                Constant::String(s) => {
+                    self.write_byte(if s.len() <= LUAI_MAXSHORTLEN {
+                        LUA_V_SHR_STR
+                    } else {
+                        LUA_V_LNG_STR
+                    });
+                    self.write_string(Some(s));
+                }
+            }
+        }
+
+        self.write_varint(proto.upvalues.len());
+        for upvalue in &proto.upvalues {
+            self.write_byte(upvalue.instack);
+            self.write_byte(upvalue.idx);
+            self.write_byte(upvalue.kind);
+        }
+
+        self.write_varint(proto.protos.len());
+        for nested in &proto.protos {
+            match (nested.get(), nested.encoded()) {
+                (Some(p), _) => self.write_proto(p, proto.source.as_ref()),
+                (None, Some(encoded)) => self.buf.extend_from_slice(encoded),
+                (None, None) => unreachable!("proto is neither loaded nor deferred"),
+            }
+        }
+
+        self.write_varint(proto.lineinfo.len());
+        self.buf.extend(proto.lineinfo.iter().map(|d| *d as u8));
+
+        self.write_varint(proto.abslineinfo.len());
+        for abs in &proto.abslineinfo {
+            self.write_varint(abs.pc as usize);
+            self.write_varint(abs.line as usize);
+        }
+
+        self.write_varint(proto.locvars.len());
+        for local in &proto.locvars {
+            self.write_string(local.varname.as_ref());
+            self.write_varint(local.startpc as usize);
+            self.write_varint(local.endpc as usize);
+        }
+
+        // Stripped chunks carry no upvalue names at all.
+        if proto.upvalues.iter().all(|upvalue| upvalue.name.is_none()) {
+            self.write_varint(0);
+        } else {
+            self.write_varint(proto.upvalues.len());
+            for upvalue in &proto.upvalues {
+                self.write_string(upvalue.name.as_ref());
+            }
+        }
+    }
+}
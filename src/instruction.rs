@@ -28,51 +28,56 @@
 //   the written unsigned value minus K, where K is half the maximum for the
 //   corresponding unsigned argument.
 
-use std::fmt::Formatter;
+use std::{fmt::Formatter, io};
 
 use crate::{opcode, opcode::OPCODES};
 
-const MAXARG_BX: isize = (1 << 17) - 1; // 131071
-const MAXARG_SBX: isize = MAXARG_BX >> 1; // 65535
-const MAXARG_SJ: isize = ((1 << 25) - 1) >> 1;
+pub(crate) const MAXARG_BX: isize = (1 << 17) - 1; // 131071
+pub(crate) const MAXARG_SBX: isize = MAXARG_BX >> 1; // 65535
+pub(crate) const MAXARG_SJ: isize = MAXARG_AX >> 1;
+pub(crate) const MAXARG_AX: isize = (1 << 25) - 1;
+pub(crate) const MAXARG_A: isize = 0xFF;
+pub(crate) const MAXARG_B: isize = 0xFF;
+pub(crate) const MAXARG_C: isize = 0xFF;
+pub(crate) const OFFSET_SC: isize = MAXARG_C >> 1;
 
 #[derive(Copy, Clone)]
 pub struct Instruction(u32);
 
 impl Instruction {
-    fn opname(self) -> &'static str {
+    pub(crate) fn opname(self) -> &'static str {
         OPCODES[self.opcode() as usize].name()
     }
 
-    fn opmode(self) -> u8 {
+    pub(crate) fn opmode(self) -> u8 {
         OPCODES[self.opcode() as usize].mode()
     }
 
-    fn mm_mode(self) -> bool {
+    pub(crate) fn mm_mode(self) -> bool {
         OPCODES[self.opcode() as usize].mm()
     }
 
-    fn ot_mode(self) -> bool {
+    pub(crate) fn ot_mode(self) -> bool {
         OPCODES[self.opcode() as usize].ot()
     }
 
-    fn it_mode(self) -> bool {
+    pub(crate) fn it_mode(self) -> bool {
         OPCODES[self.opcode() as usize].it()
     }
 
-    fn t_mode(self) -> bool {
+    pub(crate) fn t_mode(self) -> bool {
         OPCODES[self.opcode() as usize].t()
     }
 
-    fn a_mode(self) -> bool {
+    pub(crate) fn a_mode(self) -> bool {
         OPCODES[self.opcode() as usize].a()
     }
 
-    fn opcode(self) -> u8 {
+    pub(crate) fn opcode(self) -> u8 {
         self.0 as u8 & 0x7F
     }
 
-    fn abc(self) -> (isize, isize, isize, isize) {
+    pub(crate) fn abc(self) -> (isize, isize, isize, isize) {
         let a = (self.0 >> 7 & 0xFF) as isize;
         let k = (self.0 >> 15 & 0x01) as isize;
         let b = (self.0 >> 16 & 0xFF) as isize;
@@ -80,26 +85,83 @@ impl Instruction {
         (a, k, b, c)
     }
 
-    fn a_bx(self) -> (isize, isize) {
+    pub(crate) fn a_bx(self) -> (isize, isize) {
         let a = (self.0 >> 7 & 0xFF) as isize;
         let bx = (self.0 >> 15) as isize;
         (a, bx)
     }
 
-    fn a_sbx(self) -> (isize, isize) {
+    pub(crate) fn a_sbx(self) -> (isize, isize) {
         let (a, bx) = self.a_bx();
         (a, bx - MAXARG_SBX)
     }
 
-    fn ax(self) -> isize {
+    pub(crate) fn ax(self) -> isize {
         (self.0 >> 7) as isize
     }
 
-    fn sj(self) -> isize {
+    pub(crate) fn sj(self) -> isize {
         let sj = (self.0 >> 7) as isize;
         sj - MAXARG_SJ
     }
 
+    /// Encodes an iABC instruction, failing if `op` is not an iABC opcode or
+    /// an operand does not fit its field.
+    pub fn encode_abc(op: u8, a: isize, b: isize, c: isize, k: bool) -> io::Result<Self> {
+        check_mode(op, opcode::OP_MODE_ABC)?;
+        check("A", a, 0, MAXARG_A)?;
+        check("B", b, 0, MAXARG_B)?;
+        check("C", c, 0, MAXARG_C)?;
+        Ok(Self::from_abc(op, a as u32, b as u32, c as u32, k))
+    }
+
+    pub fn encode_abx(op: u8, a: isize, bx: isize) -> io::Result<Self> {
+        check_mode(op, opcode::OP_MODE_ABX)?;
+        check("A", a, 0, MAXARG_A)?;
+        check("Bx", bx, 0, MAXARG_BX)?;
+        Ok(Self::from_abx(op, a as u32, bx as u32))
+    }
+
+    pub fn encode_asbx(op: u8, a: isize, sbx: isize) -> io::Result<Self> {
+        check_mode(op, opcode::OP_MODE_ASBX)?;
+        check("A", a, 0, MAXARG_A)?;
+        check("sBx", sbx, -MAXARG_SBX, MAXARG_BX - MAXARG_SBX)?;
+        Ok(Self::from_asbx(op, a as u32, sbx as i32))
+    }
+
+    pub fn encode_ax(op: u8, ax: isize) -> io::Result<Self> {
+        check_mode(op, opcode::OP_MODE_AX)?;
+        check("Ax", ax, 0, MAXARG_AX)?;
+        Ok(Self::from_ax(op, ax as u32))
+    }
+
+    pub fn encode_sj(op: u8, sj: isize) -> io::Result<Self> {
+        check_mode(op, opcode::OP_MODE_SJ)?;
+        check("sJ", sj, -MAXARG_SJ, MAXARG_AX - MAXARG_SJ)?;
+        Ok(Self::from_sj(op, sj as i32))
+    }
+
+    /// Encodes an iABC instruction. Operands are truncated to their fields.
+    pub(crate) fn from_abc(op: u8, a: u32, b: u32, c: u32, k: bool) -> Self {
+        Self(op as u32 & 0x7F | (a & 0xFF) << 7 | (k as u32) << 15 | (b & 0xFF) << 16 | c << 24)
+    }
+
+    pub(crate) fn from_abx(op: u8, a: u32, bx: u32) -> Self {
+        Self(op as u32 & 0x7F | (a & 0xFF) << 7 | bx << 15)
+    }
+
+    pub(crate) fn from_asbx(op: u8, a: u32, sbx: i32) -> Self {
+        Self::from_abx(op, a, (sbx as isize + MAXARG_SBX) as u32)
+    }
+
+    pub(crate) fn from_ax(op: u8, ax: u32) -> Self {
+        Self(op as u32 & 0x7F | ax << 7)
+    }
+
+    pub(crate) fn from_sj(op: u8, sj: i32) -> Self {
+        Self::from_ax(op, (sj as isize + MAXARG_SJ) as u32)
+    }
+
     fn execute(self) {}
 }
 
@@ -117,6 +179,37 @@ impl std::fmt::Debug for Instruction {
     }
 }
 
+fn check_mode(op: u8, mode: u8) -> io::Result<()> {
+    match OPCODES.get(op as usize) {
+        Some(opcode) if opcode.mode() == mode => Ok(()),
+        Some(opcode) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("opcode {} has another format", opcode.name()),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown opcode {:#04x}", op),
+        )),
+    }
+}
+
+fn check(name: &str, v: isize, min: isize, max: isize) -> io::Result<()> {
+    if (min..=max).contains(&v) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} out of range [{}, {}]", name, v, min, max),
+        ))
+    }
+}
+
+impl From<Instruction> for u32 {
+    fn from(i: Instruction) -> Self {
+        i.0
+    }
+}
+
 impl From<u32> for Instruction {
     fn from(n: u32) -> Self {
         Self(n)
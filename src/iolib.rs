@@ -0,0 +1,1069 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The io library, as `liolib.c`.
+//!
+//! Files are userdata wrapping tokio handles. Library functions are
+//! synchronous, so each operation is driven to completion on a private
+//! runtime, which also works from inside the caller's own runtime. Reads and
+//! writes are buffered here, with `setvbuf` modes as in C.
+
+use std::{
+    cell::{RefCell, RefMut},
+    future::Future,
+    io::{self, SeekFrom},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, OnceLock},
+    task::{Context, Poll, Wake},
+    thread::Thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    runtime::Runtime,
+};
+
+use crate::{
+    args::Args,
+    number::{self, str2number},
+    state::State,
+    table::Table,
+    userdata::{Userdata, WeakUserdata},
+    value::{strerror, Error, Function, Value},
+};
+
+const IO_INPUT: &str = "_IO_input";
+const IO_OUTPUT: &str = "_IO_output";
+/// The registry key of the metatable of files, and their `__name`.
+const FILE_HANDLE: &str = "FILE*";
+
+/// The default size for `setvbuf`, as `LUAL_BUFFERSIZE`.
+const BUFFER_SIZE: i64 = 1024;
+/// How much is read from the stream at a time.
+const READ_CHUNK: usize = 8192;
+/// The longest numeral `read("n")` accepts, as `L_MAXLENNUM`.
+const MAX_LEN_NUM: usize = 200;
+/// The most formats `lines` takes, as `MAXARGLINE`.
+const MAX_ARG_LINE: usize = 250;
+
+// The errno values C reports for these failures.
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+const ESPIPE: i32 = 29;
+
+impl State {
+    /// Opens the io library, with `io.stdin`, `io.stdout` and `io.stderr` as
+    /// the default input and output.
+    pub fn open_io(&self) {
+        let io = Table::new();
+        self.register(
+            &io,
+            &[
+                ("close", io_close),
+                ("flush", io_flush),
+                ("input", io_input),
+                ("lines", io_lines),
+                ("open", io_open),
+                ("output", io_output),
+                ("popen", io_popen),
+                ("read", io_read),
+                ("tmpfile", io_tmpfile),
+                ("type", io_type),
+                ("write", io_write),
+            ],
+        );
+        let methods = Table::new();
+        self.register(
+            &methods,
+            &[
+                ("close", f_close),
+                ("flush", f_flush),
+                ("lines", f_lines),
+                ("read", f_read),
+                ("seek", f_seek),
+                ("setvbuf", f_setvbuf),
+                ("write", f_write),
+            ],
+        );
+        let mt = Table::new();
+        self.register(
+            &mt,
+            &[
+                ("__close", f_gc),
+                ("__gc", f_gc),
+                ("__tostring", f_tostring),
+            ],
+        );
+        mt.set_str("__index", methods);
+        mt.set_str("__name", FILE_HANDLE);
+        self.registry().set_str(FILE_HANDLE, mt);
+
+        let stdin = self.new_file(LuaFile::standard(Stream::Stdin(tokio::io::stdin())));
+        let stdout = self.new_file(LuaFile::standard(Stream::Stdout(tokio::io::stdout())));
+        let stderr = self.new_file(LuaFile::standard(Stream::Stderr(tokio::io::stderr())));
+        self.registry().set_str(IO_INPUT, stdin.clone());
+        self.registry().set_str(IO_OUTPUT, stdout.clone());
+        io.set_str("stdin", stdin);
+        io.set_str("stdout", stdout);
+        io.set_str("stderr", stderr);
+        self.set_library("io", io);
+    }
+
+    fn new_file(&self, file: LuaFile) -> Value {
+        let mt = match self.registry().get_str(FILE_HANDLE) {
+            Value::Table(mt) => Some(mt),
+            _ => None,
+        };
+        let file = Userdata::new(file, mt);
+        FILES.with(|files| {
+            let mut files = files.borrow_mut();
+            files.retain(|f| f.upgrade().is_some());
+            files.push(file.downgrade());
+        });
+        file.into()
+    }
+}
+
+thread_local! {
+    /// The file handles of this thread, as the C library tracks its open
+    /// `FILE`s for `exit`.
+    static FILES: RefCell<Vec<WeakUserdata>> = const { RefCell::new(vec![]) };
+}
+
+/// Writes out the buffered output of every live file handle, as `exit`
+/// does for the open `FILE`s, since `std::process::exit` runs no
+/// destructors.
+pub(crate) fn flush_all() {
+    FILES.with(|files| {
+        for file in files.borrow().iter().filter_map(WeakUserdata::upgrade) {
+            if let Some(mut file) = file.borrow_mut::<LuaFile>() {
+                if !file.is_closed() {
+                    let _ = file.flush();
+                }
+            }
+        }
+    });
+}
+
+/// The runtime which drives file operations. Its worker also reaps the
+/// children of `io.popen`.
+fn io_runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("rua-io")
+            .enable_all()
+            .build()
+            .expect("cannot start the io runtime")
+    })
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread, parking it while the
+/// io runtime does the work. Unlike `Runtime::block_on`, this may be called
+/// from within another runtime.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let _guard = io_runtime().enter();
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// The results of a failed file operation, as `luaL_fileresult`: fail, the
+/// message, prefixed by `fname` if given, and the errno.
+pub(crate) fn file_result(e: &io::Error, fname: Option<&str>) -> Vec<Value> {
+    let msg = match fname {
+        Some(fname) => format!("{}: {}", fname, strerror(e)),
+        None => strerror(e),
+    };
+    vec![
+        Value::Nil,
+        msg.as_str().into(),
+        Value::Integer(e.raw_os_error().unwrap_or(0) as i64),
+    ]
+}
+
+/// The results for a finished process, as `luaL_execresult`: true or fail,
+/// then `"exit"` or `"signal"` and the code.
+pub(crate) fn exec_result(status: ExitStatus) -> Vec<Value> {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return vec![Value::Nil, "signal".into(), Value::Integer(signal as i64)];
+    }
+    let code = status.code().unwrap_or(-1);
+    let ok = if code == 0 {
+        Value::Boolean(true)
+    } else {
+        Value::Nil
+    };
+    vec![ok, "exit".into(), Value::Integer(code as i64)]
+}
+
+/// A command running `prog` through the shell, as `popen` and `system` do.
+pub(crate) fn shell_command(prog: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C");
+        c
+    } else {
+        let mut c = Command::new("/bin/sh");
+        c.arg("-c");
+        c
+    };
+    command.arg(prog);
+    command
+}
+
+/// A fresh name in the temporary directory, for `tmpfile` and
+/// `os.tmpname`.
+pub(crate) fn temp_name() -> String {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let name = format!(
+        "lua_{}_{}_{:x}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        nanos
+    );
+    std::env::temp_dir()
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+enum Stream {
+    File(fs::File),
+    Stdin(tokio::io::Stdin),
+    Stdout(tokio::io::Stdout),
+    Stderr(tokio::io::Stderr),
+    /// The output of a command started with `popen(prog, "r")`.
+    PipeIn(Child, ChildStdout),
+    /// The input of a command started with `popen(prog, "w")`.
+    PipeOut(Child, ChildStdin),
+}
+
+impl Stream {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::File(f) => f.read(buf).await,
+            Stream::Stdin(s) => s.read(buf).await,
+            Stream::PipeIn(_, p) => p.read(buf).await,
+            _ => Err(io::Error::from_raw_os_error(EBADF)),
+        }
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::File(f) => f.write_all(buf).await,
+            Stream::Stdout(s) => s.write_all(buf).await,
+            Stream::Stderr(s) => s.write_all(buf).await,
+            Stream::PipeOut(_, p) => p.write_all(buf).await,
+            _ => Err(io::Error::from_raw_os_error(EBADF)),
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::File(f) => f.flush().await,
+            Stream::Stdout(s) => s.flush().await,
+            Stream::Stderr(s) => s.flush().await,
+            Stream::PipeOut(_, p) => p.flush().await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Stream::File(f) => f.seek(pos).await,
+            _ => Err(io::Error::from_raw_os_error(ESPIPE)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BufMode {
+    No,
+    Full,
+    Line,
+}
+
+/// The payload of a file handle.
+pub(crate) struct LuaFile {
+    /// `None` once closed.
+    stream: Option<Stream>,
+    /// The standard streams refuse to be closed.
+    standard: bool,
+    rbuf: Vec<u8>,
+    rpos: usize,
+    wbuf: Vec<u8>,
+    mode: BufMode,
+    size: usize,
+}
+
+impl LuaFile {
+    fn new(stream: Stream) -> Self {
+        Self {
+            stream: Some(stream),
+            standard: false,
+            rbuf: vec![],
+            rpos: 0,
+            wbuf: vec![],
+            mode: BufMode::Full,
+            size: READ_CHUNK,
+        }
+    }
+
+    /// A standard stream, unbuffered so that its output interleaves with
+    /// `print`.
+    fn standard(stream: Stream) -> Self {
+        let mut file = Self::new(stream);
+        file.standard = true;
+        file.mode = BufMode::No;
+        file
+    }
+
+    fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn stream(&mut self) -> io::Result<&mut Stream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from_raw_os_error(EBADF))
+    }
+
+    fn getc(&mut self) -> io::Result<Option<u8>> {
+        if self.rpos == self.rbuf.len() {
+            self.flush_buffer()?;
+            let mut buf = vec![0; READ_CHUNK];
+            let n = block_on(self.stream()?.read(&mut buf))?;
+            buf.truncate(n);
+            self.rbuf = buf;
+            self.rpos = 0;
+            if n == 0 {
+                return Ok(None);
+            }
+        }
+        self.rpos += 1;
+        Ok(Some(self.rbuf[self.rpos - 1]))
+    }
+
+    /// Pushes back the byte just read by `getc`.
+    fn ungetc(&mut self) {
+        self.rpos -= 1;
+    }
+
+    /// Drops bytes read ahead, moving the position back over them so the
+    /// stream is where the reader stopped.
+    fn discard_read_ahead(&mut self) -> io::Result<()> {
+        let unread = (self.rbuf.len() - self.rpos) as i64;
+        if unread > 0 {
+            if let Stream::File(_) = self.stream()? {
+                block_on(self.stream()?.seek(SeekFrom::Current(-unread)))?;
+            } else {
+                return Ok(());
+            }
+        }
+        self.rbuf.clear();
+        self.rpos = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.discard_read_ahead()?;
+        self.wbuf.extend_from_slice(data);
+        let flush = match self.mode {
+            BufMode::No => true,
+            BufMode::Line => data.contains(&b'\n'),
+            BufMode::Full => self.wbuf.len() >= self.size,
+        };
+        if flush {
+            self.flush_buffer()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the buffered output.
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        if self.wbuf.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut self.wbuf);
+        let stream = self.stream()?;
+        block_on(async {
+            stream.write_all(&data).await?;
+            stream.flush().await
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buffer()?;
+        block_on(self.stream()?.flush())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_buffer()?;
+        let pos = match pos {
+            SeekFrom::Current(n) => SeekFrom::Current(n - (self.rbuf.len() - self.rpos) as i64),
+            pos => pos,
+        };
+        let pos = block_on(self.stream()?.seek(pos))?;
+        self.rbuf.clear();
+        self.rpos = 0;
+        Ok(pos)
+    }
+
+    fn set_buffering(&mut self, mode: BufMode, size: usize) -> io::Result<()> {
+        self.flush_buffer()?;
+        self.mode = mode;
+        self.size = size.max(1);
+        Ok(())
+    }
+
+    /// Closes the stream, returning the exit status of a command.
+    fn close(&mut self) -> io::Result<Option<ExitStatus>> {
+        let flushed = self.flush_buffer();
+        let stream = self.stream.take();
+        let status = match stream {
+            Some(Stream::PipeIn(mut child, out)) => {
+                drop(out);
+                Some(block_on(child.wait())?)
+            }
+            Some(Stream::PipeOut(mut child, mut input)) => {
+                block_on(input.flush())?;
+                drop(input);
+                Some(block_on(child.wait())?)
+            }
+            Some(mut stream) => {
+                block_on(stream.flush())?;
+                None
+            }
+            None => None,
+        };
+        flushed.map(|_| status)
+    }
+}
+
+impl Drop for LuaFile {
+    // What `__gc` does for a file that is still open.
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Argument `n` as an open file, as `tofile`.
+fn to_file(args: &Args, n: usize) -> Result<RefMut<'_, LuaFile>, Error> {
+    if let Value::Userdata(u) = args.get(n) {
+        if let Some(f) = u.borrow_mut::<LuaFile>() {
+            if f.is_closed() {
+                return Err(Error::new("attempt to use a closed file"));
+            }
+            return Ok(f);
+        }
+    }
+    Err(args.type_error(n, FILE_HANDLE))
+}
+
+/// Checks that argument `n` is a file handle, open or closed.
+fn check_handle(args: &Args, n: usize) -> Result<Userdata, Error> {
+    match args.get(n) {
+        Value::Userdata(u) if u.is::<LuaFile>() => Ok(u.clone()),
+        _ => Err(args.type_error(n, FILE_HANDLE)),
+    }
+}
+
+/// The default input or output, as `getiofile`.
+fn get_io_file(state: &State, key: &str) -> Result<Value, Error> {
+    let v = state.registry().get_str(key);
+    let closed = match &v {
+        Value::Userdata(u) => u.borrow_mut::<LuaFile>().is_none_or(|f| f.is_closed()),
+        _ => true,
+    };
+    if closed {
+        let name = key.trim_start_matches("_IO_");
+        return Err(Error::new(format!("default {} file is closed", name)));
+    }
+    Ok(v)
+}
+
+fn io_file(v: &Value) -> RefMut<'_, LuaFile> {
+    match v {
+        Value::Userdata(u) => u.borrow_mut::<LuaFile>().unwrap(),
+        _ => unreachable!("not a file handle"),
+    }
+}
+
+/// Checks an `fopen` mode: `r`, `w` or `a`, then maybe `+`, then only `b`s.
+fn check_mode(mode: &[u8]) -> bool {
+    let rest = match mode.split_first() {
+        Some((b'r' | b'w' | b'a', rest)) => rest,
+        _ => return false,
+    };
+    let rest = rest.strip_prefix(b"+").unwrap_or(rest);
+    rest.iter().all(|&c| c == b'b')
+}
+
+async fn open_file(filename: &str, mode: &[u8]) -> io::Result<fs::File> {
+    let plus = mode.contains(&b'+');
+    let mut options = OpenOptions::new();
+    match mode[0] {
+        b'r' => options.read(true).write(plus),
+        b'w' => options.write(true).create(true).truncate(true).read(plus),
+        _ => options.append(true).create(true).read(plus),
+    };
+    options.open(filename).await
+}
+
+/// Opens a file or raises an error, as `opencheckfile`.
+fn open_check_file(state: &State, filename: &str, mode: &[u8]) -> Result<Value, Error> {
+    match block_on(open_file(filename, mode)) {
+        Ok(f) => Ok(state.new_file(LuaFile::new(Stream::File(f)))),
+        Err(e) => Err(Error::new(format!(
+            "cannot open file '{}' ({})",
+            filename,
+            strerror(&e)
+        ))),
+    }
+}
+
+fn io_open(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let filename = args.check_string(1)?.to_string();
+    let mode = args.opt_string(2)?.unwrap_or_else(|| "r".into());
+    if !check_mode(&mode) {
+        return Err(args.error(2, "invalid mode"));
+    }
+    match block_on(open_file(&filename, &mode)) {
+        Ok(f) => Ok(vec![state.new_file(LuaFile::new(Stream::File(f)))]),
+        Err(e) => Ok(file_result(&e, Some(&filename))),
+    }
+}
+
+fn io_popen(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let prog = args.check_string(1)?.to_string();
+    let mode = args.opt_string(2)?.unwrap_or_else(|| "r".into());
+    let read = match mode.as_bytes() {
+        b"r" => true,
+        b"w" => false,
+        _ => return Err(args.error(2, "invalid mode")),
+    };
+    let mut command = shell_command(&prog);
+    if read {
+        command.stdout(Stdio::piped());
+    } else {
+        command.stdin(Stdio::piped());
+    }
+    let spawned = block_on(async { command.spawn() });
+    let stream = spawned.map(|mut child| {
+        if read {
+            let out = child.stdout.take().unwrap();
+            Stream::PipeIn(child, out)
+        } else {
+            let input = child.stdin.take().unwrap();
+            Stream::PipeOut(child, input)
+        }
+    });
+    match stream {
+        Ok(stream) => Ok(vec![state.new_file(LuaFile::new(stream))]),
+        Err(e) => Ok(file_result(&e, Some(&prog))),
+    }
+}
+
+fn io_tmpfile(state: &State, _: Args) -> Result<Vec<Value>, Error> {
+    let name = temp_name();
+    let opened = block_on(async {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&name)
+            .await?;
+        // The file lives on while open; where that is not allowed it stays.
+        let _ = fs::remove_file(&name).await;
+        Ok::<_, io::Error>(f)
+    });
+    match opened {
+        Ok(f) => Ok(vec![state.new_file(LuaFile::new(Stream::File(f)))]),
+        Err(e) => Ok(file_result(&e, None)),
+    }
+}
+
+fn io_type(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    args.check_any(1)?;
+    Ok(vec![match args.get(1) {
+        Value::Userdata(u) => match u.borrow_mut::<LuaFile>() {
+            Some(f) if f.is_closed() => "closed file".into(),
+            Some(_) => "file".into(),
+            None => Value::Nil,
+        },
+        _ => Value::Nil,
+    }])
+}
+
+/// Sets the default input or output from argument 1, then returns it.
+fn io_file_arg(state: &State, args: &Args, key: &str, mode: &[u8]) -> Result<Vec<Value>, Error> {
+    match args.get(1) {
+        Value::Nil => {}
+        Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+            let filename = args.get(1).to_lua_string().unwrap().to_string();
+            let file = open_check_file(state, &filename, mode)?;
+            state.registry().set_str(key, file);
+        }
+        v => {
+            to_file(args, 1)?;
+            state.registry().set_str(key, v.clone());
+        }
+    }
+    Ok(vec![state.registry().get_str(key)])
+}
+
+fn io_input(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    io_file_arg(state, &args, IO_INPUT, b"r")
+}
+
+fn io_output(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    io_file_arg(state, &args, IO_OUTPUT, b"w")
+}
+
+/// Closes the file, as `aux_close`.
+fn aux_close(file: &mut LuaFile) -> Vec<Value> {
+    if file.standard {
+        return vec![Value::Nil, "cannot close standard file".into()];
+    }
+    match file.close() {
+        Ok(Some(status)) => exec_result(status),
+        Ok(None) => vec![Value::Boolean(true)],
+        Err(e) => file_result(&e, None),
+    }
+}
+
+fn io_close(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if args.is_given(1) {
+        return f_close(state, args);
+    }
+    let output = state.registry().get_str(IO_OUTPUT);
+    f_close(state, Args::new("close", vec![output]))
+}
+
+fn f_close(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut file = to_file(&args, 1)?;
+    Ok(aux_close(&mut file))
+}
+
+fn f_gc(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let u = check_handle(&args, 1)?;
+    let mut file = u.borrow_mut::<LuaFile>().unwrap();
+    if !file.is_closed() {
+        aux_close(&mut file);
+    }
+    Ok(vec![])
+}
+
+fn f_tostring(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let u = check_handle(&args, 1)?;
+    let closed = u.borrow_mut::<LuaFile>().unwrap().is_closed();
+    let s = if closed {
+        "file (closed)".to_string()
+    } else {
+        format!("file ({:p})", u.as_ptr())
+    };
+    Ok(vec![s.as_str().into()])
+}
+
+fn flush_result(file: &mut LuaFile) -> Vec<Value> {
+    match file.flush() {
+        Ok(()) => vec![Value::Boolean(true)],
+        Err(e) => file_result(&e, None),
+    }
+}
+
+fn io_flush(state: &State, _: Args) -> Result<Vec<Value>, Error> {
+    let output = get_io_file(state, IO_OUTPUT)?;
+    let mut file = io_file(&output);
+    Ok(flush_result(&mut file))
+}
+
+fn f_flush(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut file = to_file(&args, 1)?;
+    Ok(flush_result(&mut file))
+}
+
+fn io_read(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let input = get_io_file(state, IO_INPUT)?;
+    let mut file = io_file(&input);
+    g_read(&mut file, &args, 1)
+}
+
+fn f_read(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut file = to_file(&args, 1)?;
+    g_read(&mut file, &args, 2)
+}
+
+/// Reads with the formats in `args` from `first` on, as `g_read`. A failed
+/// read ends the results with fail.
+fn g_read(file: &mut LuaFile, args: &Args, first: usize) -> Result<Vec<Value>, Error> {
+    let mut results = vec![];
+    let outcome = (|| -> Result<bool, ReadError> {
+        if args.len() < first {
+            return Ok(read_line(file, true, &mut results)?);
+        }
+        for n in first..=args.len() {
+            let success = match args.get(n) {
+                Value::Integer(_) | Value::Number(_) => {
+                    let count = args.check_integer(n)? as u64;
+                    if count == 0 {
+                        test_eof(file, &mut results)?
+                    } else {
+                        read_chars(file, count, &mut results)?
+                    }
+                }
+                _ => {
+                    let format = args.check_string(n)?;
+                    let format = format.strip_prefix(b"*").unwrap_or(&format);
+                    match format.first() {
+                        Some(b'n') => read_number(file, &mut results)?,
+                        Some(b'l') => read_line(file, true, &mut results)?,
+                        Some(b'L') => read_line(file, false, &mut results)?,
+                        Some(b'a') => {
+                            read_all(file, &mut results)?;
+                            true
+                        }
+                        _ => return Err(args.error(n, "invalid format").into()),
+                    }
+                }
+            };
+            if !success {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })();
+    match outcome {
+        Ok(true) => Ok(results),
+        Ok(false) => {
+            *results.last_mut().unwrap() = Value::Nil;
+            Ok(results)
+        }
+        Err(ReadError::Lua(e)) => Err(e),
+        Err(ReadError::Io(e)) => Ok(file_result(&e, None)),
+    }
+}
+
+/// Reading stops on a bad format argument as well as on an I/O error.
+enum ReadError {
+    Lua(Error),
+    Io(io::Error),
+}
+
+impl From<Error> for ReadError {
+    fn from(e: Error) -> Self {
+        ReadError::Lua(e)
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+fn read_line(file: &mut LuaFile, chop: bool, results: &mut Vec<Value>) -> io::Result<bool> {
+    let mut line = vec![];
+    let mut newline = false;
+    while let Some(c) = file.getc()? {
+        if c == b'\n' {
+            newline = true;
+            if !chop {
+                line.push(c);
+            }
+            break;
+        }
+        line.push(c);
+    }
+    let success = newline || !line.is_empty();
+    results.push(Value::String(line.into()));
+    Ok(success)
+}
+
+fn read_all(file: &mut LuaFile, results: &mut Vec<Value>) -> io::Result<()> {
+    let mut all = file.rbuf.split_off(file.rpos);
+    file.rbuf.clear();
+    file.rpos = 0;
+    while let Some(c) = file.getc()? {
+        all.push(c);
+        all.extend_from_slice(&file.rbuf[file.rpos..]);
+        file.rpos = file.rbuf.len();
+    }
+    results.push(Value::String(all.into()));
+    Ok(())
+}
+
+fn read_chars(file: &mut LuaFile, count: u64, results: &mut Vec<Value>) -> io::Result<bool> {
+    let mut chars = vec![];
+    while (chars.len() as u64) < count {
+        match file.getc()? {
+            Some(c) => chars.push(c),
+            None => break,
+        }
+        let want = (count - chars.len() as u64).min((file.rbuf.len() - file.rpos) as u64);
+        let end = file.rpos + want as usize;
+        chars.extend_from_slice(&file.rbuf[file.rpos..end]);
+        file.rpos = end;
+    }
+    let success = !chars.is_empty();
+    results.push(Value::String(chars.into()));
+    Ok(success)
+}
+
+fn test_eof(file: &mut LuaFile, results: &mut Vec<Value>) -> io::Result<bool> {
+    let success = match file.getc()? {
+        Some(_) => {
+            file.ungetc();
+            true
+        }
+        None => false,
+    };
+    results.push("".into());
+    Ok(success)
+}
+
+/// Reads the longest prefix of a numeral, then converts it, as
+/// `read_number`.
+fn read_number(file: &mut LuaFile, results: &mut Vec<Value>) -> io::Result<bool> {
+    struct Rn<'a> {
+        file: &'a mut LuaFile,
+        c: Option<u8>,
+        buf: Vec<u8>,
+        overflow: bool,
+    }
+    impl Rn<'_> {
+        fn next(&mut self) -> io::Result<bool> {
+            if self.buf.len() >= MAX_LEN_NUM {
+                self.overflow = true;
+                return Ok(false);
+            }
+            self.buf.push(self.c.unwrap());
+            self.c = self.file.getc()?;
+            Ok(true)
+        }
+
+        fn test2(&mut self, set: &[u8; 2]) -> io::Result<bool> {
+            match self.c {
+                Some(c) if set.contains(&c) => self.next(),
+                _ => Ok(false),
+            }
+        }
+
+        fn digits(&mut self, hex: bool) -> io::Result<usize> {
+            let mut count = 0;
+            while self.c.is_some_and(|c| {
+                if hex {
+                    c.is_ascii_hexdigit()
+                } else {
+                    c.is_ascii_digit()
+                }
+            }) && self.next()?
+            {
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    let mut c = file.getc()?;
+    while c.is_some_and(|c| c.is_ascii_whitespace() || c == 0x0B) {
+        c = file.getc()?;
+    }
+    let mut rn = Rn {
+        file,
+        c,
+        buf: vec![],
+        overflow: false,
+    };
+    let mut count = 0;
+    let mut hex = false;
+    rn.test2(b"-+")?;
+    if rn.test2(b"00")? {
+        if rn.test2(b"xX")? {
+            hex = true;
+        } else {
+            count = 1;
+        }
+    }
+    count += rn.digits(hex)?;
+    if rn.test2(b"..")? {
+        count += rn.digits(hex)?;
+    }
+    if count > 0 && rn.test2(if hex { b"pP" } else { b"eE" })? {
+        rn.test2(b"-+")?;
+        rn.digits(false)?;
+    }
+    if rn.c.is_some() {
+        rn.file.ungetc();
+    }
+    let number = if rn.overflow {
+        None
+    } else {
+        str2number(&rn.buf)
+    };
+    match number {
+        Some(n) => {
+            results.push(n.into());
+            Ok(true)
+        }
+        None => {
+            results.push(Value::Nil);
+            Ok(false)
+        }
+    }
+}
+
+fn io_write(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let output = get_io_file(state, IO_OUTPUT)?;
+    let result = g_write(&mut io_file(&output), &args, 1)?;
+    Ok(result.unwrap_or_else(|| vec![output.clone()]))
+}
+
+fn f_write(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let result = g_write(&mut *to_file(&args, 1)?, &args, 2)?;
+    Ok(result.unwrap_or_else(|| vec![args.get(1).clone()]))
+}
+
+/// Writes the arguments from `first` on, as `g_write`. Returns the failure
+/// results, or `None` to return the file.
+fn g_write(file: &mut LuaFile, args: &Args, first: usize) -> Result<Option<Vec<Value>>, Error> {
+    let mut status = Ok(());
+    for n in first..=args.len() {
+        let data = match args.get(n) {
+            Value::Integer(i) => i.to_string().into_bytes(),
+            Value::Number(x) => number::format_g(*x, 14, false).into_bytes(),
+            _ => args.check_string(n)?.to_vec(),
+        };
+        if status.is_ok() {
+            status = file.write(&data);
+        }
+    }
+    Ok(status.err().map(|e| file_result(&e, None)))
+}
+
+fn f_seek(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut file = to_file(&args, 1)?;
+    let whence = args.check_option(2, Some("cur"), &["set", "cur", "end"])?;
+    let offset = args.opt_integer(3, 0)?;
+    let pos = match whence {
+        0 if offset < 0 => Err(io::Error::from_raw_os_error(EINVAL)),
+        0 => Ok(SeekFrom::Start(offset as u64)),
+        1 => Ok(SeekFrom::Current(offset)),
+        _ => Ok(SeekFrom::End(offset)),
+    };
+    match pos.and_then(|pos| file.seek(pos)) {
+        Ok(pos) => Ok(vec![Value::Integer(pos as i64)]),
+        Err(e) => Ok(file_result(&e, None)),
+    }
+}
+
+fn f_setvbuf(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut file = to_file(&args, 1)?;
+    let mode = match args.check_option(2, None, &["no", "full", "line"])? {
+        0 => BufMode::No,
+        1 => BufMode::Full,
+        _ => BufMode::Line,
+    };
+    let size = args.opt_integer(3, BUFFER_SIZE)?;
+    match file.set_buffering(mode, size as usize) {
+        Ok(()) => Ok(vec![Value::Boolean(true)]),
+        Err(e) => Ok(file_result(&e, None)),
+    }
+}
+
+fn io_lines(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut values = args.into_vec();
+    if values.is_empty() {
+        values.push(Value::Nil);
+    }
+    let to_close = !values[0].is_nil();
+    if to_close {
+        let args = Args::new("lines", values);
+        let filename = args.check_string(1)?.to_string();
+        values = args.into_vec();
+        values[0] = open_check_file(state, &filename, b"r")?;
+    } else {
+        values[0] = state.registry().get_str(IO_INPUT);
+        to_file(&Args::new("lines", values.clone()), 1)?;
+    }
+    let file = values[0].clone();
+    let iter = aux_lines(Args::new("lines", values), to_close)?;
+    if to_close {
+        Ok(vec![iter, Value::Nil, Value::Nil, file])
+    } else {
+        Ok(vec![iter])
+    }
+}
+
+fn f_lines(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    to_file(&args, 1)?;
+    Ok(vec![aux_lines(args, false)?])
+}
+
+/// The iterator reading the file in argument 1 with the formats after it,
+/// as `aux_lines`. It closes the file at the end if `to_close`.
+fn aux_lines(args: Args, to_close: bool) -> Result<Value, Error> {
+    if args.len() - 1 > MAX_ARG_LINE {
+        return Err(args.error(MAX_ARG_LINE + 2, "too many arguments"));
+    }
+    let values = args.into_vec();
+    let iter = Function::native("lines", move |_, _| {
+        let Value::Userdata(u) = &values[0] else {
+            unreachable!("not a file handle")
+        };
+        let mut file = u.borrow_mut::<LuaFile>().unwrap();
+        if file.is_closed() {
+            return Err(Error::new("file is already closed"));
+        }
+        let results = g_read(&mut file, &Args::new("lines", values.clone()), 2)?;
+        if results[0].is_truthy() {
+            return Ok(results);
+        }
+        if results.len() > 1 {
+            if let Value::String(msg) = &results[1] {
+                return Err(Error::new(msg.clone()));
+            }
+        }
+        if to_close {
+            aux_close(&mut file);
+        }
+        Ok(vec![])
+    });
+    Ok(iter.into())
+}
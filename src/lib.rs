@@ -12,16 +12,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod args;
+mod assemble;
+mod baselib;
 mod bytecode;
+mod cfg;
 #[allow(dead_code)]
 mod closure;
 mod constants;
+mod debug;
+mod decompile;
+mod dot;
+mod dump;
 #[allow(dead_code)]
 mod instruction;
+mod iolib;
+mod listing;
+mod loadlib;
+mod lpattern;
+mod mathlib;
+mod number;
+mod op;
 #[allow(dead_code)]
 pub mod opcode;
+mod oslib;
+mod pack;
 #[allow(dead_code)]
 mod proto;
+mod state;
+mod string;
+mod strlib;
+mod table;
+mod tablib;
+mod userdata;
+mod utf8lib;
+mod value;
+mod verify;
 
-pub use bytecode::undump;
+pub use args::Args;
+pub use assemble::assemble;
+pub use bytecode::{undump, undump_lazy};
+pub use cfg::{BasicBlock, Cfg, Loop};
 pub use closure::Closure;
+pub use debug::{NameKind, ObjectName};
+pub use decompile::Decompiled;
+pub use dot::{CfgDot, ProtoTreeDot};
+pub use dump::dump;
+pub use instruction::Instruction;
+pub use listing::Listing;
+pub use number::Number;
+pub use op::Op;
+pub use proto::{LazyProto, LocVar, Proto};
+pub use state::{NativeFnPtr, State};
+pub use string::{Interner, LuaString};
+pub use table::Table;
+pub use userdata::Userdata;
+pub use value::{Error, Function, NativeFn, NativeFunction, Value};
@@ -0,0 +1,365 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A disassembler whose output follows `luac -l` (and `luac -l -l` when
+//! `full`), so that listings of the same chunk can be diffed.
+
+use std::fmt::{Display, Formatter, Result};
+
+use crate::{
+    constants::{ESC_LUA, TM_NAMES},
+    instruction::{Instruction, MAXARG_C, OFFSET_SC},
+    number::fmt_number,
+    opcode::*,
+    proto::{Constant, Proto},
+    string::LuaString,
+};
+
+/// Lists `proto` and, recursively, its nested protos which have been loaded.
+pub struct Listing<'a> {
+    proto: &'a Proto,
+    full: bool,
+}
+
+impl<'a> Listing<'a> {
+    pub fn new(proto: &'a Proto, full: bool) -> Self {
+        Self { proto, full }
+    }
+
+    fn write_function(&self, f: &mut Formatter<'_>, p: &Proto) -> Result {
+        write_header(f, p)?;
+        write_code(f, p)?;
+        if self.full {
+            write_debug(f, p)?;
+        }
+        for nested in p.protos.iter().filter_map(|p| p.get()) {
+            self.write_function(f, nested)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Listing<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.write_function(f, self.proto)
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// Describes a proto as the first line of its listing does, e.g.
+/// `function <test.lua:2,4>`.
+pub(crate) fn describe(p: &Proto) -> String {
+    let source = match p.source.as_ref().map(LuaString::as_bytes) {
+        None => "?".into(),
+        Some([b'@' | b'=', name @ ..]) => String::from_utf8_lossy(name),
+        Some(s) if s.starts_with(&ESC_LUA[..1]) => "(bstring)".into(),
+        Some(_) => "(string)".into(),
+    };
+    format!(
+        "{} <{}:{},{}>",
+        if p.linedefined == 0 {
+            "main"
+        } else {
+            "function"
+        },
+        source,
+        p.linedefined,
+        p.lastlinedefined,
+    )
+}
+
+fn write_header(f: &mut Formatter<'_>, p: &Proto) -> Result {
+    writeln!(
+        f,
+        "\n{} ({} instruction{} at {:p})",
+        describe(p),
+        p.code.len(),
+        plural(p.code.len()),
+        p,
+    )?;
+    writeln!(
+        f,
+        "{}{} param{}, {} slot{}, {} upvalue{}, {} local{}, {} constant{}, {} function{}",
+        p.numparams,
+        if p.is_vararg != 0 { "+" } else { "" },
+        plural(p.numparams as usize),
+        p.maxstacksize,
+        plural(p.maxstacksize as usize),
+        p.upvalues.len(),
+        plural(p.upvalues.len()),
+        p.locvars.len(),
+        plural(p.locvars.len()),
+        p.constants.len(),
+        plural(p.constants.len()),
+        p.protos.len(),
+        plural(p.protos.len()),
+    )
+}
+
+fn write_debug(f: &mut Formatter<'_>, p: &Proto) -> Result {
+    writeln!(f, "constants ({}) for {:p}:", p.constants.len(), p)?;
+    for (i, k) in p.constants.iter().enumerate() {
+        let t = match k {
+            Constant::Nil => "N",
+            Constant::Boolean(_) => "B",
+            Constant::Number(_) => "F",
+            Constant::Integer(_) => "I",
+            Constant::String(_) => "S",
+        };
+        writeln!(f, "\t{}\t{}\t{}", i, t, ConstantDisplay(k))?;
+    }
+    writeln!(f, "locals ({}) for {:p}:", p.locvars.len(), p)?;
+    for (i, v) in p.locvars.iter().enumerate() {
+        writeln!(
+            f,
+            "\t{}\t{}\t{}\t{}",
+            i,
+            name_or_dash(&v.varname),
+            v.startpc + 1,
+            v.endpc + 1
+        )?;
+    }
+    writeln!(f, "upvalues ({}) for {:p}:", p.upvalues.len(), p)?;
+    for (i, u) in p.upvalues.iter().enumerate() {
+        writeln!(
+            f,
+            "\t{}\t{}\t{}\t{}",
+            i,
+            name_or_dash(&u.name),
+            u.instack,
+            u.idx
+        )?;
+    }
+    Ok(())
+}
+
+fn name_or_dash(name: &Option<LuaString>) -> String {
+    match name {
+        Some(name) => name.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn write_code(f: &mut Formatter<'_>, p: &Proto) -> Result {
+    for pc in 0..p.code.len() {
+        write!(f, "\t{}\t", pc + 1)?;
+        match p.line_for_pc(pc) {
+            Some(line) if line > 0 => write!(f, "[{}]\t", line)?,
+            _ => write!(f, "[-]\t")?,
+        }
+        writeln!(f, "{}", InstructionDisplay::new(p, pc))?;
+    }
+    Ok(())
+}
+
+/// Displays the instruction at `pc` with its operands and annotations as
+/// `luac -l` does, without the pc and line columns.
+pub(crate) struct InstructionDisplay<'a> {
+    proto: &'a Proto,
+    pc: usize,
+}
+
+impl<'a> InstructionDisplay<'a> {
+    pub(crate) fn new(proto: &'a Proto, pc: usize) -> Self {
+        Self { proto, pc }
+    }
+}
+
+impl Display for InstructionDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let i = self.proto.code[self.pc];
+        write!(f, "{:<9}\t", i.opname())?;
+        write_instruction(f, self.proto, self.pc, i)
+    }
+}
+
+fn write_instruction(f: &mut Formatter<'_>, p: &Proto, pc: usize, i: Instruction) -> Result {
+    let (a, k, b, c) = i.abc();
+    let (_, bx) = i.a_bx();
+    let (_, sbx) = i.a_sbx();
+    let sb = b - OFFSET_SC;
+    let sc = c - OFFSET_SC;
+    let isk = if k != 0 { "k" } else { "" };
+    let extraarg = || p.code.get(pc + 1).map_or(0, |i| i.ax());
+    let upvalue =
+        |idx: isize| name_or_dash(&p.upvalues.get(idx as usize).and_then(|u| u.name.clone()));
+    let constant = |idx: isize| match p.constants.get(idx as usize) {
+        Some(k) => ConstantDisplay(k).to_string(),
+        None => "?".to_string(),
+    };
+    let event = |c: isize| TM_NAMES.get(c as usize).copied().unwrap_or("?");
+    match i.opcode() {
+        OP_MOVE | OP_UNM | OP_BNOT | OP_NOT | OP_LEN | OP_CONCAT => write!(f, "{} {}", a, b),
+        OP_LOADI | OP_LOADF => write!(f, "{} {}", a, sbx),
+        OP_LOADK => write!(f, "{} {}\t; {}", a, bx, constant(bx)),
+        OP_LOADKX => write!(f, "{}\t; {}", a, constant(extraarg())),
+        OP_LOADFALSE | OP_LFALSESKIP | OP_LOADTRUE | OP_CLOSE | OP_TBC | OP_RETURN1
+        | OP_VARARGPREP => write!(f, "{}", a),
+        OP_LOADNIL => write!(f, "{} {}\t; {} out", a, b, b + 1),
+        OP_GETUPVAL | OP_SETUPVAL => write!(f, "{} {}\t; {}", a, b, upvalue(b)),
+        OP_GETTABUP => write!(f, "{} {} {}\t; {} {}", a, b, c, upvalue(b), constant(c)),
+        OP_GETTABLE | OP_GETI => write!(f, "{} {} {}", a, b, c),
+        OP_GETFIELD => write!(f, "{} {} {}\t; {}", a, b, c, constant(c)),
+        OP_SETTABUP => {
+            write!(
+                f,
+                "{} {} {}{}\t; {} {}",
+                a,
+                b,
+                c,
+                isk,
+                upvalue(a),
+                constant(b)
+            )?;
+            if k != 0 {
+                write!(f, " {}", constant(c))?;
+            }
+            Ok(())
+        }
+        OP_SETTABLE | OP_SETI | OP_SELF => {
+            write!(f, "{} {} {}{}", a, b, c, isk)?;
+            if k != 0 {
+                write!(f, "\t; {}", constant(c))?;
+            }
+            Ok(())
+        }
+        OP_SETFIELD => {
+            write!(f, "{} {} {}{}\t; {}", a, b, c, isk, constant(b))?;
+            if k != 0 {
+                write!(f, " {}", constant(c))?;
+            }
+            Ok(())
+        }
+        OP_NEWTABLE => write!(
+            f,
+            "{} {} {}\t; {}",
+            a,
+            b,
+            c,
+            c + extraarg() * (MAXARG_C + 1)
+        ),
+        OP_ADDI | OP_SHRI | OP_SHLI => write!(f, "{} {} {}", a, b, sc),
+        OP_ADDK | OP_SUBK | OP_MULK | OP_MODK | OP_POWK | OP_DIVK | OP_IDIVK | OP_BANDK
+        | OP_BORK | OP_BXORK => write!(f, "{} {} {}\t; {}", a, b, c, constant(c)),
+        OP_ADD | OP_SUB | OP_MUL | OP_MOD | OP_POW | OP_DIV | OP_IDIV | OP_BAND | OP_BOR
+        | OP_BXOR | OP_SHL | OP_SHR => write!(f, "{} {} {}", a, b, c),
+        OP_MMBIN => write!(f, "{} {} {}\t; {}", a, b, c, event(c)),
+        OP_MMBINI => {
+            write!(f, "{} {} {} {}\t; {}", a, sb, c, k, event(c))?;
+            if k != 0 {
+                write!(f, " flip")?;
+            }
+            Ok(())
+        }
+        OP_MMBINK => {
+            write!(f, "{} {} {} {}\t; {} {}", a, b, c, k, event(c), constant(b))?;
+            if k != 0 {
+                write!(f, " flip")?;
+            }
+            Ok(())
+        }
+        OP_JMP => write!(f, "{}\t; to {}", i.sj(), i.sj() + pc as isize + 2),
+        OP_EQ | OP_LT | OP_LE | OP_TESTSET => write!(f, "{} {} {}", a, b, k),
+        OP_EQK => write!(f, "{} {} {}\t; {}", a, b, k, constant(b)),
+        OP_EQI | OP_LTI | OP_LEI | OP_GTI | OP_GEI => write!(f, "{} {} {}", a, sb, k),
+        OP_TEST => write!(f, "{} {}", a, k),
+        OP_CALL => {
+            write!(f, "{} {} {}\t; ", a, b, c)?;
+            match b {
+                0 => write!(f, "all in ")?,
+                b => write!(f, "{} in ", b - 1)?,
+            }
+            match c {
+                0 => write!(f, "all out"),
+                c => write!(f, "{} out", c - 1),
+            }
+        }
+        OP_TAILCALL => write!(f, "{} {} {}{}\t; {} in", a, b, c, isk, b - 1),
+        OP_RETURN => {
+            write!(f, "{} {} {}{}\t; ", a, b, c, isk)?;
+            match b {
+                0 => write!(f, "all out"),
+                b => write!(f, "{} out", b - 1),
+            }
+        }
+        OP_RETURN0 => Ok(()),
+        OP_FORLOOP | OP_TFORLOOP => write!(f, "{} {}\t; to {}", a, bx, pc as isize - bx + 2),
+        OP_FORPREP => write!(f, "{} {}\t; exit to {}", a, bx, pc as isize + bx + 3),
+        OP_TFORPREP => write!(f, "{} {}\t; to {}", a, bx, pc as isize + bx + 2),
+        OP_TFORCALL => write!(f, "{} {}", a, c),
+        OP_SETLIST => {
+            write!(f, "{} {} {}", a, b, c)?;
+            if k != 0 {
+                write!(f, "\t; {}", c + extraarg() * (MAXARG_C + 1))?;
+            }
+            Ok(())
+        }
+        OP_CLOSURE => match p.protos.get(bx as usize).and_then(|p| p.get()) {
+            Some(nested) => write!(f, "{} {}\t; {:p}", a, bx, nested),
+            None => write!(f, "{} {}", a, bx),
+        },
+        OP_VARARG => {
+            write!(f, "{} {}\t; ", a, c)?;
+            match c {
+                0 => write!(f, "all out"),
+                c => write!(f, "{} out", c - 1),
+            }
+        }
+        OP_EXTRAARG => write!(f, "{}", i.ax()),
+        _ => Ok(()),
+    }
+}
+
+/// Displays a constant as `luac` does.
+struct ConstantDisplay<'a>(&'a Constant);
+
+impl Display for ConstantDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.0 {
+            Constant::Nil => write!(f, "nil"),
+            Constant::Boolean(b) => write!(f, "{}", b),
+            Constant::Number(n) => write!(f, "{}", fmt_number(*n)),
+            Constant::Integer(i) => write!(f, "{}", i),
+            Constant::String(s) => write_quoted(f, s.as_bytes()),
+        }
+    }
+}
+
+/// Writes `s` as a double-quoted Lua string literal.
+pub(crate) fn write_quoted(f: &mut Formatter<'_>, s: &[u8]) -> Result {
+    write!(f, "\"")?;
+    for &c in s {
+        match c {
+            b'"' => write!(f, "\\\"")?,
+            b'\\' => write!(f, "\\\\")?,
+            0x07 => write!(f, "\\a")?,
+            0x08 => write!(f, "\\b")?,
+            0x0C => write!(f, "\\f")?,
+            b'\n' => write!(f, "\\n")?,
+            b'\r' => write!(f, "\\r")?,
+            b'\t' => write!(f, "\\t")?,
+            0x0B => write!(f, "\\v")?,
+            0x20..=0x7E => write!(f, "{}", c as char)?,
+            c => write!(f, "\\{:03}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
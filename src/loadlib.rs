@@ -0,0 +1,301 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The package library, as `loadlib.c`.
+//!
+//! There is no `dlopen`: native modules are registered from Rust with
+//! [`State::register_module`] and found by the third searcher, in place of
+//! the C searchers. `package.cpath` is kept for scripts that read it. As
+//! with `load`, only precompiled Lua modules can be loaded.
+
+use std::fs;
+
+use crate::{
+    args::Args,
+    baselib::{closure_value, load_file},
+    state::State,
+    string::LuaString,
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+const PRELOAD_TABLE: &str = "_PRELOAD";
+/// The registry table of modules from [`State::register_module`].
+const NATIVE_TABLE: &str = "_NATIVE";
+
+const PATH_SEP: &str = ";";
+const PATH_MARK: &str = "?";
+const VERSION_SUFFIX: &str = "_5_4";
+
+#[cfg(not(windows))]
+const DIRSEP: &str = "/";
+#[cfg(not(windows))]
+const PATH_DEFAULT: &str = "/usr/local/share/lua/5.4/?.lua;/usr/local/share/lua/5.4/?/init.lua;\
+    /usr/local/lib/lua/5.4/?.lua;/usr/local/lib/lua/5.4/?/init.lua;./?.lua;./?/init.lua";
+#[cfg(not(windows))]
+const CPATH_DEFAULT: &str = "/usr/local/lib/lua/5.4/?.so;/usr/local/lib/lua/5.4/loadall.so;./?.so";
+
+#[cfg(windows)]
+const DIRSEP: &str = "\\";
+#[cfg(windows)]
+const PATH_DEFAULT: &str = "!\\lua\\?.lua;!\\lua\\?\\init.lua;!\\?.lua;!\\?\\init.lua;\
+    !\\..\\share\\lua\\5.4\\?.lua;!\\..\\share\\lua\\5.4\\?\\init.lua;.\\?.lua;.\\?\\init.lua";
+#[cfg(windows)]
+const CPATH_DEFAULT: &str = "!\\?.dll;!\\..\\lib\\lua\\5.4\\?.dll;!\\loadall.dll;.\\?.dll";
+
+const DLMSG: &str = "dynamic libraries not enabled; check your Lua installation";
+
+impl State {
+    /// Opens the package library and `require`.
+    pub fn open_package(&self) {
+        let package = Table::new();
+        self.register(
+            &package,
+            &[("loadlib", loadlib), ("searchpath", searchpath)],
+        );
+
+        let searchers = Table::new();
+        searchers.set_int(
+            1,
+            Function::native("searcher_preload", searcher_preload).into(),
+        );
+        let p = package.clone();
+        searchers.set_int(
+            2,
+            Function::native("searcher_lua", move |state, args| {
+                searcher_lua(state, &p, args)
+            })
+            .into(),
+        );
+        searchers.set_int(
+            3,
+            Function::native("searcher_native", searcher_native).into(),
+        );
+        package.set_str("searchers", searchers);
+
+        set_path(self, &package, "path", "LUA_PATH", PATH_DEFAULT);
+        set_path(self, &package, "cpath", "LUA_CPATH", CPATH_DEFAULT);
+        package.set_str(
+            "config",
+            format!("{}\n{}\n{}\n!\n-\n", DIRSEP, PATH_SEP, PATH_MARK).as_str(),
+        );
+        package.set_str("loaded", self.loaded());
+        package.set_str("preload", self.registry_table(PRELOAD_TABLE));
+
+        let p = package.clone();
+        self.globals().set_str(
+            "require",
+            Function::native("require", move |state, args| require(state, &p, args)),
+        );
+        self.set_library("package", package);
+    }
+
+    /// Registers `open` as native module `name`, in place of a C library
+    /// that `dlopen` would load. `require` finds it once the package library
+    /// is open and calls it with the name and `":native:"`.
+    pub fn register_module(
+        &self,
+        name: &'static str,
+        open: impl Fn(&State, Args) -> Result<Vec<Value>, Error> + 'static,
+    ) {
+        self.registry_table(NATIVE_TABLE)
+            .set_str(name, Function::native(name, open));
+    }
+}
+
+/// Sets `package[field]` from environment variable `env`, preferring its
+/// versioned name; a `;;` in it stands for `default`.
+fn set_path(state: &State, package: &Table, field: &str, env: &str, default: &str) {
+    let var = |name: &str| std::env::var_os(name).map(|v| v.to_string_lossy().into_owned());
+    let path = var(&format!("{}{}", env, VERSION_SUFFIX)).or_else(|| var(env));
+    let no_env = state.registry().get_str("LUA_NOENV").is_truthy();
+    let path = match path {
+        Some(path) if !no_env => match path.find(";;") {
+            None => path,
+            Some(i) => {
+                let mut b = String::new();
+                if i > 0 {
+                    b.push_str(&path[..i]);
+                    b.push_str(PATH_SEP);
+                }
+                b.push_str(default);
+                if i + 2 < path.len() {
+                    b.push_str(PATH_SEP);
+                    b.push_str(&path[i + 2..]);
+                }
+                b
+            }
+        },
+        _ => default.to_string(),
+    };
+    package.set_str(field, set_prog_dir(path).as_str());
+}
+
+/// Replaces `!` with the directory of the executable.
+#[cfg(windows)]
+fn set_prog_dir(path: String) -> String {
+    let dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_string_lossy().into_owned()));
+    match dir {
+        Some(dir) => path.replace('!', &dir),
+        None => path,
+    }
+}
+
+#[cfg(not(windows))]
+fn set_prog_dir(path: String) -> String {
+    path
+}
+
+/// Finds the first readable file for `name` in `path`, or lists the files
+/// tried, as `searchpath`.
+fn search_path(name: &str, path: &str, sep: &str, dirsep: &str) -> Result<String, String> {
+    let name = match sep.chars().next() {
+        Some(c) if name.contains(c) => name.replace(sep, dirsep),
+        _ => name.to_string(),
+    };
+    let path = path.replace(PATH_MARK, &name);
+    for filename in path.split(PATH_SEP).filter(|f| !f.is_empty()) {
+        if fs::File::open(filename).is_ok() {
+            return Ok(filename.to_string());
+        }
+    }
+    Err(format!(
+        "no file '{}'",
+        path.replace(PATH_SEP, "'\n\tno file '")
+    ))
+}
+
+fn searchpath(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let name = args.check_string(1)?.to_string();
+    let path = args.check_string(2)?.to_string();
+    let sep = args
+        .opt_string(3)?
+        .map_or(".".to_string(), |s| s.to_string());
+    let dirsep = args
+        .opt_string(4)?
+        .map_or(DIRSEP.to_string(), |s| s.to_string());
+    Ok(match search_path(&name, &path, &sep, &dirsep) {
+        Ok(filename) => vec![filename.as_str().into()],
+        Err(msg) => vec![Value::Nil, msg.as_str().into()],
+    })
+}
+
+/// Always fails, as `loadlib` built without dynamic library support.
+fn loadlib(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    args.check_string(1)?;
+    args.check_string(2)?;
+    Ok(vec![Value::Nil, DLMSG.into(), "absent".into()])
+}
+
+fn searcher_preload(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let name = args.check_string(1)?;
+    let preload = Value::Table(state.registry_table(PRELOAD_TABLE));
+    match state.index(&preload, &Value::String(name.clone()))? {
+        Value::Nil => {
+            let msg = format!("no field package.preload['{}']", name);
+            Ok(vec![msg.as_str().into()])
+        }
+        loader => Ok(vec![loader, ":preload:".into()]),
+    }
+}
+
+fn searcher_lua(state: &State, package: &Table, args: Args) -> Result<Vec<Value>, Error> {
+    let name = args.check_string(1)?.to_string();
+    let path = state
+        .index(&Value::Table(package.clone()), &"path".into())?
+        .to_lua_string()
+        .ok_or_else(|| Error::new("'package.path' must be a string"))?;
+    let filename = match search_path(&name, &path.to_string(), ".", DIRSEP) {
+        Ok(filename) => filename,
+        Err(msg) => return Ok(vec![msg.as_str().into()]),
+    };
+    match load_file(state, Some(&LuaString::from(filename.as_str())), b"bt") {
+        Ok(closure) => Ok(vec![closure_value(closure, None), filename.as_str().into()]),
+        Err(msg) => Err(Error::new(format!(
+            "error loading module '{}' from file '{}':\n\t{}",
+            name, filename, msg
+        ))),
+    }
+}
+
+fn searcher_native(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let name = args.check_string(1)?;
+    match state
+        .registry_table(NATIVE_TABLE)
+        .get(&Value::String(name.clone()))
+    {
+        Value::Nil => {
+            let msg = format!("no native module '{}'", name);
+            Ok(vec![msg.as_str().into()])
+        }
+        open => Ok(vec![open, ":native:".into()]),
+    }
+}
+
+/// Asks each of `package.searchers` for a loader of `name`, collecting
+/// their messages for the error if none has one.
+fn find_loader(state: &State, package: &Table, name: &LuaString) -> Result<(Value, Value), Error> {
+    let searchers = match state.index(&Value::Table(package.clone()), &"searchers".into())? {
+        Value::Table(t) => t,
+        _ => return Err(Error::new("'package.searchers' must be a table")),
+    };
+    let mut msg = vec![];
+    let mut i = 1;
+    loop {
+        let searcher = searchers.get_int(i);
+        if searcher.is_nil() {
+            let mut err = format!("module '{}' not found:", name).into_bytes();
+            err.extend_from_slice(&msg);
+            return Err(Error::new(err));
+        }
+        let mut results = state
+            .call(&searcher, vec![Value::String(name.clone())])?
+            .into_iter();
+        let loader = results.next().unwrap_or_default();
+        let data = results.next().unwrap_or_default();
+        match loader {
+            Value::Function(_) => return Ok((loader, data)),
+            Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                msg.extend_from_slice(b"\n\t");
+                msg.extend_from_slice(&loader.to_lua_string().unwrap());
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+fn require(state: &State, package: &Table, args: Args) -> Result<Vec<Value>, Error> {
+    let name = args.check_string(1)?;
+    let key = Value::String(name.clone());
+    let loaded = Value::Table(state.loaded());
+    let module = state.index(&loaded, &key)?;
+    if module.is_truthy() {
+        return Ok(vec![module]);
+    }
+    let (loader, data) = find_loader(state, package, &name)?;
+    let module = state.call1(&loader, vec![key.clone(), data.clone()])?;
+    if !module.is_nil() {
+        state.set_index(&loaded, key.clone(), module)?;
+    }
+    let mut module = state.index(&loaded, &key)?;
+    if module.is_nil() {
+        // The module set no value: it is loaded all the same.
+        module = Value::Boolean(true);
+        state.set_index(&loaded, key, module.clone())?;
+    }
+    Ok(vec![module, data])
+}
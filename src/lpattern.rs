@@ -0,0 +1,401 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lua pattern matching, a port of the backtracking matcher in `lstrlib.c`.
+//!
+//! Positions are byte offsets into the subject and the pattern.
+
+use crate::{
+    string::LuaString,
+    value::{Error, Value},
+};
+
+const MAXCAPTURES: usize = 32;
+/// Maximum recursion depth of [`Matcher::do_match`].
+const MAXCCALLS: usize = 200;
+const L_ESC: u8 = b'%';
+/// Characters that make a pattern more than a plain substring.
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+#[derive(Clone, Copy)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+pub(crate) struct Matcher<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    level: usize,
+    capture: [(usize, CaptureLen); MAXCAPTURES],
+    matchdepth: usize,
+}
+
+/// Whether `pat` has no magic characters, so it can be searched for as is.
+pub(crate) fn no_specials(pat: &[u8]) -> bool {
+    !pat.iter().any(|b| SPECIALS.contains(b))
+}
+
+/// Whether `c` is in the class `%cl`, as in the C locale.
+fn match_class(c: u8, cl: u8) -> bool {
+    let res = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == b'\x0B',
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return cl == c,
+    };
+    if cl.is_ascii_uppercase() {
+        !res
+    } else {
+        res
+    }
+}
+
+impl<'a> Matcher<'a> {
+    pub(crate) fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        Self {
+            src,
+            pat,
+            level: 0,
+            capture: [(0, CaptureLen::Unfinished); MAXCAPTURES],
+            matchdepth: MAXCCALLS,
+        }
+    }
+
+    /// Matches the pattern from `p` on against the subject at `s`, returning
+    /// where the match ends.
+    pub(crate) fn match_at(&mut self, s: usize, p: usize) -> Result<Option<usize>, Error> {
+        self.level = 0;
+        self.matchdepth = MAXCCALLS;
+        self.do_match(s, p)
+    }
+
+    /// The pattern byte at `p`, or 0 past its end as C's terminator.
+    fn pat_at(&self, p: usize) -> u8 {
+        self.pat.get(p).copied().unwrap_or(0)
+    }
+
+    fn class_end(&self, mut p: usize) -> Result<usize, Error> {
+        let c = self.pat[p];
+        p += 1;
+        match c {
+            L_ESC => {
+                if p == self.pat.len() {
+                    return Err(Error::new("malformed pattern (ends with '%')"));
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pat_at(p) == b'^' {
+                    p += 1;
+                }
+                // Look for a ']', the first one being part of the set.
+                loop {
+                    if p >= self.pat.len() {
+                        return Err(Error::new("malformed pattern (missing ']')"));
+                    }
+                    let c = self.pat[p];
+                    p += 1;
+                    if c == L_ESC && p < self.pat.len() {
+                        // Skip escapes such as '%]'.
+                        p += 1;
+                    }
+                    if self.pat_at(p) == b']' {
+                        break;
+                    }
+                }
+                Ok(p + 1)
+            }
+            _ => Ok(p),
+        }
+    }
+
+    /// Whether `c` is in the set `[...]` spanning `p..=ec`.
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut sig = true;
+        if self.pat_at(p + 1) == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.pat[p] == L_ESC {
+                p += 1;
+                if match_class(c, self.pat_at(p)) {
+                    return sig;
+                }
+            } else if self.pat_at(p + 1) == b'-' && p + 2 < ec {
+                p += 2;
+                if self.pat[p - 2] <= c && c <= self.pat[p] {
+                    return sig;
+                }
+            } else if self.pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let c = match self.src.get(s) {
+            Some(&c) => c,
+            None => return false,
+        };
+        match self.pat[p] {
+            b'.' => true,
+            L_ESC => match_class(c, self.pat_at(p + 1)),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, Error> {
+        if p + 1 >= self.pat.len() {
+            return Err(Error::new("malformed pattern (missing arguments to '%b')"));
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&b) {
+            return Ok(None);
+        }
+        let mut cont = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == b {
+                cont += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, Error> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        // Try with the maximum repetitions, then fewer and fewer.
+        loop {
+            if let Some(res) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(res));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, Error> {
+        loop {
+            if let Some(res) = self.do_match(s, ep + 1)? {
+                return Ok(Some(res));
+            } else if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        what: CaptureLen,
+    ) -> Result<Option<usize>, Error> {
+        if self.level >= MAXCAPTURES {
+            return Err(Error::new("too many captures"));
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.level -= 1;
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, Error> {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = CaptureLen::Len(s - self.capture[l].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.capture[l].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, Error> {
+        let l = self.check_capture(l)?;
+        let init = self.capture[l].0;
+        let len = match self.capture[l].1 {
+            CaptureLen::Len(len) => len,
+            // As in C, where its negative length never fits.
+            _ => return Ok(None),
+        };
+        let captured = &self.src[init..init + len];
+        Ok(self.src[s..].starts_with(captured).then(|| s + len))
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, Error> {
+        let l = l as i32 - b'1' as i32;
+        if l < 0
+            || l as usize >= self.level
+            || matches!(self.capture[l as usize].1, CaptureLen::Unfinished)
+        {
+            return Err(Error::new(format!(
+                "invalid capture index %{} in pattern",
+                l + 1
+            )));
+        }
+        Ok(l as usize)
+    }
+
+    fn capture_to_close(&self) -> Result<usize, Error> {
+        (0..self.level)
+            .rev()
+            .find(|&l| matches!(self.capture[l].1, CaptureLen::Unfinished))
+            .ok_or_else(|| Error::new("invalid pattern capture"))
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, Error> {
+        if self.matchdepth == 0 {
+            return Err(Error::new("pattern too complex"));
+        }
+        self.matchdepth -= 1;
+        let res = loop {
+            if p == self.pat.len() {
+                break Some(s);
+            }
+            match self.pat[p] {
+                b'(' => {
+                    break if self.pat_at(p + 1) == b')' {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pat.len() => {
+                    break (s == self.src.len()).then_some(s);
+                }
+                L_ESC if self.pat_at(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+                    Some(e) => {
+                        s = e;
+                        p += 4;
+                    }
+                    None => break None,
+                },
+                L_ESC if self.pat_at(p + 1) == b'f' => {
+                    p += 2;
+                    if self.pat_at(p) != b'[' {
+                        return Err(Error::new("missing '[' after '%f' in pattern"));
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(current, p, ep - 1)
+                    {
+                        p = ep;
+                    } else {
+                        break None;
+                    }
+                }
+                L_ESC if self.pat_at(p + 1).is_ascii_digit() => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(e) => {
+                            s = e;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let epc = self.pat_at(ep);
+                    if !self.single_match(s, p, ep) {
+                        if matches!(epc, b'*' | b'?' | b'-') {
+                            // Accept the empty match.
+                            p = ep + 1;
+                        } else {
+                            break None;
+                        }
+                    } else {
+                        match epc {
+                            b'?' => match self.do_match(s + 1, ep + 1)? {
+                                Some(res) => break Some(res),
+                                None => p = ep + 1,
+                            },
+                            b'+' => break self.max_expand(s + 1, p, ep)?,
+                            b'*' => break self.max_expand(s, p, ep)?,
+                            b'-' => break self.min_expand(s, p, ep)?,
+                            _ => {
+                                s += 1;
+                                p = ep;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        self.matchdepth += 1;
+        Ok(res)
+    }
+
+    /// Capture `i` of the match `s..e`; the whole match stands for the first
+    /// capture when the pattern has none.
+    pub(crate) fn capture(&self, i: usize, s: usize, e: usize) -> Result<Value, Error> {
+        if i >= self.level {
+            if i != 0 {
+                return Err(Error::new(format!("invalid capture index %{}", i + 1)));
+            }
+            return Ok(Value::String(LuaString::from(&self.src[s..e])));
+        }
+        let (init, len) = self.capture[i];
+        match len {
+            CaptureLen::Unfinished => Err(Error::new("unfinished capture")),
+            CaptureLen::Position => Ok(Value::Integer(init as i64 + 1)),
+            CaptureLen::Len(len) => Ok(Value::String(LuaString::from(&self.src[init..init + len]))),
+        }
+    }
+
+    /// All captures of the match `s..e`, or the whole match if there are
+    /// none and `whole_if_none` is set.
+    pub(crate) fn captures(
+        &self,
+        s: usize,
+        e: usize,
+        whole_if_none: bool,
+    ) -> Result<Vec<Value>, Error> {
+        let n = if self.level == 0 && whole_if_none {
+            1
+        } else {
+            self.level
+        };
+        (0..n).map(|i| self.capture(i, s, e)).collect()
+    }
+}
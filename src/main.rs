@@ -15,11 +15,47 @@
 use std::io::Cursor;
 
 use clap::Parser;
+use rua::Proto;
 use tokio::{fs::File, io::AsyncReadExt};
 
 #[derive(Parser, Debug)]
 struct Args {
     script: String,
+    /// Decode nested functions only when they are first needed.
+    #[clap(long)]
+    lazy: bool,
+    /// List the bytecode like `luac -l`; given twice, also list constants,
+    /// locals and upvalues.
+    #[clap(short = 'l', parse(from_occurrences))]
+    list: u64,
+    /// Print the control-flow graph of a function in DOT, where functions are
+    /// numbered in listing order from 0 for the main function.
+    #[clap(long, value_name = "FUNCTION")]
+    dot_cfg: Option<usize>,
+    /// Print the tree of nested functions in DOT.
+    #[clap(long)]
+    dot_protos: bool,
+    /// Print Lua source reconstructed from the bytecode.
+    #[clap(short, long)]
+    decompile: bool,
+    /// Assemble the script as bytecode text and write the chunk to OUTPUT.
+    #[clap(long, value_name = "OUTPUT")]
+    assemble: Option<String>,
+}
+
+/// Returns the `n`-th function of `proto` in listing order, counting `proto`
+/// itself as 0.
+fn nth_function<'a>(proto: &'a Proto, n: &mut usize) -> Option<&'a Proto> {
+    if *n == 0 {
+        return Some(proto);
+    }
+    for nested in proto.protos().iter().filter_map(|p| p.get()) {
+        *n -= 1;
+        if let Some(p) = nth_function(nested, n) {
+            return Some(p);
+        }
+    }
+    None
 }
 
 async fn slurp(filename: String) -> anyhow::Result<Cursor<Vec<u8>>> {
@@ -33,8 +69,36 @@ async fn slurp(filename: String) -> anyhow::Result<Cursor<Vec<u8>>> {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    if let Some(output) = args.assemble {
+        let text = tokio::fs::read_to_string(args.script).await?;
+        let proto = rua::assemble(&text)?;
+        tokio::fs::write(output, rua::dump(&proto)).await?;
+        return Ok(());
+    }
     let chunk = slurp(args.script).await?;
-    let closure = rua::undump(chunk).await?;
-    dbg!(closure);
+    let state = rua::State::new();
+    state.open_libs();
+    let closure = if args.lazy {
+        state.undump_lazy(chunk).await?
+    } else {
+        state.undump(chunk).await?
+    };
+    if let Some(mut n) = args.dot_cfg {
+        closure.proto().load_all().await?;
+        let proto = nth_function(closure.proto(), &mut n)
+            .ok_or_else(|| anyhow::anyhow!("no function {}", args.dot_cfg.unwrap()))?;
+        print!("{}", rua::CfgDot::new(proto));
+    } else if args.dot_protos {
+        closure.proto().load_all().await?;
+        print!("{}", rua::ProtoTreeDot::new(closure.proto()));
+    } else if args.decompile {
+        closure.proto().load_all().await?;
+        print!("{}", rua::Decompiled::new(closure.proto()));
+    } else if args.list > 0 {
+        closure.proto().load_all().await?;
+        print!("{}", rua::Listing::new(closure.proto(), args.list > 1));
+    } else {
+        dbg!(closure);
+    }
     Ok(())
 }
@@ -0,0 +1,338 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The math library, as `lmathlib.c`.
+
+use std::{
+    cell::Cell,
+    f64::consts::PI,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    args::Args,
+    number::{float_to_int, Number},
+    state::State,
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+impl State {
+    /// Opens the math library. Each opened library has its own generator
+    /// for `math.random`, randomly seeded.
+    pub fn open_math(&self) {
+        let math = Table::new();
+        self.register(
+            &math,
+            &[
+                ("abs", abs),
+                ("acos", acos),
+                ("asin", asin),
+                ("atan", atan),
+                ("ceil", ceil),
+                ("cos", cos),
+                ("exp", exp),
+                ("floor", floor),
+                ("fmod", fmod),
+                ("log", log),
+                ("max", max),
+                ("min", min),
+                ("modf", modf),
+                ("sin", sin),
+                ("sqrt", sqrt),
+                ("tan", tan),
+                ("tointeger", tointeger),
+                ("type", type_),
+                ("ult", ult),
+            ],
+        );
+        math.set_str("pi", PI);
+        math.set_str("huge", f64::INFINITY);
+        math.set_str("maxinteger", i64::MAX);
+        math.set_str("mininteger", i64::MIN);
+
+        let rng = Rc::new(Xoshiro::default());
+        rng.randomize(self);
+        let g = rng.clone();
+        math.set_str(
+            "random",
+            Function::native("random", move |_, args| random(&g, args)),
+        );
+        math.set_str(
+            "randomseed",
+            Function::native("randomseed", move |state, args| {
+                randomseed(state, &rng, args)
+            }),
+        );
+        self.set_library("math", math);
+    }
+}
+
+/// Pushes `x` as an integer if it has an exact integer value.
+fn float_or_int(x: f64) -> Value {
+    match float_to_int(x) {
+        Some(i) => Value::Integer(i),
+        None => Value::Number(x),
+    }
+}
+
+fn abs(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![match args.check_number(1)? {
+        Number::Integer(i) => Value::Integer(i.wrapping_abs()),
+        Number::Float(f) => Value::Number(f.abs()),
+    }])
+}
+
+fn floor(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if let Value::Integer(i) = args.get(1) {
+        return Ok(vec![Value::Integer(*i)]);
+    }
+    Ok(vec![float_or_int(args.check_float(1)?.floor())])
+}
+
+fn ceil(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if let Value::Integer(i) = args.get(1) {
+        return Ok(vec![Value::Integer(*i)]);
+    }
+    Ok(vec![float_or_int(args.check_float(1)?.ceil())])
+}
+
+fn fmod(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if let (Value::Integer(m), Value::Integer(d)) = (args.get(1), args.get(2)) {
+        return match d {
+            0 => Err(args.error(2, "zero")),
+            // Avoids the overflow of `mininteger % -1`.
+            -1 => Ok(vec![Value::Integer(0)]),
+            d => Ok(vec![Value::Integer(m % d)]),
+        };
+    }
+    let m = args.check_float(1)?;
+    let d = args.check_float(2)?;
+    Ok(vec![Value::Number(m % d)])
+}
+
+fn modf(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    if let Value::Integer(i) = args.get(1) {
+        return Ok(vec![Value::Integer(*i), Value::Number(0.0)]);
+    }
+    let n = args.check_float(1)?;
+    let ip = n.trunc();
+    // The test keeps the fraction of infinities at 0.
+    let frac = if n == ip { 0.0 } else { n - ip };
+    Ok(vec![Value::Number(ip), Value::Number(frac)])
+}
+
+fn unary(args: &Args, f: fn(f64) -> f64) -> Result<Vec<Value>, Error> {
+    Ok(vec![Value::Number(f(args.check_float(1)?))])
+}
+
+fn sqrt(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::sqrt)
+}
+
+fn exp(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::exp)
+}
+
+fn sin(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::sin)
+}
+
+fn cos(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::cos)
+}
+
+fn tan(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::tan)
+}
+
+fn asin(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::asin)
+}
+
+fn acos(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    unary(&args, f64::acos)
+}
+
+fn atan(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let y = args.check_float(1)?;
+    let x = args.opt_float(2, 1.0)?;
+    Ok(vec![Value::Number(y.atan2(x))])
+}
+
+fn log(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let x = args.check_float(1)?;
+    let res = if args.get(2).is_nil() {
+        x.ln()
+    } else {
+        let b = args.check_float(2)?;
+        if b == 2.0 {
+            x.log2()
+        } else if b == 10.0 {
+            x.log10()
+        } else {
+            x.ln() / b.ln()
+        }
+    };
+    Ok(vec![Value::Number(res)])
+}
+
+fn tointeger(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    match args.get(1).to_integer() {
+        Some(i) => Ok(vec![Value::Integer(i)]),
+        None => {
+            args.check_any(1)?;
+            Ok(vec![Value::Nil])
+        }
+    }
+}
+
+fn type_(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![match args.check_any(1)? {
+        Value::Integer(_) => "integer".into(),
+        Value::Number(_) => "float".into(),
+        _ => Value::Nil,
+    }])
+}
+
+fn ult(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let a = args.check_integer(1)?;
+    let b = args.check_integer(2)?;
+    Ok(vec![((a as u64) < (b as u64)).into()])
+}
+
+/// The argument `less` puts first, keeping its subtype.
+fn extreme(
+    args: &Args,
+    less: impl Fn(&Value, &Value) -> Result<bool, Error>,
+) -> Result<Vec<Value>, Error> {
+    if args.is_empty() {
+        return Err(args.error(1, "value expected"));
+    }
+    let mut best = 1;
+    args.check_number(1)?;
+    for i in 2..=args.len() {
+        args.check_number(i)?;
+        if less(args.get(i), args.get(best))? {
+            best = i;
+        }
+    }
+    Ok(vec![args.get(best).clone()])
+}
+
+fn max(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    extreme(&args, |a, b| state.less_than(b, a))
+}
+
+fn min(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    extreme(&args, |a, b| state.less_than(a, b))
+}
+
+/// The xoshiro256** generator of Lua 5.4, so that seeded sequences match the
+/// reference implementation.
+#[derive(Default)]
+struct Xoshiro {
+    s: Cell<[u64; 4]>,
+}
+
+impl Xoshiro {
+    fn next(&self) -> u64 {
+        let mut s = self.s.get();
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        self.s.set(s);
+        result
+    }
+
+    fn seed(&self, n1: u64, n2: u64) {
+        // The constant avoids a zero state.
+        self.s.set([n1, 0xff, n2, 0]);
+        // Discards the initial values to spread the seed.
+        for _ in 0..16 {
+            self.next();
+        }
+    }
+
+    /// Seeds from the current time and the address of `state`, as `randseed`.
+    fn randomize(&self, state: &State) -> (u64, u64) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let n1 = now.as_secs() ^ now.subsec_nanos() as u64;
+        let n2 = state as *const State as u64;
+        self.seed(n1, n2);
+        (n1, n2)
+    }
+
+    /// Projects `ran` into `0..=n` by rejection, so there is no bias.
+    fn project(&self, mut ran: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        // The smallest 2^b - 1 not smaller than n.
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            ran &= lim;
+            if ran <= n {
+                return ran;
+            }
+            ran = self.next();
+        }
+    }
+}
+
+/// Converts the top 53 bits of a random value to a float in `[0, 1)`.
+fn to_float(ran: u64) -> f64 {
+    (ran >> 11) as f64 * 2f64.powi(-53)
+}
+
+fn random(g: &Xoshiro, args: Args) -> Result<Vec<Value>, Error> {
+    let rv = g.next();
+    let (low, up) = match args.len() {
+        0 => return Ok(vec![Value::Number(to_float(rv))]),
+        1 => {
+            let up = args.check_integer(1)?;
+            if up == 0 {
+                return Ok(vec![Value::Integer(rv as i64)]);
+            }
+            (1, up)
+        }
+        2 => (args.check_integer(1)?, args.check_integer(2)?),
+        _ => return Err(Error::new("wrong number of arguments")),
+    };
+    if low > up {
+        return Err(args.error(1, "interval is empty"));
+    }
+    let p = g.project(rv, (up as u64).wrapping_sub(low as u64));
+    Ok(vec![Value::Integer(p.wrapping_add(low as u64) as i64)])
+}
+
+fn randomseed(state: &State, g: &Xoshiro, args: Args) -> Result<Vec<Value>, Error> {
+    let (n1, n2) = if args.is_given(1) {
+        let n1 = args.check_integer(1)? as u64;
+        let n2 = args.opt_integer(2, 0)? as u64;
+        g.seed(n1, n2);
+        (n1, n2)
+    } else {
+        g.randomize(state)
+    };
+    Ok(vec![Value::Integer(n1 as i64), Value::Integer(n2 as i64)])
+}
@@ -0,0 +1,274 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Formats a float the way Lua converts numbers to strings: `%.14g`, with
+/// `.0` appended when the result would otherwise read as an integer.
+pub fn fmt_number(x: f64) -> String {
+    let mut s = format_g(x, 14, false);
+    if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) {
+        s.push_str(".0");
+    }
+    s
+}
+
+/// C's `%.<precision>g`; `alt` is the `#` flag which keeps trailing zeros.
+pub fn format_g(x: f64, precision: usize, alt: bool) -> String {
+    if !x.is_finite() {
+        return format_non_finite(x);
+    }
+    let p = precision.max(1);
+    let exp = exponent(x, p - 1);
+    let mut s = if exp < -4 || exp >= p as i32 {
+        format_e(x, p - 1, alt)
+    } else {
+        format_f(x, (p as i32 - 1 - exp) as usize, alt)
+    };
+    if !alt {
+        s = strip_zeros(s);
+    }
+    s
+}
+
+/// C's `%.<precision>e`; `alt` is the `#` flag which keeps the decimal point.
+pub fn format_e(x: f64, precision: usize, alt: bool) -> String {
+    if !x.is_finite() {
+        return format_non_finite(x);
+    }
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let point = if alt && precision == 0 { "." } else { "" };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}{}e{}{:02}", mantissa, point, sign, exp.abs())
+}
+
+/// C's `%.<precision>f`; `alt` is the `#` flag which keeps the decimal point.
+pub fn format_f(x: f64, precision: usize, alt: bool) -> String {
+    if !x.is_finite() {
+        return format_non_finite(x);
+    }
+    let point = if alt && precision == 0 { "." } else { "" };
+    format!("{:.*}{}", precision, x, point)
+}
+
+/// C's `%a` as glibc prints it, with `precision` hexadecimal digits after
+/// the point, or as many as needed if `None`; `alt` is the `#` flag.
+pub fn format_a(x: f64, precision: Option<usize>, alt: bool) -> String {
+    if !x.is_finite() {
+        return format_non_finite(x);
+    }
+    let bits = x.to_bits();
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let biased = ((bits >> 52) & 0x7FF) as i32;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    let (mut lead, exp) = match (biased, mantissa) {
+        (0, 0) => (0, 0),
+        // Subnormals keep a leading 0 with the minimum exponent.
+        (0, _) => (0, -1022),
+        _ => (1, biased - 1023),
+    };
+    let digits = match precision {
+        None => {
+            let s = format!("{:013x}", mantissa);
+            s.trim_end_matches('0').to_string()
+        }
+        Some(p) if p < 13 => {
+            let shift = (13 - p) * 4;
+            let rem = mantissa & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            mantissa >>= shift;
+            if rem > half || (rem == half && mantissa & 1 == 1) {
+                mantissa += 1;
+                if mantissa >> (p * 4) != 0 {
+                    mantissa = 0;
+                    lead += 1;
+                }
+            }
+            if p == 0 {
+                String::new()
+            } else {
+                format!("{:0width$x}", mantissa, width = p)
+            }
+        }
+        Some(p) => format!("{:013x}{}", mantissa, "0".repeat(p - 13)),
+    };
+    let point = if !digits.is_empty() || alt { "." } else { "" };
+    format!("{}0x{}{}{}p{:+}", sign, lead, point, digits, exp)
+}
+
+fn format_non_finite(x: f64) -> String {
+    let s = if x.is_nan() { "nan" } else { "inf" };
+    if x.is_sign_negative() {
+        format!("-{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+/// The decimal exponent `x` has once rounded to `precision` digits after the
+/// point in scientific notation.
+fn exponent(x: f64, precision: usize) -> i32 {
+    let s = format!("{:.*e}", precision, x);
+    s.split_once('e').unwrap().1.parse().unwrap()
+}
+
+fn strip_zeros(s: String) -> String {
+    let (mantissa, exp) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s.as_str(), ""),
+    };
+    if !mantissa.contains('.') {
+        return s;
+    }
+    let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", mantissa, exp)
+}
+
+/// A number converted from a string, keeping Lua's integer/float subtypes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+/// Converts a numeral the way `lua_stringtonumber` does: surrounding spaces
+/// are allowed, hexadecimal integers wrap around and decimal ones that
+/// overflow become floats. `inf` and `nan` are not numerals.
+pub fn str2number(s: &[u8]) -> Option<Number> {
+    let s = trim_space(s);
+    if let Some(i) = str2int(s) {
+        return Some(Number::Integer(i));
+    }
+    if s.iter().any(|b| b.eq_ignore_ascii_case(&b'n')) {
+        return None;
+    }
+    let (negative, digits) = match s.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, s),
+    };
+    let x = if digits.len() > 1 && digits[0] == b'0' && digits[1].eq_ignore_ascii_case(&b'x') {
+        hex2float(&digits[2..])?
+    } else {
+        if !digits
+            .iter()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse::<f64>().ok()?
+    };
+    Some(Number::Float(if negative { -x } else { x }))
+}
+
+fn trim_space(s: &[u8]) -> &[u8] {
+    let is_space = |b: &u8| matches!(b, b' ' | b'\t' | b'\n' | b'\r' | 0x0B | 0x0C);
+    let start = s.iter().position(|b| !is_space(b)).unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|b| !is_space(b))
+        .map_or(start, |i| i + 1);
+    &s[start..end]
+}
+
+fn str2int(s: &[u8]) -> Option<i64> {
+    let (negative, digits) = match s.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, s),
+    };
+    let mut a = 0_i64;
+    if digits.len() > 2 && digits[0] == b'0' && digits[1].eq_ignore_ascii_case(&b'x') {
+        for b in &digits[2..] {
+            a = a
+                .wrapping_mul(16)
+                .wrapping_add((*b as char).to_digit(16)? as i64);
+        }
+    } else {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut overflow = false;
+        for b in digits {
+            let d = (*b as char).to_digit(10)? as i64;
+            match a.checked_mul(10).and_then(|a| a.checked_add(d)) {
+                Some(v) => a = v,
+                // -9223372036854775808 is still an integer.
+                None if negative && a == i64::MAX / 10 && d == 8 => a = i64::MIN,
+                None => overflow = true,
+            }
+        }
+        if overflow {
+            return None;
+        }
+        if a == i64::MIN {
+            return Some(a);
+        }
+    }
+    Some(if negative { a.wrapping_neg() } else { a })
+}
+
+/// Reads a hexadecimal float after its `0x` prefix, as C's `strtod` does.
+fn hex2float(s: &[u8]) -> Option<f64> {
+    let mut mantissa = 0_f64;
+    let mut exp = 0_i32;
+    let mut any = false;
+    let mut point = false;
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'.' if !point => point = true,
+            b => match (b as char).to_digit(16) {
+                Some(d) => {
+                    mantissa = mantissa * 16.0 + d as f64;
+                    any = true;
+                    if point {
+                        exp -= 4;
+                    }
+                }
+                None => break,
+            },
+        }
+        i += 1;
+    }
+    if !any {
+        return None;
+    }
+    if i < s.len() {
+        if !s[i].eq_ignore_ascii_case(&b'p') {
+            return None;
+        }
+        let e = std::str::from_utf8(&s[i + 1..]).ok()?;
+        if e.is_empty()
+            || !e
+                .trim_start_matches(['+', '-'])
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        exp = exp.saturating_add(e.parse::<i64>().ok()?.clamp(-100_000, 100_000) as i32);
+    }
+    Some(mantissa * 2_f64.powi(exp))
+}
+
+/// Converts a float with an exact integer value to that integer.
+pub fn float_to_int(x: f64) -> Option<i64> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    if x.floor() == x && (-LIMIT..LIMIT).contains(&x) {
+        Some(x as i64)
+    } else {
+        None
+    }
+}
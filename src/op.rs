@@ -0,0 +1,511 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+
+use crate::{
+    instruction::{Instruction, MAXARG_C, OFFSET_SC},
+    opcode::*,
+};
+
+/// A decoded instruction, with one variant per opcode carrying its operands.
+///
+/// Operands keep their Lua names: `sb`, `sc`, `sbx` and `sj` are signed,
+/// already corrected for their excess-K encoding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// R[A] := R[B]
+    Move { a: u8, b: u8 },
+    /// R[A] := sBx
+    LoadI { a: u8, sbx: i32 },
+    /// R[A] := (lua_Number)sBx
+    LoadF { a: u8, sbx: i32 },
+    /// R[A] := K[Bx]
+    LoadK { a: u8, bx: u32 },
+    /// R[A] := K[extra arg]
+    LoadKX { a: u8 },
+    /// R[A] := false
+    LoadFalse { a: u8 },
+    /// R[A] := false; pc++
+    LFalseSkip { a: u8 },
+    /// R[A] := true
+    LoadTrue { a: u8 },
+    /// R[A], R[A+1], ..., R[A+B] := nil
+    LoadNil { a: u8, b: u8 },
+    /// R[A] := UpValue[B]
+    GetUpval { a: u8, b: u8 },
+    /// UpValue[B] := R[A]
+    SetUpval { a: u8, b: u8 },
+    /// R[A] := UpValue[B][K[C]:string]
+    GetTabUp { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B][R[C]]
+    GetTable { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B][C]
+    GetI { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B][K[C]:string]
+    GetField { a: u8, b: u8, c: u8 },
+    /// UpValue[A][K[B]:string] := RK(C)
+    SetTabUp { a: u8, b: u8, c: u8, k: bool },
+    /// R[A][R[B]] := RK(C)
+    SetTable { a: u8, b: u8, c: u8, k: bool },
+    /// R[A][B] := RK(C)
+    SetI { a: u8, b: u8, c: u8, k: bool },
+    /// R[A][K[B]:string] := RK(C)
+    SetField { a: u8, b: u8, c: u8, k: bool },
+    /// R[A] := {}
+    NewTable { a: u8, b: u8, c: u8, k: bool },
+    /// R[A+1] := R[B]; R[A] := R[B][RK(C):string]
+    SelfOp { a: u8, b: u8, c: u8, k: bool },
+    /// R[A] := R[B] + sC
+    AddI { a: u8, b: u8, sc: i16 },
+    /// R[A] := R[B] + K[C]:number
+    AddK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] - K[C]:number
+    SubK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] * K[C]:number
+    MulK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] % K[C]:number
+    ModK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] ^ K[C]:number
+    PowK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] / K[C]:number
+    DivK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] // K[C]:number
+    IDivK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] & K[C]:integer
+    BAndK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] | K[C]:integer
+    BOrK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] ~ K[C]:integer
+    BXorK { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] >> sC
+    ShrI { a: u8, b: u8, sc: i16 },
+    /// R[A] := sC << R[B]
+    ShlI { a: u8, b: u8, sc: i16 },
+    /// R[A] := R[B] + R[C]
+    Add { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] - R[C]
+    Sub { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] * R[C]
+    Mul { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] % R[C]
+    Mod { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] ^ R[C]
+    Pow { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] / R[C]
+    Div { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] // R[C]
+    IDiv { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] & R[C]
+    BAnd { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] | R[C]
+    BOr { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] ~ R[C]
+    BXor { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] << R[C]
+    Shl { a: u8, b: u8, c: u8 },
+    /// R[A] := R[B] >> R[C]
+    Shr { a: u8, b: u8, c: u8 },
+    /// call C metamethod over R[A] and R[B]
+    MmBin { a: u8, b: u8, c: u8 },
+    /// call C metamethod over R[A] and sB
+    MmBinI { a: u8, sb: i16, c: u8, k: bool },
+    /// call C metamethod over R[A] and K[B]
+    MmBinK { a: u8, b: u8, c: u8, k: bool },
+    /// R[A] := -R[B]
+    Unm { a: u8, b: u8 },
+    /// R[A] := ~R[B]
+    BNot { a: u8, b: u8 },
+    /// R[A] := not R[B]
+    Not { a: u8, b: u8 },
+    /// R[A] := #R[B] (length operator)
+    Len { a: u8, b: u8 },
+    /// R[A] := R[A].. ... ..R[A + B - 1]
+    Concat { a: u8, b: u8 },
+    /// close all upvalues >= R[A]
+    Close { a: u8 },
+    /// mark variable A "to be closed"
+    Tbc { a: u8 },
+    /// pc += sJ
+    Jmp { sj: i32 },
+    /// if ((R[A] == R[B]) ~= k) then pc++
+    Eq { a: u8, b: u8, k: bool },
+    /// if ((R[A] <  R[B]) ~= k) then pc++
+    Lt { a: u8, b: u8, k: bool },
+    /// if ((R[A] <= R[B]) ~= k) then pc++
+    Le { a: u8, b: u8, k: bool },
+    /// if ((R[A] == K[B]) ~= k) then pc++
+    EqK { a: u8, b: u8, k: bool },
+    /// if ((R[A] == sB) ~= k) then pc++
+    EqI { a: u8, sb: i16, c: u8, k: bool },
+    /// if ((R[A] < sB) ~= k) then pc++
+    LtI { a: u8, sb: i16, c: u8, k: bool },
+    /// if ((R[A] <= sB) ~= k) then pc++
+    LeI { a: u8, sb: i16, c: u8, k: bool },
+    /// if ((R[A] > sB) ~= k) then pc++
+    GtI { a: u8, sb: i16, c: u8, k: bool },
+    /// if ((R[A] >= sB) ~= k) then pc++
+    GeI { a: u8, sb: i16, c: u8, k: bool },
+    /// if (not R[A] == k) then pc++
+    Test { a: u8, k: bool },
+    /// if (not R[B] == k) then pc++ else R[A] := R[B] (*)
+    TestSet { a: u8, b: u8, k: bool },
+    /// R[A], ... ,R[A+C-2] := R[A](R[A+1], ... ,R[A+B-1])
+    Call { a: u8, b: u8, c: u8 },
+    /// return R[A](R[A+1], ... ,R[A+B-1])
+    TailCall { a: u8, b: u8, c: u8, k: bool },
+    /// return R[A], ... ,R[A+B-2]
+    Return { a: u8, b: u8, c: u8, k: bool },
+    /// return
+    Return0 { a: u8 },
+    /// return R[A]
+    Return1 { a: u8 },
+    /// update counters; if loop continues then pc-=Bx;
+    ForLoop { a: u8, bx: u32 },
+    /// <check values and prepare counters>; if not to run then pc+=Bx+1;
+    ForPrep { a: u8, bx: u32 },
+    /// create upvalue for R[A + 3]; pc+=Bx
+    TForPrep { a: u8, bx: u32 },
+    /// R[A+4], ... ,R[A+3+C] := R[A](R[A+1], R[A+2]);
+    TForCall { a: u8, c: u8 },
+    /// if R[A+2] ~= nil then { R[A]=R[A+2]; pc -= Bx }
+    TForLoop { a: u8, bx: u32 },
+    /// R[A][C+i] := R[A+i], 1 <= i <= B
+    SetList { a: u8, b: u8, c: u8, k: bool },
+    /// R[A] := closure(KPROTO[Bx])
+    Closure { a: u8, bx: u32 },
+    /// R[A], R[A+1], ..., R[A+C-2] = vararg
+    VarArg { a: u8, c: u8 },
+    /// (adjust vararg parameters)
+    VarArgPrep { a: u8 },
+    /// extra (larger) argument for previous opcode
+    ExtraArg { ax: u32 },
+}
+
+impl Op {
+    pub fn opcode(&self) -> u8 {
+        use Op::*;
+        match self {
+            Move { .. } => OP_MOVE,
+            LoadI { .. } => OP_LOADI,
+            LoadF { .. } => OP_LOADF,
+            LoadK { .. } => OP_LOADK,
+            LoadKX { .. } => OP_LOADKX,
+            LoadFalse { .. } => OP_LOADFALSE,
+            LFalseSkip { .. } => OP_LFALSESKIP,
+            LoadTrue { .. } => OP_LOADTRUE,
+            LoadNil { .. } => OP_LOADNIL,
+            GetUpval { .. } => OP_GETUPVAL,
+            SetUpval { .. } => OP_SETUPVAL,
+            GetTabUp { .. } => OP_GETTABUP,
+            GetTable { .. } => OP_GETTABLE,
+            GetI { .. } => OP_GETI,
+            GetField { .. } => OP_GETFIELD,
+            SetTabUp { .. } => OP_SETTABUP,
+            SetTable { .. } => OP_SETTABLE,
+            SetI { .. } => OP_SETI,
+            SetField { .. } => OP_SETFIELD,
+            NewTable { .. } => OP_NEWTABLE,
+            SelfOp { .. } => OP_SELF,
+            AddI { .. } => OP_ADDI,
+            AddK { .. } => OP_ADDK,
+            SubK { .. } => OP_SUBK,
+            MulK { .. } => OP_MULK,
+            ModK { .. } => OP_MODK,
+            PowK { .. } => OP_POWK,
+            DivK { .. } => OP_DIVK,
+            IDivK { .. } => OP_IDIVK,
+            BAndK { .. } => OP_BANDK,
+            BOrK { .. } => OP_BORK,
+            BXorK { .. } => OP_BXORK,
+            ShrI { .. } => OP_SHRI,
+            ShlI { .. } => OP_SHLI,
+            Add { .. } => OP_ADD,
+            Sub { .. } => OP_SUB,
+            Mul { .. } => OP_MUL,
+            Mod { .. } => OP_MOD,
+            Pow { .. } => OP_POW,
+            Div { .. } => OP_DIV,
+            IDiv { .. } => OP_IDIV,
+            BAnd { .. } => OP_BAND,
+            BOr { .. } => OP_BOR,
+            BXor { .. } => OP_BXOR,
+            Shl { .. } => OP_SHL,
+            Shr { .. } => OP_SHR,
+            MmBin { .. } => OP_MMBIN,
+            MmBinI { .. } => OP_MMBINI,
+            MmBinK { .. } => OP_MMBINK,
+            Unm { .. } => OP_UNM,
+            BNot { .. } => OP_BNOT,
+            Not { .. } => OP_NOT,
+            Len { .. } => OP_LEN,
+            Concat { .. } => OP_CONCAT,
+            Close { .. } => OP_CLOSE,
+            Tbc { .. } => OP_TBC,
+            Jmp { .. } => OP_JMP,
+            Eq { .. } => OP_EQ,
+            Lt { .. } => OP_LT,
+            Le { .. } => OP_LE,
+            EqK { .. } => OP_EQK,
+            EqI { .. } => OP_EQI,
+            LtI { .. } => OP_LTI,
+            LeI { .. } => OP_LEI,
+            GtI { .. } => OP_GTI,
+            GeI { .. } => OP_GEI,
+            Test { .. } => OP_TEST,
+            TestSet { .. } => OP_TESTSET,
+            Call { .. } => OP_CALL,
+            TailCall { .. } => OP_TAILCALL,
+            Return { .. } => OP_RETURN,
+            Return0 { .. } => OP_RETURN0,
+            Return1 { .. } => OP_RETURN1,
+            ForLoop { .. } => OP_FORLOOP,
+            ForPrep { .. } => OP_FORPREP,
+            TForPrep { .. } => OP_TFORPREP,
+            TForCall { .. } => OP_TFORCALL,
+            TForLoop { .. } => OP_TFORLOOP,
+            SetList { .. } => OP_SETLIST,
+            Closure { .. } => OP_CLOSURE,
+            VarArg { .. } => OP_VARARG,
+            VarArgPrep { .. } => OP_VARARGPREP,
+            ExtraArg { .. } => OP_EXTRAARG,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        OPCODES[self.opcode() as usize].name()
+    }
+}
+
+impl TryFrom<u32> for Op {
+    type Error = io::Error;
+
+    /// Decodes an instruction, failing only on unknown opcodes. Operands the
+    /// opcode does not use are dropped.
+    fn try_from(n: u32) -> io::Result<Self> {
+        use Op::*;
+        let i = Instruction::from(n);
+        let (a, k, b, c) = i.abc();
+        let (sb, sc) = ((b - OFFSET_SC) as i16, (c - OFFSET_SC) as i16);
+        let (a, b, c, k) = (a as u8, b as u8, c as u8, k != 0);
+        let bx = i.a_bx().1 as u32;
+        let sbx = i.a_sbx().1 as i32;
+        let ax = i.ax() as u32;
+        let sj = i.sj() as i32;
+        Ok(match i.opcode() {
+            OP_MOVE => Move { a, b },
+            OP_LOADI => LoadI { a, sbx },
+            OP_LOADF => LoadF { a, sbx },
+            OP_LOADK => LoadK { a, bx },
+            OP_LOADKX => LoadKX { a },
+            OP_LOADFALSE => LoadFalse { a },
+            OP_LFALSESKIP => LFalseSkip { a },
+            OP_LOADTRUE => LoadTrue { a },
+            OP_LOADNIL => LoadNil { a, b },
+            OP_GETUPVAL => GetUpval { a, b },
+            OP_SETUPVAL => SetUpval { a, b },
+            OP_GETTABUP => GetTabUp { a, b, c },
+            OP_GETTABLE => GetTable { a, b, c },
+            OP_GETI => GetI { a, b, c },
+            OP_GETFIELD => GetField { a, b, c },
+            OP_SETTABUP => SetTabUp { a, b, c, k },
+            OP_SETTABLE => SetTable { a, b, c, k },
+            OP_SETI => SetI { a, b, c, k },
+            OP_SETFIELD => SetField { a, b, c, k },
+            OP_NEWTABLE => NewTable { a, b, c, k },
+            OP_SELF => SelfOp { a, b, c, k },
+            OP_ADDI => AddI { a, b, sc },
+            OP_ADDK => AddK { a, b, c },
+            OP_SUBK => SubK { a, b, c },
+            OP_MULK => MulK { a, b, c },
+            OP_MODK => ModK { a, b, c },
+            OP_POWK => PowK { a, b, c },
+            OP_DIVK => DivK { a, b, c },
+            OP_IDIVK => IDivK { a, b, c },
+            OP_BANDK => BAndK { a, b, c },
+            OP_BORK => BOrK { a, b, c },
+            OP_BXORK => BXorK { a, b, c },
+            OP_SHRI => ShrI { a, b, sc },
+            OP_SHLI => ShlI { a, b, sc },
+            OP_ADD => Add { a, b, c },
+            OP_SUB => Sub { a, b, c },
+            OP_MUL => Mul { a, b, c },
+            OP_MOD => Mod { a, b, c },
+            OP_POW => Pow { a, b, c },
+            OP_DIV => Div { a, b, c },
+            OP_IDIV => IDiv { a, b, c },
+            OP_BAND => BAnd { a, b, c },
+            OP_BOR => BOr { a, b, c },
+            OP_BXOR => BXor { a, b, c },
+            OP_SHL => Shl { a, b, c },
+            OP_SHR => Shr { a, b, c },
+            OP_MMBIN => MmBin { a, b, c },
+            OP_MMBINI => MmBinI { a, sb, c, k },
+            OP_MMBINK => MmBinK { a, b, c, k },
+            OP_UNM => Unm { a, b },
+            OP_BNOT => BNot { a, b },
+            OP_NOT => Not { a, b },
+            OP_LEN => Len { a, b },
+            OP_CONCAT => Concat { a, b },
+            OP_CLOSE => Close { a },
+            OP_TBC => Tbc { a },
+            OP_JMP => Jmp { sj },
+            OP_EQ => Eq { a, b, k },
+            OP_LT => Lt { a, b, k },
+            OP_LE => Le { a, b, k },
+            OP_EQK => EqK { a, b, k },
+            OP_EQI => EqI { a, sb, c, k },
+            OP_LTI => LtI { a, sb, c, k },
+            OP_LEI => LeI { a, sb, c, k },
+            OP_GTI => GtI { a, sb, c, k },
+            OP_GEI => GeI { a, sb, c, k },
+            OP_TEST => Test { a, k },
+            OP_TESTSET => TestSet { a, b, k },
+            OP_CALL => Call { a, b, c },
+            OP_TAILCALL => TailCall { a, b, c, k },
+            OP_RETURN => Return { a, b, c, k },
+            OP_RETURN0 => Return0 { a },
+            OP_RETURN1 => Return1 { a },
+            OP_FORLOOP => ForLoop { a, bx },
+            OP_FORPREP => ForPrep { a, bx },
+            OP_TFORPREP => TForPrep { a, bx },
+            OP_TFORCALL => TForCall { a, c },
+            OP_TFORLOOP => TForLoop { a, bx },
+            OP_SETLIST => SetList { a, b, c, k },
+            OP_CLOSURE => Closure { a, bx },
+            OP_VARARG => VarArg { a, c },
+            OP_VARARGPREP => VarArgPrep { a },
+            OP_EXTRAARG => ExtraArg { ax },
+            op => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown opcode {:#04x}", op),
+                ))
+            }
+        })
+    }
+}
+
+impl TryFrom<Op> for u32 {
+    type Error = io::Error;
+
+    /// Encodes an instruction, failing on operands out of range.
+    fn try_from(i: Op) -> io::Result<Self> {
+        use Op::*;
+        let op = i.opcode();
+        let i = match i {
+            Move { a, b }
+            | LoadNil { a, b }
+            | GetUpval { a, b }
+            | SetUpval { a, b }
+            | Unm { a, b }
+            | BNot { a, b }
+            | Not { a, b }
+            | Len { a, b }
+            | Concat { a, b } => iabc(op, a, b, 0, false)?,
+            LoadI { a, sbx } | LoadF { a, sbx } => {
+                Instruction::encode_asbx(op, a as isize, sbx as isize)?
+            }
+            LoadK { a, bx }
+            | ForLoop { a, bx }
+            | ForPrep { a, bx }
+            | TForPrep { a, bx }
+            | TForLoop { a, bx }
+            | Closure { a, bx } => Instruction::encode_abx(op, a as isize, bx as isize)?,
+            LoadKX { a } => Instruction::encode_abx(op, a as isize, 0)?,
+            LoadFalse { a }
+            | LFalseSkip { a }
+            | LoadTrue { a }
+            | Close { a }
+            | Tbc { a }
+            | Return0 { a }
+            | Return1 { a }
+            | VarArgPrep { a } => iabc(op, a, 0, 0, false)?,
+            GetTabUp { a, b, c }
+            | GetTable { a, b, c }
+            | GetI { a, b, c }
+            | GetField { a, b, c }
+            | AddK { a, b, c }
+            | SubK { a, b, c }
+            | MulK { a, b, c }
+            | ModK { a, b, c }
+            | PowK { a, b, c }
+            | DivK { a, b, c }
+            | IDivK { a, b, c }
+            | BAndK { a, b, c }
+            | BOrK { a, b, c }
+            | BXorK { a, b, c }
+            | Add { a, b, c }
+            | Sub { a, b, c }
+            | Mul { a, b, c }
+            | Mod { a, b, c }
+            | Pow { a, b, c }
+            | Div { a, b, c }
+            | IDiv { a, b, c }
+            | BAnd { a, b, c }
+            | BOr { a, b, c }
+            | BXor { a, b, c }
+            | Shl { a, b, c }
+            | Shr { a, b, c }
+            | MmBin { a, b, c }
+            | Call { a, b, c } => iabc(op, a, b, c, false)?,
+            SetTabUp { a, b, c, k }
+            | SetTable { a, b, c, k }
+            | SetI { a, b, c, k }
+            | SetField { a, b, c, k }
+            | NewTable { a, b, c, k }
+            | SelfOp { a, b, c, k }
+            | MmBinK { a, b, c, k }
+            | TailCall { a, b, c, k }
+            | Return { a, b, c, k }
+            | SetList { a, b, c, k } => iabc(op, a, b, c, k)?,
+            AddI { a, b, sc } | ShrI { a, b, sc } | ShlI { a, b, sc } => {
+                iabc(op, a, b, signed(sc)?, false)?
+            }
+            MmBinI { a, sb, c, k }
+            | EqI { a, sb, c, k }
+            | LtI { a, sb, c, k }
+            | LeI { a, sb, c, k }
+            | GtI { a, sb, c, k }
+            | GeI { a, sb, c, k } => iabc(op, a, signed(sb)?, c, k)?,
+            Jmp { sj } => Instruction::encode_sj(op, sj as isize)?,
+            Eq { a, b, k }
+            | Lt { a, b, k }
+            | Le { a, b, k }
+            | EqK { a, b, k }
+            | TestSet { a, b, k } => iabc(op, a, b, 0, k)?,
+            Test { a, k } => iabc(op, a, 0, 0, k)?,
+            TForCall { a, c } | VarArg { a, c } => iabc(op, a, 0, c, false)?,
+            ExtraArg { ax } => Instruction::encode_ax(op, ax as isize)?,
+        };
+        Ok(i.into())
+    }
+}
+
+fn iabc(op: u8, a: u8, b: u8, c: u8, k: bool) -> io::Result<Instruction> {
+    Instruction::encode_abc(op, a as isize, b as isize, c as isize, k)
+}
+
+/// Converts a signed operand to its excess-K encoding.
+fn signed(v: i16) -> io::Result<u8> {
+    let n = v as isize + OFFSET_SC;
+    if (0..=MAXARG_C).contains(&n) {
+        Ok(n as u8)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("signed argument {} out of range", v),
+        ))
+    }
+}
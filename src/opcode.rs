@@ -51,7 +51,7 @@ pub const OP_DIVK: u8 = 0x1b; // R[A] := R[B] / K[C]:number
 pub const OP_IDIVK: u8 = 0x1c; // R[A] := R[B] // K[C]:number
 pub const OP_BANDK: u8 = 0x1d; // R[A] := R[B] & K[C]:integer
 pub const OP_BORK: u8 = 0x1e; // R[A] := R[B] | K[C]:integer
-pub const OP_BXORK: u8 = 0x2f; // R[A] := R[B] ~ K[C]:integer
+pub const OP_BXORK: u8 = 0x1f; // R[A] := R[B] ~ K[C]:integer
 pub const OP_SHRI: u8 = 0x20; // R[A] := R[B] >> sC
 pub const OP_SHLI: u8 = 0x21; // R[A] := sC << R[B]
 pub const OP_ADD: u8 = 0x22; // R[A] := R[B] + R[C]
@@ -64,8 +64,8 @@ pub const OP_IDIV: u8 = 0x28; // R[A] := R[B] // R[C]
 pub const OP_BAND: u8 = 0x29; // R[A] := R[B] & R[C]
 pub const OP_BOR: u8 = 0x2a; // R[A] := R[B] | R[C]
 pub const OP_BXOR: u8 = 0x2b; // R[A] := R[B] ~ R[C]
-pub const OP_SHL: u8 = 0x2c; // R[A] := R[B] >> R[C]
-pub const OP_SHR: u8 = 0x2d; // R[A] := R[B] << R[C]
+pub const OP_SHL: u8 = 0x2c; // R[A] := R[B] << R[C]
+pub const OP_SHR: u8 = 0x2d; // R[A] := R[B] >> R[C]
 pub const OP_MMBIN: u8 = 0x2e; // call C metamethod over R[A] and R[B]
 pub const OP_MMBINI: u8 = 0x2f; // call C metamethod over R[A] and sB
 pub const OP_MMBINK: u8 = 0x30; // call C metamethod over R[A] and K[B]
@@ -153,8 +153,8 @@ pub const OPCODES: &[OpCode] = &[
     opcode(0, 0, 0, 0, 1, OP_MODE_ABC, "SHL"),
     opcode(0, 0, 0, 0, 1, OP_MODE_ABC, "SHR"),
     opcode(1, 0, 0, 0, 0, OP_MODE_ABC, "MMBIN"),
-    opcode(1, 0, 0, 0, 0, OP_MODE_ABC, "MMBIN"),
-    opcode(1, 0, 0, 0, 0, OP_MODE_ABC, "MMBIN"),
+    opcode(1, 0, 0, 0, 0, OP_MODE_ABC, "MMBINI"),
+    opcode(1, 0, 0, 0, 0, OP_MODE_ABC, "MMBINK"),
     opcode(0, 0, 0, 0, 1, OP_MODE_ABC, "UNM"),
     opcode(0, 0, 0, 0, 1, OP_MODE_ABC, "BNOT"),
     opcode(0, 0, 0, 0, 1, OP_MODE_ABC, "NOT"),
@@ -191,18 +191,32 @@ pub const OPCODES: &[OpCode] = &[
     opcode(0, 0, 0, 0, 0, OP_MODE_AX, "EXTRAARG"),
 ];
 
+/// Returns the metadata of opcode `op`.
+pub fn get(op: u8) -> Option<&'static OpCode> {
+    OPCODES.get(op as usize)
+}
+
+/// Returns the opcode named `name`, as `luac -l` prints it.
+pub fn find(name: &str) -> Option<u8> {
+    OPCODES
+        .iter()
+        .position(|op| op.name.eq_ignore_ascii_case(name))
+        .map(|op| op as u8)
+}
+
 pub const fn opcode(mm: u8, ot: u8, it: u8, t: u8, a: u8, mode: u8, name: &'static str) -> OpCode {
     OpCode {
-        mm: mm != 1,
-        ot: ot != 1,
-        it: it != 1,
-        t: t != 1,
-        a: a != 1,
+        mm: mm == 1,
+        ot: ot == 1,
+        it: it == 1,
+        t: t == 1,
+        a: a == 1,
         mode,
         name,
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct OpCode {
     mm: bool,
     ot: bool,
@@ -238,7 +252,7 @@ impl OpCode {
         self.mode
     }
 
-    pub fn name(&self) -> &str {
+    pub fn name(&self) -> &'static str {
         self.name
     }
 }
@@ -0,0 +1,395 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `string.pack`, `string.unpack` and `string.packsize`, as in `lstrlib.c`.
+
+use crate::{
+    args::Args,
+    state::State,
+    strlib::posrelat_start,
+    value::{Error, Value},
+};
+
+/// Maximum size of a packed integer.
+const MAXINTSIZE: usize = 16;
+/// Size of a Lua integer.
+const SZINT: usize = 8;
+/// Maximum alignment, that of the largest C scalar.
+const MAXALIGN: usize = 8;
+/// Limit of sizes in formats.
+const MAXSIZE: usize = i32::MAX as usize;
+const NATIVE_LITTLE: bool = cfg!(target_endian = "little");
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KOption {
+    Int,
+    Uint,
+    Float,
+    Number,
+    Double,
+    Char,
+    String,
+    Zstr,
+    Padding,
+    PaddAlign,
+    Nop,
+}
+
+/// The state of a format string being read.
+struct Header<'a> {
+    fmt: &'a [u8],
+    pos: usize,
+    little: bool,
+    maxalign: usize,
+}
+
+impl<'a> Header<'a> {
+    fn new(fmt: &'a [u8]) -> Self {
+        // C reads the format up to its first zero.
+        let end = fmt.iter().position(|&b| b == 0).unwrap_or(fmt.len());
+        Self {
+            fmt: &fmt[..end],
+            pos: 0,
+            little: NATIVE_LITTLE,
+            maxalign: 1,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.fmt.len()
+    }
+
+    fn getnum(&mut self, default: usize) -> usize {
+        let digit = |h: &Self| h.fmt.get(h.pos).filter(|b| b.is_ascii_digit()).copied();
+        if digit(self).is_none() {
+            return default;
+        }
+        let mut a = 0;
+        while let Some(d) = digit(self) {
+            a = a * 10 + (d - b'0') as usize;
+            self.pos += 1;
+            if a > (MAXSIZE - 9) / 10 {
+                break;
+            }
+        }
+        a
+    }
+
+    fn getnumlimit(&mut self, default: usize) -> Result<usize, Error> {
+        let size = self.getnum(default);
+        if size > MAXINTSIZE || size == 0 {
+            return Err(Error::new(format!(
+                "integral size ({}) out of limits [1,{}]",
+                size, MAXINTSIZE
+            )));
+        }
+        Ok(size)
+    }
+
+    /// Reads an option, returning it with its size.
+    fn getoption(&mut self) -> Result<(KOption, usize), Error> {
+        let opt = self.fmt[self.pos];
+        self.pos += 1;
+        Ok(match opt {
+            b'b' => (KOption::Int, 1),
+            b'B' => (KOption::Uint, 1),
+            b'h' => (KOption::Int, 2),
+            b'H' => (KOption::Uint, 2),
+            b'l' | b'j' => (KOption::Int, 8),
+            b'L' | b'J' | b'T' => (KOption::Uint, 8),
+            b'f' => (KOption::Float, 4),
+            b'n' => (KOption::Number, 8),
+            b'd' => (KOption::Double, 8),
+            b'i' => (KOption::Int, self.getnumlimit(4)?),
+            b'I' => (KOption::Uint, self.getnumlimit(4)?),
+            b's' => (KOption::String, self.getnumlimit(8)?),
+            b'c' => match self.getnum(usize::MAX) {
+                usize::MAX => {
+                    return Err(Error::new("missing size for format option 'c'"));
+                }
+                size => (KOption::Char, size),
+            },
+            b'z' => (KOption::Zstr, 0),
+            b'x' => (KOption::Padding, 1),
+            b'X' => (KOption::PaddAlign, 0),
+            b' ' => (KOption::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (KOption::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (KOption::Nop, 0)
+            }
+            b'=' => {
+                self.little = NATIVE_LITTLE;
+                (KOption::Nop, 0)
+            }
+            b'!' => {
+                self.maxalign = self.getnumlimit(MAXALIGN)?;
+                (KOption::Nop, 0)
+            }
+            _ => {
+                return Err(Error::new(format!(
+                    "invalid format option '{}'",
+                    opt as char
+                )))
+            }
+        })
+    }
+
+    /// Reads an option, returning it with its size and the padding that
+    /// aligns it after `totalsize` bytes.
+    fn getdetails(
+        &mut self,
+        args: &Args,
+        totalsize: usize,
+    ) -> Result<(KOption, usize, usize), Error> {
+        let (opt, size) = self.getoption()?;
+        // Usually, alignment follows size.
+        let mut align = size;
+        if opt == KOption::PaddAlign {
+            // 'X' gets its alignment from the following option.
+            let next = if self.done() {
+                None
+            } else {
+                Some(self.getoption()?)
+            };
+            match next {
+                Some((next, next_align)) if next != KOption::Char && next_align != 0 => {
+                    align = next_align
+                }
+                _ => return Err(args.error(1, "invalid next option for option 'X'")),
+            }
+        }
+        let ntoalign = if align <= 1 || opt == KOption::Char {
+            0
+        } else {
+            let align = align.min(self.maxalign);
+            if !align.is_power_of_two() {
+                return Err(args.error(1, "format asks for alignment not power of 2"));
+            }
+            (align - (totalsize & (align - 1))) & (align - 1)
+        };
+        Ok((opt, size, ntoalign))
+    }
+}
+
+/// Appends the `size` low bytes of `n`, sign-extending past 8 bytes if
+/// `negative`.
+pub(crate) fn pack_int(b: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let mut bytes = vec![if negative { 0xFF } else { 0 }; size];
+    for (i, byte) in bytes.iter_mut().enumerate().take(size.min(SZINT)) {
+        *byte = (n >> (8 * i)) as u8;
+    }
+    if !little {
+        bytes.reverse();
+    }
+    b.extend_from_slice(&bytes);
+}
+
+/// Reads an integer of `bytes.len()` bytes, which must fit in a Lua integer.
+pub(crate) fn unpack_int(bytes: &[u8], little: bool, signed: bool) -> Result<i64, Error> {
+    let size = bytes.len();
+    let at = |i: usize| {
+        if little {
+            bytes[i]
+        } else {
+            bytes[size - 1 - i]
+        }
+    };
+    let limit = size.min(SZINT);
+    let mut res = 0_u64;
+    for i in (0..limit).rev() {
+        res = (res << 8) | at(i) as u64;
+    }
+    if size < SZINT {
+        if signed {
+            // Sign extension.
+            let mask = 1_u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > SZINT {
+        // The extra bytes must only carry the sign.
+        let mask = if !signed || (res as i64) >= 0 {
+            0
+        } else {
+            0xFF
+        };
+        if (limit..size).any(|i| at(i) != mask) {
+            return Err(Error::new(format!(
+                "{}-byte integer does not fit into Lua Integer",
+                size
+            )));
+        }
+    }
+    Ok(res as i64)
+}
+
+fn with_endian<const N: usize>(mut bytes: [u8; N], little: bool) -> [u8; N] {
+    if little != NATIVE_LITTLE {
+        bytes.reverse();
+    }
+    bytes
+}
+
+pub(crate) fn pack(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let fmt = args.check_string(1)?;
+    let mut h = Header::new(&fmt);
+    let mut b = vec![];
+    let mut arg = 1;
+    let mut totalsize = 0;
+    while !h.done() {
+        let (opt, size, ntoalign) = h.getdetails(&args, totalsize)?;
+        totalsize += ntoalign + size;
+        b.resize(b.len() + ntoalign, 0);
+        arg += 1;
+        match opt {
+            KOption::Int => {
+                let n = args.check_integer(arg)?;
+                if size < SZINT {
+                    let lim = 1_i64 << (size * 8 - 1);
+                    if !(-lim..lim).contains(&n) {
+                        return Err(args.error(arg, "integer overflow"));
+                    }
+                }
+                pack_int(&mut b, n as u64, h.little, size, n < 0);
+            }
+            KOption::Uint => {
+                let n = args.check_integer(arg)?;
+                if size < SZINT && (n as u64) >= 1 << (size * 8) {
+                    return Err(args.error(arg, "unsigned overflow"));
+                }
+                pack_int(&mut b, n as u64, h.little, size, false);
+            }
+            KOption::Float => {
+                let f = args.check_float(arg)? as f32;
+                b.extend_from_slice(&with_endian(f.to_ne_bytes(), h.little));
+            }
+            KOption::Number | KOption::Double => {
+                let f = args.check_float(arg)?;
+                b.extend_from_slice(&with_endian(f.to_ne_bytes(), h.little));
+            }
+            KOption::Char => {
+                let s = args.check_string(arg)?;
+                if s.len() > size {
+                    return Err(args.error(arg, "string longer than given size"));
+                }
+                b.extend_from_slice(&s);
+                b.resize(b.len() + size - s.len(), 0);
+            }
+            KOption::String => {
+                let s = args.check_string(arg)?;
+                if size < SZINT && s.len() as u64 >= 1 << (size * 8) {
+                    return Err(args.error(arg, "string length does not fit in given size"));
+                }
+                pack_int(&mut b, s.len() as u64, h.little, size, false);
+                b.extend_from_slice(&s);
+                totalsize += s.len();
+            }
+            KOption::Zstr => {
+                let s = args.check_string(arg)?;
+                if s.contains(&0) {
+                    return Err(args.error(arg, "string contains zeros"));
+                }
+                b.extend_from_slice(&s);
+                b.push(0);
+                totalsize += s.len() + 1;
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => {
+                if opt == KOption::Padding {
+                    b.push(0);
+                }
+                // Consumes no argument.
+                arg -= 1;
+            }
+        }
+    }
+    Ok(vec![Value::String(b.into())])
+}
+
+pub(crate) fn packsize(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let fmt = args.check_string(1)?;
+    let mut h = Header::new(&fmt);
+    let mut totalsize = 0;
+    while !h.done() {
+        let (opt, size, ntoalign) = h.getdetails(&args, totalsize)?;
+        if matches!(opt, KOption::String | KOption::Zstr) {
+            return Err(args.error(1, "variable-length format"));
+        }
+        let size = size + ntoalign;
+        if totalsize > MAXSIZE - size {
+            return Err(args.error(1, "format result too large"));
+        }
+        totalsize += size;
+    }
+    Ok(vec![Value::Integer(totalsize as i64)])
+}
+
+pub(crate) fn unpack(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let fmt = args.check_string(1)?;
+    let data = args.check_string(2)?;
+    let ld = data.len();
+    let mut pos = posrelat_start(args.opt_integer(3, 1)?, ld) - 1;
+    if pos > ld {
+        return Err(args.error(3, "initial position out of string"));
+    }
+    let mut h = Header::new(&fmt);
+    let mut results = vec![];
+    while !h.done() {
+        let (opt, size, ntoalign) = h.getdetails(&args, pos)?;
+        if ntoalign + size > ld - pos {
+            return Err(args.error(2, "data string too short"));
+        }
+        pos += ntoalign;
+        let item = &data[pos..pos + size];
+        match opt {
+            KOption::Int | KOption::Uint => {
+                let n = unpack_int(item, h.little, opt == KOption::Int)?;
+                results.push(Value::Integer(n));
+            }
+            KOption::Float => {
+                let f = f32::from_ne_bytes(with_endian(item.try_into().unwrap(), h.little));
+                results.push(Value::Number(f as f64));
+            }
+            KOption::Number | KOption::Double => {
+                let f = f64::from_ne_bytes(with_endian(item.try_into().unwrap(), h.little));
+                results.push(Value::Number(f));
+            }
+            KOption::Char => results.push(Value::String(item.into())),
+            KOption::String => {
+                let len = unpack_int(item, h.little, false)? as u64;
+                if len > (ld - pos - size) as u64 {
+                    return Err(args.error(2, "data string too short"));
+                }
+                let start = pos + size;
+                results.push(Value::String(data[start..start + len as usize].into()));
+                pos += len as usize;
+            }
+            KOption::Zstr => {
+                let len = data[pos..].iter().position(|&b| b == 0);
+                let len = match len {
+                    Some(len) => len,
+                    None => return Err(args.error(2, "unfinished string for format 'z'")),
+                };
+                results.push(Value::String(data[pos..pos + len].into()));
+                pos += len + 1;
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => {}
+        }
+        pos += size;
+    }
+    results.push(Value::Integer(pos as i64 + 1));
+    Ok(results)
+}
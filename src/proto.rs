@@ -12,7 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::instruction::Instruction;
+use std::{fmt, io, sync::Arc};
+
+use bytes::Bytes;
+use tokio::sync::OnceCell;
+
+use crate::{
+    bytecode,
+    constants::ABSLINEINFO,
+    instruction::Instruction,
+    string::{Interner, LuaString},
+    verify::Enclosing,
+};
 
 #[derive(Debug)]
 pub struct Proto {
@@ -21,28 +32,182 @@ pub struct Proto {
     pub(crate) numparams: u8,
     pub(crate) is_vararg: u8,
     pub(crate) maxstacksize: u8,
-    pub(crate) source: Option<String>,
+    pub(crate) source: Option<LuaString>,
     pub(crate) code: Vec<Instruction>,
     pub(crate) constants: Vec<Constant>,
     pub(crate) upvalues: Vec<Upvalue>,
-    pub(crate) protos: Vec<Proto>,
+    pub(crate) protos: Vec<LazyProto>,
     pub(crate) lineinfo: Vec<i8>,
     pub(crate) abslineinfo: Vec<AbsLineInfo>,
     pub(crate) locvars: Vec<LocVar>,
 }
 
+impl Proto {
+    pub fn protos(&self) -> &[LazyProto] {
+        &self.protos
+    }
+
+    /// Returns the source line of the instruction at `pc`, or `None` if `pc` is
+    /// out of range or the proto carries no line information.
+    ///
+    /// Lines are stored as deltas from the previous instruction in `lineinfo`,
+    /// with an absolute anchor in `abslineinfo` wherever the delta does not
+    /// fit, or periodically, marked by [`ABSLINEINFO`] in `lineinfo`.
+    pub fn line_for_pc(&self, pc: usize) -> Option<i32> {
+        if pc >= self.lineinfo.len() {
+            return None;
+        }
+        let anchor = self
+            .abslineinfo
+            .partition_point(|abs| abs.pc as usize <= pc)
+            .checked_sub(1)
+            .map(|i| &self.abslineinfo[i]);
+        let (basepc, mut line) = match anchor {
+            Some(abs) => (abs.pc as usize + 1, abs.line),
+            None => (0, self.linedefined),
+        };
+        for delta in &self.lineinfo[basepc..=pc] {
+            line += *delta as i32;
+        }
+        Some(line)
+    }
+
+    /// Returns the pcs of all instructions generated for source `line`, in
+    /// ascending order.
+    pub fn pcs_for_line(&self, line: i32) -> Vec<usize> {
+        let mut anchors = self.abslineinfo.iter();
+        let mut current = self.linedefined;
+        let mut pcs = vec![];
+        for (pc, delta) in self.lineinfo.iter().enumerate() {
+            if *delta == ABSLINEINFO {
+                match anchors.next() {
+                    Some(abs) => current = abs.line,
+                    None => break,
+                }
+            } else {
+                current += *delta as i32;
+            }
+            if current == line {
+                pcs.push(pc);
+            }
+        }
+        pcs
+    }
+
+    /// Returns the locals active at `pc`, each with the register it lives in.
+    ///
+    /// `locvars` is ordered by `startpc`, and the n-th local active at a pc
+    /// occupies register n.
+    pub fn locals_at(&self, pc: usize) -> Vec<(usize, &LocVar)> {
+        self.locvars
+            .iter()
+            .take_while(|v| v.startpc as usize <= pc)
+            .filter(|v| v.is_active(pc))
+            .enumerate()
+            .collect()
+    }
+
+    /// Returns the name of the local living in `register` at `pc`, or `None`
+    /// if the register holds a temporary.
+    pub fn local_name(&self, register: usize, pc: usize) -> Option<&LuaString> {
+        self.locals_at(pc)
+            .get(register)
+            .and_then(|(_, v)| v.varname.as_ref())
+    }
+
+    /// Decodes every nested proto that is still deferred, recursively.
+    #[async_recursion::async_recursion]
+    pub async fn load_all(&self) -> io::Result<()> {
+        for p in self.protos.iter() {
+            p.load().await?.load_all().await?;
+        }
+        Ok(())
+    }
+}
+
+/// A nested proto which is either decoded already or located in the chunk and
+/// decoded on first [`load`](LazyProto::load), e.g. when `OP_CLOSURE`
+/// instantiates it.
+pub struct LazyProto {
+    proto: OnceCell<Proto>,
+    /// The encoded proto, the source inherited from its parent, what its
+    /// upvalues may capture and the interner for its strings.
+    deferred: Option<(Bytes, Option<LuaString>, Enclosing, Arc<Interner>)>,
+}
+
+impl LazyProto {
+    pub(crate) fn loaded(proto: Proto) -> Self {
+        Self {
+            proto: OnceCell::new_with(Some(proto)),
+            deferred: None,
+        }
+    }
+
+    pub(crate) fn deferred(
+        chunk: Bytes,
+        parent_source: Option<LuaString>,
+        enclosing: Enclosing,
+        strings: Arc<Interner>,
+    ) -> Self {
+        Self {
+            proto: OnceCell::new(),
+            deferred: Some((chunk, parent_source, enclosing, strings)),
+        }
+    }
+
+    /// Returns the encoded proto if it has not been decoded yet.
+    pub(crate) fn encoded(&self) -> Option<&Bytes> {
+        match self.proto.get() {
+            Some(_) => None,
+            None => self.deferred.as_ref().map(|(chunk, ..)| chunk),
+        }
+    }
+
+    /// Returns the proto if it has been decoded.
+    pub fn get(&self) -> Option<&Proto> {
+        self.proto.get()
+    }
+
+    /// Returns the proto, decoding it first if necessary.
+    pub async fn load(&self) -> io::Result<&Proto> {
+        self.proto
+            .get_or_try_init(|| async {
+                let (chunk, parent_source, enclosing, strings) =
+                    self.deferred.clone().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "proto is neither loaded nor deferred",
+                        )
+                    })?;
+                bytecode::undump_proto(chunk, parent_source, enclosing, strings).await
+            })
+            .await
+    }
+}
+
+/// Formats as the decoded proto, so that a lazily loaded tree reads the same
+/// as an eager one once [`Proto::load_all`] is done.
+impl fmt::Debug for LazyProto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(proto) => proto.fmt(f),
+            None => f.write_str("<deferred>"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Constant {
     Nil,
     Boolean(bool),
     Number(f64),
     Integer(i64),
-    String(String),
+    String(LuaString),
 }
 
 #[derive(Clone, Debug)]
 pub struct Upvalue {
-    pub(crate) name: Option<String>,
+    pub(crate) name: Option<LuaString>,
     pub(crate) instack: u8,
     pub(crate) idx: u8,
     pub(crate) kind: u8,
@@ -56,7 +221,27 @@ pub struct AbsLineInfo {
 
 #[derive(Debug)]
 pub struct LocVar {
-    pub(crate) varname: Option<String>,
+    pub(crate) varname: Option<LuaString>,
     pub(crate) startpc: i32,
     pub(crate) endpc: i32,
 }
+
+impl LocVar {
+    pub fn varname(&self) -> Option<&LuaString> {
+        self.varname.as_ref()
+    }
+
+    /// The first pc where the variable is active.
+    pub fn startpc(&self) -> i32 {
+        self.startpc
+    }
+
+    /// The first pc where the variable is dead.
+    pub fn endpc(&self) -> i32 {
+        self.endpc
+    }
+
+    pub fn is_active(&self, pc: usize) -> bool {
+        (self.startpc as usize..self.endpc as usize).contains(&pc)
+    }
+}
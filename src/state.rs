@@ -0,0 +1,411 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    args::{type_name_of, Args},
+    bytecode::{self, Reader},
+    closure::Closure,
+    number::Number,
+    string::{Interner, LuaString},
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+/// Limit of nested native calls, as `LUAI_MAXCCALLS`.
+const MAX_CALLS: usize = 200;
+
+/// Limit of `__index` and `__newindex` chains, as `MAXTAGLOOP`.
+const MAX_TAG_LOOP: usize = 2000;
+
+/// Runtime state shared by the loader and the VM.
+#[derive(Debug, Default)]
+pub struct State {
+    strings: Arc<Interner>,
+    globals: Table,
+    registry: Table,
+    string_metatable: RefCell<Option<Table>>,
+    depth: Cell<usize>,
+    pub(crate) warnings: Cell<bool>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the string, interned if it is short.
+    pub fn intern(&self, s: impl Into<LuaString> + AsRef<[u8]>) -> LuaString {
+        self.strings.intern(s)
+    }
+
+    /// The table of global variables, which loaded chunks receive as `_ENV`.
+    pub fn globals(&self) -> Table {
+        self.globals.clone()
+    }
+
+    /// A table for private state of libraries, as the C registry.
+    pub fn registry(&self) -> Table {
+        self.registry.clone()
+    }
+
+    /// The registry table at `key`, created if absent, as
+    /// `luaL_getsubtable`.
+    pub(crate) fn registry_table(&self, key: &str) -> Table {
+        match self.registry.get_str(key) {
+            Value::Table(t) => t,
+            _ => {
+                let t = Table::new();
+                self.registry.set_str(key, t.clone());
+                t
+            }
+        }
+    }
+
+    /// The table of loaded modules, `package.loaded`.
+    pub(crate) fn loaded(&self) -> Table {
+        self.registry_table("_LOADED")
+    }
+
+    /// Sets `lib` as global `name` and as loaded module `name`, as
+    /// `luaL_requiref` does for the standard libraries.
+    pub(crate) fn set_library(&self, name: &str, lib: Table) {
+        self.loaded().set_str(name, lib.clone());
+        self.globals.set_str(name, lib);
+    }
+
+    /// Loads a chunk whose short strings are interned into this state.
+    pub async fn undump<R: AsyncRead + Send + Unpin>(&self, reader: R) -> io::Result<Closure> {
+        let r = Reader::new(reader).interning(self.strings.clone());
+        Ok(self.with_env(bytecode::undump_inner(r).await?))
+    }
+
+    /// Like [`State::undump`], but nested protos are decoded lazily.
+    pub async fn undump_lazy<R: AsyncRead + Send + Unpin>(
+        &self,
+        mut reader: R,
+    ) -> io::Result<Closure> {
+        let mut chunk = vec![];
+        reader.read_to_end(&mut chunk).await?;
+        let r = Reader::lazy(Bytes::from(chunk)).interning(self.strings.clone());
+        Ok(self.with_env(bytecode::undump_inner(r).await?))
+    }
+
+    /// Loads a binary chunk held in memory, for `load` and friends.
+    pub fn load_binary(&self, chunk: impl Into<Bytes>) -> io::Result<Closure> {
+        let closure = bytecode::undump_in_memory(chunk.into(), self.strings.clone())?;
+        Ok(self.with_env(closure))
+    }
+
+    /// Sets the first upvalue of a main function to the globals, as
+    /// `lua_load` does.
+    fn with_env(&self, mut closure: Closure) -> Closure {
+        if let Some(env) = closure.upvalues.first_mut() {
+            *env = Value::Table(self.globals());
+        }
+        closure
+    }
+
+    /// Opens the standard libraries into the globals, as `luaL_openlibs`.
+    pub fn open_libs(&self) {
+        self.open_base();
+        self.open_package();
+        self.open_string();
+        self.open_table();
+        self.open_io();
+        self.open_math();
+        self.open_os();
+        self.open_utf8();
+    }
+
+    /// Closes the state, as far as `lua_close` applies without a collector:
+    /// the globals and the registry are emptied, so values only they reach,
+    /// such as open files, are dropped and finalized.
+    pub fn close(&self) {
+        for table in [&self.globals, &self.registry] {
+            let mut keys = vec![];
+            let mut key = Value::Nil;
+            while let Ok(Some((k, _))) = table.next(&key) {
+                keys.push(k.clone());
+                key = k;
+            }
+            for k in keys {
+                let _ = table.set(k, Value::Nil);
+            }
+        }
+    }
+
+    /// Registers native functions into `table`.
+    pub fn register(&self, table: &Table, functions: &[(&'static str, NativeFnPtr)]) {
+        for &(name, f) in functions {
+            table.set_str(name, Function::native(name, f));
+        }
+    }
+
+    /// Calls `f` with `args`, honouring `__call`.
+    pub fn call(&self, f: &Value, mut args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        match f {
+            Value::Function(Function::Native(native)) => {
+                if self.depth.get() >= MAX_CALLS {
+                    return Err(Error::new("stack overflow"));
+                }
+                self.depth.set(self.depth.get() + 1);
+                let results = (native.f)(self, Args::new(native.name, args));
+                self.depth.set(self.depth.get() - 1);
+                results
+            }
+            Value::Function(Function::Lua(_)) => Err(Error::new(
+                "attempt to call a Lua function: bytecode execution is not supported",
+            )),
+            f => match self.metafield(f, "__call") {
+                Value::Nil => Err(Error::new(format!(
+                    "attempt to call a {} value",
+                    type_name_of(f)
+                ))),
+                handler => {
+                    args.insert(0, f.clone());
+                    self.call(&handler, args)
+                }
+            },
+        }
+    }
+
+    /// Calls `f` and keeps its first result only.
+    pub fn call1(&self, f: &Value, args: Vec<Value>) -> Result<Value, Error> {
+        Ok(self.call(f, args)?.into_iter().next().unwrap_or_default())
+    }
+
+    pub fn metatable(&self, v: &Value) -> Option<Table> {
+        match v {
+            Value::Table(t) => t.metatable(),
+            Value::Userdata(u) => u.metatable(),
+            Value::String(_) => self.string_metatable.borrow().clone(),
+            _ => None,
+        }
+    }
+
+    pub fn set_string_metatable(&self, metatable: Option<Table>) {
+        *self.string_metatable.borrow_mut() = metatable;
+    }
+
+    /// The field `event` of the metatable of `v`, or `nil`.
+    pub fn metafield(&self, v: &Value, event: &str) -> Value {
+        match self.metatable(v) {
+            Some(mt) => mt.get_str(event),
+            None => Value::Nil,
+        }
+    }
+
+    /// `obj[key]`, honouring `__index`.
+    pub fn index(&self, obj: &Value, key: &Value) -> Result<Value, Error> {
+        let mut obj = obj.clone();
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &obj {
+                Value::Table(t) => {
+                    let v = t.get(key);
+                    if !v.is_nil() {
+                        return Ok(v);
+                    }
+                    match self.metafield(&obj, "__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        handler => handler,
+                    }
+                }
+                _ => match self.metafield(&obj, "__index") {
+                    Value::Nil => {
+                        return Err(Error::new(format!(
+                            "attempt to index a {} value",
+                            type_name_of(&obj)
+                        )))
+                    }
+                    handler => handler,
+                },
+            };
+            if let Value::Function(_) = handler {
+                return self.call1(&handler, vec![obj, key.clone()]);
+            }
+            obj = handler;
+        }
+        Err(Error::new("'__index' chain too long; possible loop"))
+    }
+
+    /// `obj[key] = value`, honouring `__newindex`.
+    pub fn set_index(&self, obj: &Value, key: Value, value: Value) -> Result<(), Error> {
+        let mut obj = obj.clone();
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &obj {
+                Value::Table(t) => match self.metafield(&obj, "__newindex") {
+                    Value::Nil => return t.set(key, value),
+                    _ if !t.get(&key).is_nil() => return t.set(key, value),
+                    handler => handler,
+                },
+                _ => match self.metafield(&obj, "__newindex") {
+                    Value::Nil => {
+                        return Err(Error::new(format!(
+                            "attempt to index a {} value",
+                            type_name_of(&obj)
+                        )))
+                    }
+                    handler => handler,
+                },
+            };
+            if let Value::Function(_) = handler {
+                self.call(&handler, vec![obj, key, value])?;
+                return Ok(());
+            }
+            obj = handler;
+        }
+        Err(Error::new("'__newindex' chain too long; possible loop"))
+    }
+
+    /// `#v`, honouring `__len`.
+    pub fn len(&self, v: &Value) -> Result<Value, Error> {
+        match self.metafield(v, "__len") {
+            Value::Nil => match v {
+                Value::String(s) => Ok(Value::Integer(s.len() as i64)),
+                Value::Table(t) => Ok(Value::Integer(t.len())),
+                v => Err(Error::new(format!(
+                    "attempt to get length of a {} value",
+                    type_name_of(v)
+                ))),
+            },
+            handler => self.call1(&handler, vec![v.clone()]),
+        }
+    }
+
+    /// `#v` as an integer, as `luaL_len`.
+    pub fn len_int(&self, v: &Value) -> Result<i64, Error> {
+        self.len(v)?
+            .to_integer()
+            .ok_or_else(|| Error::new("object length is not an integer"))
+    }
+
+    /// `a == b`, honouring `__eq`.
+    pub fn equals(&self, a: &Value, b: &Value) -> Result<bool, Error> {
+        if a.raw_equal(b) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) | (Value::Userdata(_), Value::Userdata(_)) =
+            (a, b)
+        {
+            let handler = match self.metafield(a, "__eq") {
+                Value::Nil => self.metafield(b, "__eq"),
+                handler => handler,
+            };
+            if !handler.is_nil() {
+                return Ok(self
+                    .call1(&handler, vec![a.clone(), b.clone()])?
+                    .is_truthy());
+            }
+        }
+        Ok(false)
+    }
+
+    /// `a < b`, honouring `__lt`.
+    pub fn less_than(&self, a: &Value, b: &Value) -> Result<bool, Error> {
+        self.compare(a, b, "__lt", |o| o.is_lt())
+    }
+
+    /// `a <= b`, honouring `__le`.
+    pub fn less_equal(&self, a: &Value, b: &Value) -> Result<bool, Error> {
+        self.compare(a, b, "__le", |o| o.is_le())
+    }
+
+    fn compare(
+        &self,
+        a: &Value,
+        b: &Value,
+        event: &str,
+        test: fn(std::cmp::Ordering) -> bool,
+    ) -> Result<bool, Error> {
+        match (a, b) {
+            (Value::Integer(_) | Value::Number(_), Value::Integer(_) | Value::Number(_)) => {
+                let (x, y) = (a.to_number().unwrap(), b.to_number().unwrap());
+                Ok(compare_numbers(x, y).is_some_and(test))
+            }
+            (Value::String(x), Value::String(y)) => Ok(test(x.as_bytes().cmp(y.as_bytes()))),
+            _ => {
+                let handler = match self.metafield(a, event) {
+                    Value::Nil => self.metafield(b, event),
+                    handler => handler,
+                };
+                if handler.is_nil() {
+                    let (t1, t2) = (type_name_of(a), type_name_of(b));
+                    return Err(Error::new(if t1 == t2 {
+                        format!("attempt to compare two {} values", t1)
+                    } else {
+                        format!("attempt to compare {} with {}", t1, t2)
+                    }));
+                }
+                Ok(self
+                    .call1(&handler, vec![a.clone(), b.clone()])?
+                    .is_truthy())
+            }
+        }
+    }
+
+    /// Converts any value to a string as `tostring` does, honouring
+    /// `__tostring` and `__name`.
+    pub fn tostring(&self, v: &Value) -> Result<LuaString, Error> {
+        let handler = self.metafield(v, "__tostring");
+        if !handler.is_nil() {
+            return match self.call1(&handler, vec![v.clone()])? {
+                Value::String(s) => Ok(s),
+                _ => Err(Error::new("'__tostring' must return a string")),
+            };
+        }
+        Ok(match v {
+            Value::Nil => "nil".into(),
+            Value::Boolean(b) => b.to_string().into(),
+            Value::Integer(_) | Value::Number(_) | Value::String(_) => v.to_lua_string().unwrap(),
+            v => format!("{}: {:p}", type_name_of(v), v.address().unwrap()).into(),
+        })
+    }
+}
+
+/// Plain native functions, as registered by the standard libraries.
+pub type NativeFnPtr = fn(&State, Args) -> Result<Vec<Value>, Error>;
+
+/// Orders two numbers by their mathematical values; `None` if either is NaN.
+pub(crate) fn compare_numbers(x: Number, y: Number) -> Option<std::cmp::Ordering> {
+    use std::cmp::Ordering;
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    let int_float = |i: i64, f: f64| -> Option<Ordering> {
+        if f.is_nan() {
+            None
+        } else if f >= LIMIT {
+            Some(Ordering::Less)
+        } else if f < -LIMIT {
+            Some(Ordering::Greater)
+        } else {
+            // Compare with the integral part, then the fraction decides.
+            let t = f.trunc();
+            Some((i, 0.0).partial_cmp(&(t as i64, f - t)).unwrap())
+        }
+    };
+    match (x, y) {
+        (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(&b)),
+        (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+        (Number::Integer(a), Number::Float(b)) => int_float(a, b),
+        (Number::Float(a), Number::Integer(b)) => int_float(b, a).map(Ordering::reverse),
+    }
+}
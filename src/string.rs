@@ -0,0 +1,301 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Deref,
+    str::Utf8Error,
+    sync::{Arc, Mutex, Weak},
+};
+
+use bytes::Bytes;
+
+use crate::constants::LUAI_MAXSHORTLEN;
+
+/// A Lua string, which is an arbitrary sequence of bytes rather than UTF-8
+/// text.
+///
+/// Short strings carry their hash, computed once when they are made, so that
+/// table lookups need not hash them again. Those obtained from the same
+/// [`Interner`] share their storage, which equality checks before it
+/// compares hashes and bytes; strings made directly, as the libraries do,
+/// compare equal all the same.
+#[derive(Clone)]
+pub struct LuaString(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Short(Arc<Short>),
+    Long(Bytes),
+}
+
+struct Short {
+    hash: u32,
+    bytes: Box<[u8]>,
+}
+
+impl Short {
+    fn new(bytes: &[u8]) -> Arc<Self> {
+        Arc::new(Self {
+            hash: hash(bytes),
+            bytes: bytes.into(),
+        })
+    }
+}
+
+/// Hashes `bytes` as `luaS_hash`, with a fixed seed.
+fn hash(bytes: &[u8]) -> u32 {
+    const SEED: u32 = 0x2545_f491;
+    let mut h = SEED ^ bytes.len() as u32;
+    for &b in bytes.iter().rev() {
+        h ^= (h << 5).wrapping_add(h >> 2).wrapping_add(b as u32);
+    }
+    h
+}
+
+impl LuaString {
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Short(s) => &s.bytes,
+            Repr::Long(b) => b,
+        }
+    }
+
+    pub fn is_short(&self) -> bool {
+        matches!(self.0, Repr::Short(_))
+    }
+
+    /// Returns whether both strings share the same storage, which for interned
+    /// short strings is equivalent to equality.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Short(a), Repr::Short(b)) => Arc::ptr_eq(a, b),
+            (Repr::Long(a), Repr::Long(b)) => a.as_ptr() == b.as_ptr() && a.len() == b.len(),
+            _ => false,
+        }
+    }
+
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(self.as_bytes())
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        match self.0 {
+            Repr::Short(s) => Bytes::copy_from_slice(&s.bytes),
+            Repr::Long(b) => b,
+        }
+    }
+}
+
+impl Default for LuaString {
+    fn default() -> Self {
+        Self(Repr::Short(Short::new(b"")))
+    }
+}
+
+impl PartialEq for LuaString {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Short(a), Repr::Short(b)) => {
+                Arc::ptr_eq(a, b) || (a.hash == b.hash && a.bytes == b.bytes)
+            }
+            (Repr::Long(a), Repr::Long(b)) => a == b,
+            // Lengths tell short strings from long ones.
+            _ => false,
+        }
+    }
+}
+
+impl Eq for LuaString {}
+
+impl PartialOrd for LuaString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LuaString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+/// Short strings feed only their cached hash; long ones, which are rarely
+/// keys, are hashed in full.
+impl Hash for LuaString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Repr::Short(s) => state.write_u32(s.hash),
+            Repr::Long(b) => b.hash(state),
+        }
+    }
+}
+
+impl Deref for LuaString {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for LuaString {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Debug for LuaString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{}\"", self.as_bytes().escape_ascii())
+    }
+}
+
+/// Displays the string lossily, replacing invalid UTF-8 sequences.
+impl Display for LuaString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&String::from_utf8_lossy(self.as_bytes()), f)
+    }
+}
+
+impl From<Bytes> for LuaString {
+    fn from(b: Bytes) -> Self {
+        if b.len() <= LUAI_MAXSHORTLEN {
+            Self(Repr::Short(Short::new(&b)))
+        } else {
+            Self(Repr::Long(b))
+        }
+    }
+}
+
+impl From<Vec<u8>> for LuaString {
+    fn from(v: Vec<u8>) -> Self {
+        Bytes::from(v).into()
+    }
+}
+
+impl From<&[u8]> for LuaString {
+    fn from(s: &[u8]) -> Self {
+        if s.len() <= LUAI_MAXSHORTLEN {
+            Self(Repr::Short(Short::new(s)))
+        } else {
+            Self(Repr::Long(Bytes::copy_from_slice(s)))
+        }
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(s: String) -> Self {
+        s.into_bytes().into()
+    }
+}
+
+impl From<&str> for LuaString {
+    fn from(s: &str) -> Self {
+        s.as_bytes().into()
+    }
+}
+
+/// Interns short strings so that equal short strings share one allocation, as
+/// reference Lua does. Long strings are never interned.
+///
+/// Only weak references are kept, so a string is freed with its last use;
+/// dead entries are swept whenever the table has doubled since the last
+/// sweep, as the collector clears the string table of reference Lua.
+#[derive(Default)]
+pub struct Interner {
+    strings: Mutex<Strings>,
+}
+
+#[derive(Default)]
+struct Strings {
+    /// Entries by hash.
+    buckets: HashMap<u32, Vec<Weak<Short>>>,
+    /// Number of entries, dead or alive.
+    len: usize,
+    /// Number of entries at which to sweep next.
+    sweep_at: usize,
+}
+
+impl Strings {
+    const MIN_SWEEP: usize = 64;
+
+    fn sweep(&mut self) {
+        self.buckets.retain(|_, bucket| {
+            bucket.retain(|s| s.strong_count() > 0);
+            !bucket.is_empty()
+        });
+        self.len = self.buckets.values().map(Vec::len).sum();
+        self.sweep_at = (self.len * 2).max(Self::MIN_SWEEP);
+    }
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&self, s: impl Into<LuaString> + AsRef<[u8]>) -> LuaString {
+        let bytes = s.as_ref();
+        if bytes.len() > LUAI_MAXSHORTLEN {
+            return s.into();
+        }
+        let hash = hash(bytes);
+        let mut strings = self.strings.lock().unwrap();
+        let bucket = strings.buckets.entry(hash).or_default();
+        let found = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|interned| *interned.bytes == *bytes);
+        if let Some(interned) = found {
+            return LuaString(Repr::Short(interned));
+        }
+        let short = Arc::new(Short {
+            hash,
+            bytes: bytes.into(),
+        });
+        bucket.push(Arc::downgrade(&short));
+        strings.len += 1;
+        if strings.len >= strings.sweep_at {
+            strings.sweep();
+        }
+        LuaString(Repr::Short(short))
+    }
+
+    /// Returns the number of live interned strings.
+    pub fn len(&self) -> usize {
+        let strings = self.strings.lock().unwrap();
+        strings
+            .buckets
+            .values()
+            .flatten()
+            .filter(|s| s.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Debug for Interner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interner")
+            .field("len", &self.len())
+            .finish()
+    }
+}
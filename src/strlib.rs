@@ -0,0 +1,701 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The string library, as `lstrlib.c`.
+
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    args::Args,
+    dump::dump,
+    lpattern::{no_specials, Matcher},
+    number, pack,
+    state::State,
+    string::LuaString,
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+impl State {
+    /// Opens the string library and sets the metatable of strings, so that
+    /// `s:upper()` finds `string.upper`.
+    pub fn open_string(&self) {
+        let string = Table::new();
+        self.register(
+            &string,
+            &[
+                ("byte", byte),
+                ("char", char),
+                ("dump", dump_),
+                ("find", find),
+                ("format", format),
+                ("gmatch", gmatch),
+                ("gsub", gsub),
+                ("len", len),
+                ("lower", lower),
+                ("match", match_),
+                ("pack", pack::pack),
+                ("packsize", pack::packsize),
+                ("rep", rep),
+                ("reverse", reverse),
+                ("sub", sub),
+                ("unpack", pack::unpack),
+                ("upper", upper),
+            ],
+        );
+        let mt = Table::new();
+        mt.set_str("__index", string.clone());
+        self.set_string_metatable(Some(mt));
+        self.set_library("string", string);
+    }
+}
+
+/// Translates a relative initial position: negative means back from the end,
+/// and positions before the start clip to 1.
+pub(crate) fn posrelat_start(pos: i64, len: usize) -> usize {
+    if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos < -(len as i64) {
+        1
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+/// Translates a relative end position, clipping it to `0..=len`.
+pub(crate) fn posrelat_end(pos: i64, len: usize) -> usize {
+    if pos > len as i64 {
+        len
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos < -(len as i64) {
+        0
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+fn byte(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    // The end defaults to the start as given, not as translated.
+    let init = args.opt_integer(2, 1)?;
+    let pose = posrelat_end(args.opt_integer(3, init)?, s.len());
+    let pi = posrelat_start(init, s.len());
+    if pi > pose {
+        return Ok(vec![]);
+    }
+    if pose - pi >= i32::MAX as usize {
+        return Err(Error::new("string slice too long"));
+    }
+    Ok(s[pi - 1..pose]
+        .iter()
+        .map(|&b| Value::Integer(b as i64))
+        .collect())
+}
+
+fn char(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut s = Vec::with_capacity(args.len());
+    for n in 1..=args.len() {
+        let c = args.check_integer(n)?;
+        if !(0..=u8::MAX as i64).contains(&c) {
+            return Err(args.error(n, "value out of range"));
+        }
+        s.push(c as u8);
+    }
+    Ok(vec![Value::String(s.into())])
+}
+
+/// Dumps a Lua function as a binary chunk. `strip` is accepted but debug
+/// information is always kept, which the manual allows.
+fn dump_(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    match args.get(1) {
+        Value::Function(Function::Lua(closure)) => {
+            Ok(vec![Value::String(dump(closure.proto()).into())])
+        }
+        Value::Function(_) => Err(Error::new("unable to dump given function")),
+        _ => Err(args.type_error(1, "function")),
+    }
+}
+
+fn find(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    str_find_aux(args, true)
+}
+
+fn match_(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    str_find_aux(args, false)
+}
+
+fn str_find_aux(args: Args, find: bool) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let p = args.check_string(2)?;
+    let init = posrelat_start(args.opt_integer(3, 1)?, s.len()) - 1;
+    if init > s.len() {
+        return Ok(vec![Value::Nil]);
+    }
+    if find && (args.get(4).is_truthy() || no_specials(&p)) {
+        // A plain search.
+        let found = if p.is_empty() {
+            Some(0)
+        } else {
+            s[init..].windows(p.len()).position(|w| w == &p[..])
+        };
+        if let Some(i) = found {
+            let start = init + i;
+            return Ok(vec![
+                Value::Integer(start as i64 + 1),
+                Value::Integer((start + p.len()) as i64),
+            ]);
+        }
+    } else {
+        let anchor = p.first() == Some(&b'^');
+        let pstart = anchor as usize;
+        let mut m = Matcher::new(&s, &p);
+        let mut s1 = init;
+        loop {
+            if let Some(e) = m.match_at(s1, pstart)? {
+                if find {
+                    let mut results = vec![Value::Integer(s1 as i64 + 1), Value::Integer(e as i64)];
+                    results.extend(m.captures(s1, e, false)?);
+                    return Ok(results);
+                }
+                return m.captures(s1, e, true);
+            }
+            s1 += 1;
+            if s1 > s.len() || anchor {
+                break;
+            }
+        }
+    }
+    Ok(vec![Value::Nil])
+}
+
+/// Flags valid for each conversion, as in `lstrlib.c`.
+const FLAGS_F: &[u8] = b"-+ #0";
+const FLAGS_X: &[u8] = b"-#0";
+const FLAGS_I: &[u8] = b"-+ 0";
+const FLAGS_U: &[u8] = b"-0";
+const FLAGS_C: &[u8] = b"-";
+/// Maximum size of a conversion specification such as `%-099.99d`.
+const MAX_FORMAT: usize = 32;
+
+/// A conversion specification of `string.format`, without its conversion.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Parses the flags, width and precision of `form`.
+    fn parse(form: &[u8]) -> Self {
+        let at = |i: usize| form.get(i).copied().unwrap_or(0);
+        let mut spec = Spec::default();
+        let mut i = 1;
+        loop {
+            match at(i) {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        while at(i).is_ascii_digit() {
+            spec.width = spec
+                .width
+                .saturating_mul(10)
+                .saturating_add((at(i) - b'0') as usize);
+            i += 1;
+        }
+        if at(i) == b'.' {
+            i += 1;
+            let mut precision: usize = 0;
+            while at(i).is_ascii_digit() {
+                precision = precision
+                    .saturating_mul(10)
+                    .saturating_add((at(i) - b'0') as usize);
+                i += 1;
+            }
+            spec.precision = Some(precision);
+        }
+        spec
+    }
+
+    /// The sign of a signed conversion.
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Appends `prefix` and `body` padded to the width, with zeros between
+    /// them if `zero` is set.
+    fn pad(&self, b: &mut Vec<u8>, prefix: &str, body: &[u8], zero: bool) {
+        let fill = self.width.saturating_sub(prefix.len() + body.len());
+        if self.left {
+            b.extend_from_slice(prefix.as_bytes());
+            b.extend_from_slice(body);
+            b.resize(b.len() + fill, b' ');
+        } else if zero {
+            b.extend_from_slice(prefix.as_bytes());
+            b.resize(b.len() + fill, b'0');
+            b.extend_from_slice(body);
+        } else {
+            b.resize(b.len() + fill, b' ');
+            b.extend_from_slice(prefix.as_bytes());
+            b.extend_from_slice(body);
+        }
+    }
+}
+
+/// Checks that `form` only has `flags`, a width of up to two digits that
+/// does not start with '0' and, if `precision` is allowed, a precision of up
+/// to two digits.
+fn check_format(form: &[u8], flags: &[u8], precision: bool) -> Result<(), Error> {
+    let two_digits = |mut i: usize| {
+        for _ in 0..2 {
+            if form.get(i).is_some_and(u8::is_ascii_digit) {
+                i += 1;
+            }
+        }
+        i
+    };
+    let mut i = 1;
+    while form.get(i).is_some_and(|c| flags.contains(c)) {
+        i += 1;
+    }
+    if form.get(i) != Some(&b'0') {
+        i = two_digits(i);
+        if form.get(i) == Some(&b'.') && precision {
+            i = two_digits(i + 1);
+        }
+    }
+    if form.get(i).is_some_and(u8::is_ascii_alphabetic) {
+        Ok(())
+    } else {
+        Err(Error::new(format!(
+            "invalid conversion specification: '{}'",
+            String::from_utf8_lossy(form)
+        )))
+    }
+}
+
+fn format(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let fmt = args.check_string(1)?;
+    let mut b = Vec::with_capacity(fmt.len());
+    let mut arg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            b.push(c);
+            continue;
+        }
+        if fmt.get(i) == Some(&b'%') {
+            b.push(b'%');
+            i += 1;
+            continue;
+        }
+        arg += 1;
+        if !args.is_given(arg) {
+            return Err(args.error(arg, "no value"));
+        }
+        // Span flags, width and precision, plus the conversion.
+        let len = fmt[i..]
+            .iter()
+            .take_while(|c| b"-+ #0123456789.".contains(c))
+            .count();
+        if len + 1 >= MAX_FORMAT - 10 {
+            return Err(Error::new("invalid format string to 'format'"));
+        }
+        let end = (i + len + 1).min(fmt.len());
+        let form = [b"%", &fmt[i..end]].concat();
+        let conv = fmt.get(i + len).copied();
+        i = end;
+        let spec = Spec::parse(&form);
+        match conv {
+            Some(b'c') => {
+                check_format(&form, FLAGS_C, false)?;
+                let c = args.check_integer(arg)?;
+                spec.pad(&mut b, "", &[c as u8], false);
+            }
+            Some(conv @ (b'd' | b'i' | b'u' | b'o' | b'x' | b'X')) => {
+                let n = args.check_integer(arg)?;
+                let flags = match conv {
+                    b'd' | b'i' => FLAGS_I,
+                    b'u' => FLAGS_U,
+                    _ => FLAGS_X,
+                };
+                check_format(&form, flags, true)?;
+                format_integer(&mut b, &spec, conv, n);
+            }
+            Some(conv @ (b'a' | b'A' | b'f' | b'F' | b'e' | b'E' | b'g' | b'G')) => {
+                let x = args.check_float(arg)?;
+                check_format(&form, FLAGS_F, true)?;
+                format_float(&mut b, &spec, conv, x);
+            }
+            Some(b'p') => {
+                check_format(&form, FLAGS_C, false)?;
+                let p = match args.get(arg) {
+                    Value::String(s) => Some(s.as_ptr() as *const ()),
+                    v => v.address(),
+                };
+                match p {
+                    Some(p) => spec.pad(&mut b, "", format!("{:p}", p).as_bytes(), false),
+                    None => spec.pad(&mut b, "", b"(null)", false),
+                }
+            }
+            Some(b'q') => {
+                if form.len() > 2 {
+                    return Err(Error::new("specifier '%q' cannot have modifiers"));
+                }
+                add_literal(state, &mut b, &args, arg)?;
+            }
+            Some(b's') => {
+                let s = state.tostring(args.get(arg))?;
+                if form.len() == 2 {
+                    b.extend_from_slice(&s);
+                } else {
+                    if s.contains(&0) {
+                        return Err(args.error(arg, "string contains zeros"));
+                    }
+                    check_format(&form, FLAGS_C, true)?;
+                    if spec.precision.is_none() && s.len() >= 100 {
+                        // Too long to be formatted: keep it entire.
+                        b.extend_from_slice(&s);
+                    } else {
+                        let n = spec.precision.map_or(s.len(), |p| p.min(s.len()));
+                        spec.pad(&mut b, "", &s[..n], false);
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::new(format!(
+                    "invalid conversion '{}' to 'format'",
+                    String::from_utf8_lossy(&form)
+                )))
+            }
+        }
+    }
+    Ok(vec![Value::String(b.into())])
+}
+
+fn format_integer(b: &mut Vec<u8>, spec: &Spec, conv: u8, n: i64) {
+    let (sign, mut digits) = match conv {
+        b'd' | b'i' => (spec.sign(n < 0), n.unsigned_abs().to_string()),
+        b'u' => ("", (n as u64).to_string()),
+        b'o' => ("", format!("{:o}", n as u64)),
+        b'x' => ("", format!("{:x}", n as u64)),
+        _ => ("", format!("{:X}", n as u64)),
+    };
+    match spec.precision {
+        Some(0) if n == 0 => digits.clear(),
+        Some(p) if digits.len() < p => digits.insert_str(0, &"0".repeat(p - digits.len())),
+        _ => {}
+    }
+    let prefix = match conv {
+        b'o' if spec.alt && !digits.starts_with('0') => {
+            digits.insert(0, '0');
+            sign
+        }
+        b'x' if spec.alt && n != 0 => "0x",
+        b'X' if spec.alt && n != 0 => "0X",
+        _ => sign,
+    };
+    // A precision disables the '0' flag.
+    let zero = spec.zero && spec.precision.is_none();
+    spec.pad(b, prefix, digits.as_bytes(), zero);
+}
+
+fn format_float(b: &mut Vec<u8>, spec: &Spec, conv: u8, x: f64) {
+    let y = x.abs();
+    let body = match conv.to_ascii_lowercase() {
+        b'a' => number::format_a(y, spec.precision, spec.alt),
+        b'e' => number::format_e(y, spec.precision.unwrap_or(6), spec.alt),
+        b'f' => number::format_f(y, spec.precision.unwrap_or(6), spec.alt),
+        _ => number::format_g(y, spec.precision.unwrap_or(6), spec.alt),
+    };
+    let body = if conv.is_ascii_uppercase() {
+        body.to_ascii_uppercase()
+    } else {
+        body
+    };
+    let sign = spec.sign(x.is_sign_negative());
+    // The '0x' of '%a' goes before the zeros of the padding.
+    let (prefix, body) = match body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        Some(rest) => ([sign, &body[..2]].concat(), rest),
+        None => (sign.to_string(), body.as_str()),
+    };
+    // Infinity and NaN are padded with spaces.
+    let zero = spec.zero && x.is_finite();
+    spec.pad(b, &prefix, body.as_bytes(), zero);
+}
+
+/// Appends `%q`: a literal that reads back as the same value.
+fn add_literal(state: &State, b: &mut Vec<u8>, args: &Args, arg: usize) -> Result<(), Error> {
+    match args.get(arg) {
+        Value::String(s) => add_quoted(b, s),
+        // The decimal form of the minimum integer would read as a float.
+        Value::Integer(i64::MIN) => b.extend_from_slice(b"0x8000000000000000"),
+        Value::Integer(i) => b.extend_from_slice(i.to_string().as_bytes()),
+        Value::Number(x) => {
+            let s = if *x == f64::INFINITY {
+                "1e9999".to_string()
+            } else if *x == f64::NEG_INFINITY {
+                "-1e9999".to_string()
+            } else if x.is_nan() {
+                "(0/0)".to_string()
+            } else {
+                number::format_a(*x, None, false)
+            };
+            b.extend_from_slice(s.as_bytes());
+        }
+        v @ (Value::Nil | Value::Boolean(_)) => b.extend_from_slice(&state.tostring(v)?),
+        _ => return Err(args.error(arg, "value has no literal form")),
+    }
+    Ok(())
+}
+
+fn add_quoted(b: &mut Vec<u8>, s: &[u8]) {
+    b.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        if matches!(c, b'"' | b'\\' | b'\n') {
+            b.push(b'\\');
+            b.push(c);
+        } else if c.is_ascii_control() {
+            // Pad to three digits if a digit follows.
+            if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                b.extend_from_slice(format!("\\{:03}", c).as_bytes());
+            } else {
+                b.extend_from_slice(format!("\\{}", c).as_bytes());
+            }
+        } else {
+            b.push(c);
+        }
+    }
+    b.push(b'"');
+}
+
+fn gmatch(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let p = args.check_string(2)?;
+    let init = posrelat_start(args.opt_integer(3, 1)?, s.len()) - 1;
+    // Start past the end when `init` is beyond it, so nothing matches.
+    let pos = Rc::new(Cell::new(init.min(s.len() + 1)));
+    let lastmatch = Rc::new(Cell::new(None));
+    let iter = Function::native("gmatch_aux", move |_, _| {
+        let mut m = Matcher::new(&s, &p);
+        let mut src = pos.get();
+        while src <= s.len() {
+            match m.match_at(src, 0)? {
+                Some(e) if Some(e) != lastmatch.get() => {
+                    pos.set(e);
+                    lastmatch.set(Some(e));
+                    return m.captures(src, e, true);
+                }
+                _ => src += 1,
+            }
+        }
+        pos.set(src);
+        Ok(vec![])
+    });
+    Ok(vec![iter.into()])
+}
+
+fn gsub(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let src = args.check_string(1)?;
+    let p = args.check_string(2)?;
+    let repl = args.get(3).clone();
+    match repl {
+        Value::Integer(_)
+        | Value::Number(_)
+        | Value::String(_)
+        | Value::Table(_)
+        | Value::Function(_) => {}
+        _ => return Err(args.type_error(3, "string/function/table")),
+    }
+    let max_s = args.opt_integer(4, src.len() as i64 + 1)?;
+    let anchor = p.first() == Some(&b'^');
+    let pstart = anchor as usize;
+    let mut m = Matcher::new(&src, &p);
+    let mut b = Vec::with_capacity(src.len());
+    let mut s = 0;
+    let mut lastmatch = None;
+    let mut n = 0;
+    let mut changed = false;
+    while n < max_s {
+        match m.match_at(s, pstart)? {
+            Some(e) if Some(e) != lastmatch => {
+                n += 1;
+                changed |= add_value(state, &m, &mut b, &src, s, e, &repl)?;
+                s = e;
+                lastmatch = Some(e);
+            }
+            _ if s < src.len() => {
+                b.push(src[s]);
+                s += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    let result = if changed {
+        b.extend_from_slice(&src[s..]);
+        LuaString::from(b)
+    } else {
+        src.clone()
+    };
+    Ok(vec![Value::String(result), Value::Integer(n)])
+}
+
+/// Appends the replacement for the match `s..e` to `b`, returning whether it
+/// differs from the original text.
+fn add_value(
+    state: &State,
+    m: &Matcher,
+    b: &mut Vec<u8>,
+    src: &[u8],
+    s: usize,
+    e: usize,
+    repl: &Value,
+) -> Result<bool, Error> {
+    let value = match repl {
+        Value::Function(_) => state.call1(repl, m.captures(s, e, true)?)?,
+        Value::Table(_) => state.index(repl, &m.capture(0, s, e)?)?,
+        _ => {
+            add_s(state, m, b, src, s, e, &repl.to_lua_string().unwrap())?;
+            return Ok(true);
+        }
+    };
+    if !value.is_truthy() {
+        // Keep the original text.
+        b.extend_from_slice(&src[s..e]);
+        return Ok(false);
+    }
+    match value.to_lua_string() {
+        Some(v) => {
+            b.extend_from_slice(&v);
+            Ok(true)
+        }
+        None => Err(Error::new(format!(
+            "invalid replacement value (a {})",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Appends a replacement string, expanding `%0`-`%9` and `%%`.
+fn add_s(
+    state: &State,
+    m: &Matcher,
+    b: &mut Vec<u8>,
+    src: &[u8],
+    s: usize,
+    e: usize,
+    repl: &[u8],
+) -> Result<(), Error> {
+    let mut i = 0;
+    while i < repl.len() {
+        if repl[i] != b'%' {
+            b.push(repl[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match repl.get(i) {
+            Some(b'%') => b.push(b'%'),
+            Some(b'0') => b.extend_from_slice(&src[s..e]),
+            Some(&d) if d.is_ascii_digit() => {
+                let v = m.capture((d - b'1') as usize, s, e)?;
+                b.extend_from_slice(&state.tostring(&v)?);
+            }
+            _ => return Err(Error::new("invalid use of '%' in replacement string")),
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn len(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    Ok(vec![Value::Integer(s.len() as i64)])
+}
+
+fn lower(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    Ok(vec![Value::String(s.to_ascii_lowercase().into())])
+}
+
+fn rep(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let n = args.check_integer(2)?;
+    let sep = args.opt_string(3)?.unwrap_or_default();
+    if n <= 0 {
+        return Ok(vec![Value::from("")]);
+    }
+    let n = n as usize;
+    let total = (s.len() + sep.len())
+        .checked_mul(n)
+        .filter(|total| *total < isize::MAX as usize)
+        .ok_or_else(|| Error::new("resulting string too large"))?;
+    let mut b = Vec::new();
+    b.try_reserve_exact(total)
+        .map_err(|_| Error::new("not enough memory"))?;
+    for i in 0..n {
+        if i > 0 {
+            b.extend_from_slice(&sep);
+        }
+        b.extend_from_slice(&s);
+    }
+    Ok(vec![Value::String(b.into())])
+}
+
+fn reverse(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    Ok(vec![Value::String(
+        s.iter().rev().copied().collect::<Vec<_>>().into(),
+    )])
+}
+
+fn sub(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let start = posrelat_start(args.check_integer(2)?, s.len());
+    let end = posrelat_end(args.opt_integer(3, -1)?, s.len());
+    Ok(vec![Value::String(if start <= end {
+        LuaString::from(&s[start - 1..end])
+    } else {
+        LuaString::from("")
+    })])
+}
+
+fn upper(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    Ok(vec![Value::String(s.to_ascii_uppercase().into())])
+}
@@ -0,0 +1,317 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    rc::Rc,
+};
+
+use crate::{
+    number::float_to_int,
+    string::LuaString,
+    value::{Error, Value},
+};
+
+/// A Lua table. Cloning it yields another reference to the same table.
+///
+/// Like the reference implementation, it keeps an array part for the keys
+/// `1..=n` and a hash part for the others, which iterates in insertion order.
+#[derive(Clone, Default)]
+pub struct Table(Rc<RefCell<TableData>>);
+
+#[derive(Default)]
+struct TableData {
+    array: Vec<Value>,
+    /// Keys and values of the hash part. Removed entries keep their slot with
+    /// a `nil` value, so that `next` can continue past them.
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    /// Number of entries with a `nil` value.
+    dead: usize,
+    metatable: Option<Table>,
+}
+
+/// The identity of a key in the hash part.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    String(LuaString),
+    Ref(*const ()),
+}
+
+impl Key {
+    /// Returns the key of `v`, or `None` if `v` can't index a table. Floats
+    /// with integer values are normalized to integers.
+    fn of(v: &Value) -> Option<Key> {
+        Some(match v {
+            Value::Nil => return None,
+            Value::Boolean(b) => Key::Boolean(*b),
+            Value::Integer(i) => Key::Integer(*i),
+            Value::Number(f) if f.is_nan() => return None,
+            Value::Number(f) => match float_to_int(*f) {
+                Some(i) => Key::Integer(i),
+                // Both zeros are integers, so the bits identify the float.
+                None => Key::Float(f.to_bits()),
+            },
+            Value::String(s) => Key::String(s.clone()),
+            v => Key::Ref(v.address().unwrap()),
+        })
+    }
+}
+
+/// Normalizes a float key with an integer value to that integer.
+fn normalize(key: Value) -> Value {
+    match key {
+        Value::Number(f) => match float_to_int(f) {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(f),
+        },
+        key => key,
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a table with room for `narray` sequence elements and `nhash`
+    /// other entries.
+    pub fn with_capacity(narray: usize, nhash: usize) -> Self {
+        Self(Rc::new(RefCell::new(TableData {
+            array: Vec::with_capacity(narray),
+            entries: Vec::with_capacity(nhash),
+            index: HashMap::with_capacity(nhash),
+            ..TableData::default()
+        })))
+    }
+
+    pub fn ptr_eq(&self, other: &Table) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+
+    pub fn metatable(&self) -> Option<Table> {
+        self.0.borrow().metatable.clone()
+    }
+
+    pub fn set_metatable(&self, metatable: Option<Table>) {
+        self.0.borrow_mut().metatable = metatable;
+    }
+
+    /// Reads `t[key]` without invoking metamethods.
+    pub fn get(&self, key: &Value) -> Value {
+        let data = self.0.borrow();
+        if let Some(i) = key.to_array_index(data.array.len()) {
+            return data.array[i].clone();
+        }
+        match Key::of(key).and_then(|k| data.index.get(&k)) {
+            Some(&i) => data.entries[i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_int(&self, i: i64) -> Value {
+        self.get(&Value::Integer(i))
+    }
+
+    pub fn get_str(&self, name: &str) -> Value {
+        self.get(&Value::String(name.into()))
+    }
+
+    /// Writes `t[key] = value` without invoking metamethods.
+    pub fn set(&self, key: Value, value: Value) -> Result<(), Error> {
+        let k = match Key::of(&key) {
+            Some(k) => k,
+            None if key.is_nil() => return Err(Error::new("index is nil")),
+            None => return Err(Error::new("index is NaN")),
+        };
+        let key = normalize(key);
+        let mut data = self.0.borrow_mut();
+        let len = data.array.len();
+        if let Some(i) = key.to_array_index(len) {
+            data.array[i] = value;
+            return Ok(());
+        }
+        if let Some(&i) = data.index.get(&k) {
+            match (data.entries[i].1.is_nil(), value.is_nil()) {
+                (true, false) => data.dead -= 1,
+                (false, true) => data.dead += 1,
+                _ => {}
+            }
+            data.entries[i].1 = value;
+            return Ok(());
+        }
+        if value.is_nil() {
+            return Ok(());
+        }
+        if k == Key::Integer(len as i64 + 1) {
+            data.array.push(value);
+            data.migrate();
+            return Ok(());
+        }
+        if data.dead > 8 && data.dead * 2 > data.entries.len() {
+            data.compact();
+        }
+        let i = data.entries.len();
+        data.entries.push((key, value));
+        data.index.insert(k, i);
+        Ok(())
+    }
+
+    pub fn set_int(&self, i: i64, value: Value) {
+        self.set(Value::Integer(i), value).unwrap();
+    }
+
+    pub fn set_str(&self, name: &str, value: impl Into<Value>) {
+        self.set(Value::String(name.into()), value.into()).unwrap();
+    }
+
+    /// Returns a border of the table, that is some `n` where `t[n]` is not
+    /// `nil` and `t[n + 1]` is, or 0 if `t[1]` is `nil`.
+    pub fn len(&self) -> i64 {
+        let data = self.0.borrow();
+        let n = data.array.len();
+        if n > 0 && data.array[n - 1].is_nil() {
+            // t[lo] is non-nil (or lo is 0) and t[hi] is nil.
+            let (mut lo, mut hi) = (0, n);
+            while hi - lo > 1 {
+                let m = (lo + hi) / 2;
+                if data.array[m - 1].is_nil() {
+                    hi = m;
+                } else {
+                    lo = m;
+                }
+            }
+            return lo as i64;
+        }
+        drop(data);
+        let present = |i: i64| !self.get_int(i).is_nil();
+        let (mut lo, mut hi) = (n as i64, n as i64 + 1);
+        while present(hi) {
+            lo = hi;
+            if hi > i64::MAX / 2 {
+                // Pathological table: fall back to a linear search.
+                let mut i = lo;
+                while i < i64::MAX && present(i + 1) {
+                    i += 1;
+                }
+                return i;
+            }
+            hi *= 2;
+        }
+        while hi - lo > 1 {
+            let m = lo + (hi - lo) / 2;
+            if present(m) {
+                lo = m;
+            } else {
+                hi = m;
+            }
+        }
+        lo
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next(&Value::Nil).ok().flatten().is_none()
+    }
+
+    /// Returns the entry after `key` in traversal order, starting from the
+    /// first one when `key` is `nil`.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, Error> {
+        let data = self.0.borrow();
+        let n = data.array.len();
+        let mut start = match key {
+            Value::Nil => 0,
+            key => match key.to_array_index(n) {
+                Some(i) => i + 1,
+                None => match Key::of(key).and_then(|k| data.index.get(&k)) {
+                    Some(&i) => n + i + 1,
+                    None => return Err(Error::new("invalid key to 'next'")),
+                },
+            },
+        };
+        while start < n {
+            if !data.array[start].is_nil() {
+                return Ok(Some((
+                    Value::Integer(start as i64 + 1),
+                    data.array[start].clone(),
+                )));
+            }
+            start += 1;
+        }
+        Ok(data.entries[start - n..]
+            .iter()
+            .find(|(_, v)| !v.is_nil())
+            .cloned())
+    }
+
+    /// The sequence `t[1..=len]` as a vector, read without metamethods.
+    pub fn sequence(&self) -> Vec<Value> {
+        (1..=self.len()).map(|i| self.get_int(i)).collect()
+    }
+}
+
+impl TableData {
+    /// Moves the integer keys following the array part into it.
+    fn migrate(&mut self) {
+        loop {
+            let k = Key::Integer(self.array.len() as i64 + 1);
+            let i = match self.index.get(&k) {
+                Some(&i) if !self.entries[i].1.is_nil() => i,
+                _ => break,
+            };
+            self.index.remove(&k);
+            let value = std::mem::take(&mut self.entries[i].1);
+            self.array.push(value);
+            // The slot stays behind as a dead entry.
+            self.dead += 1;
+        }
+    }
+
+    /// Drops dead entries from the hash part.
+    fn compact(&mut self) {
+        self.entries.retain(|(_, v)| !v.is_nil());
+        self.index.clear();
+        for (i, (k, _)) in self.entries.iter().enumerate() {
+            self.index.insert(Key::of(k).unwrap(), i);
+        }
+        self.dead = 0;
+    }
+}
+
+impl Value {
+    /// The position of the value in an array part of length `len`, if it is
+    /// one of the keys `1..=len`.
+    fn to_array_index(&self, len: usize) -> Option<usize> {
+        let i = match self {
+            Value::Integer(i) => *i,
+            Value::Number(f) => float_to_int(*f)?,
+            _ => return None,
+        };
+        (1..=len as i64).contains(&i).then(|| i as usize - 1)
+    }
+}
+
+impl Debug for Table {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "table: {:p}", Rc::as_ptr(&self.0))
+    }
+}
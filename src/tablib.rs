@@ -0,0 +1,402 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The table library, as `ltablib.c`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    args::Args,
+    state::State,
+    table::Table,
+    value::{Error, Value},
+};
+
+const TAB_R: u8 = 1;
+const TAB_W: u8 = 2;
+const TAB_L: u8 = 4;
+const TAB_RW: u8 = TAB_R | TAB_W;
+
+/// `lua_checkstack` fails past this many slots.
+const MAX_STACK: u64 = 1_000_000;
+
+impl State {
+    /// Opens the table library.
+    pub fn open_table(&self) {
+        let table = Table::new();
+        self.register(
+            &table,
+            &[
+                ("concat", concat),
+                ("insert", insert),
+                ("move", move_),
+                ("pack", pack),
+                ("remove", remove),
+                ("sort", sort),
+                ("unpack", unpack),
+            ],
+        );
+        self.set_library("table", table);
+    }
+}
+
+/// Checks that argument `n` is a table, or has the metamethods standing in
+/// for the accesses in `what`.
+fn check_tab(state: &State, args: &Args, n: usize, what: u8) -> Result<(), Error> {
+    let v = args.get(n);
+    if let Value::Table(_) = v {
+        return Ok(());
+    }
+    let ok = state.metatable(v).is_some_and(|mt| {
+        let has = |event| !mt.get_str(event).is_nil();
+        (what & TAB_R == 0 || has("__index"))
+            && (what & TAB_W == 0 || has("__newindex"))
+            && (what & TAB_L == 0 || has("__len"))
+    });
+    if ok {
+        Ok(())
+    } else {
+        Err(args.type_error(n, "table"))
+    }
+}
+
+/// `#t` after checking `t` for the accesses in `what`, as `aux_getn`.
+fn getn(state: &State, args: &Args, n: usize, what: u8) -> Result<i64, Error> {
+    check_tab(state, args, n, what | TAB_L)?;
+    state.len_int(args.get(n))
+}
+
+fn geti(state: &State, t: &Value, i: i64) -> Result<Value, Error> {
+    state.index(t, &Value::Integer(i))
+}
+
+fn seti(state: &State, t: &Value, i: i64, v: Value) -> Result<(), Error> {
+    state.set_index(t, Value::Integer(i), v)
+}
+
+fn insert(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.get(1);
+    // The first empty element.
+    let e = getn(state, &args, 1, TAB_RW)?.wrapping_add(1);
+    let pos = match args.len() {
+        2 => e,
+        3 => {
+            let pos = args.check_integer(2)?;
+            if (pos as u64).wrapping_sub(1) >= e as u64 {
+                return Err(args.error(2, "position out of bounds"));
+            }
+            for i in (pos + 1..=e).rev() {
+                let v = geti(state, t, i - 1)?;
+                seti(state, t, i, v)?;
+            }
+            pos
+        }
+        _ => return Err(Error::new("wrong number of arguments to 'insert'")),
+    };
+    seti(state, t, pos, args.get(args.len()).clone())?;
+    Ok(vec![])
+}
+
+fn remove(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.get(1);
+    let size = getn(state, &args, 1, TAB_RW)?;
+    let mut pos = args.opt_integer(2, size)?;
+    // A given position may also be `size + 1`.
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(args.error(2, "position out of bounds"));
+    }
+    let result = geti(state, t, pos)?;
+    while pos < size {
+        let v = geti(state, t, pos + 1)?;
+        seti(state, t, pos, v)?;
+        pos += 1;
+    }
+    seti(state, t, pos, Value::Nil)?;
+    Ok(vec![result])
+}
+
+fn move_(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let f = args.check_integer(2)?;
+    let e = args.check_integer(3)?;
+    let t = args.check_integer(4)?;
+    let tt = if args.get(5).is_nil() { 1 } else { 5 };
+    check_tab(state, &args, 1, TAB_R)?;
+    check_tab(state, &args, tt, TAB_W)?;
+    let (a1, dest) = (args.get(1), args.get(tt));
+    if e >= f {
+        if !(f > 0 || e < i64::MAX + f) {
+            return Err(args.error(3, "too many elements to move"));
+        }
+        let n = e - f + 1;
+        if t > i64::MAX - n + 1 {
+            return Err(args.error(4, "destination wrap around"));
+        }
+        if t > e || t <= f || (tt != 1 && !state.equals(a1, dest)?) {
+            for i in 0..n {
+                let v = geti(state, a1, f + i)?;
+                seti(state, dest, t + i, v)?;
+            }
+        } else {
+            for i in (0..n).rev() {
+                let v = geti(state, a1, f + i)?;
+                seti(state, dest, t + i, v)?;
+            }
+        }
+    }
+    Ok(vec![dest.clone()])
+}
+
+fn concat(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.get(1);
+    let last = getn(state, &args, 1, TAB_R)?;
+    let sep = args.opt_string(2)?.unwrap_or_default();
+    let first = args.opt_integer(3, 1)?;
+    let last = args.opt_integer(4, last)?;
+    let mut b = Vec::new();
+    let mut i = first;
+    while i <= last {
+        match geti(state, t, i)?.to_lua_string() {
+            Some(s) => b.extend_from_slice(&s),
+            None => {
+                return Err(Error::new(format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    i
+                )))
+            }
+        }
+        if i == last {
+            break;
+        }
+        b.extend_from_slice(&sep);
+        i += 1;
+    }
+    Ok(vec![Value::String(b.into())])
+}
+
+fn pack(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let values = args.into_vec();
+    let t = Table::with_capacity(values.len(), 1);
+    let n = values.len() as i64;
+    for (i, v) in values.into_iter().enumerate() {
+        t.set_int(i as i64 + 1, v);
+    }
+    t.set_str("n", n);
+    Ok(vec![t.into()])
+}
+
+fn unpack(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let t = args.get(1);
+    let i = args.opt_integer(2, 1)?;
+    let e = match args.get(3) {
+        Value::Nil => state.len_int(t)?,
+        _ => args.check_integer(3)?,
+    };
+    if i > e {
+        return Ok(vec![]);
+    }
+    // The number of elements minus 1, computed so as not to overflow.
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= MAX_STACK {
+        return Err(Error::new("too many results to unpack"));
+    }
+    let mut results = Vec::with_capacity(n as usize + 1);
+    for k in 0..=n {
+        results.push(geti(state, t, i.wrapping_add(k as i64))?);
+    }
+    Ok(results)
+}
+
+fn sort(state: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let n = getn(state, &args, 1, TAB_RW)?;
+    if n > 1 {
+        if n >= i32::MAX as i64 {
+            return Err(args.error(1, "array too big"));
+        }
+        if !args.get(2).is_nil() {
+            args.check_function(2)?;
+        }
+        let t = args.get(1);
+        // Without a metatable every access is raw, so the sequence can be
+        // sorted in a buffer and stored back in one go.
+        let items = match t {
+            Value::Table(table) if table.metatable().is_none() => {
+                Items::Buffer(table.sequence(), table.clone())
+            }
+            _ => Items::Object(t.clone()),
+        };
+        let mut sorter = Sorter {
+            state,
+            comp: args.get(2).clone(),
+            items,
+        };
+        sorter.auxsort(1, n as u64, 0)?;
+        if let Items::Buffer(values, table) = sorter.items {
+            for (i, v) in values.into_iter().enumerate() {
+                table.set_int(i as i64 + 1, v);
+            }
+        }
+    }
+    Ok(vec![])
+}
+
+/// Intervals shorter than this always use their middle point as pivot.
+const RANLIMIT: u64 = 100;
+
+enum Items {
+    Buffer(Vec<Value>, Table),
+    Object(Value),
+}
+
+/// The quicksort of `ltablib.c`, which detects inconsistent order functions
+/// instead of running off the ends of the array.
+struct Sorter<'a> {
+    state: &'a State,
+    comp: Value,
+    items: Items,
+}
+
+impl Sorter<'_> {
+    fn get(&self, i: u64) -> Result<Value, Error> {
+        match &self.items {
+            Items::Buffer(values, _) => Ok(values[i as usize - 1].clone()),
+            Items::Object(t) => geti(self.state, t, i as i64),
+        }
+    }
+
+    fn set(&mut self, i: u64, v: Value) -> Result<(), Error> {
+        match &mut self.items {
+            Items::Buffer(values, _) => {
+                values[i as usize - 1] = v;
+                Ok(())
+            }
+            Items::Object(t) => seti(self.state, t, i as i64, v),
+        }
+    }
+
+    /// `a[i] = vi; a[j] = vj`.
+    fn set2(&mut self, i: u64, vi: Value, j: u64, vj: Value) -> Result<(), Error> {
+        self.set(i, vi)?;
+        self.set(j, vj)
+    }
+
+    fn less(&self, a: &Value, b: &Value) -> Result<bool, Error> {
+        match &self.comp {
+            Value::Nil => self.state.less_than(a, b),
+            f => Ok(self.state.call1(f, vec![a.clone(), b.clone()])?.is_truthy()),
+        }
+    }
+
+    fn auxsort(&mut self, mut lo: u64, mut up: u64, mut rnd: u32) -> Result<(), Error> {
+        while lo < up {
+            // Sort elements `lo`, `p` and `up`.
+            let (a_lo, a_up) = (self.get(lo)?, self.get(up)?);
+            if self.less(&a_up, &a_lo)? {
+                self.set2(lo, a_up, up, a_lo)?;
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let mut p = if up - lo < RANLIMIT || rnd == 0 {
+                (lo + up) / 2
+            } else {
+                choose_pivot(lo, up, rnd)
+            };
+            let (a_p, a_lo) = (self.get(p)?, self.get(lo)?);
+            if self.less(&a_p, &a_lo)? {
+                self.set2(p, a_lo, lo, a_p)?;
+            } else {
+                let a_up = self.get(up)?;
+                if self.less(&a_up, &a_p)? {
+                    self.set2(p, a_up, up, a_p)?;
+                }
+            }
+            if up - lo == 2 {
+                break;
+            }
+            // Keep the pivot at `up - 1` while partitioning.
+            let pivot = self.get(p)?;
+            let a_up1 = self.get(up - 1)?;
+            self.set2(p, a_up1, up - 1, pivot.clone())?;
+            p = self.partition(lo, up, &pivot)?;
+            // Recurse into the smaller half and loop on the larger one.
+            let n;
+            if p - lo < up - p {
+                self.auxsort(lo, p - 1, rnd)?;
+                n = p - lo;
+                lo = p + 1;
+            } else {
+                self.auxsort(p + 1, up, rnd)?;
+                n = up - p;
+                up = p - 1;
+            }
+            if (up.wrapping_sub(lo)) / 128 > n {
+                rnd = randomize_pivot();
+            }
+        }
+        Ok(())
+    }
+
+    /// Partitions `a[lo..=up]` around `pivot`, which sits at `up - 1`, and
+    /// returns its final position.
+    fn partition(&mut self, lo: u64, up: u64, pivot: &Value) -> Result<u64, Error> {
+        let mut i = lo;
+        let mut j = up - 1;
+        loop {
+            // Invariant: a[lo..=i] <= pivot <= a[j..=up].
+            let a_i = loop {
+                i += 1;
+                let a_i = self.get(i)?;
+                if !self.less(&a_i, pivot)? {
+                    break a_i;
+                }
+                if i == up - 1 {
+                    return Err(invalid_order());
+                }
+            };
+            let a_j = loop {
+                j -= 1;
+                let a_j = self.get(j)?;
+                if !self.less(pivot, &a_j)? {
+                    break a_j;
+                }
+                if j < i {
+                    return Err(invalid_order());
+                }
+            };
+            if j < i {
+                self.set2(up - 1, a_i, i, pivot.clone())?;
+                return Ok(i);
+            }
+            self.set2(i, a_j, j, a_i)?;
+        }
+    }
+}
+
+fn invalid_order() -> Error {
+    Error::new("invalid order function for sorting")
+}
+
+/// Picks a pivot in the middle half of `lo..=up`.
+fn choose_pivot(lo: u64, up: u64, rnd: u32) -> u64 {
+    let r4 = (up - lo) / 4;
+    rnd as u64 % (r4 * 2) + (lo + r4)
+}
+
+/// A cheap source of variation for pivots, as `l_randomizePivot`.
+fn randomize_pivot() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_secs() as u32).wrapping_add(now.subsec_nanos())
+}
@@ -0,0 +1,95 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    cell::{RefCell, RefMut},
+    fmt::{Debug, Formatter},
+    rc::{Rc, Weak},
+};
+
+use crate::table::Table;
+
+/// A full userdata: a Rust value owned by Lua, with its own metatable.
+/// Cloning it yields another reference to the same userdata.
+///
+/// There is no collector to run `__gc`; the wrapped value is dropped with
+/// the last reference instead, so its `Drop` should release what `__gc`
+/// would.
+#[derive(Clone)]
+pub struct Userdata(Rc<UserdataData>);
+
+struct UserdataData {
+    value: RefCell<Box<dyn Any>>,
+    metatable: RefCell<Option<Table>>,
+}
+
+impl Userdata {
+    pub fn new<T: Any>(value: T, metatable: Option<Table>) -> Self {
+        Self(Rc::new(UserdataData {
+            value: RefCell::new(Box::new(value)),
+            metatable: RefCell::new(metatable),
+        }))
+    }
+
+    pub fn ptr_eq(&self, other: &Userdata) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+
+    /// A reference to this userdata that does not keep it alive.
+    pub(crate) fn downgrade(&self) -> WeakUserdata {
+        WeakUserdata(Rc::downgrade(&self.0))
+    }
+
+    pub fn metatable(&self) -> Option<Table> {
+        self.0.metatable.borrow().clone()
+    }
+
+    pub fn set_metatable(&self, metatable: Option<Table>) {
+        *self.0.metatable.borrow_mut() = metatable;
+    }
+
+    /// Whether the wrapped value is a `T`.
+    pub fn is<T: Any>(&self) -> bool {
+        self.0.value.borrow().is::<T>()
+    }
+
+    /// Borrows the wrapped value if it is a `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is already borrowed.
+    pub fn borrow_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.0.value.borrow_mut(), |v| v.downcast_mut::<T>()).ok()
+    }
+}
+
+/// A userdata reference from [`Userdata::downgrade`].
+pub(crate) struct WeakUserdata(Weak<UserdataData>);
+
+impl WeakUserdata {
+    pub(crate) fn upgrade(&self) -> Option<Userdata> {
+        self.0.upgrade().map(Userdata)
+    }
+}
+
+impl Debug for Userdata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "userdata: {:p}", Rc::as_ptr(&self.0))
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The utf8 library, as `lutf8lib.c`.
+//!
+//! Strings are plain bytes. Sequences of up to six bytes encode values up
+//! to 2^31 - 1, as in Lua 5.4; the non-lax functions also reject surrogates
+//! and values past U+10FFFF.
+
+use crate::{
+    args::Args,
+    state::State,
+    table::Table,
+    value::{Error, Function, Value},
+};
+
+const MAX_UNICODE: u32 = 0x10FFFF;
+const MAX_UTF: u32 = 0x7FFFFFFF;
+
+/// Matches exactly one UTF-8 byte sequence, assuming a valid string.
+const CHARPATTERN: &[u8] = b"[\0-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+const MSG_INVALID: &str = "invalid UTF-8 code";
+
+impl State {
+    /// Opens the utf8 library.
+    pub fn open_utf8(&self) {
+        let utf8 = Table::new();
+        self.register(
+            &utf8,
+            &[
+                ("char", char),
+                ("codepoint", codepoint),
+                ("codes", codes),
+                ("len", len),
+                ("offset", offset),
+            ],
+        );
+        utf8.set_str("charpattern", Value::String(CHARPATTERN.into()));
+        self.set_library("utf8", utf8);
+    }
+}
+
+fn is_cont(s: &[u8], i: usize) -> bool {
+    // Past the end reads as the terminating zero of a C string.
+    s.get(i).is_some_and(|c| c & 0xC0 == 0x80)
+}
+
+/// Translates a relative position; negative means back from the end.
+fn posrelat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
+
+/// Decodes the sequence at the start of `s`, returning the value and the
+/// sequence length, or `None` if it is invalid.
+fn decode(s: &[u8], strict: bool) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 6] = [!0, 0x80, 0x800, 0x10000, 0x200000, 0x4000000];
+    let mut c = *s.first().unwrap_or(&0) as u32;
+    let mut res = 0_u32;
+    let mut count = 0;
+    if c < 0x80 {
+        res = c;
+    } else {
+        while c & 0x40 != 0 {
+            count += 1;
+            let cc = *s.get(count)? as u32;
+            if cc & 0xC0 != 0x80 {
+                return None;
+            }
+            res = (res << 6) | (cc & 0x3F);
+            c <<= 1;
+        }
+        if count > 5 {
+            return None;
+        }
+        res |= (c & 0x7F) << (count * 5);
+        if res > MAX_UTF || res < LIMITS[count] {
+            return None;
+        }
+    }
+    if strict && (res > MAX_UNICODE || (0xD800..=0xDFFF).contains(&res)) {
+        return None;
+    }
+    Some((res, count + 1))
+}
+
+/// Encodes `x`, which must not exceed `MAX_UTF`, as `luaO_utf8esc` does.
+fn encode(mut x: u32, buf: &mut Vec<u8>) {
+    if x < 0x80 {
+        buf.push(x as u8);
+        return;
+    }
+    let mut tail = vec![];
+    // The largest value that still fits in the first byte.
+    let mut mfb = 0x3F;
+    loop {
+        tail.push(0x80 | (x & 0x3F) as u8);
+        x >>= 6;
+        mfb >>= 1;
+        if x <= mfb {
+            break;
+        }
+    }
+    buf.push(((!mfb << 1) | x) as u8);
+    buf.extend(tail.iter().rev());
+}
+
+fn char(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let mut buf = vec![];
+    for i in 1..=args.len() {
+        let code = args.check_integer(i)? as u64;
+        if code > MAX_UTF as u64 {
+            return Err(args.error(i, "value out of range"));
+        }
+        encode(code as u32, &mut buf);
+    }
+    Ok(vec![Value::String(buf.into())])
+}
+
+fn len(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let len = s.len() as i64;
+    let mut posi = posrelat(args.opt_integer(2, 1)?, s.len());
+    let posj = posrelat(args.opt_integer(3, -1)?, s.len()) - 1;
+    let strict = !args.get(4).is_truthy();
+    if posi < 1 || posi - 1 > len {
+        return Err(args.error(2, "initial position out of bounds"));
+    }
+    posi -= 1;
+    if posj >= len {
+        return Err(args.error(3, "final position out of bounds"));
+    }
+    let mut n = 0;
+    while posi <= posj {
+        match decode(&s[posi as usize..], strict) {
+            Some((_, size)) => posi += size as i64,
+            None => return Ok(vec![Value::Nil, Value::Integer(posi + 1)]),
+        }
+        n += 1;
+    }
+    Ok(vec![Value::Integer(n)])
+}
+
+fn codepoint(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let posi = posrelat(args.opt_integer(2, 1)?, s.len());
+    let pose = posrelat(args.opt_integer(3, posi)?, s.len());
+    let strict = !args.get(4).is_truthy();
+    if posi < 1 {
+        return Err(args.error(2, "out of bounds"));
+    }
+    if pose > s.len() as i64 {
+        return Err(args.error(3, "out of bounds"));
+    }
+    if posi > pose {
+        return Ok(vec![]);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(Error::new("string slice too long"));
+    }
+    let mut codes = vec![];
+    let mut i = posi as usize - 1;
+    while i < pose as usize {
+        let (code, size) = decode(&s[i..], strict).ok_or_else(|| Error::new(MSG_INVALID))?;
+        codes.push(Value::Integer(code as i64));
+        i += size;
+    }
+    Ok(codes)
+}
+
+fn offset(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let len = s.len() as i64;
+    let mut n = args.check_integer(2)?;
+    let default = if n >= 0 { 1 } else { len + 1 };
+    let mut posi = posrelat(args.opt_integer(3, default)?, s.len());
+    if posi < 1 || posi - 1 > len {
+        return Err(args.error(3, "position out of bounds"));
+    }
+    posi -= 1;
+    let cont = |i: i64| is_cont(&s, i as usize);
+    if n == 0 {
+        // Finds the beginning of the current sequence.
+        while posi > 0 && cont(posi) {
+            posi -= 1;
+        }
+    } else {
+        if cont(posi) {
+            return Err(Error::new("initial position is a continuation byte"));
+        }
+        if n < 0 {
+            while n < 0 && posi > 0 {
+                posi -= 1;
+                while posi > 0 && cont(posi) {
+                    posi -= 1;
+                }
+                n += 1;
+            }
+        } else {
+            // The first character is where we already are.
+            n -= 1;
+            while n > 0 && posi < len {
+                posi += 1;
+                while cont(posi) {
+                    posi += 1;
+                }
+                n -= 1;
+            }
+        }
+    }
+    if n == 0 {
+        Ok(vec![Value::Integer(posi + 1)])
+    } else {
+        Ok(vec![Value::Nil])
+    }
+}
+
+fn codes(_: &State, args: Args) -> Result<Vec<Value>, Error> {
+    let s = args.check_string(1)?;
+    let strict = !args.get(2).is_truthy();
+    if is_cont(&s, 0) {
+        return Err(args.error(1, MSG_INVALID));
+    }
+    let iter = Function::native("codes_iter", move |_, args| {
+        let s = args.get(1).to_lua_string().unwrap_or_default();
+        let mut n = args.get(2).to_integer().unwrap_or(0) as u64 as usize;
+        while n < s.len() && is_cont(&s, n) {
+            n += 1;
+        }
+        if n >= s.len() {
+            return Ok(vec![]);
+        }
+        match decode(&s[n..], strict) {
+            Some((code, size)) if !is_cont(&s, n + size) => Ok(vec![
+                Value::Integer(n as i64 + 1),
+                Value::Integer(code as i64),
+            ]),
+            _ => Err(Error::new(MSG_INVALID)),
+        }
+    });
+    Ok(vec![iter.into(), Value::String(s), Value::Integer(0)])
+}
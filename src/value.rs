@@ -0,0 +1,308 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    io,
+    rc::Rc,
+};
+
+use crate::{
+    args::Args,
+    closure::Closure,
+    number::{self, float_to_int, Number},
+    state::State,
+    string::LuaString,
+    table::Table,
+    userdata::Userdata,
+};
+
+/// A Lua value.
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(LuaString),
+    Table(Table),
+    Function(Function),
+    Userdata(Userdata),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::Userdata(_) => "userdata",
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// Whether the value counts as true in conditions: all but `nil` and
+    /// `false` do.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// Primitive equality, without calling `__eq`. Integers and floats with
+    /// the same mathematical value are equal.
+    pub fn raw_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(i), Value::Number(f)) | (Value::Number(f), Value::Integer(i)) => {
+                float_to_int(*f) == Some(*i)
+            }
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => a.ptr_eq(b),
+            (Value::Function(a), Value::Function(b)) => a.ptr_eq(b),
+            (Value::Userdata(a), Value::Userdata(b)) => a.ptr_eq(b),
+            _ => false,
+        }
+    }
+
+    /// Converts numbers and numeric strings to numbers.
+    pub fn to_number(&self) -> Option<Number> {
+        match self {
+            Value::Integer(i) => Some(Number::Integer(*i)),
+            Value::Number(f) => Some(Number::Float(*f)),
+            Value::String(s) => number::str2number(s),
+            _ => None,
+        }
+    }
+
+    /// Converts the value to a float if it is a number or a numeric string.
+    pub fn to_float(&self) -> Option<f64> {
+        self.to_number().map(|n| match n {
+            Number::Integer(i) => i as f64,
+            Number::Float(f) => f,
+        })
+    }
+
+    /// Converts the value to an integer if it is a number or a numeric string
+    /// with an exact integer value.
+    pub fn to_integer(&self) -> Option<i64> {
+        match self.to_number()? {
+            Number::Integer(i) => Some(i),
+            Number::Float(f) => float_to_int(f),
+        }
+    }
+
+    /// Converts strings and numbers to strings, as `lua_tolstring` does.
+    pub fn to_lua_string(&self) -> Option<LuaString> {
+        match self {
+            Value::String(s) => Some(s.clone()),
+            Value::Integer(i) => Some(i.to_string().into()),
+            Value::Number(f) => Some(number::fmt_number(*f).into()),
+            _ => None,
+        }
+    }
+
+    /// The identity of a table, function or userdata, as printed by
+    /// `tostring`.
+    pub fn address(&self) -> Option<*const ()> {
+        match self {
+            Value::Table(t) => Some(t.as_ptr()),
+            Value::Function(f) => Some(f.as_ptr()),
+            Value::Userdata(u) => Some(u.as_ptr()),
+            _ => None,
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => f.write_str("nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Number(n) => f.write_str(&number::fmt_number(*n)),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Table(t) => write!(f, "{:?}", t),
+            Value::Function(func) => write!(f, "{:?}", func),
+            Value::Userdata(u) => write!(f, "{:?}", u),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        match n {
+            Number::Integer(i) => Value::Integer(i),
+            Number::Float(f) => Value::Number(f),
+        }
+    }
+}
+
+impl From<LuaString> for Value {
+    fn from(s: LuaString) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<Table> for Value {
+    fn from(t: Table) -> Self {
+        Value::Table(t)
+    }
+}
+
+impl From<Function> for Value {
+    fn from(f: Function) -> Self {
+        Value::Function(f)
+    }
+}
+
+impl From<Userdata> for Value {
+    fn from(u: Userdata) -> Self {
+        Value::Userdata(u)
+    }
+}
+
+/// The signature of functions implemented in Rust. They receive their
+/// arguments and return their results.
+pub type NativeFn = dyn Fn(&State, Args) -> Result<Vec<Value>, Error>;
+
+#[derive(Clone)]
+pub enum Function {
+    Native(NativeFunction),
+    Lua(Rc<Closure>),
+}
+
+impl Function {
+    /// Wraps a Rust function. `name` is used in argument errors.
+    pub fn native(
+        name: &'static str,
+        f: impl Fn(&State, Args) -> Result<Vec<Value>, Error> + 'static,
+    ) -> Self {
+        Function::Native(NativeFunction {
+            name,
+            f: Rc::new(f),
+        })
+    }
+
+    pub fn ptr_eq(&self, other: &Function) -> bool {
+        self.as_ptr() == other.as_ptr()
+    }
+
+    fn as_ptr(&self) -> *const () {
+        match self {
+            Function::Native(f) => Rc::as_ptr(&f.f) as *const (),
+            Function::Lua(c) => Rc::as_ptr(c) as *const (),
+        }
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Function::Native(native) => write!(f, "builtin: {:p}", Rc::as_ptr(&native.f)),
+            Function::Lua(c) => write!(f, "function: {:p}", Rc::as_ptr(c)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub(crate) name: &'static str,
+    pub(crate) f: Rc<NativeFn>,
+}
+
+/// A Lua error, which carries an arbitrary value.
+#[derive(Clone, Debug)]
+pub struct Error {
+    value: Value,
+}
+
+impl Error {
+    pub fn new(msg: impl Into<LuaString>) -> Self {
+        Self {
+            value: Value::String(msg.into()),
+        }
+    }
+
+    pub fn from_value(value: Value) -> Self {
+        Self { value }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.value.to_lua_string() {
+            Some(s) => write!(f, "{}", s),
+            None => write!(f, "(error object is a {} value)", self.value.type_name()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::new(strerror(&e))
+    }
+}
+
+/// The message of an I/O error as C's `strerror` words it, without Rust's
+/// " (os error N)" suffix.
+pub(crate) fn strerror(e: &io::Error) -> String {
+    let s = e.to_string();
+    match s.rfind(" (os error ") {
+        Some(i) => s[..i].to_string(),
+        None => s,
+    }
+}
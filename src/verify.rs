@@ -0,0 +1,462 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static checks over a [`Proto`] so that a hostile chunk is rejected at load
+//! time rather than letting the VM index out of range.
+
+use std::io;
+
+use crate::{
+    constants::{TM_ADD, TM_SHR},
+    instruction::Instruction,
+    opcode::*,
+    proto::{Constant, Proto},
+};
+
+/// What the upvalues of a nested proto may capture: the registers and the
+/// upvalues of the function enclosing it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Enclosing {
+    pub(crate) maxstacksize: u8,
+    pub(crate) upvalues: usize,
+}
+
+/// Checks every instruction of `proto` against its register, constant,
+/// upvalue and nested proto counts, along with jump targets and the pairing
+/// of instructions that must come together, then its line information and,
+/// unless it is a main function, its upvalues against `enclosing`. Nested
+/// protos are checked when they are decoded.
+pub(crate) fn verify(proto: &Proto, enclosing: Option<Enclosing>) -> io::Result<()> {
+    Verifier { proto }.verify(enclosing)
+}
+
+struct Verifier<'a> {
+    proto: &'a Proto,
+}
+
+impl<'a> Verifier<'a> {
+    fn verify(&self, enclosing: Option<Enclosing>) -> io::Result<()> {
+        let p = self.proto;
+        if p.numparams > p.maxstacksize {
+            return Err(invalid(format!(
+                "numparams {} exceeds maxstacksize {}",
+                p.numparams, p.maxstacksize
+            )));
+        }
+        if !p.lineinfo.is_empty() && p.lineinfo.len() != p.code.len() {
+            return Err(invalid(format!(
+                "lineinfo has {} entries for {} instructions",
+                p.lineinfo.len(),
+                p.code.len()
+            )));
+        }
+        match p.code.last().map(|i| i.opcode()) {
+            Some(OP_RETURN | OP_RETURN0 | OP_RETURN1 | OP_JMP) => {}
+            _ => return Err(invalid("code does not end with a return")),
+        }
+        for (pc, i) in p.code.iter().enumerate() {
+            self.verify_instruction(pc, *i).map_err(|msg| {
+                invalid(format!("bad instruction at pc {} ({:?}): {}", pc, i, msg))
+            })?;
+        }
+        self.verify_abslineinfo()?;
+        if let Some(enclosing) = enclosing {
+            self.verify_upvalues(enclosing)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the anchors are in order and each marks an instruction, so that
+    /// [`Proto::line_for_pc`] stays in range.
+    fn verify_abslineinfo(&self) -> io::Result<()> {
+        let p = self.proto;
+        let mut prev = None;
+        for abs in &p.abslineinfo {
+            if abs.pc < 0 || abs.pc as usize >= p.lineinfo.len() {
+                return Err(invalid(format!(
+                    "abslineinfo pc {} out of range (lineinfo: {})",
+                    abs.pc,
+                    p.lineinfo.len()
+                )));
+            }
+            if prev.is_some_and(|prev| abs.pc <= prev) {
+                return Err(invalid(format!("abslineinfo pc {} out of order", abs.pc)));
+            }
+            prev = Some(abs.pc);
+        }
+        Ok(())
+    }
+
+    /// Checks each upvalue captures a register or an upvalue the enclosing
+    /// function has.
+    fn verify_upvalues(&self, enclosing: Enclosing) -> io::Result<()> {
+        for (n, upvalue) in self.proto.upvalues.iter().enumerate() {
+            let (what, limit) = if upvalue.instack != 0 {
+                ("register", enclosing.maxstacksize as usize)
+            } else {
+                ("upvalue", enclosing.upvalues)
+            };
+            if upvalue.idx as usize >= limit {
+                return Err(invalid(format!(
+                    "upvalue {} captures {} {} out of range ({})",
+                    n, what, upvalue.idx, limit
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_instruction(&self, pc: usize, i: Instruction) -> Result<(), String> {
+        let op = i.opcode();
+        if op as usize >= OPCODES.len() {
+            return Err(format!("invalid opcode {}", op));
+        }
+        let (a, k, b, c) = i.abc();
+        let (_, bx) = i.a_bx();
+        let k = k != 0;
+        match op {
+            OP_MOVE | OP_UNM | OP_BNOT | OP_NOT | OP_LEN => {
+                self.reg(a)?;
+                self.reg(b)
+            }
+            OP_LOADI | OP_LOADF | OP_LOADFALSE | OP_LOADTRUE | OP_CLOSE | OP_TBC | OP_RETURN1 => {
+                self.reg(a)
+            }
+            OP_LFALSESKIP => {
+                self.reg(a)?;
+                self.target(pc as isize + 2)
+            }
+            OP_LOADK => {
+                self.reg(a)?;
+                self.constant(bx)
+            }
+            OP_LOADKX => {
+                self.reg(a)?;
+                self.constant(self.extraarg(pc)?)
+            }
+            OP_LOADNIL => self.reg(a + b),
+            OP_GETUPVAL | OP_SETUPVAL => {
+                self.reg(a)?;
+                self.upvalue(b)
+            }
+            OP_GETTABUP => {
+                self.reg(a)?;
+                self.upvalue(b)?;
+                self.string_constant(c)
+            }
+            OP_GETTABLE => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.reg(c)
+            }
+            OP_GETI => {
+                self.reg(a)?;
+                self.reg(b)
+            }
+            OP_GETFIELD => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.string_constant(c)
+            }
+            OP_SETTABUP => {
+                self.upvalue(a)?;
+                self.string_constant(b)?;
+                self.rk(k, c)
+            }
+            OP_SETTABLE => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.rk(k, c)
+            }
+            OP_SETI => {
+                self.reg(a)?;
+                self.rk(k, c)
+            }
+            OP_SETFIELD => {
+                self.reg(a)?;
+                self.string_constant(b)?;
+                self.rk(k, c)
+            }
+            OP_NEWTABLE => {
+                self.reg(a)?;
+                self.extraarg(pc).map(drop)
+            }
+            OP_SELF => {
+                self.reg(a + 1)?;
+                self.reg(b)?;
+                if k {
+                    self.string_constant(c)
+                } else {
+                    self.reg(c)
+                }
+            }
+            OP_ADDI | OP_SHRI | OP_SHLI => {
+                self.reg(a)?;
+                self.reg(b)
+            }
+            OP_ADDK | OP_SUBK | OP_MULK | OP_MODK | OP_POWK | OP_DIVK | OP_IDIVK | OP_BANDK
+            | OP_BORK | OP_BXORK => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.numeric_constant(c)
+            }
+            OP_ADD | OP_SUB | OP_MUL | OP_MOD | OP_POW | OP_DIV | OP_IDIV | OP_BAND | OP_BOR
+            | OP_BXOR | OP_SHL | OP_SHR => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.reg(c)
+            }
+            OP_MMBIN => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.event(c)
+            }
+            OP_MMBINI => {
+                self.reg(a)?;
+                self.event(c)
+            }
+            OP_MMBINK => {
+                self.reg(a)?;
+                self.constant(b)?;
+                self.event(c)
+            }
+            OP_CONCAT => {
+                if b < 2 {
+                    return Err(format!("concatenation of {} values", b));
+                }
+                self.reg(a + b - 1)
+            }
+            OP_JMP => self.target(pc as isize + 1 + i.sj()),
+            OP_EQ | OP_LT | OP_LE | OP_TESTSET => {
+                self.reg(a)?;
+                self.reg(b)?;
+                self.followed_by_jump(pc)
+            }
+            OP_EQK => {
+                self.reg(a)?;
+                self.constant(b)?;
+                self.followed_by_jump(pc)
+            }
+            OP_EQI | OP_LTI | OP_LEI | OP_GTI | OP_GEI | OP_TEST => {
+                self.reg(a)?;
+                self.followed_by_jump(pc)
+            }
+            OP_CALL => {
+                self.reg(a)?;
+                if b > 0 {
+                    self.reg(a + b - 1)?;
+                }
+                if c > 1 {
+                    self.reg(a + c - 2)?;
+                }
+                Ok(())
+            }
+            OP_TAILCALL => {
+                self.reg(a)?;
+                if b > 0 {
+                    self.reg(a + b - 1)?;
+                }
+                Ok(())
+            }
+            OP_RETURN => {
+                if b > 1 {
+                    self.reg(a + b - 2)?;
+                }
+                Ok(())
+            }
+            OP_RETURN0 => Ok(()),
+            OP_FORLOOP => {
+                self.reg(a + 3)?;
+                self.target(pc as isize + 1 - bx)
+            }
+            OP_FORPREP => {
+                self.reg(a + 3)?;
+                self.target(pc as isize + 2 + bx)
+            }
+            OP_TFORPREP => {
+                self.reg(a + 3)?;
+                let target = pc as isize + 1 + bx;
+                self.target(target)?;
+                match self.proto.code[target as usize].opcode() {
+                    OP_TFORCALL => Ok(()),
+                    _ => Err("generic for does not jump to OP_TFORCALL".to_string()),
+                }
+            }
+            OP_TFORCALL => {
+                self.reg(a + 3 + c.max(1))?;
+                match self.proto.code.get(pc + 1).map(|i| i.opcode()) {
+                    Some(OP_TFORLOOP) => Ok(()),
+                    _ => Err("not followed by OP_TFORLOOP".to_string()),
+                }
+            }
+            OP_TFORLOOP => {
+                self.reg(a + 4)?;
+                self.target(pc as isize + 1 - bx)
+            }
+            OP_SETLIST => {
+                self.reg(a)?;
+                if b > 0 {
+                    self.reg(a + b)?;
+                }
+                if k {
+                    self.extraarg(pc)?;
+                }
+                Ok(())
+            }
+            OP_CLOSURE => {
+                self.reg(a)?;
+                if bx as usize >= self.proto.protos.len() {
+                    return Err(format!(
+                        "proto {} out of range (protos: {})",
+                        bx,
+                        self.proto.protos.len()
+                    ));
+                }
+                Ok(())
+            }
+            OP_VARARG => {
+                self.vararg()?;
+                self.reg(a)?;
+                if c > 1 {
+                    self.reg(a + c - 2)?;
+                }
+                Ok(())
+            }
+            OP_VARARGPREP => {
+                self.vararg()?;
+                if pc != 0 {
+                    return Err("not the first instruction".to_string());
+                }
+                Ok(())
+            }
+            OP_EXTRAARG => {
+                let prev = match pc.checked_sub(1).map(|pc| self.proto.code[pc]) {
+                    Some(prev) => prev,
+                    None => return Err("no instruction to extend".to_string()),
+                };
+                match prev.opcode() {
+                    OP_LOADKX | OP_NEWTABLE => Ok(()),
+                    OP_SETLIST if prev.abc().1 != 0 => Ok(()),
+                    _ => Err(format!("cannot extend {}", prev.opname())),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn reg(&self, r: isize) -> Result<(), String> {
+        if r >= self.proto.maxstacksize as isize {
+            return Err(format!(
+                "register {} out of range (maxstacksize: {})",
+                r, self.proto.maxstacksize
+            ));
+        }
+        Ok(())
+    }
+
+    fn rk(&self, k: bool, c: isize) -> Result<(), String> {
+        if k {
+            self.constant(c)
+        } else {
+            self.reg(c)
+        }
+    }
+
+    fn constant(&self, idx: isize) -> Result<(), String> {
+        self.constant_of(idx).map(drop)
+    }
+
+    fn constant_of(&self, idx: isize) -> Result<&Constant, String> {
+        self.proto.constants.get(idx as usize).ok_or_else(|| {
+            format!(
+                "constant {} out of range (constants: {})",
+                idx,
+                self.proto.constants.len()
+            )
+        })
+    }
+
+    fn string_constant(&self, idx: isize) -> Result<(), String> {
+        match self.constant_of(idx)? {
+            Constant::String(_) => Ok(()),
+            k => Err(format!("constant {} is not a string: {:?}", idx, k)),
+        }
+    }
+
+    fn numeric_constant(&self, idx: isize) -> Result<(), String> {
+        match self.constant_of(idx)? {
+            Constant::Number(_) | Constant::Integer(_) => Ok(()),
+            k => Err(format!("constant {} is not a number: {:?}", idx, k)),
+        }
+    }
+
+    fn upvalue(&self, idx: isize) -> Result<(), String> {
+        if idx as usize >= self.proto.upvalues.len() {
+            return Err(format!(
+                "upvalue {} out of range (upvalues: {})",
+                idx,
+                self.proto.upvalues.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks `c` is an event an `OP_MMBIN*` may refer to.
+    fn event(&self, c: isize) -> Result<(), String> {
+        if !(TM_ADD as isize..=TM_SHR as isize).contains(&c) {
+            return Err(format!("invalid metamethod event {}", c));
+        }
+        Ok(())
+    }
+
+    fn vararg(&self) -> Result<(), String> {
+        if self.proto.is_vararg == 0 {
+            return Err("vararg instruction in a non-vararg function".to_string());
+        }
+        Ok(())
+    }
+
+    fn target(&self, pc: isize) -> Result<(), String> {
+        if pc < 0 || pc as usize >= self.proto.code.len() {
+            return Err(format!(
+                "jump target {} out of range (code: {})",
+                pc,
+                self.proto.code.len()
+            ));
+        }
+        match self.proto.code[pc as usize].opcode() {
+            OP_EXTRAARG => Err(format!("jump target {} is OP_EXTRAARG", pc)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the argument of the `OP_EXTRAARG` which must follow `pc`.
+    fn extraarg(&self, pc: usize) -> Result<isize, String> {
+        match self.proto.code.get(pc + 1) {
+            Some(next) if next.opcode() == OP_EXTRAARG => Ok(next.ax()),
+            _ => Err("not followed by OP_EXTRAARG".to_string()),
+        }
+    }
+
+    fn followed_by_jump(&self, pc: usize) -> Result<(), String> {
+        match self.proto.code.get(pc + 1) {
+            Some(next) if next.opcode() == OP_JMP => Ok(()),
+            _ => Err("not followed by OP_JMP".to_string()),
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
@@ -0,0 +1,132 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::opcode::*;
+
+/// Assembles `text` and dumps it, to compare with a chunk written by hand.
+fn assemble(text: &str) -> Vec<u8> {
+    rua::dump(&rua::assemble(text).unwrap())
+}
+
+fn error(text: &str) -> String {
+    rua::assemble(text).unwrap_err().to_string()
+}
+
+#[test]
+fn operands() {
+    let text = r#"
+        .const "x"
+        .const 1.5
+        NEWTABLE 0 2 0
+        EXTRAARG 0
+        SETFIELD 0 0 1k
+        LOADK 1 1
+        LOADI 2 -5
+        GETTABLE 3 0 2
+        RETURN 0 4 1"#;
+    let expected = Function {
+        maxstacksize: 4,
+        code: vec![
+            i(OP_NEWTABLE, &[0, 2, 0]),
+            i(OP_EXTRAARG, &[0]),
+            i(OP_SETFIELD, &[0, 0, 1, 1]),
+            i(OP_LOADK, &[1, 1]),
+            i(OP_LOADI, &[2, -5]),
+            i(OP_GETTABLE, &[3, 0, 2]),
+            i(OP_RETURN, &[0, 4, 1]),
+        ],
+        constants: vec!["\"x\"", "1.5"],
+        ..Default::default()
+    };
+    assert_eq!(assemble(text), chunk(&expected));
+}
+
+#[test]
+fn labels() {
+    let text = "
+        LOADI 0 1
+        LOADI 1 3
+        LOADI 2 1
+        FORPREP 0 exit
+        body:
+        TEST 0 0
+        JMP body
+        JMP skip
+        LOADFALSE 0
+        skip:
+        FORLOOP 0 body
+        exit:
+        TFORPREP 3 call
+        gbody:
+        LOADNIL 7 0
+        call:
+        TFORCALL 3 1
+        TFORLOOP 3 gbody
+        CLOSURE 0 g
+        RETURN0
+        .function f
+        RETURN0
+        .end
+        .function g
+        RETURN0
+        .end";
+    let empty = || Function {
+        maxstacksize: 2,
+        code: vec![i(OP_RETURN0, &[])],
+        ..Default::default()
+    };
+    // Jumps count from the next instruction; FORPREP skips its FORLOOP too.
+    let expected = Function {
+        maxstacksize: 8,
+        code: vec![
+            i(OP_LOADI, &[0, 1]),
+            i(OP_LOADI, &[1, 3]),
+            i(OP_LOADI, &[2, 1]),
+            i(OP_FORPREP, &[0, 4]),
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[-2]),
+            i(OP_JMP, &[1]),
+            i(OP_LOADFALSE, &[0]),
+            i(OP_FORLOOP, &[0, 5]),
+            i(OP_TFORPREP, &[3, 1]),
+            i(OP_LOADNIL, &[7, 0]),
+            i(OP_TFORCALL, &[3, 0, 1]),
+            i(OP_TFORLOOP, &[3, 3]),
+            i(OP_CLOSURE, &[0, 1]),
+            i(OP_RETURN0, &[]),
+        ],
+        protos: vec![empty(), empty()],
+        ..Default::default()
+    };
+    assert_eq!(assemble(text), chunk(&expected));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        error("JMP nowhere\nRETURN0"),
+        "line 1: undefined label 'nowhere'"
+    );
+    assert_eq!(error("a:\na:\nRETURN0"), "line 2: duplicate label 'a'");
+    assert_eq!(error("FOO 1"), "line 1: unknown opcode 'FOO'");
+    assert_eq!(error("CLOSURE 0 h\nRETURN0"), "line 1: no function 'h'");
+    assert_eq!(error("JMP 1 2"), "line 1: too many operands");
+    assert_eq!(
+        error("LOADI 0 99999999"),
+        "line 1: sBx 99999999 out of range [-65535, 65536]"
+    );
+}
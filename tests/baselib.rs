@@ -0,0 +1,326 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{call, state};
+use rua::{Function, State, Table, Userdata, Value};
+
+fn call1(state: &State, name: &str, args: Vec<Value>) -> Value {
+    call(state, name, args)
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+fn show(state: &State, v: &Value) -> String {
+    common::show(state, std::slice::from_ref(v))
+}
+
+#[test]
+fn globals() {
+    let state = state();
+    let g = state.globals();
+    assert!(g.get_str("_G").raw_equal(&Value::Table(g.clone())));
+    assert_eq!(show(&state, &g.get_str("_VERSION")), "Lua 5.4");
+}
+
+#[test]
+fn tonumber() {
+    let state = state();
+    let n = |args: Vec<Value>| show(&state, &call1(&state, "tonumber", args));
+    assert_eq!(n(vec!["0x10".into()]), "16");
+    assert_eq!(n(vec!["  1e1  ".into()]), "10.0");
+    assert_eq!(n(vec!["10".into(), 2.into()]), "2");
+    assert_eq!(n(vec!["ff".into(), 16.into()]), "255");
+    assert_eq!(n(vec!["-zz".into(), 36.into()]), "-1295");
+    assert_eq!(n(vec!["+10".into(), 16.into()]), "16");
+    assert_eq!(n(vec!["+".into(), 16.into()]), "nil");
+    assert_eq!(n(vec!["+-1".into(), 16.into()]), "nil");
+    assert_eq!(n(vec!["8".into(), 8.into()]), "nil");
+    assert_eq!(n(vec!["1e".into()]), "nil");
+    assert_eq!(n(vec![Value::Boolean(true)]), "nil");
+    assert_eq!(
+        call(&state, "tonumber", vec!["1".into(), 99.into()]).unwrap_err(),
+        "bad argument #2 to 'tonumber' (base out of range)"
+    );
+    assert_eq!(
+        call(&state, "tonumber", vec![10.into(), 16.into()]).unwrap_err(),
+        "bad argument #1 to 'tonumber' (string expected, got number)"
+    );
+}
+
+#[test]
+fn tostring() {
+    let state = state();
+    assert_eq!(
+        show(&state, &call1(&state, "tostring", vec![1.5.into()])),
+        "1.5"
+    );
+    assert_eq!(
+        show(&state, &call1(&state, "tostring", vec![(-0.0).into()])),
+        "-0.0"
+    );
+    let t = Table::new();
+    let mt = Table::new();
+    mt.set_str("__name", "Point");
+    t.set_metatable(Some(mt.clone()));
+    assert!(
+        show(&state, &call1(&state, "tostring", vec![t.clone().into()])).starts_with("Point: 0x")
+    );
+    mt.set_str("__tostring", state.globals().get_str("type"));
+    assert_eq!(
+        show(&state, &call1(&state, "tostring", vec![t.into()])),
+        "table"
+    );
+    assert_eq!(
+        call(&state, "tostring", vec![]).unwrap_err(),
+        "bad argument #1 to 'tostring' (value expected)"
+    );
+}
+
+#[test]
+fn select() {
+    let state = state();
+    let args = |n: Value| vec![n, "a".into(), "b".into(), "c".into()];
+    assert_eq!(
+        show(&state, &call1(&state, "select", args("#".into()))),
+        "3"
+    );
+    let r = call(&state, "select", args(2.into())).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(show(&state, &r[0]), "b");
+    let r = call(&state, "select", args((-1).into())).unwrap();
+    assert_eq!(r.len(), 1);
+    assert_eq!(show(&state, &r[0]), "c");
+    assert!(call(&state, "select", args(9.into())).unwrap().is_empty());
+    assert_eq!(
+        call(&state, "select", args((-4).into())).unwrap_err(),
+        "bad argument #1 to 'select' (index out of range)"
+    );
+}
+
+#[test]
+fn pairs_and_ipairs() {
+    let state = state();
+    let t = Table::new();
+    t.set_int(1, "x".into());
+    t.set_int(2, "y".into());
+    t.set_str("k", "v");
+    let mut r = call(&state, "pairs", vec![t.clone().into()]).unwrap();
+    let mut seen = vec![];
+    loop {
+        let next = r[0].clone();
+        let kv = state.call(&next, vec![r[1].clone(), r[2].clone()]).unwrap();
+        if kv[0].is_nil() {
+            break;
+        }
+        seen.push(format!("{}={}", show(&state, &kv[0]), show(&state, &kv[1])));
+        r[2] = kv[0].clone();
+    }
+    assert_eq!(seen, ["1=x", "2=y", "k=v"]);
+
+    let r = call(&state, "ipairs", vec![t.clone().into()]).unwrap();
+    let kv = state.call(&r[0], vec![r[1].clone(), 1.into()]).unwrap();
+    assert_eq!(show(&state, &kv[0]), "2");
+    let kv = state.call(&r[0], vec![r[1].clone(), 2.into()]).unwrap();
+    assert!(kv[0].is_nil());
+
+    let mt = Table::new();
+    mt.set_str("__pairs", state.globals().get_str("select"));
+    t.set_metatable(Some(mt));
+    assert_eq!(
+        call(&state, "pairs", vec![t.into()]).unwrap_err(),
+        "bad argument #1 to 'select' (number expected, got table)"
+    );
+}
+
+#[test]
+fn metatables() {
+    let state = state();
+    let t = Table::new();
+    let mt = Table::new();
+    call(
+        &state,
+        "setmetatable",
+        vec![t.clone().into(), mt.clone().into()],
+    )
+    .unwrap();
+    assert!(call1(&state, "getmetatable", vec![t.clone().into()]).raw_equal(&mt.clone().into()));
+    mt.set_str("__metatable", "locked");
+    assert_eq!(
+        show(
+            &state,
+            &call1(&state, "getmetatable", vec![t.clone().into()])
+        ),
+        "locked"
+    );
+    assert_eq!(
+        call(&state, "setmetatable", vec![t.clone().into(), Value::Nil]).unwrap_err(),
+        "cannot change a protected metatable"
+    );
+    assert_eq!(
+        call(&state, "setmetatable", vec![t.into(), 1.into()]).unwrap_err(),
+        "bad argument #2 to 'setmetatable' (nil or table expected, got number)"
+    );
+}
+
+#[test]
+fn eq_metamethod() {
+    let state = state();
+    let mt = Table::new();
+    mt.set_str(
+        "__eq",
+        Function::native("eq", |_, args| {
+            let (Value::Userdata(a), Value::Userdata(b)) = (args.get(1), args.get(2)) else {
+                return Ok(vec![false.into()]);
+            };
+            let a = *a.borrow_mut::<i64>().unwrap();
+            let b = *b.borrow_mut::<i64>().unwrap();
+            Ok(vec![(a == b).into()])
+        }),
+    );
+    let one = Value::from(Userdata::new(1_i64, Some(mt.clone())));
+    let also_one = Value::from(Userdata::new(1_i64, Some(mt.clone())));
+    let two = Value::from(Userdata::new(2_i64, Some(mt)));
+    assert!(state.equals(&one, &also_one).unwrap());
+    assert!(!state.equals(&one, &two).unwrap());
+    // Only values of the same type reach __eq.
+    assert!(!state.equals(&one, &1.into()).unwrap());
+
+    let t = Table::new();
+    let u = Table::new();
+    let mt = Table::new();
+    mt.set_str("__eq", Function::native("eq", |_, _| Ok(vec![true.into()])));
+    t.set_metatable(Some(mt));
+    assert!(state.equals(&t.into(), &u.into()).unwrap());
+}
+
+#[test]
+fn raw_access() {
+    let state = state();
+    let t = Table::new();
+    call(
+        &state,
+        "rawset",
+        vec![t.clone().into(), 1.0.into(), "a".into()],
+    )
+    .unwrap();
+    assert_eq!(
+        show(
+            &state,
+            &call1(&state, "rawget", vec![t.clone().into(), 1.into()])
+        ),
+        "a"
+    );
+    assert_eq!(
+        show(&state, &call1(&state, "rawlen", vec![t.clone().into()])),
+        "1"
+    );
+    assert_eq!(
+        show(&state, &call1(&state, "rawlen", vec!["abc".into()])),
+        "3"
+    );
+    assert_eq!(
+        call(&state, "rawlen", vec![Value::Nil]).unwrap_err(),
+        "bad argument #1 to 'rawlen' (table or string expected, got nil)"
+    );
+    assert_eq!(
+        call(
+            &state,
+            "rawset",
+            vec![t.clone().into(), Value::Nil, 1.into()]
+        )
+        .unwrap_err(),
+        "index is nil"
+    );
+    assert_eq!(
+        show(
+            &state,
+            &call1(&state, "rawequal", vec![1.into(), 1.0.into()])
+        ),
+        "true"
+    );
+    assert_eq!(
+        show(
+            &state,
+            &call1(&state, "rawequal", vec![t.into(), Table::new().into()])
+        ),
+        "false"
+    );
+}
+
+#[test]
+fn errors() {
+    let state = state();
+    let r = call(&state, "assert", vec![1.into(), "m".into()]).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(
+        call(&state, "assert", vec![Value::Boolean(false)]).unwrap_err(),
+        "assertion failed!"
+    );
+    assert_eq!(
+        call(&state, "assert", vec![Value::Nil, "boom".into()]).unwrap_err(),
+        "boom"
+    );
+    let error = state.globals().get_str("error");
+    let r = call(&state, "pcall", vec![error.clone(), Table::new().into()]).unwrap();
+    assert_eq!(show(&state, &r[0]), "false");
+    assert_eq!(r[1].type_name(), "table");
+    let r = call(
+        &state,
+        "pcall",
+        vec![state.globals().get_str("type"), 1.into()],
+    )
+    .unwrap();
+    assert_eq!(show(&state, &r[1]), "number");
+    let r = call(
+        &state,
+        "xpcall",
+        vec![error, state.globals().get_str("type"), "x".into()],
+    )
+    .unwrap();
+    assert_eq!(show(&state, &r[1]), "string");
+}
+
+#[test]
+fn load_binary_chunks() {
+    let state = state();
+    let proto = rua::assemble("RETURN0\n").unwrap();
+    let chunk = rua::dump(&proto);
+    let chunk = Value::String(rua::LuaString::from(chunk));
+    let r = call(&state, "load", vec![chunk.clone()]).unwrap();
+    assert_eq!(r[0].type_name(), "function");
+    let r = call(&state, "load", vec![chunk.clone(), Value::Nil, "t".into()]).unwrap();
+    assert!(r[0].is_nil());
+    assert_eq!(
+        show(&state, &r[1]),
+        "attempt to load a binary chunk (mode is 't')"
+    );
+    let mut truncated = chunk.to_lua_string().unwrap().to_vec();
+    truncated.truncate(20);
+    let r = call(&state, "load", vec![rua::LuaString::from(truncated).into()]).unwrap();
+    assert_eq!(
+        show(&state, &r[1]),
+        "binary string: bad binary format (truncated chunk)"
+    );
+    let r = call(&state, "load", vec!["return 1".into()]).unwrap();
+    assert!(r[0].is_nil());
+    let r = call(&state, "loadfile", vec!["/nonexistent.lua".into()]).unwrap();
+    assert_eq!(
+        show(&state, &r[1]),
+        "cannot open /nonexistent.lua: No such file or directory"
+    );
+}
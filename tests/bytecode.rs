@@ -0,0 +1,111 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::opcode::*;
+
+/// A main function with two nested functions, the first with one of its own.
+fn nested() -> Vec<u8> {
+    let inner = Function {
+        linedefined: 2,
+        lastlinedefined: 2,
+        maxstacksize: 2,
+        code: vec![i(OP_LOADK, &[0, 0]), i(OP_RETURN1, &[0])],
+        constants: vec!["\"inner\""],
+        ..Default::default()
+    };
+    let f = Function {
+        linedefined: 1,
+        lastlinedefined: 3,
+        maxstacksize: 2,
+        code: vec![i(OP_CLOSURE, &[0, 0]), i(OP_RETURN, &[0, 2, 1])],
+        constants: vec!["42"],
+        protos: vec![inner],
+        ..Default::default()
+    };
+    let g = Function {
+        linedefined: 4,
+        lastlinedefined: 4,
+        numparams: 1,
+        maxstacksize: 2,
+        code: vec![i(OP_RETURN1, &[0])],
+        ..Default::default()
+    };
+    chunk(&Function {
+        source: Some("@nested.lua"),
+        maxstacksize: 2,
+        code: vec![
+            i(OP_CLOSURE, &[0, 0]),
+            i(OP_SETTABUP, &[0, 0, 0]),
+            i(OP_CLOSURE, &[0, 1]),
+            i(OP_SETTABUP, &[0, 1, 0]),
+            i(OP_RETURN, &[0, 1, 1]),
+        ],
+        constants: vec!["\"f\"", "\"g\""],
+        upvalues: vec![("_ENV", 1, 0)],
+        protos: vec![f, g],
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn nested_protos_wait_for_load() {
+    let chunk = nested();
+    let closure = rua::undump_lazy(&chunk[..]).await.unwrap();
+    let protos = closure.proto().protos();
+    assert_eq!(protos.len(), 2);
+    assert!(protos.iter().all(|p| p.get().is_none()));
+
+    let f = protos[0].load().await.unwrap();
+    assert!(std::ptr::eq(protos[0].get().unwrap(), f));
+    assert!(protos[1].get().is_none());
+    // Its own nested proto waits in turn.
+    assert!(f.protos()[0].get().is_none());
+    f.protos()[0].load().await.unwrap();
+    // Decoding once is enough.
+    assert!(std::ptr::eq(protos[0].load().await.unwrap(), f));
+}
+
+#[tokio::test]
+async fn eager_undump_decodes_every_proto() {
+    let chunk = nested();
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    let protos = closure.proto().protos();
+    assert!(protos.iter().all(|p| p.get().is_some()));
+    assert!(protos[0].get().unwrap().protos()[0].get().is_some());
+}
+
+#[tokio::test]
+async fn load_all_matches_eager_undump() {
+    let chunk = nested();
+    let eager = rua::undump(&chunk[..]).await.unwrap();
+    let lazy = rua::undump_lazy(&chunk[..]).await.unwrap();
+    assert_ne!(format!("{:?}", lazy), format!("{:?}", eager));
+
+    lazy.proto().load_all().await.unwrap();
+    assert_eq!(format!("{:?}", lazy), format!("{:?}", eager));
+}
+
+#[tokio::test]
+async fn dump_copies_deferred_protos() {
+    let chunk = nested();
+    let eager = rua::undump(&chunk[..]).await.unwrap();
+    let lazy = rua::undump_lazy(&chunk[..]).await.unwrap();
+    assert_eq!(rua::dump(lazy.proto()), chunk);
+
+    lazy.proto().load_all().await.unwrap();
+    assert_eq!(rua::dump(lazy.proto()), rua::dump(eager.proto()));
+}
@@ -0,0 +1,256 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use std::ops::Range;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, Cfg};
+
+async fn cfg(numparams: u8, code: Vec<u32>) -> Cfg {
+    let chunk = chunk(&Function {
+        numparams,
+        maxstacksize: 8,
+        code,
+        ..Default::default()
+    });
+    Cfg::new(rua::undump(&chunk[..]).await.unwrap().proto())
+}
+
+fn pcs(cfg: &Cfg) -> Vec<Range<usize>> {
+    cfg.blocks().iter().map(|b| b.pcs.clone()).collect()
+}
+
+fn succs(cfg: &Cfg) -> Vec<Vec<usize>> {
+    cfg.blocks().iter().map(|b| b.succs.clone()).collect()
+}
+
+fn idoms(cfg: &Cfg) -> Vec<Option<usize>> {
+    (0..cfg.blocks().len()).map(|b| cfg.idom(b)).collect()
+}
+
+/// Each loop as its header, latches and body.
+fn loops(cfg: &Cfg) -> Vec<(usize, Vec<usize>, Vec<usize>)> {
+    cfg.loops()
+        .iter()
+        .map(|l| (l.header, l.latches.clone(), l.body.clone()))
+        .collect()
+}
+
+#[tokio::test]
+async fn if_else() {
+    // if x then x = 1 else x = 2 end return x
+    let cfg = cfg(
+        1,
+        vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[2]),
+            i(OP_LOADI, &[0, 1]),
+            i(OP_JMP, &[1]),
+            i(OP_LOADI, &[0, 2]),
+            i(OP_RETURN1, &[0]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..1, 1..2, 2..4, 4..5, 5..6]);
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![3], vec![4], vec![4], vec![]]);
+    assert_eq!(cfg.blocks()[4].preds, [2, 3]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0), Some(1), Some(0)]);
+    assert_eq!(cfg.dominated_by(0), [1, 2, 4]);
+    assert!(cfg.dominates(1, 3) && !cfg.dominates(1, 4));
+    assert_eq!(cfg.block_of(3), 2);
+    assert!(loops(&cfg).is_empty());
+}
+
+#[tokio::test]
+async fn test_with_k() {
+    // if not x then x = 1 end
+    let cfg = cfg(
+        1,
+        vec![
+            i(OP_TEST, &[0, 0, 0, 1]),
+            i(OP_JMP, &[1]),
+            i(OP_LOADI, &[0, 1]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..1, 1..2, 2..3, 3..4]);
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![3], vec![3], vec![]]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0), Some(0)]);
+}
+
+#[tokio::test]
+async fn lfalseskip() {
+    // return x == y
+    let cfg = cfg(
+        2,
+        vec![
+            i(OP_EQ, &[0, 1, 0, 1]),
+            i(OP_JMP, &[1]),
+            i(OP_LFALSESKIP, &[2]),
+            i(OP_LOADTRUE, &[2]),
+            i(OP_RETURN1, &[2]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..1, 1..2, 2..3, 3..4, 4..5]);
+    // LFALSESKIP skips the LOADTRUE that the jump lands on.
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![3], vec![4], vec![4], vec![]]);
+    assert_eq!(cfg.blocks()[4].preds, [2, 3]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0), Some(1), Some(0)]);
+}
+
+#[tokio::test]
+async fn while_loop() {
+    // while x do x = false end
+    let cfg = cfg(
+        1,
+        vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[2]),
+            i(OP_LOADFALSE, &[0]),
+            i(OP_JMP, &[-4]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..1, 1..2, 2..4, 4..5]);
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![3], vec![0], vec![]]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0), Some(1)]);
+    assert_eq!(loops(&cfg), [(0, vec![2], vec![0, 2])]);
+}
+
+#[tokio::test]
+async fn numeric_for() {
+    // for i = 1, 10 do local j = i end
+    let cfg = cfg(
+        0,
+        vec![
+            i(OP_LOADI, &[0, 1]),
+            i(OP_LOADI, &[1, 10]),
+            i(OP_LOADI, &[2, 1]),
+            i(OP_FORPREP, &[0, 1]),
+            i(OP_MOVE, &[4, 3]),
+            i(OP_FORLOOP, &[0, 2]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..4, 4..6, 6..7]);
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![2, 1], vec![]]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0)]);
+    assert_eq!(loops(&cfg), [(1, vec![1], vec![1])]);
+}
+
+#[tokio::test]
+async fn generic_for() {
+    // for k, v in next, t do local x = k end
+    let cfg = cfg(
+        0,
+        vec![
+            i(OP_LOADNIL, &[0, 3]),
+            i(OP_TFORPREP, &[0, 1]),
+            i(OP_MOVE, &[6, 4]),
+            i(OP_TFORCALL, &[0, 0, 2]),
+            i(OP_TFORLOOP, &[0, 3]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..2, 2..3, 3..5, 5..6]);
+    // TFORPREP jumps straight to the TFORCALL, and TFORLOOP back to the body.
+    assert_eq!(succs(&cfg), [vec![2], vec![2], vec![3, 1], vec![]]);
+    assert_eq!(cfg.blocks()[2].preds, [0, 1]);
+    assert_eq!(idoms(&cfg), [None, Some(2), Some(0), Some(2)]);
+    assert_eq!(loops(&cfg), [(2, vec![1], vec![1, 2])]);
+}
+
+#[tokio::test]
+async fn repeat_until() {
+    // repeat x = not x until x
+    let cfg = cfg(
+        1,
+        vec![
+            i(OP_NOT, &[0, 0]),
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[-3]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..2, 2..3, 3..4]);
+    assert_eq!(succs(&cfg), [vec![1, 2], vec![0], vec![]]);
+    assert_eq!(idoms(&cfg), [None, Some(0), Some(0)]);
+    assert_eq!(loops(&cfg), [(0, vec![1], vec![0, 1])]);
+}
+
+#[tokio::test]
+async fn goto_and_unreachable_code() {
+    // ::top:: x = 1 goto top
+    let cfg = cfg(
+        0,
+        vec![i(OP_LOADI, &[0, 1]), i(OP_JMP, &[-2]), i(OP_RETURN0, &[])],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..2, 2..3]);
+    assert_eq!(succs(&cfg), [vec![0], vec![]]);
+    assert!(!cfg.is_reachable(1));
+    assert_eq!(cfg.reverse_postorder(), [0]);
+    assert_eq!(idoms(&cfg), [None, None]);
+    assert_eq!(loops(&cfg), [(0, vec![0], vec![0])]);
+}
+
+#[tokio::test]
+async fn nested_loops() {
+    // while x do while y do y = false end x = false end
+    let cfg = cfg(
+        2,
+        vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[6]),
+            i(OP_TEST, &[1, 0, 0, 0]),
+            i(OP_JMP, &[2]),
+            i(OP_LOADFALSE, &[1]),
+            i(OP_JMP, &[-4]),
+            i(OP_LOADFALSE, &[0]),
+            i(OP_JMP, &[-8]),
+            i(OP_RETURN0, &[]),
+        ],
+    )
+    .await;
+    assert_eq!(pcs(&cfg), [0..1, 1..2, 2..3, 3..4, 4..6, 6..8, 8..9]);
+    assert_eq!(
+        succs(&cfg),
+        [
+            vec![1, 2],
+            vec![6],
+            vec![3, 4],
+            vec![5],
+            vec![2],
+            vec![0],
+            vec![]
+        ]
+    );
+    assert_eq!(
+        idoms(&cfg),
+        [None, Some(0), Some(0), Some(2), Some(2), Some(3), Some(1)]
+    );
+    assert_eq!(cfg.dominated_by(2), [3, 4]);
+    assert_eq!(
+        loops(&cfg),
+        [(0, vec![5], vec![0, 2, 3, 4, 5]), (2, vec![4], vec![2, 4])]
+    );
+}
@@ -0,0 +1,148 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lua 5.4 binary chunks written field by field, the way `luac` dumps them.
+
+use rua::opcode::*;
+
+/// A function to dump. Constants are Lua literals: `nil`, `true`, `false`,
+/// numbers and double-quoted strings. Upvalues are `(name, instack, idx)` and
+/// locals `(name, startpc, endpc)`.
+#[derive(Default)]
+pub struct Function {
+    pub source: Option<&'static str>,
+    pub linedefined: usize,
+    pub lastlinedefined: usize,
+    pub numparams: u8,
+    pub is_vararg: bool,
+    pub maxstacksize: u8,
+    pub code: Vec<u32>,
+    pub constants: Vec<&'static str>,
+    pub upvalues: Vec<(&'static str, u8, u8)>,
+    pub protos: Vec<Function>,
+    pub lineinfo: Vec<i8>,
+    pub abslineinfo: Vec<(usize, usize)>,
+    pub locvars: Vec<(&'static str, usize, usize)>,
+}
+
+/// Encodes `op` with the fields of its mode in order: A, B, C and k; A and
+/// Bx; A and sBx; Ax; or sJ. Missing fields are zero.
+pub fn i(op: u8, fields: &[i32]) -> u32 {
+    let field = |n: usize| fields.get(n).copied().unwrap_or(0) as u32;
+    let (op, a) = (op as u32, field(0) << 7);
+    match OPCODES[op as usize].mode() {
+        OP_MODE_ABC => op | a | field(1) << 16 | field(2) << 24 | field(3) << 15,
+        OP_MODE_ABX => op | a | field(1) << 15,
+        OP_MODE_ASBX => op | a | (field(1).wrapping_add(0xffff)) << 15,
+        OP_MODE_AX => op | field(0) << 7,
+        _ => op | (field(0).wrapping_add(0xff_ffff)) << 7,
+    }
+}
+
+/// Dumps `main` as the main function of a chunk.
+pub fn chunk(main: &Function) -> Vec<u8> {
+    let mut w = b"\x1bLua\x54\x00\x19\x93\r\n\x1a\n\x04\x08\x08".to_vec();
+    w.extend(0x5678_i64.to_le_bytes());
+    w.extend(370.5_f64.to_le_bytes());
+    w.push(main.upvalues.len() as u8);
+    main.dump(&mut w);
+    w
+}
+
+impl Function {
+    fn dump(&self, w: &mut Vec<u8>) {
+        string(w, self.source);
+        size(w, self.linedefined);
+        size(w, self.lastlinedefined);
+        w.extend([self.numparams, self.is_vararg as u8, self.maxstacksize]);
+        size(w, self.code.len());
+        for i in &self.code {
+            w.extend(i.to_le_bytes());
+        }
+        size(w, self.constants.len());
+        for k in &self.constants {
+            constant(w, k);
+        }
+        size(w, self.upvalues.len());
+        for &(_, instack, idx) in &self.upvalues {
+            w.extend([instack, idx, 0]);
+        }
+        size(w, self.protos.len());
+        for p in &self.protos {
+            p.dump(w);
+        }
+        size(w, self.lineinfo.len());
+        w.extend(self.lineinfo.iter().map(|&d| d as u8));
+        size(w, self.abslineinfo.len());
+        for &(pc, line) in &self.abslineinfo {
+            size(w, pc);
+            size(w, line);
+        }
+        size(w, self.locvars.len());
+        for &(name, startpc, endpc) in &self.locvars {
+            string(w, Some(name));
+            size(w, startpc);
+            size(w, endpc);
+        }
+        size(w, self.upvalues.len());
+        for &(name, ..) in &self.upvalues {
+            string(w, Some(name));
+        }
+    }
+}
+
+/// Writes `x` in groups of 7 bits, most significant first, with the high bit
+/// set on the last byte.
+fn size(w: &mut Vec<u8>, mut x: usize) {
+    let mut groups = vec![(x & 0x7f) as u8 | 0x80];
+    x >>= 7;
+    while x != 0 {
+        groups.push((x & 0x7f) as u8);
+        x >>= 7;
+    }
+    w.extend(groups.iter().rev());
+}
+
+fn string(w: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        None => size(w, 0),
+        Some(s) => {
+            size(w, s.len() + 1);
+            w.extend(s.as_bytes());
+        }
+    }
+}
+
+fn constant(w: &mut Vec<u8>, k: &str) {
+    match k {
+        "nil" => w.push(0x00),
+        "false" => w.push(0x01),
+        "true" => w.push(0x11),
+        _ if k.starts_with('"') => {
+            let s = &k[1..k.len() - 1];
+            w.push(if s.len() <= 40 { 0x04 } else { 0x14 });
+            string(w, Some(s));
+        }
+        _ => match k.parse::<i64>() {
+            Ok(n) => {
+                w.push(0x03);
+                w.extend(n.to_le_bytes());
+            }
+            Err(_) => {
+                w.push(0x13);
+                w.extend(k.parse::<f64>().unwrap().to_le_bytes());
+            }
+        },
+    }
+}
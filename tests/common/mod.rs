@@ -0,0 +1,63 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared by the library tests.
+
+use std::process::{Command, ExitStatus};
+
+use rua::{State, Value};
+
+/// A state with the standard libraries open.
+pub fn state() -> State {
+    let state = State::new();
+    state.open_libs();
+    state
+}
+
+/// Calls the function at `path`, a global name or `lib.name`, returning the
+/// error message if it fails.
+pub fn call(state: &State, path: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    let mut f = Value::Table(state.globals());
+    for name in path.split('.') {
+        f = state.index(&f, &name.into()).unwrap();
+    }
+    state.call(&f, args).map_err(|e| e.to_string())
+}
+
+/// Renders `values` with `tostring`, joined by spaces.
+pub fn show(state: &State, values: &[Value]) -> String {
+    values
+        .iter()
+        .map(|v| state.tostring(v).unwrap().to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reruns test `name` alone in a child process with `env` set, returning its
+/// exit status; `None` in the child, which runs the test body instead. Tests
+/// that depend on the environment use it rather than setting variables in a
+/// process whose other tests run in parallel.
+#[allow(dead_code)]
+pub fn rerun(name: &str, env: &[(&str, &str)]) -> Option<ExitStatus> {
+    if std::env::var_os("RUA_TEST_CHILD").is_some() {
+        return None;
+    }
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args([name, "--exact", "--quiet"])
+        .env("RUA_TEST_CHILD", "1")
+        .envs(env.iter().copied())
+        .status()
+        .unwrap();
+    Some(status)
+}
@@ -0,0 +1,185 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, Closure, NameKind, ObjectName};
+
+async fn load(f: Function) -> Closure {
+    rua::undump(&chunk(&f)[..]).await.unwrap()
+}
+
+/// A main function running `code`, with upvalues `_ENV` and `u`.
+fn function(code: Vec<u32>) -> Function {
+    Function {
+        maxstacksize: 8,
+        code,
+        upvalues: vec![("_ENV", 1, 0), ("u", 1, 1)],
+        ..Default::default()
+    }
+}
+
+fn named(kind: NameKind, name: &str) -> Option<ObjectName> {
+    Some(ObjectName {
+        kind,
+        name: name.into(),
+    })
+}
+
+#[tokio::test]
+async fn global() {
+    // print()
+    let closure = load(Function {
+        constants: vec!["\"print\""],
+        ..function(vec![
+            i(OP_GETTABUP, &[0, 0, 0]),
+            i(OP_CALL, &[0, 1, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    let proto = closure.proto();
+    assert_eq!(proto.object_name(1, 0), named(NameKind::Global, "print"));
+    assert_eq!(proto.call_name(1), named(NameKind::Global, "print"));
+    assert_eq!(proto.varinfo(1, 0), " (global 'print')");
+}
+
+#[tokio::test]
+async fn fields() {
+    // local t, _ENV
+    // t.x() t[1]() t.y() _ENV.z()
+    let closure = load(Function {
+        constants: vec!["\"x\"", "\"y\"", "\"z\""],
+        locvars: vec![("t", 0, 10), ("_ENV", 0, 10)],
+        ..function(vec![
+            i(OP_GETFIELD, &[2, 0, 0]),
+            i(OP_CALL, &[2, 1, 1]),
+            i(OP_GETI, &[2, 0, 1]),
+            i(OP_CALL, &[2, 1, 1]),
+            i(OP_LOADK, &[3, 1]),
+            i(OP_GETTABLE, &[2, 0, 3]),
+            i(OP_CALL, &[2, 1, 1]),
+            i(OP_GETFIELD, &[2, 1, 2]),
+            i(OP_CALL, &[2, 1, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    let proto = closure.proto();
+    assert_eq!(proto.varinfo(1, 2), " (field 'x')");
+    assert_eq!(proto.varinfo(3, 2), " (field 'integer index')");
+    assert_eq!(proto.varinfo(6, 2), " (field 'y')");
+    // Indexing a local named _ENV reads a global.
+    assert_eq!(proto.varinfo(8, 2), " (global 'z')");
+}
+
+#[tokio::test]
+async fn method() {
+    // local t
+    // t:m()
+    let closure = load(Function {
+        constants: vec!["\"m\""],
+        locvars: vec![("t", 0, 3)],
+        ..function(vec![
+            i(OP_SELF, &[1, 0, 0, 1]),
+            i(OP_CALL, &[1, 2, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(closure.proto().call_name(1), named(NameKind::Method, "m"));
+    assert_eq!(closure.proto().varinfo(1, 1), " (method 'm')");
+}
+
+#[tokio::test]
+async fn upvalue_local_and_constant() {
+    // local f
+    // u() f() ("s")()
+    let closure = load(Function {
+        constants: vec!["\"s\""],
+        locvars: vec![("f", 0, 7)],
+        ..function(vec![
+            i(OP_GETUPVAL, &[1, 1]),
+            i(OP_CALL, &[1, 1, 1]),
+            i(OP_MOVE, &[1, 0]),
+            i(OP_CALL, &[1, 1, 1]),
+            i(OP_LOADK, &[1, 0]),
+            i(OP_CALL, &[1, 1, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    let proto = closure.proto();
+    assert_eq!(proto.varinfo(1, 1), " (upvalue 'u')");
+    // A copy is named after its source.
+    assert_eq!(proto.varinfo(3, 1), " (local 'f')");
+    assert_eq!(proto.varinfo(3, 0), " (local 'f')");
+    assert_eq!(proto.varinfo(5, 1), " (constant 's')");
+}
+
+#[tokio::test]
+async fn conditional_and_unknown() {
+    // local x
+    // if x then y = "s" end y()
+    let closure = load(Function {
+        constants: vec!["\"s\""],
+        locvars: vec![("x", 0, 5)],
+        ..function(vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[1]),
+            i(OP_LOADK, &[1, 0]),
+            i(OP_CALL, &[1, 1, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    let proto = closure.proto();
+    // The constant was loaded only if x held.
+    assert_eq!(proto.object_name(3, 1), None);
+    assert_eq!(proto.varinfo(3, 1), "");
+    assert_eq!(proto.varinfo(3, 5), "");
+}
+
+#[tokio::test]
+async fn for_iterator_and_metamethods() {
+    // local a, b
+    // for _ in a do end
+    // return a + b, #a, a.k, a == b
+    let closure = load(Function {
+        constants: vec!["\"k\""],
+        locvars: vec![("a", 0, 10), ("b", 0, 10)],
+        ..function(vec![
+            i(OP_TFORPREP, &[2, 0]),
+            i(OP_TFORCALL, &[2, 0, 1]),
+            i(OP_TFORLOOP, &[2, 2]),
+            i(OP_ADD, &[2, 0, 1]),
+            i(OP_MMBIN, &[0, 1, 6]),
+            i(OP_LEN, &[3, 0]),
+            i(OP_GETFIELD, &[4, 0, 0]),
+            i(OP_EQ, &[0, 1, 0, 0]),
+            i(OP_JMP, &[0]),
+            i(OP_RETURN, &[2, 4, 1]),
+        ])
+    })
+    .await;
+    let proto = closure.proto();
+    let iterator = named(NameKind::ForIterator, "for iterator");
+    assert_eq!(proto.call_name(1), iterator);
+    assert_eq!(proto.call_name(4), named(NameKind::Metamethod, "add"));
+    assert_eq!(proto.call_name(5), named(NameKind::Metamethod, "len"));
+    assert_eq!(proto.call_name(6), named(NameKind::Metamethod, "index"));
+    assert_eq!(proto.call_name(7), named(NameKind::Metamethod, "eq"));
+    assert_eq!(proto.call_name(8), None);
+}
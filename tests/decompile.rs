@@ -0,0 +1,213 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, Decompiled};
+
+/// Decompiles `main` back to Lua source.
+async fn decompile(main: Function) -> String {
+    let chunk = chunk(&main);
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    Decompiled::new(closure.proto()).to_string()
+}
+
+/// A main function running `code` with `_ENV` as its upvalue.
+fn main(code: Vec<u32>) -> Function {
+    Function {
+        maxstacksize: 10,
+        code,
+        upvalues: vec![("_ENV", 1, 0)],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn if_else() {
+    let lua = decompile(Function {
+        numparams: 1,
+        locvars: vec![("x", 0, 6)],
+        ..main(vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[2]),
+            i(OP_LOADI, &[0, 1]),
+            i(OP_JMP, &[1]),
+            i(OP_LOADI, &[0, 2]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(lua, "if x then\n  x = 1\nelse\n  x = 2\nend\n");
+}
+
+#[tokio::test]
+async fn while_loop() {
+    // The second test jumps over the break out of the loop.
+    let lua = decompile(Function {
+        numparams: 2,
+        locvars: vec![("a", 0, 8), ("b", 0, 8)],
+        ..main(vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[5]),
+            i(OP_TEST, &[1, 0, 0, 0]),
+            i(OP_JMP, &[1]),
+            i(OP_JMP, &[2]),
+            i(OP_LOADFALSE, &[0]),
+            i(OP_JMP, &[-7]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(lua, "while a and not b do\n  a = false\nend\n");
+}
+
+#[tokio::test]
+async fn repeat_until() {
+    let lua = decompile(Function {
+        numparams: 1,
+        locvars: vec![("x", 0, 4)],
+        ..main(vec![
+            i(OP_LOADFALSE, &[0]),
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[-3]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(lua, "repeat\n  x = false\nuntil x\n");
+}
+
+#[tokio::test]
+async fn numeric_for() {
+    let lua = decompile(Function {
+        constants: vec!["\"print\""],
+        locvars: vec![
+            ("(for state)", 3, 9),
+            ("(for state)", 3, 9),
+            ("(for state)", 3, 9),
+            ("i", 4, 7),
+        ],
+        ..main(vec![
+            i(OP_LOADI, &[0, 1]),
+            i(OP_LOADI, &[1, 10]),
+            i(OP_LOADI, &[2, 1]),
+            i(OP_FORPREP, &[0, 3]),
+            i(OP_GETTABUP, &[4, 0, 0]),
+            i(OP_MOVE, &[5, 3]),
+            i(OP_CALL, &[4, 2, 1]),
+            i(OP_FORLOOP, &[0, 4]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(lua, "for i = 1, 10 do\n  print(i)\nend\n");
+}
+
+#[tokio::test]
+async fn generic_for() {
+    let lua = decompile(Function {
+        constants: vec!["\"pairs\"", "\"t\"", "\"print\""],
+        locvars: vec![
+            ("(for state)", 3, 12),
+            ("(for state)", 3, 12),
+            ("(for state)", 3, 12),
+            ("(for state)", 3, 12),
+            ("k", 4, 8),
+            ("v", 4, 8),
+        ],
+        ..main(vec![
+            i(OP_GETTABUP, &[0, 0, 0]),
+            i(OP_GETTABUP, &[1, 0, 1]),
+            i(OP_CALL, &[0, 2, 5]),
+            i(OP_TFORPREP, &[0, 4]),
+            i(OP_GETTABUP, &[6, 0, 2]),
+            i(OP_MOVE, &[7, 4]),
+            i(OP_MOVE, &[8, 5]),
+            i(OP_CALL, &[6, 3, 1]),
+            i(OP_TFORCALL, &[0, 0, 2]),
+            i(OP_TFORLOOP, &[0, 6]),
+            i(OP_CLOSE, &[0]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(lua, "for k, v in pairs(t) do\n  print(k, v)\nend\n");
+}
+
+#[tokio::test]
+async fn functions_tables_and_methods() {
+    let f = Function {
+        numparams: 2,
+        maxstacksize: 3,
+        code: vec![
+            i(OP_ADD, &[2, 0, 1]),
+            i(OP_MMBIN, &[0, 1, 6]),
+            i(OP_RETURN1, &[2]),
+        ],
+        locvars: vec![("a", 0, 3), ("b", 0, 3)],
+        ..Default::default()
+    };
+    let lua = decompile(Function {
+        constants: vec!["\"f\"", "\"obj\"", "\"m\"", "\"k\"", "\"v\""],
+        protos: vec![f],
+        locvars: vec![("t", 7, 12)],
+        ..main(vec![
+            i(OP_CLOSURE, &[0, 0]),
+            i(OP_SETTABUP, &[0, 0, 0]),
+            i(OP_NEWTABLE, &[0, 1, 1]),
+            i(OP_EXTRAARG, &[0]),
+            i(OP_SETFIELD, &[0, 3, 4, 1]),
+            i(OP_LOADI, &[1, 1]),
+            i(OP_SETLIST, &[0, 1, 0]),
+            i(OP_GETTABUP, &[1, 0, 1]),
+            i(OP_SELF, &[1, 1, 2, 1]),
+            i(OP_MOVE, &[3, 0]),
+            i(OP_CALL, &[1, 3, 1]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(
+        lua,
+        "function f(a, b)\n  return a + b\nend\n\
+         local t = { k = \"v\", 1 }\n\
+         obj:m(t)\n"
+    );
+}
+
+#[tokio::test]
+async fn goto_fallback() {
+    // Entering the loop in its middle makes it irreducible.
+    let lua = decompile(Function {
+        numparams: 1,
+        locvars: vec![("x", 0, 5)],
+        ..main(vec![
+            i(OP_JMP, &[1]),
+            i(OP_LOADFALSE, &[0]),
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[-3]),
+            i(OP_RETURN0, &[]),
+        ])
+    })
+    .await;
+    assert_eq!(
+        lua,
+        "goto label_3\n\
+         ::label_2::\n\
+         x = false\n\
+         ::label_3::\n\
+         if not x then\n  goto label_2\nend\n"
+    );
+}
@@ -0,0 +1,108 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, CfgDot, ProtoTreeDot};
+
+/// `while x do x = "again" end return x`, with an unreachable `RETURN0` and
+/// nested functions `f`, holding `g`, and `h`.
+fn fixture() -> Vec<u8> {
+    let leaf = |linedefined, lastlinedefined, protos| Function {
+        linedefined,
+        lastlinedefined,
+        maxstacksize: 2,
+        code: vec![i(OP_RETURN0, &[])],
+        protos,
+        ..Default::default()
+    };
+    chunk(&Function {
+        source: Some("@w.lua"),
+        numparams: 1,
+        maxstacksize: 2,
+        code: vec![
+            i(OP_TEST, &[0, 0, 0, 0]),
+            i(OP_JMP, &[3]),
+            i(OP_LOADK, &[0, 0]),
+            i(OP_JMP, &[-4]),
+            i(OP_RETURN0, &[]),
+            i(OP_RETURN1, &[0]),
+        ],
+        constants: vec!["\"again\""],
+        protos: vec![leaf(4, 8, vec![leaf(5, 7, vec![])]), leaf(9, 9, vec![])],
+        lineinfo: vec![1, 0, 1, 0, 0, 1],
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn cfg() {
+    let chunk = fixture();
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    assert_eq!(
+        CfgDot::new(closure.proto()).to_string(),
+        r#"digraph cfg {
+  label="main <w.lua:0,0>";
+  node [shape=box, fontname=monospace];
+  b0 [label="1 [1] TEST      0 0\l"];
+  b1 [label="2 [1] JMP       3 ; to 6\l"];
+  b2 [label="3 [2] LOADK     0 0 ; \"again\"\l4 [2] JMP       -4 ; to 1\l"];
+  b3 [label="5 [2] RETURN0   \l", style=dashed];
+  b4 [label="6 [3] RETURN1   0\l"];
+  b0 -> b1;
+  b0 -> b2;
+  b1 -> b4;
+  b2 -> b0 [style=bold];
+}
+"#
+    );
+}
+
+#[tokio::test]
+async fn proto_tree() {
+    let chunk = fixture();
+    let closure = rua::undump_lazy(&chunk[..]).await.unwrap();
+    closure.proto().protos()[0].load().await.unwrap();
+    assert_eq!(
+        ProtoTreeDot::new(closure.proto()).to_string(),
+        r#"digraph protos {
+  node [shape=box];
+  f0 [label="main <w.lua:0,0>"];
+  f0 -> f1 [label="0"];
+  f1 [label="function <w.lua:4,8>"];
+  f1 -> f2 [label="0"];
+  f2 [label="(not loaded)", style=dashed];
+  f0 -> f3 [label="1"];
+  f3 [label="(not loaded)", style=dashed];
+}
+"#
+    );
+
+    closure.proto().load_all().await.unwrap();
+    assert_eq!(
+        ProtoTreeDot::new(closure.proto()).to_string(),
+        r#"digraph protos {
+  node [shape=box];
+  f0 [label="main <w.lua:0,0>"];
+  f0 -> f1 [label="0"];
+  f1 [label="function <w.lua:4,8>"];
+  f1 -> f2 [label="0"];
+  f2 [label="function <w.lua:5,7>"];
+  f0 -> f3 [label="1"];
+  f3 [label="function <w.lua:9,9>"];
+}
+"#
+    );
+}
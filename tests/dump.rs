@@ -0,0 +1,113 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Every kind of constant, local and line information, with nested functions.
+const FIXTURE: &str = r#"
+.source "@fixture.lua"
+.vararg
+.upvalue _ENV 1 0
+.const nil
+.const true
+.const false
+.const 42
+.const -1.5
+.const "short"
+.const "a long string that is not interned by the loader"
+.local x 1 end
+.line 1
+VARARGPREP 0
+LOADK 0 3
+.line 1000
+CLOSURE 1 f
+RETURN 0 1 1
+end:
+.function f
+.linedefined 1000
+.lastlinedefined 1002
+.params 1
+.upvalue x 1 0
+.upvalue _ENV 0 0
+.line 1001
+GETUPVAL 1 0
+RETURN1 1
+.end
+"#;
+
+#[tokio::test]
+async fn round_trip() {
+    let proto = rua::assemble(FIXTURE).unwrap();
+    let chunk = rua::dump(&proto);
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    assert_eq!(rua::dump(closure.proto()), chunk);
+    assert_eq!(closure.proto().line_for_pc(2), Some(1000));
+    let f = closure.proto().protos()[0].get().unwrap();
+    assert_eq!(f.line_for_pc(1), Some(1001));
+}
+
+#[test]
+fn matches_luac() {
+    // What `luac` writes for an empty file named `a`.
+    let proto = rua::assemble(
+        r#"
+        .source "@a"
+        .vararg
+        .upvalue _ENV 1 0
+        .line 1
+        VARARGPREP 0
+        RETURN 0 1 1"#,
+    )
+    .unwrap();
+    #[rustfmt::skip]
+    let chunk = [
+        // Header.
+        0x1b, b'L', b'u', b'a', 0x54, 0x00,
+        0x19, 0x93, b'\r', b'\n', 0x1a, b'\n',
+        0x04, 0x08, 0x08,
+        0x78, 0x56, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x77, 0x40,
+        // Upvalues of the main closure.
+        0x01,
+        // Source, lines, params, vararg and slots.
+        0x83, b'@', b'a', 0x80, 0x80, 0x00, 0x01, 0x02,
+        // Code.
+        0x82, 0x51, 0x00, 0x00, 0x00, 0x46, 0x00, 0x01, 0x01,
+        // Constants, upvalues and protos.
+        0x80, 0x81, 0x01, 0x00, 0x00, 0x80,
+        // Line info, absolute line info, locals and upvalue names.
+        0x82, 0x01, 0x00, 0x80, 0x80, 0x81, 0x85, b'_', b'E', b'N', b'V',
+    ];
+    assert_eq!(rua::dump(&proto), chunk);
+}
+
+#[tokio::test]
+async fn non_utf8_strings() {
+    let proto = rua::assemble(
+        r#"
+        .const "\xff\x00"
+        .local "\xfe\x80" 0 1
+        LOADK 0 0
+        RETURN0"#,
+    )
+    .unwrap();
+    let chunk = rua::dump(&proto);
+    let has = |bytes: &[u8]| chunk.windows(bytes.len()).any(|w| w == bytes);
+    // A short string constant and a local name, each of two bytes.
+    assert!(has(&[0x81, 0x04, 0x83, 0xff, 0x00]));
+    assert!(has(&[0x81, 0x83, 0xfe, 0x80]));
+
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    let name = closure.proto().local_name(0, 0).unwrap();
+    assert_eq!(name.as_bytes(), b"\xfe\x80");
+    assert_eq!(rua::dump(closure.proto()), chunk);
+}
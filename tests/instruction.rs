@@ -0,0 +1,69 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rua::{opcode::*, Instruction, Op};
+
+fn op(i: Instruction) -> Op {
+    Op::try_from(u32::from(i)).unwrap()
+}
+
+#[test]
+fn encode_each_format() {
+    let i = Instruction::encode_abc(OP_SETFIELD, 1, 2, 255, true).unwrap();
+    assert_eq!(
+        op(i),
+        Op::SetField {
+            a: 1,
+            b: 2,
+            c: 255,
+            k: true
+        }
+    );
+    let i = Instruction::encode_abx(OP_LOADK, 255, (1 << 17) - 1).unwrap();
+    assert_eq!(
+        op(i),
+        Op::LoadK {
+            a: 255,
+            bx: (1 << 17) - 1
+        }
+    );
+    let i = Instruction::encode_asbx(OP_LOADI, 0, -65535).unwrap();
+    assert_eq!(op(i), Op::LoadI { a: 0, sbx: -65535 });
+    let i = Instruction::encode_ax(OP_EXTRAARG, (1 << 25) - 1).unwrap();
+    assert_eq!(op(i), Op::ExtraArg { ax: (1 << 25) - 1 });
+    let i = Instruction::encode_sj(OP_JMP, -(1 << 24) + 1).unwrap();
+    assert_eq!(op(i), Op::Jmp { sj: -(1 << 24) + 1 });
+}
+
+#[test]
+fn reject_out_of_range() {
+    assert!(Instruction::encode_abc(OP_MOVE, 256, 0, 0, false).is_err());
+    assert!(Instruction::encode_abc(OP_MOVE, 0, -1, 0, false).is_err());
+    assert!(Instruction::encode_abc(OP_ADD, 0, 0, 256, false).is_err());
+    assert!(Instruction::encode_abx(OP_LOADK, 0, 1 << 17).is_err());
+    assert!(Instruction::encode_abx(OP_LOADK, 0, -1).is_err());
+    assert!(Instruction::encode_asbx(OP_LOADI, 0, -65536).is_err());
+    assert!(Instruction::encode_asbx(OP_LOADI, 0, 65537).is_err());
+    assert!(Instruction::encode_ax(OP_EXTRAARG, 1 << 25).is_err());
+    assert!(Instruction::encode_sj(OP_JMP, 1 << 24).is_ok());
+    assert!(Instruction::encode_sj(OP_JMP, (1 << 24) + 1).is_err());
+    assert!(Instruction::encode_sj(OP_JMP, -(1 << 24)).is_err());
+}
+
+#[test]
+fn reject_mismatched_format() {
+    assert!(Instruction::encode_abc(OP_JMP, 0, 0, 0, false).is_err());
+    assert!(Instruction::encode_abx(OP_LOADI, 0, 0).is_err());
+    assert!(Instruction::encode_sj(0x7F, 0).is_err());
+}
@@ -0,0 +1,272 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{show, state};
+use rua::{State, Value};
+
+fn temp_path(name: &str) -> String {
+    let mut path = std::env::temp_dir();
+    path.push(format!("rua_iolib_{}_{}", std::process::id(), name));
+    path.to_string_lossy().into_owned()
+}
+
+/// Calls `io.<name>`.
+fn io(state: &State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    common::call(state, &format!("io.{}", name), args)
+}
+
+/// Calls `file:<name>(...)`.
+fn method(state: &State, file: &Value, name: &str, mut args: Vec<Value>) -> Result<String, String> {
+    let f = state.index(file, &name.into()).unwrap();
+    args.insert(0, file.clone());
+    let r = state.call(&f, args).map_err(|e| e.to_string())?;
+    Ok(show(state, &r))
+}
+
+fn open(state: &State, path: &str, mode: &str) -> Value {
+    io(state, "open", vec![path.into(), mode.into()]).unwrap()[0].clone()
+}
+
+#[test]
+fn read_formats() {
+    let state = state();
+    let path = temp_path("formats");
+    let f = open(&state, &path, "w");
+    let r = method(
+        &state,
+        &f,
+        "write",
+        vec![
+            "hello\n".into(),
+            42.into(),
+            " ".into(),
+            1.5.into(),
+            " ".into(),
+            1e15.into(),
+            "\n  3.25e1 0x10 x".into(),
+        ],
+    )
+    .unwrap();
+    assert!(r.starts_with("file ("));
+    assert_eq!(method(&state, &f, "close", vec![]).unwrap(), "true");
+
+    let f = open(&state, &path, "r");
+    let read = |args| method(&state, &f, "read", args).unwrap();
+    assert_eq!(read(vec![]), "hello");
+    assert_eq!(read(vec!["L".into()]), "42 1.5 1e+15\n");
+    assert_eq!(read(vec!["n".into(), "*n".into()]), "32.5 16");
+    assert_eq!(read(vec!["n".into()]), "nil");
+    assert_eq!(read(vec![1.into()]), "x");
+    assert_eq!(read(vec![0.into()]), "nil");
+    assert_eq!(read(vec!["a".into()]), "");
+    assert_eq!(read(vec!["l".into()]), "nil");
+    assert_eq!(read(vec![5.into()]), "nil");
+    assert_eq!(
+        method(&state, &f, "read", vec!["x".into()]).unwrap_err(),
+        "bad argument #2 to 'read' (invalid format)"
+    );
+    method(&state, &f, "close", vec![]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn seek_and_buffering() {
+    let state = state();
+    let path = temp_path("seek");
+    let f = open(&state, &path, "w+");
+    let call = |name, args| method(&state, &f, name, args).unwrap();
+    call("write", vec!["abcdef".into()]);
+    assert_eq!(call("seek", vec!["set".into(), 2.into()]), "2");
+    assert_eq!(call("read", vec![2.into()]), "cd");
+    assert_eq!(call("seek", vec![]), "4");
+    assert_eq!(call("seek", vec!["end".into()]), "6");
+    call("write", vec!["g".into()]);
+    assert_eq!(call("seek", vec!["set".into()]), "0");
+    assert_eq!(call("read", vec!["a".into()]), "abcdefg");
+    assert_eq!(
+        call("seek", vec!["set".into(), (-1).into()]),
+        "nil Invalid argument 22"
+    );
+    assert_eq!(
+        method(&state, &f, "seek", vec!["bad".into()]).unwrap_err(),
+        "bad argument #2 to 'seek' (invalid option 'bad')"
+    );
+
+    // Buffered output reaches the file once flushed.
+    assert_eq!(call("setvbuf", vec!["full".into(), 1024.into()]), "true");
+    call("write", vec!["h".into()]);
+    assert_eq!(std::fs::read(&path).unwrap(), b"abcdefg");
+    assert_eq!(call("flush", vec![]), "true");
+    assert_eq!(std::fs::read(&path).unwrap(), b"abcdefgh");
+    call("close", vec![]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn lines() {
+    let state = state();
+    let path = temp_path("lines");
+    std::fs::write(&path, "one\ntwo\n\nfour").unwrap();
+
+    let r = io(&state, "lines", vec![path.as_str().into()]).unwrap();
+    assert_eq!(r.len(), 4);
+    let mut lines = vec![];
+    loop {
+        let v = state.call(&r[0], vec![]).unwrap();
+        if v.is_empty() || v[0].is_nil() {
+            break;
+        }
+        lines.push(show(&state, &v));
+    }
+    assert_eq!(lines, ["one", "two", "", "four"]);
+    assert_eq!(
+        method(&state, &r[3], "seek", vec![]).unwrap_err(),
+        "attempt to use a closed file"
+    );
+    assert_eq!(
+        state.call(&r[0], vec![]).unwrap_err().to_string(),
+        "file is already closed"
+    );
+
+    let f = open(&state, &path, "r");
+    let lines = state.index(&f, &"lines".into()).unwrap();
+    let iter = state
+        .call(&lines, vec![f.clone(), 3.into(), "L".into()])
+        .unwrap();
+    let v = state.call(&iter[0], vec![]).unwrap();
+    assert_eq!(show(&state, &v), "one \n");
+    let v = state.call(&iter[0], vec![]).unwrap();
+    assert_eq!(show(&state, &v), "two \n");
+    method(&state, &f, "close", vec![]).unwrap();
+
+    let missing = temp_path("missing");
+    assert_eq!(
+        io(&state, "lines", vec![missing.as_str().into()]).unwrap_err(),
+        format!("cannot open file '{}' (No such file or directory)", missing)
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_errors_and_types() {
+    let state = state();
+    let missing = temp_path("nonexistent");
+    let r = io(&state, "open", vec![missing.as_str().into()]).unwrap();
+    assert_eq!(
+        show(&state, &r),
+        format!("nil {}: No such file or directory 2", missing)
+    );
+    assert_eq!(
+        io(&state, "open", vec![missing.as_str().into(), "rw".into()]).unwrap_err(),
+        "bad argument #2 to 'open' (invalid mode)"
+    );
+
+    let path = temp_path("types");
+    let f = open(&state, &path, "wb");
+    let type_of = |v: &Value| show(&state, &io(&state, "type", vec![v.clone()]).unwrap());
+    assert_eq!(type_of(&f), "file");
+    method(&state, &f, "close", vec![]).unwrap();
+    assert_eq!(type_of(&f), "closed file");
+    assert_eq!(type_of(&1.into()), "nil");
+    assert_eq!(state.tostring(&f).unwrap().to_string(), "file (closed)");
+    assert_eq!(
+        method(&state, &f, "read", vec![]).unwrap_err(),
+        "attempt to use a closed file"
+    );
+    let read = state.index(&f, &"read".into()).unwrap();
+    assert_eq!(
+        state.call(&read, vec![1.into()]).unwrap_err().to_string(),
+        "bad argument #1 to 'read' (FILE* expected, got number)"
+    );
+
+    let stdout = state
+        .index(&state.globals().get_str("io"), &"stdout".into())
+        .unwrap();
+    assert_eq!(
+        method(&state, &stdout, "close", vec![]).unwrap(),
+        "nil cannot close standard file"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn default_files() {
+    let state = state();
+    let path = temp_path("default");
+    io(&state, "output", vec![path.as_str().into()]).unwrap();
+    let r = io(&state, "write", vec!["first\n".into(), 2.into()]).unwrap();
+    assert_eq!(show(&state, &r[..1]).split(' ').next(), Some("file"));
+    assert_eq!(show(&state, &io(&state, "close", vec![]).unwrap()), "true");
+    assert_eq!(
+        io(&state, "write", vec!["x".into()]).unwrap_err(),
+        "default output file is closed"
+    );
+
+    io(&state, "input", vec![path.as_str().into()]).unwrap();
+    let r = io(&state, "read", vec!["l".into(), "n".into()]).unwrap();
+    assert_eq!(show(&state, &r), "first 2");
+    assert_eq!(
+        io(&state, "read", vec!["x".into()]).unwrap_err(),
+        "bad argument #1 to 'read' (invalid format)"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn tmpfile() {
+    let state = state();
+    let f = io(&state, "tmpfile", vec![]).unwrap()[0].clone();
+    method(&state, &f, "write", vec!["scratch".into()]).unwrap();
+    method(&state, &f, "seek", vec!["set".into()]).unwrap();
+    assert_eq!(
+        method(&state, &f, "read", vec!["a".into()]).unwrap(),
+        "scratch"
+    );
+    assert_eq!(method(&state, &f, "close", vec![]).unwrap(), "true");
+}
+
+#[cfg(unix)]
+#[test]
+fn popen() {
+    let state = state();
+    let p = io(&state, "popen", vec!["echo hi; echo there".into()]).unwrap()[0].clone();
+    assert_eq!(
+        method(&state, &p, "read", vec!["a".into()]).unwrap(),
+        "hi\nthere\n"
+    );
+    assert_eq!(method(&state, &p, "close", vec![]).unwrap(), "true exit 0");
+
+    let p = io(&state, "popen", vec!["exit 3".into()]).unwrap()[0].clone();
+    assert_eq!(method(&state, &p, "close", vec![]).unwrap(), "nil exit 3");
+
+    let path = temp_path("popen");
+    let p = io(
+        &state,
+        "popen",
+        vec![format!("cat > {}", path).as_str().into(), "w".into()],
+    )
+    .unwrap()[0]
+        .clone();
+    method(&state, &p, "write", vec!["piped".into()]).unwrap();
+    method(&state, &p, "close", vec![]).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"piped");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        io(&state, "popen", vec!["true".into(), "rw".into()]).unwrap_err(),
+        "bad argument #2 to 'popen' (invalid mode)"
+    );
+}
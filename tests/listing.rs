@@ -0,0 +1,161 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, Listing, Proto};
+
+/// Lists `proto`, with `ADDR` in `expected` standing for its address.
+fn check(proto: &Proto, full: bool, expected: &str) {
+    let expected = expected.replace("ADDR", &format!("{:p}", proto));
+    assert_eq!(Listing::new(proto, full).to_string(), expected);
+}
+
+#[tokio::test]
+async fn full() {
+    // local t = {}
+    // for i = 1, 3 do
+    //   t[i] = i * 2.5
+    // end
+    // print(#t, "done")
+    let chunk = chunk(&Function {
+        source: Some("@t.lua"),
+        is_vararg: true,
+        maxstacksize: 6,
+        code: vec![
+            i(OP_VARARGPREP, &[0]),
+            i(OP_NEWTABLE, &[0, 0, 0]),
+            i(OP_EXTRAARG, &[0]),
+            i(OP_LOADI, &[1, 1]),
+            i(OP_LOADI, &[2, 3]),
+            i(OP_LOADI, &[3, 1]),
+            i(OP_FORPREP, &[1, 3]),
+            i(OP_MULK, &[5, 4, 0]),
+            i(OP_MMBINK, &[4, 0, 8]),
+            i(OP_SETTABLE, &[0, 4, 5]),
+            i(OP_FORLOOP, &[1, 4]),
+            i(OP_GETTABUP, &[1, 0, 1]),
+            i(OP_LEN, &[2, 0]),
+            i(OP_LOADK, &[3, 2]),
+            i(OP_CALL, &[1, 3, 1]),
+            i(OP_RETURN, &[1, 1, 1]),
+        ],
+        constants: vec!["2.5", "\"print\"", "\"done\""],
+        upvalues: vec![("_ENV", 1, 0)],
+        lineinfo: vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, -1, 3, 0, 0, 0, 0],
+        locvars: vec![
+            ("t", 3, 16),
+            ("(for state)", 6, 11),
+            ("(for state)", 6, 11),
+            ("(for state)", 6, 11),
+            ("i", 7, 10),
+        ],
+        ..Default::default()
+    });
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    check(
+        closure.proto(),
+        true,
+        "\nmain <t.lua:0,0> (16 instructions at ADDR)\n\
+         0+ params, 6 slots, 1 upvalue, 5 locals, 3 constants, 0 functions\n\
+         \t1\t[1]\tVARARGPREP\t0\n\
+         \t2\t[1]\tNEWTABLE \t0 0 0\t; 0\n\
+         \t3\t[1]\tEXTRAARG \t0\n\
+         \t4\t[2]\tLOADI    \t1 1\n\
+         \t5\t[2]\tLOADI    \t2 3\n\
+         \t6\t[2]\tLOADI    \t3 1\n\
+         \t7\t[2]\tFORPREP  \t1 3\t; exit to 12\n\
+         \t8\t[3]\tMULK     \t5 4 0\t; 2.5\n\
+         \t9\t[3]\tMMBINK   \t4 0 8 0\t; __mul 2.5\n\
+         \t10\t[3]\tSETTABLE \t0 4 5\n\
+         \t11\t[2]\tFORLOOP  \t1 4\t; to 8\n\
+         \t12\t[5]\tGETTABUP \t1 0 1\t; _ENV \"print\"\n\
+         \t13\t[5]\tLEN      \t2 0\n\
+         \t14\t[5]\tLOADK    \t3 2\t; \"done\"\n\
+         \t15\t[5]\tCALL     \t1 3 1\t; 2 in 0 out\n\
+         \t16\t[5]\tRETURN   \t1 1 1\t; 0 out\n\
+         constants (3) for ADDR:\n\
+         \t0\tF\t2.5\n\
+         \t1\tS\t\"print\"\n\
+         \t2\tS\t\"done\"\n\
+         locals (5) for ADDR:\n\
+         \t0\tt\t4\t17\n\
+         \t1\t(for state)\t7\t12\n\
+         \t2\t(for state)\t7\t12\n\
+         \t3\t(for state)\t7\t12\n\
+         \t4\ti\t8\t11\n\
+         upvalues (1) for ADDR:\n\
+         \t0\t_ENV\t1\t0\n",
+    );
+}
+
+#[tokio::test]
+async fn nested() {
+    // function f(x)
+    //   return x + 1
+    // end
+    let f = Function {
+        linedefined: 1,
+        lastlinedefined: 3,
+        numparams: 1,
+        maxstacksize: 2,
+        code: vec![
+            // sB and sC are stored in excess 127.
+            i(OP_ADDI, &[1, 0, 128]),
+            i(OP_MMBINI, &[0, 128, 6]),
+            i(OP_RETURN1, &[1]),
+            i(OP_RETURN0, &[]),
+        ],
+        lineinfo: vec![1, 0, 0, 1],
+        locvars: vec![("x", 0, 4)],
+        ..Default::default()
+    };
+    let chunk = chunk(&Function {
+        source: Some("@f.lua"),
+        is_vararg: true,
+        maxstacksize: 2,
+        code: vec![
+            i(OP_VARARGPREP, &[0]),
+            i(OP_CLOSURE, &[0, 0]),
+            i(OP_SETTABUP, &[0, 0, 0]),
+            i(OP_RETURN, &[0, 1, 1]),
+        ],
+        constants: vec!["\"f\""],
+        upvalues: vec![("_ENV", 1, 0)],
+        protos: vec![f],
+        lineinfo: vec![1, 2, -2, 2],
+        ..Default::default()
+    });
+    let closure = rua::undump(&chunk[..]).await.unwrap();
+    let proto = closure.proto();
+    let f = format!("{:p}", proto.protos()[0].get().unwrap());
+    check(
+        proto,
+        false,
+        &"\nmain <f.lua:0,0> (4 instructions at ADDR)\n\
+          0+ params, 2 slots, 1 upvalue, 0 locals, 1 constant, 1 function\n\
+          \t1\t[1]\tVARARGPREP\t0\n\
+          \t2\t[3]\tCLOSURE  \t0 0\t; NESTED\n\
+          \t3\t[1]\tSETTABUP \t0 0 0\t; _ENV \"f\"\n\
+          \t4\t[3]\tRETURN   \t0 1 1\t; 0 out\n\
+          \nfunction <f.lua:1,3> (4 instructions at NESTED)\n\
+          1 param, 2 slots, 0 upvalues, 1 local, 0 constants, 0 functions\n\
+          \t1\t[2]\tADDI     \t1 0 1\n\
+          \t2\t[2]\tMMBINI   \t0 1 6 0\t; __add\n\
+          \t3\t[2]\tRETURN1  \t1\n\
+          \t4\t[3]\tRETURN0  \t\n"
+            .replace("NESTED", &f),
+    );
+}
@@ -0,0 +1,248 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::{rerun, show, state};
+use rua::{Function, State, Table, Value};
+
+fn package(state: &State) -> Value {
+    state.globals().get_str("package")
+}
+
+fn field(state: &State, name: &str) -> Value {
+    state.index(&package(state), &name.into()).unwrap()
+}
+
+fn require(state: &State, name: &str) -> Result<Vec<Value>, String> {
+    common::call(state, "require", vec![name.into()])
+}
+
+/// A fresh directory of modules, with `package.path` searching it only.
+fn module_dir(state: &State, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rua_loadlib_{}_{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = format!("{0}/?.lua;{0}/?/init.lua", dir.display());
+    state
+        .set_index(&package(state), "path".into(), path.as_str().into())
+        .unwrap();
+    dir
+}
+
+#[test]
+fn loaded_libraries() {
+    let state = state();
+    let loaded = field(&state, "loaded");
+    for name in [
+        "_G", "package", "string", "table", "io", "os", "math", "utf8",
+    ] {
+        let lib = state.index(&loaded, &name.into()).unwrap();
+        let global = state.globals().get_str(name);
+        assert!(lib.raw_equal(&global), "{}", name);
+        let r = require(&state, name).unwrap();
+        assert_eq!(r.len(), 1);
+        assert!(r[0].raw_equal(&global), "{}", name);
+    }
+    assert_eq!(show(&state, &[field(&state, "config")]), "/\n;\n?\n!\n-\n");
+}
+
+#[test]
+fn preload() {
+    let state = state();
+    let preload = field(&state, "preload");
+    let loader = Function::native("loader", |_, args| {
+        let module = Table::new();
+        module.set_str("name", args.get(1).clone());
+        module.set_str("data", args.get(2).clone());
+        Ok(vec![module.into()])
+    });
+    state
+        .set_index(&preload, "mod".into(), loader.into())
+        .unwrap();
+    let r = require(&state, "mod").unwrap();
+    assert_eq!(show(&state, &r[1..]), ":preload:");
+    let name = state.index(&r[0], &"name".into()).unwrap();
+    let data = state.index(&r[0], &"data".into()).unwrap();
+    assert_eq!(show(&state, &[name, data]), "mod :preload:");
+    // Now it is cached, and the loader runs no more.
+    state.set_index(&preload, "mod".into(), Value::Nil).unwrap();
+    let again = require(&state, "mod").unwrap();
+    assert!(again[0].raw_equal(&r[0]));
+
+    // A loader returning nothing leaves true as the module.
+    let quiet = Function::native("quiet", |_, _| Ok(vec![]));
+    state
+        .set_index(&preload, "quiet".into(), quiet.into())
+        .unwrap();
+    assert_eq!(
+        show(&state, &require(&state, "quiet").unwrap()),
+        "true :preload:"
+    );
+    let loaded = field(&state, "loaded");
+    assert_eq!(
+        show(&state, &[state.index(&loaded, &"quiet".into()).unwrap()]),
+        "true"
+    );
+}
+
+#[test]
+fn native_modules() {
+    let state = state();
+    state.register_module("native.mod", |_, args| {
+        let module = Table::new();
+        module.set_str("answer", 42);
+        module.set_str("data", args.get(2).clone());
+        Ok(vec![module.into()])
+    });
+    let r = require(&state, "native.mod").unwrap();
+    assert_eq!(show(&state, &r[1..]), ":native:");
+    let answer = state.index(&r[0], &"answer".into()).unwrap();
+    assert_eq!(show(&state, &[answer]), "42");
+}
+
+#[test]
+fn not_found() {
+    let state = state();
+    state
+        .set_index(
+            &package(&state),
+            "path".into(),
+            "/nonexistent/?.lua;/nonexistent/?/init.lua".into(),
+        )
+        .unwrap();
+    assert_eq!(
+        require(&state, "a.b").unwrap_err(),
+        "module 'a.b' not found:\n\
+         \tno field package.preload['a.b']\n\
+         \tno file '/nonexistent/a/b.lua'\n\
+         \tno file '/nonexistent/a/b/init.lua'\n\
+         \tno native module 'a.b'"
+    );
+
+    state
+        .set_index(&package(&state), "path".into(), Value::Nil)
+        .unwrap();
+    assert_eq!(
+        require(&state, "a").unwrap_err(),
+        "'package.path' must be a string"
+    );
+    state
+        .set_index(&package(&state), "searchers".into(), 1.into())
+        .unwrap();
+    assert_eq!(
+        require(&state, "a").unwrap_err(),
+        "'package.searchers' must be a table"
+    );
+}
+
+#[test]
+fn lua_modules() {
+    let state = state();
+    let dir = module_dir(&state, "lua");
+    std::fs::write(dir.join("text.lua"), "return 1\n").unwrap();
+    let proto = rua::assemble("RETURN0\n").unwrap();
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::write(dir.join("bin").join("init.lua"), rua::dump(&proto)).unwrap();
+
+    let file = dir.join("text.lua").display().to_string();
+    assert_eq!(
+        require(&state, "text").unwrap_err(),
+        format!(
+            "error loading module 'text' from file '{0}':\n\t{0}: cannot load text chunks, only precompiled ones",
+            file
+        )
+    );
+
+    // Precompiled modules load, though their code cannot run.
+    let searchers = field(&state, "searchers");
+    let lua = state.index(&searchers, &2.into()).unwrap();
+    let r = state.call(&lua, vec!["bin".into()]).unwrap();
+    assert_eq!(r[0].type_name(), "function");
+    let file = dir.join("bin").join("init.lua").display().to_string();
+    assert_eq!(show(&state, &r[1..]), file);
+    assert_eq!(
+        require(&state, "bin").unwrap_err(),
+        "attempt to call a Lua function: bytecode execution is not supported"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn searchpath_and_loadlib() {
+    let state = state();
+    let dir = module_dir(&state, "searchpath");
+    std::fs::create_dir_all(dir.join("x")).unwrap();
+    std::fs::write(dir.join("x").join("mod.lua"), "").unwrap();
+
+    let searchpath = field(&state, "searchpath");
+    let search = |args: Vec<Value>| show(&state, &state.call(&searchpath, args).unwrap());
+    let path = format!("{0}/?.txt;;{0}/?.lua", dir.display());
+    assert_eq!(
+        search(vec!["x.mod".into(), path.as_str().into()]),
+        format!("{}/x/mod.lua", dir.display())
+    );
+    assert_eq!(
+        search(vec![
+            "a_b.c".into(),
+            "p/?;q/?.x".into(),
+            "_".into(),
+            "-".into()
+        ]),
+        "nil no file 'p/a-b.c'\n\tno file 'q/a-b.c.x'"
+    );
+    assert_eq!(
+        search(vec!["a".into(), "?;;?".into(), "".into()]),
+        "nil no file 'a'\n\tno file ''\n\tno file 'a'"
+    );
+
+    let loadlib = field(&state, "loadlib");
+    let r = state
+        .call(&loadlib, vec!["lib.so".into(), "luaopen_lib".into()])
+        .unwrap();
+    assert_eq!(
+        show(&state, &r),
+        "nil dynamic libraries not enabled; check your Lua installation absent"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn path_from_environment() {
+    const DEFAULT: &str = "/usr/local/share/lua/5.4/?.lua;/usr/local/share/lua/5.4/?/init.lua;\
+        /usr/local/lib/lua/5.4/?.lua;/usr/local/lib/lua/5.4/?/init.lua;./?.lua;./?/init.lua";
+    let env = [
+        ("LUA_PATH", "/unversioned/?.lua"),
+        ("LUA_PATH_5_4", "/first/?.lua;;/last/?.lua"),
+        ("LUA_CPATH", "/c/?.so"),
+    ];
+    if let Some(status) = rerun("path_from_environment", &env) {
+        assert!(status.success());
+        return;
+    }
+    let state = state();
+    let path = |state: &State| show(state, &[field(state, "path")]);
+    assert_eq!(
+        path(&state),
+        format!("/first/?.lua;{};/last/?.lua", DEFAULT)
+    );
+    assert_eq!(show(&state, &[field(&state, "cpath")]), "/c/?.so");
+
+    let state = State::new();
+    state.registry().set_str("LUA_NOENV", true);
+    state.open_package();
+    assert_eq!(path(&state), DEFAULT);
+}
@@ -0,0 +1,155 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{show, state};
+use rua::{State, Value};
+
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<String, String> {
+    let r = common::call(state, &format!("math.{}", name), args)?;
+    Ok(show(state, &r))
+}
+
+#[test]
+fn constants() {
+    let state = state();
+    let math = state.globals().get_str("math");
+    let field = |name: &str| {
+        let v = state.index(&math, &name.into()).unwrap();
+        state.tostring(&v).unwrap().to_string()
+    };
+    assert_eq!(field("maxinteger"), "9223372036854775807");
+    assert_eq!(field("mininteger"), "-9223372036854775808");
+    assert_eq!(field("huge"), "inf");
+    assert_eq!(field("pi"), "3.1415926535898");
+}
+
+#[test]
+fn integer_results() {
+    let state = state();
+    let c = |name, args| call(&state, name, args).unwrap();
+    assert_eq!(c("floor", vec![3.7.into()]), "3");
+    assert_eq!(c("floor", vec![(-3.5).into()]), "-4");
+    assert_eq!(c("ceil", vec![3.2.into()]), "4");
+    assert_eq!(c("floor", vec![5.into()]), "5");
+    assert_eq!(c("floor", vec![1e100.into()]), "1e+100");
+    assert_eq!(c("ceil", vec!["2.5".into()]), "3");
+    assert_eq!(c("abs", vec![(-3).into()]), "3");
+    assert_eq!(c("abs", vec![i64::MIN.into()]), "-9223372036854775808");
+    assert_eq!(c("abs", vec![(-0.5).into()]), "0.5");
+    assert_eq!(c("modf", vec![3.5.into()]), "3.0 0.5");
+    assert_eq!(c("modf", vec![(-3.5).into()]), "-3.0 -0.5");
+    assert_eq!(c("modf", vec![f64::INFINITY.into()]), "inf 0.0");
+    assert_eq!(c("modf", vec![4.into()]), "4 0.0");
+}
+
+#[test]
+fn fmod() {
+    let state = state();
+    let c = |args| call(&state, "fmod", args);
+    assert_eq!(c(vec![7.into(), 3.into()]).unwrap(), "1");
+    assert_eq!(c(vec![(-7).into(), 3.into()]).unwrap(), "-1");
+    assert_eq!(c(vec![i64::MIN.into(), (-1).into()]).unwrap(), "0");
+    assert_eq!(c(vec![7.5.into(), 2.into()]).unwrap(), "1.5");
+    assert_eq!(
+        c(vec![(-6.5).into(), f64::INFINITY.into()]).unwrap(),
+        "-6.5"
+    );
+    assert_eq!(
+        c(vec![1.into(), 0.into()]).unwrap_err(),
+        "bad argument #2 to 'fmod' (zero)"
+    );
+}
+
+#[test]
+fn conversions() {
+    let state = state();
+    let c = |name, args| call(&state, name, args);
+    assert_eq!(c("tointeger", vec![3.0.into()]).unwrap(), "3");
+    assert_eq!(c("tointeger", vec![3.5.into()]).unwrap(), "nil");
+    assert_eq!(c("tointeger", vec!["8".into()]).unwrap(), "8");
+    assert_eq!(c("tointeger", vec![Value::Boolean(true)]).unwrap(), "nil");
+    assert_eq!(
+        c("tointeger", vec![]).unwrap_err(),
+        "bad argument #1 to 'tointeger' (value expected)"
+    );
+    assert_eq!(c("type", vec![1.into()]).unwrap(), "integer");
+    assert_eq!(c("type", vec![1.0.into()]).unwrap(), "float");
+    assert_eq!(c("type", vec!["1".into()]).unwrap(), "nil");
+    assert_eq!(c("ult", vec![1.into(), (-1).into()]).unwrap(), "true");
+    assert_eq!(c("ult", vec![(-1).into(), 1.into()]).unwrap(), "false");
+}
+
+#[test]
+fn min_max_and_functions() {
+    let state = state();
+    let c = |name, args| call(&state, name, args);
+    assert_eq!(
+        c("max", vec![1.into(), 2.5.into(), 2.into()]).unwrap(),
+        "2.5"
+    );
+    assert_eq!(c("min", vec![1.0.into(), 1.into()]).unwrap(), "1.0");
+    assert_eq!(c("min", vec![3.into(), (-2).into()]).unwrap(), "-2");
+    assert_eq!(
+        c("max", vec![]).unwrap_err(),
+        "bad argument #1 to 'max' (value expected)"
+    );
+    assert_eq!(
+        c("min", vec![1.into(), "x".into()]).unwrap_err(),
+        "bad argument #2 to 'min' (number expected, got string)"
+    );
+    assert_eq!(c("sqrt", vec![16.into()]).unwrap(), "4.0");
+    assert_eq!(c("log", vec![8.into(), 2.into()]).unwrap(), "3.0");
+    assert_eq!(c("log", vec![1000.into(), 10.into()]).unwrap(), "3.0");
+    assert_eq!(c("log", vec![1.into()]).unwrap(), "0.0");
+    assert_eq!(
+        c("atan", vec![1.into(), 0.into()]).unwrap(),
+        "1.5707963267949"
+    );
+    assert_eq!(c("exp", vec![0.into()]).unwrap(), "1.0");
+}
+
+#[test]
+fn seeded_random() {
+    let state = state();
+    let c = |args| call(&state, "random", args);
+    assert_eq!(call(&state, "randomseed", vec![42.into()]).unwrap(), "42 0");
+    let rolls = (0..8)
+        .map(|_| c(vec![1.into(), 100.into()]).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rolls, ["50", "76", "86", "54", "64", "7", "25", "3"]);
+
+    call(&state, "randomseed", vec![42.into()]).unwrap();
+    assert_eq!(c(vec![]).unwrap(), "0.93081217803957");
+    call(&state, "randomseed", vec![123.into(), 456.into()]).unwrap();
+    assert_eq!(c(vec![0.into()]).unwrap(), "-7482266044409867603");
+
+    for _ in 0..100 {
+        let r = c(vec![6.into()]).unwrap().parse::<i64>().unwrap();
+        assert!((1..=6).contains(&r));
+    }
+    assert_eq!(
+        c(vec![i64::MIN.into(), i64::MIN.into()]).unwrap(),
+        "-9223372036854775808"
+    );
+    assert_eq!(
+        c(vec![2.into(), 1.into()]).unwrap_err(),
+        "bad argument #1 to 'random' (interval is empty)"
+    );
+    assert_eq!(
+        c(vec![1.into(), 2.into(), 3.into()]).unwrap_err(),
+        "wrong number of arguments"
+    );
+}
@@ -0,0 +1,117 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rua::{opcode::*, Op};
+
+/// Raw instructions with opcode `op` and operand bits drawn from boundary
+/// values and a fixed pseudo-random sequence.
+fn samples(op: u8) -> Vec<u32> {
+    let mut x = 0x2545_f491_u32;
+    let mut v = vec![
+        0,
+        u32::MAX,
+        0x5555_5555,
+        0xAAAA_AAAA,
+        0x8000_0000,
+        0x0000_8000,
+    ];
+    for _ in 0..1000 {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        v.push(x);
+    }
+    v.into_iter().map(|n| n & !0x7F | op as u32).collect()
+}
+
+#[test]
+fn round_trip_every_opcode() {
+    for (code, _) in OPCODES.iter().enumerate() {
+        for raw in samples(code as u8) {
+            let op = Op::try_from(raw).unwrap();
+            assert_eq!(op.opcode(), code as u8, "{:?}", op);
+            let encoded = u32::try_from(op).unwrap();
+            // Only operand bits the opcode uses survive.
+            assert_eq!(
+                encoded & !raw,
+                0,
+                "{:?}: {:#x} from {:#x}",
+                op,
+                encoded,
+                raw
+            );
+            assert_eq!(Op::try_from(encoded).unwrap(), op);
+        }
+    }
+}
+
+#[test]
+fn unknown_opcodes() {
+    for code in OPCODES.len() as u32..0x80 {
+        assert!(Op::try_from(code).is_err());
+    }
+}
+
+#[test]
+fn signed_operands() {
+    let i = |op: u8, bits: u32| op as u32 | bits;
+    assert_eq!(
+        Op::try_from(i(OP_LOADI, 65534 << 15)).unwrap(),
+        Op::LoadI { a: 0, sbx: -1 }
+    );
+    assert_eq!(
+        Op::try_from(i(OP_JMP, (((1 << 24) - 1) + 3) << 7)).unwrap(),
+        Op::Jmp { sj: 3 }
+    );
+    assert_eq!(
+        Op::try_from(i(OP_ADDI, 1 << 7 | 2 << 16 | 126 << 24)).unwrap(),
+        Op::AddI { a: 1, b: 2, sc: -1 }
+    );
+    assert_eq!(
+        Op::try_from(i(OP_EQK, 3 << 7 | 1 << 15 | 4 << 16)).unwrap(),
+        Op::EqK {
+            a: 3,
+            b: 4,
+            k: true
+        }
+    );
+}
+
+#[test]
+fn out_of_range_operands() {
+    assert!(u32::try_from(Op::AddI {
+        a: 0,
+        b: 0,
+        sc: 129
+    })
+    .is_err());
+    assert!(u32::try_from(Op::AddI {
+        a: 0,
+        b: 0,
+        sc: -128
+    })
+    .is_err());
+    assert!(u32::try_from(Op::AddI {
+        a: 0,
+        b: 0,
+        sc: 128
+    })
+    .is_ok());
+    assert!(u32::try_from(Op::LoadI { a: 0, sbx: 65537 }).is_err());
+    assert!(u32::try_from(Op::LoadI { a: 0, sbx: -65536 }).is_err());
+    assert!(u32::try_from(Op::LoadK { a: 0, bx: 1 << 17 }).is_err());
+    assert!(u32::try_from(Op::Jmp { sj: 1 << 24 }).is_ok());
+    assert!(u32::try_from(Op::Jmp { sj: (1 << 24) + 1 }).is_err());
+    assert!(u32::try_from(Op::ExtraArg { ax: 1 << 25 }).is_err());
+}
@@ -0,0 +1,159 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rua::opcode::{self, *};
+
+type Row = (u8, &'static str, u8, u8, u8, u8, u8, u8);
+
+/// `luaP_opmodes` and `opnames` of Lua 5.4's `lopcodes.c`, in order:
+/// opcode, name, MM, OT, IT, T, A, mode.
+const LOPCODES: &[Row] = &[
+    (OP_MOVE, "MOVE", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_LOADI, "LOADI", 0, 0, 0, 0, 1, OP_MODE_ASBX),
+    (OP_LOADF, "LOADF", 0, 0, 0, 0, 1, OP_MODE_ASBX),
+    (OP_LOADK, "LOADK", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_LOADKX, "LOADKX", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_LOADFALSE, "LOADFALSE", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_LFALSESKIP, "LFALSESKIP", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_LOADTRUE, "LOADTRUE", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_LOADNIL, "LOADNIL", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_GETUPVAL, "GETUPVAL", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SETUPVAL, "SETUPVAL", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_GETTABUP, "GETTABUP", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_GETTABLE, "GETTABLE", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_GETI, "GETI", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_GETFIELD, "GETFIELD", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SETTABUP, "SETTABUP", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_SETTABLE, "SETTABLE", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_SETI, "SETI", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_SETFIELD, "SETFIELD", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_NEWTABLE, "NEWTABLE", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SELF, "SELF", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_ADDI, "ADDI", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_ADDK, "ADDK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SUBK, "SUBK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_MULK, "MULK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_MODK, "MODK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_POWK, "POWK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_DIVK, "DIVK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_IDIVK, "IDIVK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BANDK, "BANDK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BORK, "BORK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BXORK, "BXORK", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SHRI, "SHRI", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SHLI, "SHLI", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_ADD, "ADD", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SUB, "SUB", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_MUL, "MUL", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_MOD, "MOD", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_POW, "POW", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_DIV, "DIV", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_IDIV, "IDIV", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BAND, "BAND", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BOR, "BOR", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BXOR, "BXOR", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SHL, "SHL", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_SHR, "SHR", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_MMBIN, "MMBIN", 1, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_MMBINI, "MMBINI", 1, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_MMBINK, "MMBINK", 1, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_UNM, "UNM", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_BNOT, "BNOT", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_NOT, "NOT", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_LEN, "LEN", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_CONCAT, "CONCAT", 0, 0, 0, 0, 1, OP_MODE_ABC),
+    (OP_CLOSE, "CLOSE", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_TBC, "TBC", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_JMP, "JMP", 0, 0, 0, 0, 0, OP_MODE_SJ),
+    (OP_EQ, "EQ", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_LT, "LT", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_LE, "LE", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_EQK, "EQK", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_EQI, "EQI", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_LTI, "LTI", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_LEI, "LEI", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_GTI, "GTI", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_GEI, "GEI", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_TEST, "TEST", 0, 0, 0, 1, 0, OP_MODE_ABC),
+    (OP_TESTSET, "TESTSET", 0, 0, 0, 1, 1, OP_MODE_ABC),
+    (OP_CALL, "CALL", 0, 1, 1, 0, 1, OP_MODE_ABC),
+    (OP_TAILCALL, "TAILCALL", 0, 1, 1, 0, 1, OP_MODE_ABC),
+    (OP_RETURN, "RETURN", 0, 0, 1, 0, 0, OP_MODE_ABC),
+    (OP_RETURN0, "RETURN0", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_RETURN1, "RETURN1", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_FORLOOP, "FORLOOP", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_FORPREP, "FORPREP", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_TFORPREP, "TFORPREP", 0, 0, 0, 0, 0, OP_MODE_ABX),
+    (OP_TFORCALL, "TFORCALL", 0, 0, 0, 0, 0, OP_MODE_ABC),
+    (OP_TFORLOOP, "TFORLOOP", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_SETLIST, "SETLIST", 0, 0, 1, 0, 0, OP_MODE_ABC),
+    (OP_CLOSURE, "CLOSURE", 0, 0, 0, 0, 1, OP_MODE_ABX),
+    (OP_VARARG, "VARARG", 0, 1, 0, 0, 1, OP_MODE_ABC),
+    (OP_VARARGPREP, "VARARGPREP", 0, 0, 1, 0, 1, OP_MODE_ABC),
+    (OP_EXTRAARG, "EXTRAARG", 0, 0, 0, 0, 0, OP_MODE_AX),
+];
+
+#[test]
+fn matches_lopcodes() {
+    assert_eq!(OPCODES.len(), LOPCODES.len());
+    for (i, &(op, name, mm, ot, it, t, a, mode)) in LOPCODES.iter().enumerate() {
+        assert_eq!(op as usize, i, "OP_{} is numbered {:#04x}", name, op);
+        let opcode = &OPCODES[i];
+        assert_eq!(opcode.name(), name, "name of {:#04x}", op);
+        assert_eq!(opcode.mm(), mm == 1, "MM of {}", name);
+        assert_eq!(opcode.ot(), ot == 1, "OT of {}", name);
+        assert_eq!(opcode.it(), it == 1, "IT of {}", name);
+        assert_eq!(opcode.t(), t == 1, "T of {}", name);
+        assert_eq!(opcode.a(), a == 1, "A of {}", name);
+        assert_eq!(opcode.mode(), mode, "mode of {}", name);
+    }
+}
+
+#[test]
+fn lookup_by_name_and_number() {
+    for &(op, name, ..) in LOPCODES {
+        assert_eq!(opcode::find(name), Some(op));
+        assert_eq!(opcode::find(&name.to_lowercase()), Some(op));
+        assert_eq!(opcode::get(op).map(|op| op.name()), Some(name));
+    }
+    assert_eq!(opcode::find("NOSUCHOP"), None);
+    assert_eq!(opcode::get(OPCODES.len() as u8), None);
+}
+
+#[test]
+fn names_are_unique() {
+    for (i, op) in OPCODES.iter().enumerate() {
+        assert_eq!(opcode::find(op.name()), Some(i as u8), "{}", op.name());
+    }
+}
+
+/// `OP_BXORK` was once numbered 0x2f, colliding with `OP_MMBINI`.
+#[test]
+fn bxork_is_not_mmbini() {
+    assert_eq!(OP_BXORK, 0x1f);
+    assert_eq!(OPCODES[0x1f].name(), "BXORK");
+    assert_eq!(OPCODES[0x2f].name(), "MMBINI");
+}
+
+/// The flags were once stored inverted, so an instruction setting A read
+/// as one that does not, a test as no test, and so on.
+#[test]
+fn flags_are_not_inverted() {
+    let op = |code: u8| &OPCODES[code as usize];
+    assert!(op(OP_MOVE).a() && !op(OP_MOVE).t() && !op(OP_MOVE).mm());
+    assert!(op(OP_EQ).t() && !op(OP_EQ).a());
+    assert!(op(OP_MMBIN).mm() && !op(OP_MMBIN).a());
+    assert!(op(OP_CALL).it() && op(OP_CALL).ot());
+    assert!(op(OP_RETURN).it() && !op(OP_RETURN).ot());
+}
@@ -0,0 +1,390 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{rerun, state};
+use rua::{State, Table, Value};
+
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    common::call(state, &format!("os.{}", name), args)
+}
+
+fn show(state: &State, name: &str, args: Vec<Value>) -> Result<String, String> {
+    Ok(common::show(state, &call(state, name, args)?))
+}
+
+fn date_table(fields: &[(&str, Value)]) -> Table {
+    let t = Table::new();
+    for (k, v) in fields {
+        t.set_str(k, v.clone());
+    }
+    t
+}
+
+#[test]
+fn date() {
+    let state = state();
+    let date = |fmt: &str, t: i64| show(&state, "date", vec![fmt.into(), t.into()]);
+    assert_eq!(
+        date("!%Y-%m-%d %H:%M:%S", 0).unwrap(),
+        "1970-01-01 00:00:00"
+    );
+    assert_eq!(date("!%c", 0).unwrap(), "Thu Jan  1 00:00:00 1970");
+    assert_eq!(
+        date(
+            "!%a %A %b %B %h|%C %y %D|%e|%j %U %W|%I %p %r|%z %Z %%",
+            1609459200
+        )
+        .unwrap(),
+        "Fri Friday Jan January Jan|20 21 01/01/21| 1|001 00 00|12 AM 12:00:00 AM|+0000 GMT %"
+    );
+    // ISO 8601 weeks may belong to the next or the previous year.
+    assert_eq!(date("!%G-W%V-%u %g", 1609459200).unwrap(), "2020-W53-5 20");
+    assert_eq!(date("!%G-W%V-%u", 1230508800).unwrap(), "2009-W01-1");
+    assert_eq!(
+        date("!%F %T %Ey %OH", -1).unwrap(),
+        "1969-12-31 23:59:59 69 23"
+    );
+    assert_eq!(
+        date("%Ez", 0).unwrap_err(),
+        "bad argument #1 to 'date' (invalid conversion specifier '%Ez')"
+    );
+    assert_eq!(
+        date("%Y%", 0).unwrap_err(),
+        "bad argument #1 to 'date' (invalid conversion specifier '%')"
+    );
+    assert_eq!(
+        date("%Y", i64::MAX).unwrap_err(),
+        "date result cannot be represented in this installation"
+    );
+    assert_eq!(
+        show(&state, "date", vec!["%Y".into(), 1.5.into()]).unwrap_err(),
+        "bad argument #2 to 'date' (number has no integer representation)"
+    );
+
+    let t = call(&state, "date", vec!["!*t".into(), 0.into()]).unwrap()[0].clone();
+    let field = |k: &str| {
+        state
+            .tostring(&state.index(&t, &k.into()).unwrap())
+            .unwrap()
+            .to_string()
+    };
+    let fields = [
+        "year", "month", "day", "hour", "min", "sec", "wday", "yday", "isdst",
+    ];
+    assert_eq!(fields.map(field).join(" "), "1970 1 1 0 0 0 5 1 false");
+}
+
+#[test]
+fn time() {
+    if let Some(status) = rerun("time", &[("TZ", "UTC0")]) {
+        assert!(status.success());
+        return;
+    }
+    let state = state();
+    let now = call(&state, "time", vec![]).unwrap()[0]
+        .to_integer()
+        .unwrap();
+    assert!(now > 1_600_000_000);
+
+    let t = date_table(&[
+        ("year", 2000.into()),
+        ("month", 1.into()),
+        ("day", 1.into()),
+        ("hour", 0.into()),
+    ]);
+    assert_eq!(show(&state, "time", vec![t.into()]).unwrap(), "946684800");
+
+    // The fields are normalized in place; hour defaults to noon.
+    let t = date_table(&[
+        ("year", 2021.into()),
+        ("month", 14.into()),
+        ("day", 0.into()),
+        ("sec", "-1".into()),
+    ]);
+    assert_eq!(
+        show(&state, "time", vec![t.clone().into()]).unwrap(),
+        "1643630399"
+    );
+    let fields = [
+        "year", "month", "day", "hour", "min", "sec", "wday", "yday", "isdst",
+    ];
+    assert_eq!(
+        fields
+            .map(|k| state.tostring(&t.get_str(k)).unwrap().to_string())
+            .join(" "),
+        "2022 1 31 11 59 59 2 31 false"
+    );
+
+    let time = |fields: &[(&str, Value)]| {
+        show(&state, "time", vec![date_table(fields).into()]).unwrap_err()
+    };
+    assert_eq!(
+        time(&[("year", 2000.into())]),
+        "field 'month' missing in date table"
+    );
+    assert_eq!(
+        time(&[
+            ("year", 2000.into()),
+            ("month", "x".into()),
+            ("day", 1.into())
+        ]),
+        "field 'month' is not an integer"
+    );
+    assert_eq!(
+        time(&[
+            ("year", 2000.into()),
+            ("month", 1.into()),
+            ("day", 1.5.into())
+        ]),
+        "field 'day' is not an integer"
+    );
+    assert_eq!(
+        time(&[
+            ("year", i64::MAX.into()),
+            ("month", 1.into()),
+            ("day", 1.into())
+        ]),
+        "field 'year' is out-of-bound"
+    );
+    assert_eq!(
+        time(&[
+            ("year", 2000.into()),
+            ("month", 1.into()),
+            ("day", 2147483648_i64.into())
+        ]),
+        "field 'day' is out-of-bound"
+    );
+    assert_eq!(
+        show(&state, "time", vec![1.into()]).unwrap_err(),
+        "bad argument #1 to 'time' (table expected, got number)"
+    );
+
+    assert_eq!(
+        show(&state, "difftime", vec![10.into(), 4.into()]).unwrap(),
+        "6.0"
+    );
+    let clock = call(&state, "clock", vec![]).unwrap()[0]
+        .to_float()
+        .unwrap();
+    assert!(clock >= 0.0);
+}
+
+#[cfg(unix)]
+#[test]
+fn local_time() {
+    if let Some(status) = rerun("local_time", &[("TZ", "XST-5:30")]) {
+        assert!(status.success());
+        return;
+    }
+    let state = state();
+    let date = |fmt: &str, t: i64| show(&state, "date", vec![fmt.into(), t.into()]).unwrap();
+    assert_eq!(date("%F %T %z %Z", 0), "1970-01-01 05:30:00 +0530 XST");
+    assert_eq!(date("!%F %T %z %Z", 0), "1970-01-01 00:00:00 +0000 GMT");
+    let t = call(&state, "date", vec!["*t".into(), 0.into()]).unwrap()[0].clone();
+    let field = |k: &str| {
+        state
+            .tostring(&state.index(&t, &k.into()).unwrap())
+            .unwrap()
+            .to_string()
+    };
+    let fields = ["hour", "min", "isdst"];
+    assert_eq!(fields.map(field).join(" "), "5 30 false");
+
+    let t = date_table(&[
+        ("year", 1970.into()),
+        ("month", 1.into()),
+        ("day", 1.into()),
+        ("hour", 5.into()),
+        ("min", 30.into()),
+    ]);
+    assert_eq!(show(&state, "time", vec![t.into()]).unwrap(), "0");
+}
+
+#[cfg(unix)]
+#[test]
+fn daylight_saving_time() {
+    let tz = "EST5EDT,M3.2.0,M11.1.0";
+    if let Some(status) = rerun("daylight_saving_time", &[("TZ", tz)]) {
+        assert!(status.success());
+        return;
+    }
+    let state = state();
+    let date = |fmt: &str, t: i64| show(&state, "date", vec![fmt.into(), t.into()]).unwrap();
+    // 2021-01-01 and 2021-07-01, at midnight UTC.
+    assert_eq!(date("%F %H %z %Z", 1609459200), "2020-12-31 19 -0500 EST");
+    assert_eq!(date("%F %H %z %Z", 1625097600), "2021-06-30 20 -0400 EDT");
+    let t = call(&state, "date", vec!["*t".into(), 1625097600.into()]).unwrap()[0].clone();
+    assert_eq!(
+        state
+            .tostring(&state.index(&t, &"isdst".into()).unwrap())
+            .unwrap()
+            .to_string(),
+        "true"
+    );
+
+    let summer = date_table(&[
+        ("year", 2021.into()),
+        ("month", 7.into()),
+        ("day", 1.into()),
+    ]);
+    assert_eq!(
+        show(&state, "time", vec![summer.clone().into()]).unwrap(),
+        "1625155200"
+    );
+    assert_eq!(
+        state
+            .tostring(&summer.get_str("isdst"))
+            .unwrap()
+            .to_string(),
+        "true"
+    );
+    // An explicit isdst shifts a time that contradicts it.
+    let winter = date_table(&[
+        ("year", 2021.into()),
+        ("month", 1.into()),
+        ("day", 1.into()),
+        ("isdst", true.into()),
+    ]);
+    assert_eq!(
+        show(&state, "time", vec![winter.clone().into()]).unwrap(),
+        "1609516800"
+    );
+    assert_eq!(
+        state.tostring(&winter.get_str("hour")).unwrap().to_string(),
+        "11"
+    );
+}
+
+#[test]
+fn files() {
+    let state = state();
+    let name = call(&state, "tmpname", vec![]).unwrap()[0].clone();
+    let path = state.tostring(&name).unwrap().to_string();
+    assert!(std::path::Path::new(&path).exists());
+
+    let moved = format!("{}.moved", path);
+    assert_eq!(
+        show(&state, "rename", vec![name.clone(), moved.as_str().into()]).unwrap(),
+        "true"
+    );
+    assert_eq!(
+        show(&state, "remove", vec![name.clone()]).unwrap(),
+        format!("nil {}: No such file or directory 2", path)
+    );
+    assert_eq!(
+        show(&state, "remove", vec![moved.as_str().into()]).unwrap(),
+        "true"
+    );
+
+    // Empty directories can be removed too.
+    std::fs::create_dir(&path).unwrap();
+    assert_eq!(show(&state, "remove", vec![name]).unwrap(), "true");
+}
+
+#[test]
+fn process() {
+    let state = state();
+    let getenv = |name: &str| show(&state, "getenv", vec![name.into()]).unwrap();
+    assert_eq!(getenv("PATH"), std::env::var("PATH").unwrap());
+    assert_eq!(getenv("RUA_SURELY_UNSET_VARIABLE"), "nil");
+    assert_eq!(getenv("A=B"), "nil");
+
+    assert_eq!(show(&state, "setlocale", vec![]).unwrap(), "C");
+    assert_eq!(
+        show(&state, "setlocale", vec!["POSIX".into()]).unwrap(),
+        "C"
+    );
+    assert_eq!(
+        show(&state, "setlocale", vec!["fr_FR".into()]).unwrap(),
+        "nil"
+    );
+    assert_eq!(
+        show(&state, "setlocale", vec!["C".into(), "all!".into()]).unwrap_err(),
+        "bad argument #2 to 'setlocale' (invalid option 'all!')"
+    );
+
+    assert_eq!(show(&state, "execute", vec![]).unwrap(), "true");
+    #[cfg(unix)]
+    {
+        assert_eq!(
+            show(&state, "execute", vec!["true".into()]).unwrap(),
+            "true exit 0"
+        );
+        assert_eq!(
+            show(&state, "execute", vec!["exit 7".into()]).unwrap(),
+            "nil exit 7"
+        );
+        assert_eq!(
+            show(&state, "execute", vec!["kill -9 $$".into()]).unwrap(),
+            "nil signal 9"
+        );
+    }
+}
+
+#[test]
+fn sandboxed() {
+    let state = State::new();
+    state.open_os_sandboxed();
+    let os = state.globals().get_str("os");
+    for name in ["clock", "date", "difftime", "setlocale", "time"] {
+        assert!(
+            !state.index(&os, &name.into()).unwrap().is_nil(),
+            "{}",
+            name
+        );
+    }
+    for name in ["execute", "exit", "getenv", "remove", "rename", "tmpname"] {
+        assert!(state.index(&os, &name.into()).unwrap().is_nil(), "{}", name);
+    }
+}
+
+#[test]
+fn exit() {
+    let path = std::env::temp_dir().join(format!("rua_oslib_{}_exit", std::process::id()));
+    for (close, code) in [("false", 3), ("true", 0)] {
+        let path = path.with_extension(close);
+        let env = [
+            ("RUA_EXIT_PATH", path.to_str().unwrap()),
+            ("RUA_EXIT_CLOSE", close),
+        ];
+        if let Some(status) = rerun("exit", &env) {
+            // The buffered output is flushed whether or not the state is
+            // closed.
+            assert_eq!(status.code(), Some(code));
+            assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+            std::fs::remove_file(&path).unwrap();
+            continue;
+        }
+
+        let state = state();
+        let io = state.globals().get_str("io");
+        let open = state.index(&io, &"open".into()).unwrap();
+        let path = std::env::var("RUA_EXIT_PATH").unwrap();
+        let f = state
+            .call1(&open, vec![path.as_str().into(), "w".into()])
+            .unwrap();
+        let write = state.index(&f, &"write".into()).unwrap();
+        state.call(&write, vec![f.clone(), "hello".into()]).unwrap();
+        let close = std::env::var("RUA_EXIT_CLOSE").unwrap() == "true";
+        let code = if close {
+            Value::Boolean(true)
+        } else {
+            3.into()
+        };
+        call(&state, "exit", vec![code, close.into()]).unwrap();
+        unreachable!("os.exit returned");
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{show, state};
+use rua::{LuaString, State, Value};
+
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    common::call(state, &format!("string.{}", name), args)
+}
+
+fn pack(state: &State, fmt: &str, mut args: Vec<Value>) -> Result<Vec<u8>, String> {
+    args.insert(0, fmt.into());
+    let r = call(state, "pack", args)?;
+    Ok(r[0].to_lua_string().unwrap().to_vec())
+}
+
+fn unpack(state: &State, fmt: &str, data: &[u8]) -> Result<String, String> {
+    let r = call(
+        state,
+        "unpack",
+        vec![fmt.into(), LuaString::from(data).into()],
+    )?;
+    Ok(show(state, &r))
+}
+
+fn packsize(state: &State, fmt: &str) -> Result<String, String> {
+    let r = call(state, "packsize", vec![fmt.into()])?;
+    Ok(state.tostring(&r[0]).unwrap().to_string())
+}
+
+#[test]
+fn integers() {
+    let state = state();
+    assert_eq!(
+        pack(&state, "<i4", vec![100.into()]).unwrap(),
+        [100, 0, 0, 0]
+    );
+    assert_eq!(pack(&state, ">I2", vec![258.into()]).unwrap(), [1, 2]);
+    assert_eq!(
+        pack(&state, "<i3", vec![(-2).into()]).unwrap(),
+        [0xFE, 0xFF, 0xFF]
+    );
+    assert_eq!(pack(&state, "i16", vec![(-1).into()]).unwrap(), [0xFF; 16]);
+    assert_eq!(unpack(&state, "i16", &[0xFF; 16]).unwrap(), "-1 17");
+    assert_eq!(
+        unpack(&state, "<i3 >h", &[0xFE, 0xFF, 0xFF, 1, 0]).unwrap(),
+        "-2 256 6"
+    );
+    assert_eq!(
+        unpack(&state, "<I3", &[0xFE, 0xFF, 0xFF]).unwrap(),
+        "16777214 4"
+    );
+    let min = pack(&state, ">j", vec![i64::MIN.into()]).unwrap();
+    assert_eq!(
+        unpack(&state, ">j", &min).unwrap(),
+        "-9223372036854775808 9"
+    );
+    let mut nine = vec![0; 9];
+    nine[8] = 1;
+    assert_eq!(
+        unpack(&state, "<I9", &nine).unwrap_err(),
+        "9-byte integer does not fit into Lua Integer"
+    );
+    assert_eq!(
+        pack(&state, "b", vec![128.into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (integer overflow)"
+    );
+    assert_eq!(
+        pack(&state, "B", vec![(-1).into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (unsigned overflow)"
+    );
+    assert_eq!(
+        pack(&state, "i", vec![1.5.into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (number has no integer representation)"
+    );
+}
+
+#[test]
+fn floats_and_strings() {
+    let state = state();
+    let data = pack(&state, "<f d", vec![1.5.into(), (-0.25).into()]).unwrap();
+    assert_eq!(&data[..4], [0, 0, 0xC0, 0x3F]);
+    assert_eq!(unpack(&state, "<f d", &data).unwrap(), "1.5 -0.25 13");
+
+    let data = pack(
+        &state,
+        "s1 z c5",
+        vec!["abc".into(), "hi".into(), "xyz".into()],
+    )
+    .unwrap();
+    assert_eq!(data, b"\x03abchi\0xyz\0\0");
+    assert_eq!(
+        unpack(&state, "s1 z c5", &data).unwrap(),
+        "abc hi xyz\0\0 13"
+    );
+
+    assert_eq!(
+        pack(&state, "s1", vec!["x".repeat(256).as_str().into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (string length does not fit in given size)"
+    );
+    assert_eq!(
+        pack(&state, "z", vec!["a\0b".into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (string contains zeros)"
+    );
+    assert_eq!(
+        pack(&state, "c2", vec!["abc".into()]).unwrap_err(),
+        "bad argument #2 to 'pack' (string longer than given size)"
+    );
+    assert_eq!(
+        unpack(&state, "z", b"abc").unwrap_err(),
+        "bad argument #2 to 'unpack' (unfinished string for format 'z')"
+    );
+    assert_eq!(
+        unpack(&state, "s1", b"\x05abc").unwrap_err(),
+        "bad argument #2 to 'unpack' (data string too short)"
+    );
+    assert_eq!(
+        unpack(&state, "i4", b"abc").unwrap_err(),
+        "bad argument #2 to 'unpack' (data string too short)"
+    );
+}
+
+#[test]
+fn alignment() {
+    let state = state();
+    assert_eq!(
+        pack(&state, "<!4 b i4", vec![1.into(), 2.into()]).unwrap(),
+        [1, 0, 0, 0, 2, 0, 0, 0]
+    );
+    assert_eq!(packsize(&state, "!4 b i4").unwrap(), "8");
+    assert_eq!(packsize(&state, "b d").unwrap(), "9");
+    assert_eq!(packsize(&state, "!8 b d").unwrap(), "16");
+    assert_eq!(packsize(&state, "!b Xi4").unwrap(), "4");
+    assert_eq!(packsize(&state, "!2 b Xi8 b").unwrap(), "3");
+    assert_eq!(packsize(&state, "c3 x i2").unwrap(), "6");
+    assert_eq!(
+        packsize(&state, "!3 b i3").unwrap_err(),
+        "bad argument #1 to 'packsize' (format asks for alignment not power of 2)"
+    );
+    assert_eq!(
+        pack(&state, "X", vec![]).unwrap_err(),
+        "bad argument #1 to 'pack' (invalid next option for option 'X')"
+    );
+    assert_eq!(
+        pack(&state, "Xc1", vec![]).unwrap_err(),
+        "bad argument #1 to 'pack' (invalid next option for option 'X')"
+    );
+}
+
+#[test]
+fn format_errors() {
+    let state = state();
+    assert_eq!(
+        packsize(&state, "s").unwrap_err(),
+        "bad argument #1 to 'packsize' (variable-length format)"
+    );
+    assert_eq!(
+        packsize(&state, "i17").unwrap_err(),
+        "integral size (17) out of limits [1,16]"
+    );
+    assert_eq!(
+        packsize(&state, "i0").unwrap_err(),
+        "integral size (0) out of limits [1,16]"
+    );
+    assert_eq!(
+        packsize(&state, "y").unwrap_err(),
+        "invalid format option 'y'"
+    );
+    assert_eq!(
+        packsize(&state, "c").unwrap_err(),
+        "missing size for format option 'c'"
+    );
+    assert_eq!(
+        packsize(&state, "c1000000000 c1000000000 c1000000000").unwrap_err(),
+        "bad argument #1 to 'packsize' (format result too large)"
+    );
+}
+
+#[test]
+fn positions() {
+    let state = state();
+    let r = call(
+        &state,
+        "unpack",
+        vec!["b".into(), "abc".into(), (-1).into()],
+    )
+    .unwrap();
+    assert_eq!(r[0].to_integer(), Some(99));
+    assert_eq!(r[1].to_integer(), Some(4));
+    assert_eq!(
+        call(&state, "unpack", vec!["b".into(), "abc".into(), 5.into()]).unwrap_err(),
+        "bad argument #3 to 'unpack' (initial position out of string)"
+    );
+    let r = call(&state, "unpack", vec!["".into(), "abc".into(), 4.into()]).unwrap();
+    assert_eq!(r[0].to_integer(), Some(4));
+}
@@ -0,0 +1,111 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, Closure, Proto};
+
+/// `ABSLINEINFO` in `lineinfo`, marking a pc anchored in `abslineinfo`.
+const ABS: i8 = -0x80;
+
+async fn load(f: Function) -> Closure {
+    rua::undump(&chunk(&f)[..]).await.unwrap()
+}
+
+fn name(proto: &Proto, register: usize, pc: usize) -> Option<String> {
+    proto.local_name(register, pc).map(|name| name.to_string())
+}
+
+#[tokio::test]
+async fn lines() {
+    // Line 12 comes back twice by an anchor, the second time right after
+    // the jump to line 1000.
+    let mut code = vec![i(OP_LOADNIL, &[0, 0]); 7];
+    code.push(i(OP_RETURN0, &[]));
+    let closure = load(Function {
+        linedefined: 10,
+        lastlinedefined: 1000,
+        maxstacksize: 2,
+        code,
+        lineinfo: vec![1, 1, ABS, 1, ABS, -1, ABS, ABS],
+        abslineinfo: vec![(2, 500), (4, 12), (6, 1000), (7, 12)],
+        ..Default::default()
+    })
+    .await;
+    let proto = closure.proto();
+    let lines: Vec<_> = (0..9).map(|pc| proto.line_for_pc(pc)).collect();
+    let expected = [11, 12, 500, 501, 12, 11, 1000, 12].map(Some);
+    assert_eq!(lines[..8], expected);
+    assert_eq!(lines[8], None);
+
+    assert_eq!(proto.pcs_for_line(12), [1, 4, 7]);
+    assert_eq!(proto.pcs_for_line(11), [0, 5]);
+    assert_eq!(proto.pcs_for_line(501), [3]);
+    assert!(proto.pcs_for_line(13).is_empty());
+}
+
+#[tokio::test]
+async fn no_lines() {
+    let closure = load(Function {
+        maxstacksize: 2,
+        code: vec![i(OP_RETURN0, &[])],
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(closure.proto().line_for_pc(0), None);
+    assert!(closure.proto().pcs_for_line(0).is_empty());
+}
+
+#[tokio::test]
+async fn locals() {
+    // local x = nil
+    // do
+    //   local x = nil
+    //   x = nil
+    //   x = nil
+    // end
+    // local y = nil
+    // return
+    let closure = load(Function {
+        maxstacksize: 2,
+        code: vec![
+            i(OP_LOADNIL, &[0, 0]),
+            i(OP_LOADNIL, &[1, 0]),
+            i(OP_LOADNIL, &[1, 0]),
+            i(OP_LOADNIL, &[1, 0]),
+            i(OP_LOADNIL, &[1, 0]),
+            i(OP_RETURN0, &[]),
+        ],
+        locvars: vec![("x", 1, 6), ("x", 2, 4), ("y", 5, 6)],
+        ..Default::default()
+    })
+    .await;
+    let proto = closure.proto();
+    assert!(proto.locals_at(0).is_empty());
+    assert_eq!(name(proto, 0, 0), None);
+
+    // The inner x shadows the outer one from the next register.
+    let locals = proto.locals_at(3);
+    let starts: Vec<_> = locals.iter().map(|(r, v)| (*r, v.startpc())).collect();
+    assert_eq!(starts, [(0, 1), (1, 2)]);
+    assert_eq!(name(proto, 0, 3).as_deref(), Some("x"));
+    assert_eq!(name(proto, 1, 3).as_deref(), Some("x"));
+
+    // Once the inner x is dead its register holds a temporary, then y.
+    assert_eq!(name(proto, 1, 4), None);
+    assert_eq!(name(proto, 1, 5).as_deref(), Some("y"));
+    assert_eq!(name(proto, 0, 5).as_deref(), Some("x"));
+    assert_eq!(name(proto, 2, 5), None);
+}
@@ -0,0 +1,65 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rua::{Interner, LuaString, Table, Value};
+
+#[test]
+fn short_strings_are_interned() {
+    let strings = Interner::new();
+    let a = strings.intern("name");
+    let b = strings.intern(String::from("name"));
+    assert!(a.is_short());
+    assert!(a.ptr_eq(&b));
+    assert_eq!(strings.len(), 1);
+
+    // Strings made elsewhere still equal them, and find the same keys.
+    let c = LuaString::from("name");
+    assert!(!a.ptr_eq(&c));
+    assert_eq!(a, c);
+    let t = Table::new();
+    t.set(Value::String(a), 1.into()).unwrap();
+    assert_eq!(t.get(&Value::String(c)).to_integer(), Some(1));
+    assert!(t.get(&"other".into()).is_nil());
+
+    let long = "x".repeat(41);
+    let a = strings.intern(long.as_str());
+    let b = strings.intern(long.as_str());
+    assert!(!a.is_short());
+    assert!(!a.ptr_eq(&b));
+    assert_eq!(a, b);
+    assert_eq!(strings.len(), 1);
+}
+
+#[test]
+fn dead_strings_are_freed() {
+    let strings = Interner::new();
+    let kept = strings.intern("kept");
+    for i in 0..10_000 {
+        strings.intern(i.to_string());
+    }
+    assert_eq!(strings.len(), 1);
+    // Sweeping keeps live strings.
+    assert!(strings.intern("kept").ptr_eq(&kept));
+    drop(kept);
+    assert_eq!(strings.len(), 0);
+}
+
+#[test]
+fn order_and_hash_follow_contents() {
+    let long = LuaString::from("b".repeat(50));
+    let mut v = vec![long.clone(), "b".into(), "a".into(), "".into()];
+    v.sort();
+    assert_eq!(v, ["".into(), "a".into(), "b".into(), long]);
+    assert_eq!(LuaString::default(), LuaString::from(""));
+}
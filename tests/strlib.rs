@@ -0,0 +1,463 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::{show, state};
+use rua::{State, Table, Value};
+
+/// Calls `string.<name>` and renders the results, or returns the error
+/// message.
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<String, String> {
+    let results = common::call(state, &format!("string.{}", name), args)?;
+    Ok(show(state, &results))
+}
+
+fn ok(state: &State, name: &str, args: Vec<Value>) -> String {
+    call(state, name, args).unwrap()
+}
+
+#[test]
+fn find() {
+    let state = state();
+    let find = |args: Vec<Value>| ok(&state, "find", args);
+    assert_eq!(find(vec!["hello world".into(), "o w".into()]), "5 7");
+    assert_eq!(find(vec!["hello".into(), "l+".into()]), "3 4");
+    assert_eq!(find(vec!["hello".into(), "".into(), 10.into()]), "nil");
+    assert_eq!(find(vec!["hello".into(), "".into(), 6.into()]), "6 5");
+    assert_eq!(
+        find(vec!["a.b".into(), ".".into(), 1.into(), true.into()]),
+        "2 2"
+    );
+    assert_eq!(
+        find(vec!["key = val".into(), "(%w+) = (%w+)".into()]),
+        "1 9 key val"
+    );
+    assert_eq!(find(vec!["aaa".into(), "^a".into(), 2.into()]), "2 2");
+    assert_eq!(find(vec!["abc".into(), "b".into(), (-1).into()]), "nil");
+    assert_eq!(
+        find(vec!["THE (quick) fox".into(), "%((%a+)%)".into()]),
+        "5 11 quick"
+    );
+}
+
+#[test]
+fn match_() {
+    let state = state();
+    let m = |s: &str, p: &str| ok(&state, "match", vec![s.into(), p.into()]);
+    assert_eq!(m("hello 123 world", "%d+"), "123");
+    assert_eq!(m("  trim  ", "^%s*(.-)%s*$"), "trim");
+    assert_eq!(m("x = f(a(b)c) + 1", "%b()"), "(a(b)c)");
+    assert_eq!(m("THE (quick) fox", "%f[%a]%a+%f[%A]"), "THE");
+    assert_eq!(m("hello", "()ll()"), "3 5");
+    assert_eq!(m("abcabc", "(abc)%1"), "abc");
+    assert_eq!(m("[x]", "[]x[]+"), "[x]");
+    assert_eq!(m("a-b", "[%-]"), "-");
+    assert_eq!(m("2024-01-02", "(%d+)-(%d+)-(%d+)"), "2024 01 02");
+    assert_eq!(m("abc", "^b"), "nil");
+    assert_eq!(m("abc", "c$"), "c");
+    assert_eq!(m("a$c", "$c"), "$c");
+    assert_eq!(m("aaab", "a-b"), "aaab");
+    assert_eq!(m("ab", "a?b"), "ab");
+    assert_eq!(m("b", "a?b"), "b");
+    assert_eq!(m("\0x", "%z?x"), "x");
+    assert_eq!(m("Z9_", "[^%l]+"), "Z9_");
+}
+
+#[test]
+fn pattern_errors() {
+    let state = state();
+    let m = |p: &str| call(&state, "match", vec!["abc".into(), p.into()]).unwrap_err();
+    assert_eq!(m("%"), "malformed pattern (ends with '%')");
+    assert_eq!(m("[a"), "malformed pattern (missing ']')");
+    assert_eq!(m("%b"), "malformed pattern (missing arguments to '%b')");
+    assert_eq!(m("%fa"), "missing '[' after '%f' in pattern");
+    assert_eq!(m("(a"), "unfinished capture");
+    assert_eq!(m("a)"), "invalid pattern capture");
+    assert_eq!(m("(a)%2"), "invalid capture index %2 in pattern");
+    assert_eq!(
+        call(
+            &state,
+            "match",
+            vec![
+                "a".repeat(40).as_str().into(),
+                "(a)".repeat(33).as_str().into()
+            ]
+        )
+        .unwrap_err(),
+        "too many captures"
+    );
+    let long = "a".repeat(300);
+    assert_eq!(
+        call(
+            &state,
+            "match",
+            vec![long.as_str().into(), "a?".repeat(300).as_str().into()]
+        )
+        .unwrap_err(),
+        "pattern too complex"
+    );
+}
+
+#[test]
+fn gmatch() {
+    let state = state();
+    let string = state.globals().get_str("string");
+    let gmatch = state.index(&string, &"gmatch".into()).unwrap();
+    let collect = |args: Vec<Value>| {
+        let iter = state.call1(&gmatch, args).unwrap();
+        let mut out = vec![];
+        loop {
+            let r = state.call(&iter, vec![]).unwrap();
+            if r.is_empty() || r[0].is_nil() {
+                break out;
+            }
+            let r: Vec<_> = r
+                .iter()
+                .map(|v| state.tostring(v).unwrap().to_string())
+                .collect();
+            out.push(r.join("="));
+        }
+    };
+    assert_eq!(
+        collect(vec!["one two  three".into(), "%a+".into()]),
+        ["one", "two", "three"]
+    );
+    assert_eq!(
+        collect(vec!["a=1, b=2".into(), "(%w+)=(%w+)".into()]),
+        ["a=1", "b=2"]
+    );
+    assert_eq!(collect(vec!["abc".into(), "".into()]), ["", "", "", ""]);
+    assert_eq!(
+        collect(vec!["abc".into(), ".".into(), 2.into()]),
+        ["b", "c"]
+    );
+    assert_eq!(
+        collect(vec!["abc".into(), ".".into(), 9.into()]),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn gsub() {
+    let state = state();
+    let gsub = |args: Vec<Value>| ok(&state, "gsub", args);
+    assert_eq!(
+        gsub(vec!["hello world".into(), "o".into(), "0".into()]),
+        "hell0 w0rld 2"
+    );
+    assert_eq!(
+        gsub(vec!["hello world".into(), "(%w+)".into(), "<%1>".into()]),
+        "<hello> <world> 2"
+    );
+    assert_eq!(
+        gsub(vec![
+            "hello world".into(),
+            "%w+".into(),
+            "%0 %0".into(),
+            1.into()
+        ]),
+        "hello hello world 1"
+    );
+    assert_eq!(gsub(vec!["abc".into(), "".into(), "-".into()]), "-a-b-c- 4");
+    assert_eq!(gsub(vec!["abc".into(), "^".into(), ">".into()]), ">abc 1");
+    assert_eq!(
+        gsub(vec!["a b".into(), "()".into(), "%1".into()]),
+        "1a2 3b4 4"
+    );
+    assert_eq!(
+        gsub(vec!["100%".into(), "%%".into(), "%%%%".into()]),
+        "100%% 1"
+    );
+
+    let t = Table::new();
+    t.set_str("name", "Lua");
+    assert_eq!(
+        gsub(vec!["$name is $other".into(), "%$(%w+)".into(), t.into()]),
+        "Lua is $other 2"
+    );
+    let upper = state
+        .index(&state.globals().get_str("string"), &"upper".into())
+        .unwrap();
+    assert_eq!(
+        gsub(vec!["hello world".into(), "%w+".into(), upper]),
+        "HELLO WORLD 2"
+    );
+
+    assert_eq!(
+        call(&state, "gsub", vec!["x".into(), "x".into(), "%2".into()]).unwrap_err(),
+        "invalid capture index %2"
+    );
+    assert_eq!(
+        call(&state, "gsub", vec!["x".into(), "x".into(), "%".into()]).unwrap_err(),
+        "invalid use of '%' in replacement string"
+    );
+    assert_eq!(
+        call(&state, "gsub", vec!["x".into(), "x".into(), true.into()]).unwrap_err(),
+        "bad argument #3 to 'gsub' (string/function/table expected, got boolean)"
+    );
+    let t = Table::new();
+    t.set_str("x", Table::new());
+    assert_eq!(
+        call(&state, "gsub", vec!["x".into(), "x".into(), t.into()]).unwrap_err(),
+        "invalid replacement value (a table)"
+    );
+}
+
+#[test]
+fn basics() {
+    let state = state();
+    assert_eq!(ok(&state, "len", vec!["a\0b".into()]), "3");
+    assert_eq!(
+        ok(&state, "sub", vec!["hello".into(), 2.into(), (-2).into()]),
+        "ell"
+    );
+    assert_eq!(ok(&state, "sub", vec!["hello".into(), (-3).into()]), "llo");
+    assert_eq!(
+        ok(&state, "sub", vec!["hello".into(), 4.into(), 2.into()]),
+        ""
+    );
+    assert_eq!(
+        ok(&state, "sub", vec!["hello".into(), 0.into(), 100.into()]),
+        "hello"
+    );
+    assert_eq!(ok(&state, "byte", vec!["ABC".into()]), "65");
+    assert_eq!(
+        ok(&state, "byte", vec!["ABC".into(), 1.into(), (-1).into()]),
+        "65 66 67"
+    );
+    assert_eq!(ok(&state, "byte", vec!["ABC".into(), 10.into()]), "");
+    // The default end is the raw start, so these select nothing.
+    assert_eq!(ok(&state, "byte", vec!["abc".into(), 0.into()]), "");
+    assert_eq!(ok(&state, "byte", vec!["abc".into(), (-10).into()]), "");
+    assert_eq!(ok(&state, "byte", vec!["abc".into(), (-1).into()]), "99");
+    assert_eq!(ok(&state, "char", vec![72.into(), 105.into()]), "Hi");
+    assert_eq!(
+        call(&state, "char", vec![256.into()]).unwrap_err(),
+        "bad argument #1 to 'char' (value out of range)"
+    );
+    assert_eq!(
+        ok(&state, "rep", vec!["ab".into(), 3.into(), ",".into()]),
+        "ab,ab,ab"
+    );
+    assert_eq!(ok(&state, "rep", vec!["ab".into(), 0.into()]), "");
+    assert_eq!(ok(&state, "reverse", vec!["abc".into()]), "cba");
+    assert_eq!(ok(&state, "upper", vec!["MiXeD 1".into()]), "MIXED 1");
+    assert_eq!(ok(&state, "lower", vec!["MiXeD 1".into()]), "mixed 1");
+    assert_eq!(ok(&state, "len", vec![12.5.into()]), "4");
+}
+
+#[test]
+fn string_metatable() {
+    let state = state();
+    let s = Value::from("x");
+    let upper = state.index(&s, &"upper".into()).unwrap();
+    let r = state.call1(&upper, vec![s]).unwrap();
+    assert_eq!(state.tostring(&r).unwrap().to_string(), "X");
+}
+
+#[test]
+fn dump() {
+    let state = state();
+    let proto = rua::assemble("RETURN0\n").unwrap();
+    let chunk = rua::dump(&proto);
+    let load = state.globals().get_str("load");
+    let f = state
+        .call1(&load, vec![rua::LuaString::from(chunk.clone()).into()])
+        .unwrap();
+    let string = state.globals().get_str("string");
+    let dump = state.index(&string, &"dump".into()).unwrap();
+    let dumped = state.call1(&dump, vec![f]).unwrap();
+    assert_eq!(dumped.to_lua_string().unwrap().as_bytes(), &chunk[..]);
+    assert_eq!(
+        call(&state, "dump", vec![dump]).unwrap_err(),
+        "unable to dump given function"
+    );
+}
+
+#[test]
+fn format() {
+    let state = state();
+    let f = |fmt: &str, args: Vec<Value>| {
+        let mut args = args;
+        args.insert(0, fmt.into());
+        ok(&state, "format", args)
+    };
+    assert_eq!(
+        f("%5.2f|%-10s|%%", vec![1.23456.into(), "abc".into()]),
+        " 1.23|abc       |%"
+    );
+    assert_eq!(
+        f(
+            "%x %#x %X %#o %o",
+            vec![255.into(), 255.into(), (-1).into(), 8.into(), 0.into()]
+        ),
+        "ff 0xff FFFFFFFFFFFFFFFF 010 0"
+    );
+    assert_eq!(
+        f(
+            "%05d|%+d|% d|%.3d|%5.0d|%-4i|",
+            vec![
+                (-42).into(),
+                5.into(),
+                5.into(),
+                7.into(),
+                0.into(),
+                1.into()
+            ]
+        ),
+        "-0042|+5| 5|007|     |1   |"
+    );
+    assert_eq!(f("%d %d", vec![3.0.into(), "10".into()]), "3 10");
+    assert_eq!(f("%u", vec![(-1).into()]), "18446744073709551615");
+    assert_eq!(
+        f(
+            "%g %g %g %#g %g",
+            vec![
+                100000.into(),
+                1e6.into(),
+                0.0001.into(),
+                1.into(),
+                0.1.into()
+            ]
+        ),
+        "100000 1e+06 0.0001 1.00000 0.1"
+    );
+    assert_eq!(
+        f(
+            "%e|%10.3e|%G|%E",
+            vec![12345.678.into(), 0.5.into(), 1e-10.into(), 0.into()]
+        ),
+        "1.234568e+04| 5.000e-01|1E-10|0.000000E+00"
+    );
+    assert_eq!(
+        f(
+            "%f %05f %f %+.1f %08.2f",
+            vec![
+                f64::INFINITY.into(),
+                f64::NEG_INFINITY.into(),
+                (-0.0).into(),
+                2.25.into(),
+                (-1.23456).into()
+            ]
+        ),
+        "inf  -inf -0.000000 +2.2 -0001.23"
+    );
+    assert_eq!(
+        f(
+            "%a %A %.3a %.1a %a %010a",
+            vec![
+                1.0.into(),
+                0.5.into(),
+                1.into(),
+                1.96875.into(),
+                0.1.into(),
+                1.into()
+            ]
+        ),
+        "0x1p+0 0X1P-1 0x1.000p+0 0x2.0p+0 0x1.999999999999ap-4 0x00001p+0"
+    );
+    assert_eq!(
+        f("%a %a", vec![0.0.into(), 5e-324.into()]),
+        "0x0p+0 0x0.0000000000001p-1022"
+    );
+    assert_eq!(
+        f("%c%3c|%-3c|", vec![65.into(), 66.into(), 67.into()]),
+        "A  B|C  |"
+    );
+    assert_eq!(
+        f(
+            "%5.1s|%.10s|%s",
+            vec!["abc".into(), "abc".into(), 1.5.into()]
+        ),
+        "    a|abc|1.5"
+    );
+
+    assert_eq!(
+        f("%q", vec!["a\nb\"\\\x001\x7f\r".into()]),
+        "\"a\\\nb\\\"\\\\\\0001\\127\\13\""
+    );
+    assert_eq!(
+        f(
+            "%q %q %q %q %q %q %q %q",
+            vec![
+                i64::MIN.into(),
+                42.into(),
+                f64::INFINITY.into(),
+                f64::NEG_INFINITY.into(),
+                f64::NAN.into(),
+                0.1.into(),
+                1.0.into(),
+                Value::Nil
+            ]
+        ),
+        "0x8000000000000000 42 1e9999 -1e9999 (0/0) 0x1.999999999999ap-4 0x1p+0 nil"
+    );
+    assert_eq!(f("%q", vec![true.into()]), "true");
+
+    let err = |fmt: &str, args: Vec<Value>| {
+        let mut args = args;
+        args.insert(0, fmt.into());
+        call(&state, "format", args).unwrap_err()
+    };
+    assert_eq!(
+        err("%y", vec![1.into()]),
+        "invalid conversion '%y' to 'format'"
+    );
+    assert_eq!(
+        err("%5", vec![1.into()]),
+        "invalid conversion '%5' to 'format'"
+    );
+    assert_eq!(
+        err("%10q", vec![1.into()]),
+        "specifier '%q' cannot have modifiers"
+    );
+    assert_eq!(
+        err("%123d", vec![1.into()]),
+        "invalid conversion specification: '%123d'"
+    );
+    assert_eq!(
+        err("%#d", vec![1.into()]),
+        "invalid conversion specification: '%#d'"
+    );
+    assert_eq!(
+        err("%010s", vec![1.into()]),
+        "invalid conversion specification: '%010s'"
+    );
+    assert_eq!(
+        err("%.3c", vec![1.into()]),
+        "invalid conversion specification: '%.3c'"
+    );
+    assert_eq!(err("%d", vec![]), "bad argument #2 to 'format' (no value)");
+    assert_eq!(
+        err("%d", vec![3.5.into()]),
+        "bad argument #2 to 'format' (number has no integer representation)"
+    );
+    assert_eq!(
+        err("%d", vec!["x".into()]),
+        "bad argument #2 to 'format' (number expected, got string)"
+    );
+    assert_eq!(
+        err("%q", vec![Table::new().into()]),
+        "bad argument #2 to 'format' (value has no literal form)"
+    );
+    assert_eq!(
+        err("%5s", vec!["a\0b".into()]),
+        "bad argument #2 to 'format' (string contains zeros)"
+    );
+    assert_eq!(
+        err(&format!("%{}d", "0".repeat(30)), vec![1.into()]),
+        "invalid format string to 'format'"
+    );
+}
@@ -0,0 +1,257 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::state;
+use rua::{Function, State, Table, Value};
+
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    common::call(state, &format!("table.{}", name), args)
+}
+
+fn list(values: &[i64]) -> Table {
+    let t = Table::new();
+    for (i, v) in values.iter().enumerate() {
+        t.set_int(i as i64 + 1, (*v).into());
+    }
+    t
+}
+
+/// Renders the sequence of `t`.
+fn show(state: &State, t: &Table) -> String {
+    common::show(state, &t.sequence())
+}
+
+/// A table whose accesses all go through metamethods to `backing`.
+fn proxy(backing: &Table) -> Table {
+    let mt = Table::new();
+    mt.set_str("__index", backing.clone());
+    mt.set_str("__newindex", backing.clone());
+    let b = backing.clone();
+    mt.set_str(
+        "__len",
+        Function::native("len", move |_, _| Ok(vec![b.len().into()])),
+    );
+    let t = Table::new();
+    t.set_metatable(Some(mt));
+    t
+}
+
+#[test]
+fn insert_and_remove() {
+    let state = state();
+    let t = list(&[1, 2, 3]);
+    call(&state, "insert", vec![t.clone().into(), 4.into()]).unwrap();
+    call(&state, "insert", vec![t.clone().into(), 1.into(), 0.into()]).unwrap();
+    assert_eq!(show(&state, &t), "0 1 2 3 4");
+    assert_eq!(
+        call(&state, "insert", vec![t.clone().into(), 7.into(), 0.into()]).unwrap_err(),
+        "bad argument #2 to 'insert' (position out of bounds)"
+    );
+    assert_eq!(
+        call(&state, "insert", vec![t.clone().into()]).unwrap_err(),
+        "wrong number of arguments to 'insert'"
+    );
+
+    let r = call(&state, "remove", vec![t.clone().into(), 1.into()]).unwrap();
+    assert_eq!(r[0].to_integer(), Some(0));
+    let r = call(&state, "remove", vec![t.clone().into()]).unwrap();
+    assert_eq!(r[0].to_integer(), Some(4));
+    assert_eq!(show(&state, &t), "1 2 3");
+    let r = call(&state, "remove", vec![t.clone().into(), 4.into()]).unwrap();
+    assert!(r[0].is_nil());
+    assert_eq!(
+        call(&state, "remove", vec![t.clone().into(), 5.into()]).unwrap_err(),
+        "bad argument #2 to 'remove' (position out of bounds)"
+    );
+    let empty = Table::new();
+    let r = call(&state, "remove", vec![empty.into()]).unwrap();
+    assert!(r[0].is_nil());
+}
+
+#[test]
+fn concat() {
+    let state = state();
+    let t = list(&[1, 2, 3]);
+    t.set_int(4, "x".into());
+    let c = |args: Vec<Value>| {
+        let r = call(&state, "concat", args)?;
+        Ok::<_, String>(state.tostring(&r[0]).unwrap().to_string())
+    };
+    assert_eq!(c(vec![t.clone().into()]).unwrap(), "123x");
+    assert_eq!(
+        c(vec![t.clone().into(), ", ".into()]).unwrap(),
+        "1, 2, 3, x"
+    );
+    assert_eq!(
+        c(vec![t.clone().into(), "-".into(), 2.into(), 3.into()]).unwrap(),
+        "2-3"
+    );
+    assert_eq!(
+        c(vec![t.clone().into(), "-".into(), 3.into(), 2.into()]).unwrap(),
+        ""
+    );
+    t.set_int(2, Value::Boolean(true));
+    assert_eq!(
+        c(vec![t.into()]).unwrap_err(),
+        "invalid value (at index 2) in table for 'concat'"
+    );
+    assert_eq!(
+        c(vec![1.into()]).unwrap_err(),
+        "bad argument #1 to 'concat' (table expected, got number)"
+    );
+}
+
+#[test]
+fn pack_and_unpack() {
+    let state = state();
+    let r = call(&state, "pack", vec![1.into(), Value::Nil, 3.into()]).unwrap();
+    let Value::Table(t) = &r[0] else {
+        panic!("pack returned {:?}", r[0])
+    };
+    assert_eq!(t.get_str("n").to_integer(), Some(3));
+    assert_eq!(t.get_int(3).to_integer(), Some(3));
+
+    let t = list(&[1, 2, 3]);
+    let r = call(&state, "unpack", vec![t.clone().into()]).unwrap();
+    assert_eq!(r.len(), 3);
+    let r = call(&state, "unpack", vec![t.clone().into(), 2.into(), 5.into()]).unwrap();
+    assert_eq!(r.len(), 4);
+    assert!(r[3].is_nil());
+    let r = call(&state, "unpack", vec![t.clone().into(), 3.into(), 2.into()]).unwrap();
+    assert!(r.is_empty());
+    assert_eq!(
+        call(
+            &state,
+            "unpack",
+            vec![t.into(), i64::MIN.into(), i64::MAX.into()]
+        )
+        .unwrap_err(),
+        "too many results to unpack"
+    );
+}
+
+#[test]
+fn move_elements() {
+    let state = state();
+    let t = list(&[1, 2, 3, 4, 5]);
+    let mv = |args: Vec<Value>| call(&state, "move", args);
+    mv(vec![t.clone().into(), 1.into(), 3.into(), 3.into()]).unwrap();
+    assert_eq!(show(&state, &t), "1 2 1 2 3");
+    mv(vec![t.clone().into(), 2.into(), 5.into(), 1.into()]).unwrap();
+    assert_eq!(show(&state, &t), "2 1 2 3 3");
+
+    let dest = Table::new();
+    let r = mv(vec![
+        t.clone().into(),
+        1.into(),
+        3.into(),
+        1.into(),
+        dest.clone().into(),
+    ])
+    .unwrap();
+    assert!(r[0].raw_equal(&dest.clone().into()));
+    assert_eq!(show(&state, &dest), "2 1 2");
+
+    assert_eq!(
+        mv(vec![
+            t.clone().into(),
+            (-1).into(),
+            i64::MAX.into(),
+            1.into()
+        ])
+        .unwrap_err(),
+        "bad argument #3 to 'move' (too many elements to move)"
+    );
+    assert_eq!(
+        mv(vec![t.into(), 1.into(), 3.into(), i64::MAX.into()]).unwrap_err(),
+        "bad argument #4 to 'move' (destination wrap around)"
+    );
+}
+
+#[test]
+fn metamethods() {
+    let state = state();
+    let backing = list(&[3, 1, 2]);
+    let p = proxy(&backing);
+    call(&state, "insert", vec![p.clone().into(), 0.into()]).unwrap();
+    call(&state, "sort", vec![p.clone().into()]).unwrap();
+    assert!(p.get_int(1).is_nil());
+    assert_eq!(show(&state, &backing), "0 1 2 3");
+    let r = call(&state, "concat", vec![p.clone().into(), "".into()]).unwrap();
+    assert_eq!(state.tostring(&r[0]).unwrap().to_string(), "0123");
+    let r = call(&state, "unpack", vec![p.into()]).unwrap();
+    assert_eq!(r.len(), 4);
+
+    // Strings have `__index` but no `__newindex` or `__len`.
+    let s: Value = "x".into();
+    assert_eq!(
+        call(&state, "insert", vec![s, 1.into()]).unwrap_err(),
+        "bad argument #1 to 'insert' (table expected, got string)"
+    );
+}
+
+#[test]
+fn sort() {
+    let state = state();
+    let t = list(&[5, 3, 8, 1, 9, 2, 7]);
+    call(&state, "sort", vec![t.clone().into()]).unwrap();
+    assert_eq!(show(&state, &t), "1 2 3 5 7 8 9");
+
+    let greater = Function::native("greater", |state, args| {
+        Ok(vec![state.less_than(args.get(2), args.get(1))?.into()])
+    });
+    call(&state, "sort", vec![t.clone().into(), greater.into()]).unwrap();
+    assert_eq!(show(&state, &t), "9 8 7 5 3 2 1");
+
+    let words = Table::new();
+    for (i, w) in ["pear", "apple", "fig"].iter().enumerate() {
+        words.set_int(i as i64 + 1, (*w).into());
+    }
+    call(&state, "sort", vec![words.clone().into()]).unwrap();
+    assert_eq!(show(&state, &words), "apple fig pear");
+
+    words.set_int(2, 1.into());
+    assert_eq!(
+        call(&state, "sort", vec![words.into()]).unwrap_err(),
+        "attempt to compare number with string"
+    );
+    assert_eq!(
+        call(&state, "sort", vec![t.into(), 1.into()]).unwrap_err(),
+        "bad argument #2 to 'sort' (function expected, got number)"
+    );
+}
+
+#[test]
+fn sort_invalid_order() {
+    let state = state();
+    let t = list(&(0..1000).map(|i| i % 7).collect::<Vec<_>>());
+    let always = Function::native("always", |_, _| Ok(vec![true.into()]));
+    assert_eq!(
+        call(&state, "sort", vec![t.into(), always.into()]).unwrap_err(),
+        "invalid order function for sorting"
+    );
+}
+
+#[test]
+fn sort_large() {
+    let state = state();
+    let n = 1_000_000_i64;
+    // A fixed permutation of 1..=n, since 7919 is coprime with n.
+    let t = list(&(0..n).map(|i| i * 7919 % n + 1).collect::<Vec<_>>());
+    call(&state, "sort", vec![t.clone().into()]).unwrap();
+    assert_eq!(t.len(), n);
+    assert!((1..=n).all(|i| t.get_int(i).to_integer() == Some(i)));
+}
@@ -0,0 +1,192 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use common::state;
+use rua::{LuaString, State, Value};
+
+fn bytes(s: &[u8]) -> Value {
+    LuaString::from(s).into()
+}
+
+fn call(state: &State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, String> {
+    common::call(state, &format!("utf8.{}", name), args)
+}
+
+fn show(state: &State, name: &str, args: Vec<Value>) -> Result<String, String> {
+    Ok(common::show(state, &call(state, name, args)?))
+}
+
+#[test]
+fn char() {
+    let state = state();
+    let r = call(
+        &state,
+        "char",
+        vec![72.into(), 0xE9.into(), 0x10FFFF.into(), 0x7FFFFFFF.into()],
+    )
+    .unwrap();
+    assert_eq!(
+        r[0].to_lua_string().unwrap().to_vec(),
+        b"H\xC3\xA9\xF4\x8F\xBF\xBF\xFD\xBF\xBF\xBF\xBF\xBF"
+    );
+    assert_eq!(
+        call(&state, "char", vec![0x80000000_i64.into()]).unwrap_err(),
+        "bad argument #1 to 'char' (value out of range)"
+    );
+    assert_eq!(
+        call(&state, "char", vec![(-1).into()]).unwrap_err(),
+        "bad argument #1 to 'char' (value out of range)"
+    );
+    let utf8 = state.globals().get_str("utf8");
+    let pattern = state.index(&utf8, &"charpattern".into()).unwrap();
+    assert_eq!(
+        pattern.to_lua_string().unwrap().to_vec(),
+        b"[\0-\x7F\xC2-\xFD][\x80-\xBF]*"
+    );
+}
+
+#[test]
+fn len() {
+    let state = state();
+    let len = |args| show(&state, "len", args);
+    assert_eq!(len(vec!["häll€".into()]).unwrap(), "5");
+    assert_eq!(len(vec!["häll€".into(), 4.into()]).unwrap(), "3");
+    assert_eq!(len(vec!["häll€".into(), 3.into()]).unwrap(), "nil 3");
+    assert_eq!(len(vec!["".into()]).unwrap(), "0");
+    assert_eq!(len(vec![bytes(b"a\xFF")]).unwrap(), "nil 2");
+    assert_eq!(len(vec![bytes(b"\xC0\x80")]).unwrap(), "nil 1");
+    // Surrogates and values past U+10FFFF need the lax flag.
+    for s in [
+        &b"\xED\xA0\x80"[..],
+        b"\xF4\x90\x80\x80",
+        b"\xFD\xBF\xBF\xBF\xBF\xBF",
+    ] {
+        assert_eq!(len(vec![bytes(s)]).unwrap(), "nil 1");
+        assert_eq!(
+            len(vec![bytes(s), 1.into(), (-1).into(), true.into()]).unwrap(),
+            "1"
+        );
+    }
+    assert_eq!(
+        len(vec!["abc".into(), 5.into()]).unwrap_err(),
+        "bad argument #2 to 'len' (initial position out of bounds)"
+    );
+    assert_eq!(
+        len(vec!["abc".into(), 1.into(), 4.into()]).unwrap_err(),
+        "bad argument #3 to 'len' (final position out of bounds)"
+    );
+}
+
+#[test]
+fn codepoint() {
+    let state = state();
+    let cp = |args| show(&state, "codepoint", args);
+    assert_eq!(cp(vec!["häll€".into()]).unwrap(), "104");
+    assert_eq!(
+        cp(vec!["häll€".into(), 1.into(), (-1).into()]).unwrap(),
+        "104 228 108 108 8364"
+    );
+    assert_eq!(cp(vec!["abc".into(), 3.into(), 2.into()]).unwrap(), "");
+    assert_eq!(cp(vec![bytes(b"\xFF")]).unwrap_err(), "invalid UTF-8 code");
+    assert_eq!(
+        cp(vec![
+            bytes(b"\xF4\x90\x80\x80"),
+            1.into(),
+            1.into(),
+            true.into()
+        ])
+        .unwrap(),
+        "1114112"
+    );
+    assert_eq!(
+        cp(vec!["abc".into(), 0.into()]).unwrap_err(),
+        "bad argument #2 to 'codepoint' (out of bounds)"
+    );
+    assert_eq!(
+        cp(vec!["abc".into(), 1.into(), 4.into()]).unwrap_err(),
+        "bad argument #3 to 'codepoint' (out of bounds)"
+    );
+}
+
+#[test]
+fn offset() {
+    let state = state();
+    let off = |args| show(&state, "offset", args);
+    let s: Value = "a€b".into();
+    assert_eq!(off(vec![s.clone(), 1.into()]).unwrap(), "1");
+    assert_eq!(off(vec![s.clone(), 2.into()]).unwrap(), "2");
+    assert_eq!(off(vec![s.clone(), 3.into()]).unwrap(), "5");
+    assert_eq!(off(vec![s.clone(), 4.into()]).unwrap(), "6");
+    assert_eq!(off(vec![s.clone(), 5.into()]).unwrap(), "nil");
+    assert_eq!(off(vec![s.clone(), (-1).into()]).unwrap(), "5");
+    assert_eq!(off(vec![s.clone(), (-2).into()]).unwrap(), "2");
+    assert_eq!(off(vec![s.clone(), (-4).into()]).unwrap(), "nil");
+    assert_eq!(off(vec![s.clone(), 0.into(), 4.into()]).unwrap(), "2");
+    assert_eq!(
+        off(vec![s.clone(), 1.into(), 3.into()]).unwrap_err(),
+        "initial position is a continuation byte"
+    );
+    assert_eq!(
+        off(vec![s, 1.into(), 7.into()]).unwrap_err(),
+        "bad argument #3 to 'offset' (position out of bounds)"
+    );
+}
+
+#[test]
+fn codes() {
+    let state = state();
+    let collect = |args: Vec<Value>| {
+        let r = call(&state, "codes", args)?;
+        let mut out = vec![];
+        let mut control = r[2].clone();
+        loop {
+            let v = state
+                .call(&r[0], vec![r[1].clone(), control])
+                .map_err(|e| e.to_string())?;
+            if v.is_empty() || v[0].is_nil() {
+                return Ok::<_, String>(out.join(" "));
+            }
+            out.push(format!(
+                "{}:{}",
+                show_value(&state, &v[0]),
+                show_value(&state, &v[1])
+            ));
+            control = v[0].clone();
+        }
+    };
+    assert_eq!(collect(vec!["a€b".into()]).unwrap(), "1:97 2:8364 5:98");
+    assert_eq!(collect(vec!["".into()]).unwrap(), "");
+    assert_eq!(
+        collect(vec![bytes(b"a\xFF")]).unwrap_err(),
+        "invalid UTF-8 code"
+    );
+    assert_eq!(
+        collect(vec![bytes(b"\xF4\x90\x80\x80")]).unwrap_err(),
+        "invalid UTF-8 code"
+    );
+    assert_eq!(
+        collect(vec![bytes(b"\xF4\x90\x80\x80"), true.into()]).unwrap(),
+        "1:1114112"
+    );
+    assert_eq!(
+        collect(vec![bytes(b"\x80")]).unwrap_err(),
+        "bad argument #1 to 'codes' (invalid UTF-8 code)"
+    );
+}
+
+fn show_value(state: &State, v: &Value) -> String {
+    state.tostring(v).unwrap().to_string()
+}
@@ -0,0 +1,253 @@
+// Copyright 2022 tison <wander4096@gmail.com>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chunk;
+
+use chunk::{chunk, i, Function};
+use rua::{opcode::*, State};
+
+/// Undumps a main function of `maxstacksize` registers running `code` and
+/// returns why it was rejected.
+async fn reject(maxstacksize: u8, code: Vec<u32>) -> String {
+    reject_function(Function {
+        maxstacksize,
+        code,
+        constants: vec!["\"x\"", "1"],
+        upvalues: vec![("_ENV", 1, 0)],
+        ..Default::default()
+    })
+    .await
+}
+
+async fn reject_function(main: Function) -> String {
+    let chunk = chunk(&main);
+    rua::undump(&chunk[..]).await.unwrap_err().to_string()
+}
+
+#[tokio::test]
+async fn operands_out_of_range() {
+    let ret = i(OP_RETURN0, &[]);
+    assert_eq!(
+        reject(2, vec![i(OP_MOVE, &[2, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("MOVE" (2, 0, 0, 0)): register 2 out of range (maxstacksize: 2)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_LOADK, &[0, 2]), ret]).await,
+        r#"bad instruction at pc 0 ("LOADK" (0, 2)): constant 2 out of range (constants: 2)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_GETFIELD, &[0, 0, 2]), ret]).await,
+        r#"bad instruction at pc 0 ("GETFIELD" (0, 0, 0, 2)): constant 2 out of range (constants: 2)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_GETTABUP, &[0, 0, 1]), ret]).await,
+        r#"bad instruction at pc 0 ("GETTABUP" (0, 0, 0, 1)): constant 1 is not a string: Integer(1)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_ADDK, &[0, 0, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("ADDK" (0, 0, 0, 0)): constant 0 is not a number: String("x")"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_GETUPVAL, &[0, 1]), ret]).await,
+        r#"bad instruction at pc 0 ("GETUPVAL" (0, 0, 1, 0)): upvalue 1 out of range (upvalues: 1)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_CLOSURE, &[0, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("CLOSURE" (0, 0)): proto 0 out of range (protos: 0)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_MMBIN, &[0, 1, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("MMBIN" (0, 0, 1, 0)): invalid metamethod event 0"#
+    );
+}
+
+#[tokio::test]
+async fn jump_targets() {
+    let ret = i(OP_RETURN0, &[]);
+    assert_eq!(
+        reject(2, vec![i(OP_JMP, &[5]), ret]).await,
+        r#"bad instruction at pc 0 ("JMP" 5): jump target 6 out of range (code: 2)"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_JMP, &[-2]), ret]).await,
+        r#"bad instruction at pc 0 ("JMP" -2): jump target -1 out of range (code: 2)"#
+    );
+    assert_eq!(
+        reject(
+            2,
+            vec![
+                i(OP_JMP, &[1]),
+                i(OP_LOADKX, &[0]),
+                i(OP_EXTRAARG, &[0]),
+                ret
+            ]
+        )
+        .await,
+        r#"bad instruction at pc 0 ("JMP" 1): jump target 2 is OP_EXTRAARG"#
+    );
+    assert_eq!(
+        reject(5, vec![i(OP_TFORPREP, &[0, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("TFORPREP" (0, 0)): generic for does not jump to OP_TFORCALL"#
+    );
+    assert_eq!(
+        reject(6, vec![i(OP_TFORCALL, &[0, 0, 1]), ret]).await,
+        r#"bad instruction at pc 0 ("TFORCALL" (0, 0, 0, 1)): not followed by OP_TFORLOOP"#
+    );
+}
+
+#[tokio::test]
+async fn instruction_pairs() {
+    let ret = i(OP_RETURN0, &[]);
+    assert_eq!(
+        reject(2, vec![i(OP_LOADKX, &[0]), ret]).await,
+        r#"bad instruction at pc 0 ("LOADKX" (0, 0)): not followed by OP_EXTRAARG"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_NEWTABLE, &[0, 0, 0]), ret]).await,
+        r#"bad instruction at pc 0 ("NEWTABLE" (0, 0, 0, 0)): not followed by OP_EXTRAARG"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_SETLIST, &[0, 0, 0, 1]), ret]).await,
+        r#"bad instruction at pc 0 ("SETLIST" (0, 1, 0, 0)): not followed by OP_EXTRAARG"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_EXTRAARG, &[0]), ret]).await,
+        r#"bad instruction at pc 0 ("EXTRAARG" 0): no instruction to extend"#
+    );
+    assert_eq!(
+        reject(
+            2,
+            vec![i(OP_SETLIST, &[0, 0, 0]), i(OP_EXTRAARG, &[0]), ret]
+        )
+        .await,
+        r#"bad instruction at pc 1 ("EXTRAARG" 0): cannot extend SETLIST"#
+    );
+    assert_eq!(
+        reject(2, vec![i(OP_EQ, &[0, 1]), ret]).await,
+        r#"bad instruction at pc 0 ("EQ" (0, 0, 1, 0)): not followed by OP_JMP"#
+    );
+}
+
+#[tokio::test]
+async fn function_shape() {
+    assert_eq!(
+        reject(2, vec![i(OP_LOADNIL, &[0, 0])]).await,
+        "code does not end with a return"
+    );
+    assert_eq!(reject(2, vec![]).await, "code does not end with a return");
+    assert_eq!(
+        reject(2, vec![i(OP_VARARGPREP, &[0]), i(OP_RETURN0, &[])]).await,
+        r#"bad instruction at pc 0 ("VARARGPREP" (0, 0, 0, 0)): vararg instruction in a non-vararg function"#
+    );
+    let vararg = |code| Function {
+        is_vararg: true,
+        maxstacksize: 2,
+        code,
+        ..Default::default()
+    };
+    let ret = i(OP_RETURN0, &[]);
+    assert_eq!(
+        reject_function(vararg(vec![
+            i(OP_LOADNIL, &[0, 0]),
+            i(OP_VARARGPREP, &[0]),
+            ret
+        ]))
+        .await,
+        r#"bad instruction at pc 1 ("VARARGPREP" (0, 0, 0, 0)): not the first instruction"#
+    );
+}
+
+/// Assembles `text` and loads it back from its binary chunk.
+fn load(text: &str) -> Result<(), String> {
+    let chunk = rua::dump(&rua::assemble(text).unwrap());
+    load_chunk(chunk)
+}
+
+fn load_chunk(chunk: Vec<u8>) -> Result<(), String> {
+    State::new()
+        .load_binary(chunk)
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+/// A main function of two registers and one upvalue whose nested function
+/// captures `upvalue`.
+fn nested(upvalue: &str) -> String {
+    format!(
+        ".upvalue _ENV 1 0
+         .maxstack 2
+         CLOSURE 0 f
+         RETURN0
+         .function f
+         .upvalue {}
+         RETURN0
+         .end",
+        upvalue
+    )
+}
+
+#[test]
+fn nested_upvalues() {
+    assert_eq!(load(&nested("x 1 1")), Ok(()));
+    assert_eq!(load(&nested("x 0 0")), Ok(()));
+    assert_eq!(
+        load(&nested("x 1 2")).unwrap_err(),
+        "upvalue 0 captures register 2 out of range (2)"
+    );
+    assert_eq!(
+        load(&nested("x 0 1")).unwrap_err(),
+        "upvalue 0 captures upvalue 1 out of range (1)"
+    );
+}
+
+#[tokio::test]
+async fn nested_upvalues_lazily() {
+    let chunk = rua::dump(&rua::assemble(&nested("x 1 7")).unwrap());
+    let closure = rua::undump_lazy(&chunk[..]).await.unwrap();
+    let f = &closure.proto().protos()[0];
+    assert!(f.get().is_none());
+    assert_eq!(
+        f.load().await.unwrap_err().to_string(),
+        "upvalue 0 captures register 7 out of range (2)"
+    );
+}
+
+#[test]
+fn abslineinfo() {
+    // Both lines are too far from the previous one for a delta, so each
+    // instruction has an anchor: pc 0 at line 1000, pc 1 at line 2000.
+    let text = ".line 1000
+                LOADNIL 0 0
+                .line 2000
+                RETURN0";
+    let proto = rua::assemble(text).unwrap();
+    assert_eq!(proto.line_for_pc(1), Some(2000));
+    let chunk = rua::dump(&proto);
+    let anchors = [0x82, 0x80, 0x07, 0xe8, 0x81, 0x0f, 0xd0];
+    let at = chunk
+        .windows(anchors.len())
+        .position(|w| w == anchors)
+        .unwrap();
+    assert_eq!(load_chunk(chunk.clone()), Ok(()));
+
+    let patch = |pc0: u8, pc1: u8| {
+        let mut chunk = chunk.clone();
+        chunk[at + 1] = 0x80 | pc0;
+        chunk[at + 4] = 0x80 | pc1;
+        load_chunk(chunk).unwrap_err()
+    };
+    assert_eq!(patch(1, 0), "abslineinfo pc 0 out of order");
+    assert_eq!(patch(0, 0), "abslineinfo pc 0 out of order");
+    assert_eq!(patch(0, 2), "abslineinfo pc 2 out of range (lineinfo: 2)");
+}